@@ -0,0 +1,110 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+#![deny(warnings, clippy::all)]
+
+//! Node.js bindings for system_proxy.
+//!
+//! Expose an asynchronous `lookup(url)` function which consults the curl environment first, and
+//! then the best available system resolver of the current platform, and a `watch(callback)`
+//! function which calls `callback` whenever the proxy configuration changes.
+
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use napi::bindgen_prelude::{spawn, spawn_blocking};
+use napi::threadsafe_function::{ErrorStrategy, ThreadsafeFunction, ThreadsafeFunctionCallMode};
+use napi::{Error, JsFunction, Result, Status};
+use napi_derive::napi;
+use system_proxy::proxy::ProxyResolver;
+use system_proxy::watch::ProxyWatcher;
+use system_proxy::SystemProxyResolver;
+use tokio::task::JoinHandle;
+use url::Url;
+
+/// Get the shared system proxy resolver, creating it on first use.
+///
+/// Creating the resolver probes the system backends and may block.
+fn resolver() -> Arc<SystemProxyResolver> {
+    static RESOLVER: Mutex<Option<Arc<SystemProxyResolver>>> = Mutex::new(None);
+    let mut resolver = RESOLVER.lock().unwrap_or_else(PoisonError::into_inner);
+    resolver
+        .get_or_insert_with(|| Arc::new(system_proxy::default()))
+        .clone()
+}
+
+/// Lookup the proxy for `url`.
+///
+/// Resolve to the proxy URL as string, or `null` for a direct connection.  Consult the curl
+/// environment first, and then the best available system resolver, e.g. the Freedesktop proxy
+/// resolver portal on Linux or WinHttp on Windows; see `SystemProxyResolver`.  Run the lookup on
+/// a blocking task, because system resolvers block.  Reject if `url` is not a valid URL.
+#[napi]
+pub async fn lookup(url: String) -> Result<Option<String>> {
+    let url = Url::parse(&url)
+        .map_err(|error| Error::new(Status::InvalidArg, format!("Invalid URL {url}: {error}")))?;
+    let decision = spawn_blocking(move || resolver().decide(&url))
+        .await
+        .map_err(|error| Error::from_reason(error.to_string()))?;
+    Ok(decision.into_proxy().map(String::from))
+}
+
+/// A changed proxy setting.
+#[napi(object)]
+pub struct ProxySettingChange {
+    /// The path of the changed setting, e.g. `environment.http_proxy`.
+    pub field: String,
+    /// The old value, or `null` if the setting was absent.
+    pub old: Option<String>,
+    /// The new value, or `null` if the setting is absent now.
+    pub new: Option<String>,
+}
+
+/// A running watch of the proxy configuration; see `watch`.
+#[napi]
+pub struct ProxyWatch {
+    task: JoinHandle<()>,
+}
+
+#[napi]
+impl ProxyWatch {
+    /// Stop watching, and never call the callback again.
+    #[napi]
+    pub fn stop(&self) {
+        self.task.abort();
+    }
+}
+
+/// Call `callback` with the changed settings whenever the proxy configuration changes.
+///
+/// Subscribe to changes of the system settings and the network where supported, and check the
+/// configuration every `intervalSeconds`, 30 by default, if no subscription is available.  Return a handle whose `stop()` ends the watch; the watch
+/// keeps the process alive until stopped.
+#[napi(
+    ts_args_type = "callback: (changes: ProxySettingChange[]) => void, intervalSeconds?: number"
+)]
+pub fn watch(callback: JsFunction, interval_seconds: Option<u32>) -> Result<ProxyWatch> {
+    let callback: ThreadsafeFunction<Vec<ProxySettingChange>, ErrorStrategy::Fatal> =
+        callback.create_threadsafe_function(0, |context| Ok(vec![context.value]))?;
+    let interval = Duration::from_secs(interval_seconds.unwrap_or(30).into());
+    let mut watcher = ProxyWatcher::new(interval);
+    let task = spawn(async move {
+        while let Some(change) = watcher.next().await {
+            let changes = change
+                .changes
+                .into_iter()
+                .map(|change| ProxySettingChange {
+                    field: change.field,
+                    old: change.old,
+                    new: change.new,
+                })
+                .collect();
+            callback.call(changes, ThreadsafeFunctionCallMode::NonBlocking);
+        }
+    });
+    Ok(ProxyWatch { task })
+}
@@ -0,0 +1,251 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+#![deny(warnings, clippy::all)]
+
+//! Python bindings for system_proxy.
+//!
+//! Exposes the curl environment proxies and no-proxy rules of the `env` module, lookups with the
+//! best available resolver of the system and explanations of their decisions, diagnostics, and
+//! snapshots of the proxy configuration to Python.
+
+use std::sync::Mutex;
+
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::sync::GILOnceCell;
+use pyo3::types::PyDict;
+use system_proxy::diagnostics::{self, Level};
+use system_proxy::env::{self, NoProxy};
+use system_proxy::proxy::ProxyResolver;
+use system_proxy::snapshot::SystemProxySnapshot;
+use system_proxy::SystemProxyResolver;
+use url::Url;
+
+fn parse_url(url: &str) -> PyResult<Url> {
+    Url::parse(url).map_err(|error| PyValueError::new_err(format!("Invalid URL {url}: {error}")))
+}
+
+/// Rules for when not to use a proxy.
+#[pyclass(name = "NoProxyRules", module = "system_proxy", frozen)]
+#[derive(Debug, Clone)]
+struct PyNoProxyRules(env::NoProxyRules);
+
+#[pymethods]
+impl PyNoProxyRules {
+    /// Parse no proxy rules in curl's `$no_proxy` format.
+    #[staticmethod]
+    fn parse_curl_env(value: &str) -> Self {
+        Self(env::NoProxyRules::parse_curl_env(value))
+    }
+
+    /// Lookup no proxy rules in `$no_proxy` and `$NO_PROXY`.
+    ///
+    /// Return `None` if both variables are unset.
+    #[staticmethod]
+    fn from_curl_env() -> Option<Self> {
+        env::NoProxyRules::from_curl_env().map(Self)
+    }
+
+    /// Whether not to use a proxy for the given `url`.
+    fn no_proxy_for(&self, url: &str) -> PyResult<bool> {
+        Ok(self.0.no_proxy_for(&parse_url(url)?))
+    }
+
+    /// Whether to use a proxy for the given `url`.
+    fn proxy_allowed_for(&self, url: &str) -> PyResult<bool> {
+        Ok(self.0.proxy_allowed_for(&parse_url(url)?))
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}", self.0)
+    }
+
+    fn __eq__(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+/// Proxies extracted from the curl environment.
+#[pyclass(name = "EnvProxies", module = "system_proxy", frozen)]
+#[derive(Debug, Clone)]
+struct PyEnvProxies(env::EnvProxies);
+
+#[pymethods]
+impl PyEnvProxies {
+    /// Get proxies defined in the curl environment.
+    #[staticmethod]
+    fn from_curl_env() -> Self {
+        Self(env::EnvProxies::from_curl_env())
+    }
+
+    /// The proxy for `http:` URLs, if any.
+    #[getter]
+    fn http(&self) -> Option<String> {
+        self.0.http.as_ref().map(ToString::to_string)
+    }
+
+    /// The proxy for `https:` URLs, if any.
+    #[getter]
+    fn https(&self) -> Option<String> {
+        self.0.https.as_ref().map(ToString::to_string)
+    }
+
+    /// Rules for when not to use a proxy, if any.
+    #[getter]
+    fn no_proxy_rules(&self) -> Option<PyNoProxyRules> {
+        self.0.no_proxy_rules.clone().map(PyNoProxyRules)
+    }
+
+    /// Whether no proxies were set in the environment.
+    fn is_unset(&self) -> bool {
+        self.0.is_unset()
+    }
+
+    /// Lookup the proxy for the given `url`.
+    ///
+    /// Return the proxy URL as string, or `None` for a direct connection.
+    fn lookup(&self, url: &str) -> PyResult<Option<String>> {
+        Ok(self.0.lookup(&parse_url(url)?).map(ToString::to_string))
+    }
+
+    /// Get why to connect directly to the given `url`, e.g. the matching no-proxy rule.
+    ///
+    /// Return `None` if `url` uses a proxy.
+    fn direct_reason(&self, url: &str) -> PyResult<Option<String>> {
+        Ok(self
+            .0
+            .direct_reason(&parse_url(url)?)
+            .map(|reason| reason.to_string()))
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}", self.0)
+    }
+}
+
+/// The resolver for `lookup`, created on first use.
+static RESOLVER: GILOnceCell<SystemProxyResolver> = GILOnceCell::new();
+
+/// Lookup the proxy for `url` with the best available resolver of the system.
+///
+/// Consult the curl environment first, and then the system resolver of the platform, e.g. the
+/// Freedesktop proxy resolver portal on Linux, or WinHttp on Windows.  Return the proxy URL as
+/// string, or `None` for a direct connection.
+#[pyfunction]
+fn lookup(py: Python<'_>, url: &str) -> PyResult<Option<String>> {
+    let url = parse_url(url)?;
+    let resolver = RESOLVER.get_or_init(py, system_proxy::default);
+    Ok(py
+        .allow_threads(|| resolver.for_url(&url))
+        .map(String::from))
+}
+
+/// Get why the best available resolver of the system connects directly to `url`.
+///
+/// Return a description of the reason, e.g. the matching no-proxy rule, or `None` if `url` uses
+/// a proxy.
+#[pyfunction]
+fn direct_reason(py: Python<'_>, url: &str) -> PyResult<Option<String>> {
+    let url = parse_url(url)?;
+    let resolver = RESOLVER.get_or_init(py, system_proxy::default);
+    Ok(py
+        .allow_threads(|| resolver.direct_reason(&url))
+        .map(|reason| reason.to_string()))
+}
+
+/// Explain the decision of the best available resolver of the system for `url`.
+///
+/// Return a dict with the `url`, the `proxy` to use or `None`, the `direct_reason` if `url` does
+/// not use a proxy, and the name of the `system` resolver, e.g. `portal`, if any.
+#[pyfunction]
+fn explain<'py>(py: Python<'py>, url: &str) -> PyResult<Bound<'py, PyDict>> {
+    let url = parse_url(url)?;
+    let resolver = RESOLVER.get_or_init(py, system_proxy::default);
+    let (proxy, reason) = py.allow_threads(|| match resolver.direct_reason(&url) {
+        Some(reason) => (None, Some(reason)),
+        None => (resolver.for_url(&url), None),
+    });
+    let explanation = PyDict::new(py);
+    explanation.set_item("url", url.as_str())?;
+    explanation.set_item("proxy", proxy.map(String::from))?;
+    explanation.set_item("direct_reason", reason.map(|reason| reason.to_string()))?;
+    explanation.set_item("system", resolver.system_name())?;
+    Ok(explanation)
+}
+
+/// The Python callable which receives diagnostics, if any.
+static DIAGNOSTICS_HANDLER: Mutex<Option<Py<PyAny>>> = Mutex::new(None);
+
+/// Pass a diagnostic `message` at `level` to the Python handler.
+fn emit_diagnostic(level: Level, message: &str) {
+    let level = match level {
+        Level::Warn => "warning",
+        Level::Info => "info",
+        Level::Debug => "debug",
+    };
+    Python::with_gil(|py| {
+        let handler = DIAGNOSTICS_HANDLER
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .as_ref()
+            .map(|handler| handler.clone_ref(py));
+        if let Some(handler) = handler {
+            if let Err(error) = handler.call1(py, (level, message)) {
+                error.write_unraisable(py, None);
+            }
+        }
+    });
+}
+
+/// Call `handler` with the level and the message of every diagnostic of system_proxy.
+///
+/// The level is one of `"warning"`, `"info"`, or `"debug"`, e.g. for `logging.getLevelName`.
+/// Pass `None` to stop receiving diagnostics.
+#[pyfunction]
+fn set_diagnostics_handler(py: Python<'_>, handler: Option<Py<PyAny>>) -> PyResult<()> {
+    if let Some(handler) = &handler {
+        if !handler.bind(py).is_callable() {
+            return Err(PyValueError::new_err(
+                "Diagnostics handler must be callable",
+            ));
+        }
+    }
+    let sink = handler
+        .as_ref()
+        .map(|_| emit_diagnostic as diagnostics::Sink);
+    *DIAGNOSTICS_HANDLER
+        .lock()
+        .unwrap_or_else(|error| error.into_inner()) = handler;
+    diagnostics::set_sink(sink);
+    Ok(())
+}
+
+/// Capture a snapshot of the proxy configuration of the system, for bug reports.
+///
+/// Return a dict with the proxy environment variables, the settings of all platform settings
+/// stores, and the answers of all backends for some probe URLs.
+#[pyfunction]
+fn snapshot(py: Python<'_>) -> PyResult<Bound<'_, PyAny>> {
+    let snapshot = py.allow_threads(|| async_io::block_on(SystemProxySnapshot::capture()));
+    let json = serde_json::to_string(&snapshot)
+        .map_err(|error| PyRuntimeError::new_err(error.to_string()))?;
+    py.import("json")?.call_method1("loads", (json,))
+}
+
+/// Lookup HTTP proxies in various ways.
+#[pymodule]
+#[pyo3(name = "system_proxy")]
+fn system_proxy_python(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyNoProxyRules>()?;
+    m.add_class::<PyEnvProxies>()?;
+    m.add_function(wrap_pyfunction!(lookup, m)?)?;
+    m.add_function(wrap_pyfunction!(direct_reason, m)?)?;
+    m.add_function(wrap_pyfunction!(explain, m)?)?;
+    m.add_function(wrap_pyfunction!(set_diagnostics_handler, m)?)?;
+    m.add_function(wrap_pyfunction!(snapshot, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,30 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! This example demonstrates how to use the portal resolver without tokio, on a runtime built on
+//! async-io, like async-std or smol.
+
+#[cfg(all(feature = "portal", feature = "async-io", not(feature = "tokio")))]
+fn main() {
+    let url = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "https://example.com".to_string());
+    let url = url::Url::parse(&url).unwrap();
+    let proxy = async_io::block_on(async {
+        let resolver = system_proxy::unix::FreedesktopPortalProxyResolver::connect().await?;
+        resolver.lookup(&url).await
+    })
+    .unwrap();
+    match proxy {
+        Some(proxy) => println!("Portal provided proxy {proxy}"),
+        None => println!("Portal says direct connection"),
+    }
+}
+
+#[cfg(not(all(feature = "portal", feature = "async-io", not(feature = "tokio"))))]
+fn main() {
+    panic!("--features portal,async-io without tokio required for this example");
+}
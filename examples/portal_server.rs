@@ -0,0 +1,32 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! This example demonstrates how to provide the proxy resolver portal from the proxy
+//! environment variables, e.g. on a headless system without a portal implementation.
+
+#[cfg(all(feature = "portal-server", feature = "tokio"))]
+fn main() {
+    use system_proxy::env::EnvProxies;
+    use system_proxy::unix::ProxyResolverPortal;
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let _connection = ProxyResolverPortal::new(EnvProxies::from_curl_env())
+            .serve_session()
+            .await
+            .unwrap();
+        println!("Serving the proxy resolver portal, press Ctrl+C to stop");
+        std::future::pending::<()>().await;
+    });
+}
+
+#[cfg(not(all(feature = "portal-server", feature = "tokio")))]
+fn main() {
+    panic!("--features portal-server,tokio required for this example");
+}
@@ -5,34 +5,22 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 //! This example demonstrates how to use environment proxies and the async
-//! portal resolver with the reqwest library.
+//! portal resolver with the reqwest library, through a blocking adapter.
 
-#[cfg(feature = "portal")]
+#[cfg(all(feature = "portal", feature = "tokio"))]
 async fn do_request() -> Result<(), Box<dyn std::error::Error>> {
+    use system_proxy::proxy::{AsyncProxyResolver, ProxyResolver};
+
     let portal_resolver = system_proxy::unix::FreedesktopPortalProxyResolver::connect().await?;
-    let env_proxies = system_proxy::env::from_curl_env();
+    // Consult the curl environment first, and then the portal.  reqwest asks for proxies
+    // synchronously, so block on the async portal resolver with a blocking adapter.
+    let resolver = system_proxy::SystemProxyResolver::new(portal_resolver.into_blocking());
     let proxy = reqwest::Proxy::custom(move |url| {
-        let proxy = env_proxies.lookup(url).map(Clone::clone);
-        println!("Environment provided proxy {proxy:?}");
-        proxy.or_else(|| {
-            // Create a one-shot channel to bridge from the async proxy resolver to the synchronous
-            // proxy interface of reqwest.
-            let (tx, rx) = tokio::sync::oneshot::channel();
-            let url = url.clone();
-            let portal_resolver = portal_resolver.clone();
-            tokio::task::spawn(async move {
-                let result = portal_resolver.lookup(&url).await;
-                tx.send(result).unwrap();
-            });
-            let proxy = tokio::task::block_in_place(|| rx.blocking_recv())
-                .unwrap()
-                .unwrap_or_else(|err| {
-                    eprintln!("Proxy lookup on portal failed: {}", err);
-                    None
-                });
-            println!("Portal provided proxy {proxy:?}");
-            proxy
-        })
+        // Blocking on the portal would stall the runtime, so tell tokio to move other tasks off
+        // this worker thread meanwhile.
+        let proxy = tokio::task::block_in_place(|| resolver.for_url(url));
+        println!("System provided proxy {proxy:?}");
+        proxy
     });
 
     let client = reqwest::Client::builder()
@@ -49,9 +37,9 @@ async fn do_request() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-#[cfg(feature = "portal")]
+#[cfg(all(feature = "portal", feature = "tokio"))]
 fn main() {
-    // We must use a multi-threaded runtime for tokio::task::block_in_place and channels.
+    // We must use a multi-threaded runtime for tokio::task::block_in_place.
     let runtime = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .build()
@@ -60,7 +48,7 @@ fn main() {
     runtime.block_on(do_request()).unwrap();
 }
 
-#[cfg(not(feature = "portal"))]
+#[cfg(not(all(feature = "portal", feature = "tokio")))]
 fn main() {
-    panic!("--features portal required for this example");
+    panic!("--features portal,tokio required for this example");
 }
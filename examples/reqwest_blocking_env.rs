@@ -7,22 +7,20 @@
 //! This example demonstrates how to use environment proxies with
 //! the reqwest library.
 
+use system_proxy::diagnostics::set_sink;
+use system_proxy::proxy::{LoggingResolver, ProxyResolver};
+
 fn main() {
-    let proxy = system_proxy::env::from_curl_env();
+    // Print diagnostics, including the decisions of the resolver below
+    set_sink(Some(|level, message| eprintln!("{level:?}: {message}")));
+    let proxy = LoggingResolver::new("env", system_proxy::env::from_curl_env());
     let client = reqwest::blocking::Client::builder()
         .user_agent(concat!(
             env!("CARGO_PKG_NAME"),
             "/",
             env!("CARGO_PKG_VERSION")
         ))
-        .proxy(reqwest::Proxy::custom(move |url| {
-            let proxy_url = proxy.lookup(url);
-            match &proxy_url {
-                None => println!("Using direct connection for URL {}", url),
-                Some(u) => println!("Using proxy {} for URL {}", u, url),
-            }
-            proxy_url.cloned()
-        }))
+        .proxy(reqwest::Proxy::custom(move |url| proxy.for_url(url)))
         .build()
         .unwrap();
 
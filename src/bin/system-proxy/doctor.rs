@@ -0,0 +1,259 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Diagnose the proxy configuration of the system.
+
+use std::io::{Result, Write};
+use std::path::PathBuf;
+
+use serde::Serialize;
+use url::Url;
+
+/// Environment variables relevant for proxy configuration.
+const VARIABLES: [&str; 6] = [
+    "http_proxy",
+    "HTTP_PROXY",
+    "https_proxy",
+    "HTTPS_PROXY",
+    "no_proxy",
+    "NO_PROXY",
+];
+
+/// Environment variables which tell about the desktop session.
+#[cfg(all(unix, not(target_os = "macos")))]
+const SESSION_VARIABLES: [&str; 3] = [
+    "XDG_CURRENT_DESKTOP",
+    "XDG_SESSION_TYPE",
+    "DBUS_SESSION_BUS_ADDRESS",
+];
+
+/// URLs to probe system backends with.
+const PROBE_URLS: [&str; 2] = ["http://example.com/", "https://example.com/"];
+
+/// Known files which may contain proxy settings.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn settings_sources() -> Vec<(&'static str, PathBuf)> {
+    let mut sources = Vec::new();
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    if let Some(config_home) = config_home {
+        sources.push((
+            "dconf user database",
+            config_home.join("dconf").join("user"),
+        ));
+        sources.push(("KDE proxy settings", config_home.join("kioslaverc")));
+    }
+    sources.push(("system environment", PathBuf::from("/etc/environment")));
+    sources.push(("SUSE proxy settings", PathBuf::from("/etc/sysconfig/proxy")));
+    sources
+}
+
+/// Known files which may contain proxy settings.
+#[cfg(target_os = "macos")]
+fn settings_sources() -> Vec<(&'static str, PathBuf)> {
+    vec![(
+        "system configuration",
+        PathBuf::from("/Library/Preferences/SystemConfiguration/preferences.plist"),
+    )]
+}
+
+/// Known files which may contain proxy settings.
+#[cfg(not(unix))]
+fn settings_sources() -> Vec<(&'static str, PathBuf)> {
+    Vec::new()
+}
+
+/// An environment variable.
+#[derive(Debug, Serialize)]
+pub struct Variable {
+    name: &'static str,
+    /// The value of the variable, or `None` if unset.
+    value: Option<String>,
+}
+
+impl Variable {
+    fn lookup(name: &'static str) -> Self {
+        Self {
+            name,
+            value: std::env::var_os(name).map(|value| value.to_string_lossy().into_owned()),
+        }
+    }
+}
+
+/// The result of probing a backend with a URL.
+#[derive(Debug, Serialize)]
+pub struct Probe {
+    url: Url,
+    /// The proxy the backend returned, or `None` for a direct connection or if the lookup failed.
+    proxy: Option<Url>,
+    /// The error if the lookup failed.
+    error: Option<String>,
+}
+
+/// A backend and its probes.
+#[derive(Debug, Serialize)]
+pub struct Backend {
+    name: &'static str,
+    /// Whether this backend was enabled at build time.
+    enabled: bool,
+    /// The results of probing this backend; empty if the backend was not enabled.
+    probes: Vec<Probe>,
+}
+
+impl Backend {
+    fn probe<E, F>(name: &'static str, lookup: F) -> Self
+    where
+        E: std::fmt::Display,
+        F: Fn(&Url) -> std::result::Result<Option<Url>, E>,
+    {
+        let probes = PROBE_URLS
+            .iter()
+            .map(|url| {
+                let url = Url::parse(url).unwrap();
+                match lookup(&url) {
+                    Ok(proxy) => Probe {
+                        url,
+                        proxy,
+                        error: None,
+                    },
+                    Err(error) => Probe {
+                        url,
+                        proxy: None,
+                        error: Some(error.to_string()),
+                    },
+                }
+            })
+            .collect();
+        Self {
+            name,
+            enabled: true,
+            probes,
+        }
+    }
+
+    #[cfg(not(all(feature = "portal", feature = "gio")))]
+    fn disabled(name: &'static str) -> Self {
+        Self {
+            name,
+            enabled: false,
+            probes: Vec::new(),
+        }
+    }
+}
+
+/// A known source of proxy settings.
+#[derive(Debug, Serialize)]
+pub struct SettingsSource {
+    name: &'static str,
+    path: PathBuf,
+    /// Whether the source exists on this system.
+    found: bool,
+}
+
+/// A report about the proxy configuration of this system.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    version: &'static str,
+    os: &'static str,
+    arch: &'static str,
+    environment: Vec<Variable>,
+    backends: Vec<Backend>,
+    settings_sources: Vec<SettingsSource>,
+}
+
+impl Report {
+    /// Collect a report about the proxy configuration.
+    pub fn collect() -> Self {
+        #[allow(unused_mut)]
+        let mut environment = VARIABLES
+            .into_iter()
+            .map(Variable::lookup)
+            .collect::<Vec<_>>();
+        #[cfg(all(unix, not(target_os = "macos")))]
+        environment.extend(SESSION_VARIABLES.into_iter().map(Variable::lookup));
+
+        let backends = vec![
+            Backend::probe("environment", |url| {
+                Ok::<_, std::convert::Infallible>(
+                    system_proxy::env::from_curl_env().lookup(url).cloned(),
+                )
+            }),
+            #[cfg(feature = "portal")]
+            Backend::probe("portal", crate::resolve::lookup_portal),
+            #[cfg(not(feature = "portal"))]
+            Backend::disabled("portal"),
+            #[cfg(feature = "gio")]
+            Backend::probe("gio", crate::resolve::lookup_gio),
+            #[cfg(not(feature = "gio"))]
+            Backend::disabled("gio"),
+        ];
+
+        let settings_sources = settings_sources()
+            .into_iter()
+            .map(|(name, path)| SettingsSource {
+                name,
+                found: path.exists(),
+                path,
+            })
+            .collect();
+
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            os: std::env::consts::OS,
+            arch: std::env::consts::ARCH,
+            environment,
+            backends,
+            settings_sources,
+        }
+    }
+
+    /// Write this report as human-readable text to `sink`.
+    pub fn write_text<W: Write>(&self, sink: &mut W) -> Result<()> {
+        writeln!(
+            sink,
+            "{} {} on {}/{}",
+            env!("CARGO_PKG_NAME"),
+            self.version,
+            self.os,
+            self.arch
+        )?;
+
+        writeln!(sink, "\nEnvironment:")?;
+        for variable in &self.environment {
+            match &variable.value {
+                Some(value) => writeln!(sink, "  {}={value}", variable.name)?,
+                None => writeln!(sink, "  {} is not set", variable.name)?,
+            }
+        }
+
+        writeln!(sink, "\nBackends:")?;
+        for backend in &self.backends {
+            if !backend.enabled {
+                writeln!(sink, "  {}: not enabled at build time", backend.name)?;
+                continue;
+            }
+            writeln!(sink, "  {}:", backend.name)?;
+            for probe in &backend.probes {
+                match (&probe.proxy, &probe.error) {
+                    (_, Some(error)) => writeln!(sink, "    {} -> failed: {error}", probe.url)?,
+                    (Some(proxy), None) => writeln!(sink, "    {} -> {proxy}", probe.url)?,
+                    (None, None) => writeln!(sink, "    {} -> direct", probe.url)?,
+                }
+            }
+        }
+
+        writeln!(sink, "\nSettings sources:")?;
+        if self.settings_sources.is_empty() {
+            writeln!(sink, "  no known settings sources on this system")?;
+        }
+        for source in &self.settings_sources {
+            let found = if source.found { "found" } else { "not found" };
+            writeln!(sink, "  {}: {} {found}", source.name, source.path.display())?;
+        }
+        Ok(())
+    }
+}
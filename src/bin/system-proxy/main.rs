@@ -0,0 +1,384 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+#![deny(warnings, clippy::all)]
+
+//! Inspect system proxy settings on the command line.
+
+use std::error::Error;
+use std::future::Future;
+use std::io::Write;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use clap::{Parser, Subcommand};
+use futures_util::StreamExt;
+use serde::Serialize;
+use system_proxy::diagnostics::Level;
+use system_proxy::env::NoProxyRules;
+use system_proxy::snapshot::Change;
+use system_proxy::watch::ProxyWatcher;
+use url::Url;
+
+mod doctor;
+mod export;
+#[cfg(feature = "pac")]
+mod pac_eval;
+mod resolve;
+mod store;
+
+/// Inspect system proxy settings.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Args {
+    /// Print machine-readable JSON instead of human-readable text.
+    ///
+    /// Print errors as JSON objects with an "error" key to standard output.
+    #[arg(long, global = true)]
+    json: bool,
+    /// Do not cache the decisions of the system resolver on disk.
+    ///
+    /// By default cache decisions of the system resolver for five minutes in the user cache
+    /// directory, to speed up repeated invocations.  The watch command never uses the cache.
+    #[arg(long, global = true)]
+    no_cache: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Print the proxy to use for a URL, or "direct" for a direct connection.
+    Lookup {
+        /// The URL to lookup a proxy for.
+        url: Url,
+    },
+    /// Watch the proxy configuration, and print every change and the proxy for a URL.
+    ///
+    /// Print the proxy for the URL or "direct", and then, whenever the proxy configuration
+    /// changes, every changed setting followed by the proxy for the URL.  Subscribe to changes
+    /// of the system settings where supported, and otherwise check the configuration
+    /// periodically.  With --json print one JSON object per line, with the changed settings and
+    /// the outcome of the lookup.
+    Watch {
+        /// The URL to watch the proxy for.
+        url: Url,
+        /// The number of seconds to wait between checks if the system settings can't be watched.
+        #[arg(long, default_value = "5")]
+        interval: u64,
+    },
+    /// Print a report about the proxy configuration of this system.
+    ///
+    /// Prints relevant environment variables, probes all backends enabled at build time, and
+    /// lists known sources of proxy settings found on this system.  Please include this report
+    /// in bug reports.
+    Doctor,
+    /// Export the proxy configuration for other tools.
+    ///
+    /// Export the proxies from the curl environment if any.  Otherwise export the proxies the
+    /// system backends return for http and https URLs; in this case the exported configuration
+    /// has no rules for when not to use a proxy.  --json implies --format json.
+    Export {
+        /// The format to export to.
+        #[arg(long, value_enum, default_value = "env")]
+        format: export::Format,
+    },
+    /// Evaluate a proxy auto-config script for a URL.
+    ///
+    /// Print the raw result of FindProxyForURL, and then the proxies in the result in order of
+    /// preference, one per line, or "direct" for a direct connection.  Warn about invalid entries
+    /// in the result.
+    #[cfg(feature = "pac")]
+    PacEval {
+        /// The path or URL of the script; supports http: and file: URLs.
+        script: String,
+        /// The URL to evaluate the script for.
+        url: Url,
+    },
+    /// Write proxy settings to the system settings, and enable the proxy.
+    ///
+    /// Writes the GNOME settings on Linux and other Unix systems if built with Gio, the Internet
+    /// Settings in the registry on Windows, and the settings of a network service with
+    /// networksetup on macOS.  GNOME has a single SOCKS proxy for all URLs, so SOCKS proxies for
+    /// http and https URLs must be the same.  Running applications may not pick up the new
+    /// settings until restarted.
+    Set {
+        /// The proxy for http URLs.
+        #[arg(long)]
+        http: Option<Url>,
+        /// The proxy for https URLs.
+        #[arg(long)]
+        https: Option<Url>,
+        /// When not to use a proxy, in the format of $no_proxy.
+        #[arg(long, default_value = "")]
+        no_proxy: String,
+        /// The network service to configure, e.g. "Wi-Fi" (macOS only).
+        #[arg(long)]
+        service: Option<String>,
+    },
+    /// Disable the proxy in the system settings.
+    Unset {
+        /// The network service to configure, e.g. "Wi-Fi" (macOS only).
+        #[arg(long)]
+        service: Option<String>,
+    },
+}
+
+/// Print a single JSON `value` on a line.
+fn print_json<T: serde::Serialize>(value: &T) -> Result<(), Box<dyn Error>> {
+    let mut stdout = std::io::stdout().lock();
+    serde_json::to_writer(&mut stdout, value)?;
+    writeln!(stdout)?;
+    Ok(())
+}
+
+/// Print the outcome of a lookup.
+fn print_outcome(outcome: &resolve::Outcome, json: bool) -> Result<(), Box<dyn Error>> {
+    if json {
+        print_json(outcome)
+    } else {
+        match &outcome.proxy {
+            Some(proxy) => println!("{proxy}"),
+            None => println!("direct"),
+        }
+        Ok(())
+    }
+}
+
+/// Print warnings of the library to standard error, e.g. if a system resolver failed.
+fn print_warning(level: Level, message: &str) {
+    if level == Level::Warn {
+        eprintln!("warning: {message}");
+    }
+}
+
+/// A change of the proxy configuration, and the outcome of a lookup after the change.
+#[derive(Debug, Serialize)]
+struct WatchEvent {
+    /// The changed settings; empty for the first event.
+    changes: Vec<Change>,
+    /// The outcome of a lookup after the change.
+    outcome: resolve::Outcome,
+}
+
+impl WatchEvent {
+    /// Write every change on a line of its own, and then the proxy or "direct".
+    fn write_text<W: Write>(&self, sink: &mut W) -> std::io::Result<()> {
+        for change in &self.changes {
+            writeln!(sink, "changed {change}")?;
+        }
+        match &self.outcome.proxy {
+            Some(proxy) => writeln!(sink, "{proxy}"),
+            None => writeln!(sink, "direct"),
+        }
+    }
+
+    /// Print this event.
+    fn print(&self, json: bool) -> Result<(), Box<dyn Error>> {
+        if json {
+            print_json(self)
+        } else {
+            Ok(self.write_text(&mut std::io::stdout().lock())?)
+        }
+    }
+}
+
+/// Run `future` to completion.
+///
+/// With the tokio feature run `future` on a tokio runtime, which zbus needs to talk to the portal.
+fn block_on<F: Future>(future: F) -> Result<F::Output, Box<dyn Error>> {
+    #[cfg(feature = "tokio")]
+    return Ok(tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(future));
+    #[cfg(not(feature = "tokio"))]
+    Ok(futures_executor::block_on(future))
+}
+
+/// Print the proxy for `url`, and then every change of the proxy configuration.
+///
+/// Create a new resolver without cache for every lookup, to pick up changed settings.
+fn watch(url: &Url, interval: Duration, json: bool) -> Result<(), Box<dyn Error>> {
+    let lookup = || resolve::Outcome::lookup(&resolve::Resolver::new(false), url);
+    let mut watcher = ProxyWatcher::new(interval);
+    WatchEvent {
+        changes: Vec::new(),
+        outcome: lookup(),
+    }
+    .print(json)?;
+    block_on(async {
+        while let Some(change) = watcher.next().await {
+            WatchEvent {
+                changes: change.changes,
+                outcome: lookup(),
+            }
+            .print(json)?;
+        }
+        Ok(())
+    })?
+}
+
+/// Run `command`, and cache decisions of the system resolver on disk if `cache` is true.
+fn run(command: Command, json: bool, cache: bool) -> Result<ExitCode, Box<dyn Error>> {
+    match command {
+        Command::Lookup { url } => {
+            let resolver = resolve::Resolver::new(cache);
+            print_outcome(&resolve::Outcome::lookup(&resolver, &url), json)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Watch { url, interval } => {
+            watch(&url, Duration::from_secs(interval), json)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Doctor => {
+            let report = doctor::Report::collect();
+            if json {
+                print_json(&report)?;
+            } else {
+                report.write_text(&mut std::io::stdout().lock())?;
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Export { format } => {
+            let format = if json { export::Format::Json } else { format };
+            let proxies = resolve::Resolver::new(cache).detect();
+            export::write_proxies(&mut std::io::stdout().lock(), format, &proxies)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        #[cfg(feature = "pac")]
+        Command::PacEval { script, url } => {
+            let evaluation = pac_eval::Evaluation::evaluate(&script, url)?;
+            if json {
+                print_json(&evaluation)?;
+            } else {
+                evaluation.write_text(&mut std::io::stdout().lock())?;
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Set {
+            http,
+            https,
+            no_proxy,
+            service,
+        } => {
+            let settings = store::Settings {
+                http,
+                https,
+                no_proxy: NoProxyRules::parse_curl_env(no_proxy),
+            };
+            store::set(&settings, service.as_deref())?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Unset { service } => {
+            store::unset(service.as_deref())?;
+            Ok(ExitCode::SUCCESS)
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    system_proxy::diagnostics::set_sink(Some(print_warning));
+    match run(args.command, args.json, !args.no_cache) {
+        Ok(code) => code,
+        Err(error) => {
+            if args.json {
+                // If we can't even print the error there's nothing left to do
+                let _ = print_json(&serde_json::json!({ "error": error.to_string() }));
+            } else {
+                eprintln!("error: {error}");
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn verify_args() {
+        use clap::CommandFactory;
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn parse_set_args() {
+        let args = Args::try_parse_from([
+            "system-proxy",
+            "set",
+            "--http",
+            "socks5://proxy.example.com:1080",
+            "--no-proxy",
+            "localhost,.example.com",
+            "--json",
+            "--no-cache",
+        ])
+        .unwrap();
+        assert!(args.json);
+        assert!(args.no_cache);
+        match args.command {
+            Command::Set {
+                http,
+                https,
+                no_proxy,
+                service,
+            } => {
+                assert_eq!(
+                    http,
+                    Some(Url::parse("socks5://proxy.example.com:1080").unwrap())
+                );
+                assert_eq!(https, None);
+                assert_eq!(no_proxy, "localhost,.example.com");
+                assert_eq!(service, None);
+            }
+            command => panic!("Unexpected command {command:?}"),
+        }
+        assert!(Args::try_parse_from(["system-proxy", "lookup", "not a url"]).is_err());
+    }
+
+    #[test]
+    fn watch_event_text() {
+        let url = Url::parse("http://example.com/").unwrap();
+        let event = WatchEvent {
+            changes: vec![Change {
+                field: "environment.http_proxy".to_string(),
+                old: None,
+                new: Some("http://proxy.example.com:3128".to_string()),
+            }],
+            outcome: resolve::Outcome {
+                url: url.clone(),
+                source: Some(resolve::Source::ENVIRONMENT),
+                proxy: Some(Url::parse("http://proxy.example.com:3128").unwrap()),
+            },
+        };
+        let mut text = Vec::new();
+        event.write_text(&mut text).unwrap();
+        assert_eq!(
+            String::from_utf8(text).unwrap(),
+            "changed environment.http_proxy: <unset> -> http://proxy.example.com:3128\nhttp://proxy.example.com:3128/\n"
+        );
+
+        let event = WatchEvent {
+            changes: Vec::new(),
+            outcome: resolve::Outcome {
+                url,
+                source: None,
+                proxy: None,
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({
+                "changes": [],
+                "outcome": {"url": "http://example.com/", "source": null, "proxy": null},
+            })
+        );
+    }
+}
@@ -0,0 +1,105 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Evaluate proxy auto-config scripts.
+
+use std::io::{Result, Write};
+
+use serde::Serialize;
+use system_proxy::pac::{parse_result, PacResolver};
+use system_proxy::proxy::ProxyDecision;
+use url::Url;
+
+/// Load the script at `location`, which is either a URL or the path of a file.
+fn load(location: &str) -> Result<PacResolver> {
+    match Url::parse(location) {
+        // Single letter schemes are drive letters of Windows paths.
+        Ok(url) if 1 < url.scheme().len() => PacResolver::from_url(&url),
+        _ => PacResolver::from_file(location),
+    }
+}
+
+/// The result of a proxy auto-config script for a URL.
+#[derive(Debug, Serialize)]
+pub struct Evaluation {
+    /// The URL passed to the script.
+    url: Url,
+    /// The raw result of `FindProxyForURL`.
+    result: String,
+    /// The proxies in the result, in order of preference, or `None` for a direct connection.
+    candidates: Vec<Option<Url>>,
+}
+
+impl Evaluation {
+    /// Evaluate the script at `location` for `url`.
+    pub fn evaluate(location: &str, url: Url) -> Result<Self> {
+        let result = load(location)?.find_proxy_for_url(&url)?;
+        let candidates = parse_result(&result)
+            .into_iter()
+            .filter_map(|decision| match decision {
+                ProxyDecision::Proxy(proxy) => Some(Some(proxy)),
+                ProxyDecision::Direct => Some(None),
+                ProxyDecision::Unspecified => None,
+            })
+            .collect();
+        Ok(Self {
+            url,
+            result,
+            candidates,
+        })
+    }
+
+    /// Write the raw result, and then every candidate on a line of its own.
+    pub fn write_text<W: Write>(&self, sink: &mut W) -> Result<()> {
+        writeln!(sink, "{}", self.result)?;
+        for candidate in &self.candidates {
+            match candidate {
+                Some(proxy) => writeln!(sink, "  {proxy}")?,
+                None => writeln!(sink, "  direct")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn evaluate_script_file() {
+        let path =
+            std::env::temp_dir().join(format!("system-proxy-cli-{}.pac", std::process::id()));
+        std::fs::write(
+            &path,
+            "function FindProxyForURL(url, host) { return 'PROXY proxy:3128; FOO bar; DIRECT'; }",
+        )
+        .unwrap();
+        let url = Url::parse("http://example.com/").unwrap();
+        let evaluation = Evaluation::evaluate(path.to_str().unwrap(), url.clone());
+        std::fs::remove_file(&path).unwrap();
+        let evaluation = evaluation.unwrap();
+        assert_eq!(evaluation.url, url);
+        assert_eq!(evaluation.result, "PROXY proxy:3128; FOO bar; DIRECT");
+        assert_eq!(
+            evaluation.candidates,
+            vec![Some(Url::parse("http://proxy:3128").unwrap()), None]
+        );
+        let mut text = Vec::new();
+        evaluation.write_text(&mut text).unwrap();
+        assert_eq!(
+            String::from_utf8(text).unwrap(),
+            "PROXY proxy:3128; FOO bar; DIRECT\n  http://proxy:3128/\n  direct\n"
+        );
+    }
+
+    #[test]
+    fn evaluate_missing_script() {
+        let url = Url::parse("http://example.com/").unwrap();
+        assert!(Evaluation::evaluate("/does/not/exist.pac", url).is_err());
+    }
+}
@@ -0,0 +1,321 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Resolve proxies with the best available resolver of the system.
+
+use std::fmt::{Display, Formatter};
+
+use serde::Serialize;
+use system_proxy::env::EnvProxies;
+use system_proxy::proxy::{DiskCachingResolver, ProxyDecision, ProxyResolver};
+use system_proxy::SystemProxyResolver;
+use url::Url;
+
+/// A source of proxy information, e.g. `environment` or the name of the system resolver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct Source(&'static str);
+
+impl Source {
+    /// The curl environment variables.
+    pub const ENVIRONMENT: Source = Source("environment");
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// The proxy resolved for a URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resolution {
+    /// The source which provided the proxy.
+    ///
+    /// `None` if no source provided any information, i.e. if the environment had no proxy and
+    /// the system resolver had no proxy configuration or there was no system resolver.
+    pub source: Option<Source>,
+    /// The proxy to use, or `None` for a direct connection.
+    pub proxy: Option<Url>,
+}
+
+/// The outcome of looking up the proxy for a URL.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Outcome {
+    /// The URL to lookup a proxy for.
+    pub url: Url,
+    /// The source which provided the proxy, if any.
+    pub source: Option<Source>,
+    /// The proxy to use, or `None` for a direct connection.
+    pub proxy: Option<Url>,
+}
+
+impl Outcome {
+    /// Lookup the proxy for `url` with `resolver`.
+    pub fn lookup(resolver: &Resolver, url: &Url) -> Self {
+        let resolution = resolver.lookup(url);
+        Self {
+            url: url.clone(),
+            source: resolution.source,
+            proxy: resolution.proxy,
+        }
+    }
+}
+
+/// Resolve the proxy for `url` on the Freedesktop proxy resolver portal.
+#[cfg(feature = "portal")]
+pub fn lookup_portal(url: &Url) -> zbus::Result<Option<Url>> {
+    use system_proxy::unix::FreedesktopPortalProxyResolver;
+    zbus::block_on(async {
+        FreedesktopPortalProxyResolver::connect()
+            .await?
+            .lookup(url)
+            .await
+    })
+}
+
+/// Resolve the proxy for `url` with the default Gio proxy resolver.
+#[cfg(feature = "gio")]
+pub fn lookup_gio(url: &Url) -> Result<Option<Url>, gio::glib::Error> {
+    use system_proxy::unix::GioProxyResolver;
+    let resolver = GioProxyResolver::default();
+    if resolver.is_supported() {
+        resolver.lookup_blocking(url)
+    } else {
+        Err(gio::glib::Error::new(
+            gio::IOErrorEnum::NotSupported,
+            "Gio proxy resolver not supported, is glib-networking installed?",
+        ))
+    }
+}
+
+/// Get a key for the disk cache, which captures the settings the system resolver reads.
+///
+/// The [`Debug`](std::fmt::Debug) representation of [`SystemProxyResolver`] only covers the
+/// environment and the name of the system resolver, so add all settings which are cheap to read:
+/// The GNOME proxy settings with Gio, and the Internet Settings on Windows.  The portal and proxy
+/// auto-config scripts may still change their answers while these settings remain the same; the
+/// TTL of the cache bounds how long we use stale decisions in this case.
+fn cache_key(resolver: &SystemProxyResolver) -> String {
+    #[allow(unused_mut)]
+    let mut key = format!("{resolver:?}");
+    #[cfg(feature = "gio")]
+    key.push_str(&format!(
+        " {:?}",
+        system_proxy::unix::GnomeProxySettings::read().ok()
+    ));
+    #[cfg(windows)]
+    key.push_str(&format!(
+        " {:?}",
+        system_proxy::windows::InternetSettings::read().ok()
+    ));
+    key
+}
+
+/// The resolver of this tool.
+#[derive(Debug)]
+pub enum Resolver {
+    /// Ask the system resolver for every lookup.
+    Uncached(SystemProxyResolver),
+    /// Cache the decisions of the system resolver on disk, across invocations.
+    Cached(DiskCachingResolver<SystemProxyResolver>),
+}
+
+impl Resolver {
+    /// Get the best available resolver of the system, with a cache on disk if `cache` is true.
+    ///
+    /// Consult the curl environment first, and then the best available system resolver of the
+    /// current platform; see [`SystemProxyResolver`].
+    pub fn new(cache: bool) -> Self {
+        Self::with_system(system_proxy::default(), cache)
+    }
+
+    fn with_system(system: SystemProxyResolver, cache: bool) -> Self {
+        if cache {
+            let key = cache_key(&system);
+            Self::Cached(DiskCachingResolver::new(system).with_config_key(&key))
+        } else {
+            Self::Uncached(system)
+        }
+    }
+
+    /// Get the underlying system resolver.
+    fn system(&self) -> &SystemProxyResolver {
+        match self {
+            Self::Uncached(system) => system,
+            Self::Cached(cached) => cached.resolver(),
+        }
+    }
+
+    /// Lookup the proxy for `url`, and tell which source decided.
+    ///
+    /// If the environment decides, e.g. because a no-proxy rule matches `url`, connect
+    /// directly without asking the system resolver, or the cache.  The system resolver prints
+    /// warnings if it fails, and leaves the decision to the next source.
+    pub fn lookup(&self, url: &Url) -> Resolution {
+        let system = self.system();
+        let env = system.env().decide(url);
+        if env.is_specified() {
+            // The environment decided, either for a proxy or for a direct connection from $no_proxy
+            return Resolution {
+                source: Some(Source::ENVIRONMENT),
+                proxy: env.into_proxy(),
+            };
+        }
+        let decision = match self {
+            Self::Uncached(system) => system.decide(url),
+            Self::Cached(cached) => cached.decide(url),
+        };
+        match decision {
+            ProxyDecision::Unspecified => Resolution {
+                source: None,
+                proxy: None,
+            },
+            decision => Resolution {
+                source: system.system_name().map(Source),
+                proxy: decision.into_proxy(),
+            },
+        }
+    }
+
+    /// Detect the proxy configuration of the system.
+    ///
+    /// Return the proxies from the curl environment if any.  Otherwise lookup the proxies for
+    /// `http://example.com/` and `https://example.com/` with the system resolver.  In this case
+    /// the result has no rules for when not to use a proxy, because system resolvers don't
+    /// expose their configuration.
+    pub fn detect(&self) -> EnvProxies {
+        let env = self.system().env();
+        if !env.is_unset() {
+            return env.clone();
+        }
+        let http = self.lookup(&Url::parse("http://example.com/").unwrap());
+        let https = self.lookup(&Url::parse("https://example.com/").unwrap());
+        EnvProxies {
+            http: http.proxy,
+            https: https.proxy,
+            no_proxy_rules: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// Set the curl proxy variables to `http` and `no_proxy` while running `f`.
+    fn with_env<F: FnOnce()>(http: Option<&str>, no_proxy: Option<&str>, f: F) {
+        temp_env::with_vars(
+            [
+                ("http_proxy", http),
+                ("HTTP_PROXY", None),
+                ("https_proxy", None),
+                ("HTTPS_PROXY", None),
+                ("no_proxy", no_proxy),
+                ("NO_PROXY", None),
+            ],
+            f,
+        );
+    }
+
+    /// A system resolver with a proxy for https URLs.
+    fn system() -> SystemProxyResolver {
+        SystemProxyResolver::new(EnvProxies {
+            https: Some(Url::parse("http://system-proxy:3128").unwrap()),
+            ..EnvProxies::unset()
+        })
+    }
+
+    fn lookup(resolver: &Resolver, url: &str) -> Resolution {
+        resolver.lookup(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn lookup_sources() {
+        with_env(
+            Some("http://env-proxy:3128"),
+            Some("internal.example.com"),
+            || {
+                let resolver = Resolver::with_system(system(), false);
+                assert_eq!(
+                    lookup(&resolver, "http://example.com/"),
+                    Resolution {
+                        source: Some(Source::ENVIRONMENT),
+                        proxy: Some(Url::parse("http://env-proxy:3128").unwrap()),
+                    }
+                );
+                assert_eq!(
+                    lookup(&resolver, "http://internal.example.com/"),
+                    Resolution {
+                        source: Some(Source::ENVIRONMENT),
+                        proxy: None,
+                    }
+                );
+                assert_eq!(
+                    lookup(&resolver, "https://example.com/"),
+                    Resolution {
+                        source: Some(Source("custom")),
+                        proxy: Some(Url::parse("http://system-proxy:3128").unwrap()),
+                    }
+                );
+                assert_eq!(resolver.detect(), resolver.system().env().clone());
+            },
+        );
+        with_env(None, None, || {
+            let resolver =
+                Resolver::with_system(SystemProxyResolver::new(EnvProxies::unset()), false);
+            assert_eq!(
+                lookup(&resolver, "https://example.com/"),
+                Resolution {
+                    source: None,
+                    proxy: None,
+                }
+            );
+        });
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn cached_lookup() {
+        let cache_home = std::env::temp_dir().join(format!(
+            "system-proxy-cli-{}-cached-lookup",
+            std::process::id()
+        ));
+        temp_env::with_var("XDG_CACHE_HOME", Some(&cache_home), || {
+            with_env(None, None, || {
+                let resolver = Resolver::with_system(system(), true);
+                let path = match &resolver {
+                    Resolver::Cached(cached) => cached.path().unwrap().to_owned(),
+                    Resolver::Uncached(_) => panic!("Resolver not cached"),
+                };
+                assert!(path.starts_with(&cache_home));
+                assert_eq!(
+                    resolver.detect(),
+                    EnvProxies {
+                        https: Some(Url::parse("http://system-proxy:3128").unwrap()),
+                        ..EnvProxies::unset()
+                    }
+                );
+                let contents = std::fs::read_to_string(&path).unwrap();
+                assert!(
+                    contents
+                        .lines()
+                        .any(|line| line.contains(" https://example.com ")
+                            && line.ends_with(" http://system-proxy:3128/")),
+                    "{contents}"
+                );
+            });
+            // Another environment does not use the cached decisions.
+            with_env(None, Some("other.example.com"), || {
+                let unset = SystemProxyResolver::new(EnvProxies::unset());
+                let other = Resolver::with_system(unset, true);
+                assert_eq!(lookup(&other, "https://example.com/").proxy, None);
+            });
+        });
+        std::fs::remove_dir_all(cache_home).unwrap();
+    }
+}
@@ -0,0 +1,329 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Write proxy settings to the platform settings store.
+//!
+//! This module writes the GNOME settings with the `gio` feature on Linux and other Unix systems,
+//! the Internet Settings in the Windows registry, and the settings of network services on macOS.
+
+use std::error::Error;
+
+#[cfg(any(unix, windows))]
+use system_proxy::env::NoProxyRule;
+use system_proxy::env::NoProxyRules;
+use url::Url;
+
+/// Proxy settings to write.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settings {
+    /// The proxy for http URLs.
+    pub http: Option<Url>,
+    /// The proxy for https URLs.
+    pub https: Option<Url>,
+    /// When not to use a proxy.
+    pub no_proxy: NoProxyRules,
+}
+
+/// Convert `rules` to host patterns with `*` wildcards, as used by GNOME, Windows and macOS.
+#[cfg(any(unix, windows))]
+#[cfg_attr(
+    not(any(windows, target_os = "macos", feature = "gio")),
+    allow(dead_code)
+)]
+fn host_patterns(rules: &[NoProxyRule]) -> Vec<String> {
+    rules
+        .iter()
+        .flat_map(|rule| match rule {
+            NoProxyRule::MatchExact(host) => vec![host.to_string()],
+            NoProxyRule::MatchSubdomain(subdomain) => {
+                vec![subdomain[1..].to_string(), format!("*{subdomain}")]
+            }
+            // Only Windows has a pattern for simple host names
+            NoProxyRule::MatchSimpleHostnames if cfg!(windows) => vec!["<local>".to_string()],
+            NoProxyRule::MatchSimpleHostnames => Vec::new(),
+            // Windows has no pattern for networks
+            NoProxyRule::MatchCidr(_) if cfg!(windows) => Vec::new(),
+            NoProxyRule::MatchCidr(network) => vec![network.to_string()],
+        })
+        .collect()
+}
+
+/// Get host and port of `proxy`.
+#[cfg(any(unix, windows))]
+#[cfg_attr(
+    not(any(windows, target_os = "macos", feature = "gio")),
+    allow(dead_code)
+)]
+fn host_and_port(proxy: &Url) -> Result<(&str, u16), Box<dyn Error>> {
+    let host = proxy
+        .host_str()
+        .ok_or_else(|| format!("Proxy URL {proxy} has no host"))?;
+    let port = proxy
+        .port_or_known_default()
+        .ok_or_else(|| format!("Proxy URL {proxy} has no port"))?;
+    Ok((host, port))
+}
+
+/// A proxy in the GNOME settings.
+#[cfg(all(unix, not(target_os = "macos")))]
+#[cfg_attr(not(feature = "gio"), allow(dead_code))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GnomeProxy {
+    /// The http proxy for http URLs.
+    Http,
+    /// The http proxy for https URLs.
+    Https,
+    /// The SOCKS proxy for all URLs.
+    Socks,
+}
+
+/// Get the GNOME proxy to set to `proxy`, for URLs with the given `scheme`.
+///
+/// GNOME has http proxies for http and https URLs, and a single SOCKS proxy for all URLs, so
+/// set SOCKS proxies like `socks5://` as SOCKS proxy regardless of `scheme`.  Fail for all other
+/// kinds of proxies.
+#[cfg(all(unix, not(target_os = "macos")))]
+#[cfg_attr(not(feature = "gio"), allow(dead_code))]
+fn gnome_proxy(scheme: &str, proxy: &Url) -> Result<GnomeProxy, Box<dyn Error>> {
+    match (proxy.scheme(), scheme) {
+        ("http", "https") => Ok(GnomeProxy::Https),
+        ("http", _) => Ok(GnomeProxy::Http),
+        ("socks" | "socks4" | "socks4a" | "socks5" | "socks5h", _) => Ok(GnomeProxy::Socks),
+        (other, _) => Err(format!("GNOME does not support {other} proxies like {proxy}").into()),
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos"), feature = "gio"))]
+mod platform {
+    use std::error::Error;
+
+    use system_proxy::env::NoProxyRules;
+    use system_proxy::unix::{GnomeProxyMode, GnomeProxyServer, GnomeProxySettings};
+
+    use super::{gnome_proxy, host_and_port, host_patterns, GnomeProxy, Settings};
+
+    pub fn set(settings: &Settings, _service: Option<&str>) -> Result<(), Box<dyn Error>> {
+        let mut gnome = GnomeProxySettings::read()?;
+        gnome.http = None;
+        gnome.https = None;
+        gnome.socks = None;
+        for (scheme, proxy) in [("http", &settings.http), ("https", &settings.https)] {
+            if let Some(proxy) = proxy {
+                let (host, port) = host_and_port(proxy)?;
+                let server = Some(GnomeProxyServer {
+                    host: host.to_string(),
+                    port,
+                });
+                let current = match gnome_proxy(scheme, proxy)? {
+                    GnomeProxy::Http => &mut gnome.http,
+                    GnomeProxy::Https => &mut gnome.https,
+                    GnomeProxy::Socks => &mut gnome.socks,
+                };
+                if current.is_some() && *current != server {
+                    return Err("GNOME supports only a single SOCKS proxy".into());
+                }
+                *current = server;
+            }
+        }
+        match &settings.no_proxy {
+            // GNOME has no wildcard to bypass the proxy for all hosts, so disable the proxy instead
+            NoProxyRules::All => gnome.mode = GnomeProxyMode::None,
+            NoProxyRules::Rules(rules) => {
+                gnome.ignore_hosts = host_patterns(rules);
+                gnome.mode = GnomeProxyMode::Manual;
+            }
+        }
+        Ok(gnome.write()?)
+    }
+
+    pub fn unset(_service: Option<&str>) -> Result<(), Box<dyn Error>> {
+        Ok(GnomeProxySettings::write_mode(GnomeProxyMode::None)?)
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::error::Error;
+
+    use system_proxy::env::NoProxyRules;
+    use system_proxy::windows::InternetSettings;
+
+    use super::{host_and_port, host_patterns, Settings};
+
+    pub fn set(settings: &Settings, _service: Option<&str>) -> Result<(), Box<dyn Error>> {
+        let mut current = InternetSettings::read()?;
+        let mut servers = Vec::new();
+        for (scheme, proxy) in [("http", &settings.http), ("https", &settings.https)] {
+            if let Some(proxy) = proxy {
+                let (host, port) = host_and_port(proxy)?;
+                servers.push(format!("{scheme}={host}:{port}"));
+            }
+        }
+        current.proxy_server = servers.join(";");
+        match &settings.no_proxy {
+            NoProxyRules::All => current.proxy_enable = false,
+            NoProxyRules::Rules(rules) => {
+                current.proxy_override = host_patterns(rules).join(";");
+                current.proxy_enable = true;
+            }
+        }
+        Ok(current.write()?)
+    }
+
+    pub fn unset(_service: Option<&str>) -> Result<(), Box<dyn Error>> {
+        let mut current = InternetSettings::read()?;
+        current.proxy_enable = false;
+        Ok(current.write()?)
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::error::Error;
+
+    use system_proxy::env::NoProxyRules;
+    use system_proxy::macos::{NetworkService, ProxyKind, ProxyServer};
+
+    use super::{host_and_port, host_patterns, Settings};
+
+    fn require_service(service: Option<&str>) -> Result<NetworkService, Box<dyn Error>> {
+        service
+            .map(NetworkService::new)
+            .ok_or_else(|| "Missing network service, please use --service".into())
+    }
+
+    pub fn set(settings: &Settings, service: Option<&str>) -> Result<(), Box<dyn Error>> {
+        let service = require_service(service)?;
+        let proxies = [
+            (ProxyKind::Web, &settings.http),
+            (ProxyKind::SecureWeb, &settings.https),
+        ];
+        for (kind, proxy) in proxies {
+            let server = match proxy {
+                Some(proxy) => {
+                    let (host, port) = host_and_port(proxy)?;
+                    Some(ProxyServer {
+                        host: host.to_string(),
+                        port,
+                    })
+                }
+                None => None,
+            };
+            service.set_proxy(kind, server.as_ref())?;
+        }
+        let domains = match &settings.no_proxy {
+            NoProxyRules::All => vec!["*".to_string()],
+            NoProxyRules::Rules(rules) => host_patterns(rules),
+        };
+        Ok(service.set_bypass_domains(&domains)?)
+    }
+
+    pub fn unset(service: Option<&str>) -> Result<(), Box<dyn Error>> {
+        let service = require_service(service)?;
+        service.set_proxy(ProxyKind::Web, None)?;
+        Ok(service.set_proxy(ProxyKind::SecureWeb, None)?)
+    }
+}
+
+#[cfg(not(any(windows, target_os = "macos", all(unix, feature = "gio"))))]
+mod platform {
+    use std::error::Error;
+
+    use super::Settings;
+
+    /// Why we can't write proxy settings.
+    const UNSUPPORTED: &str = if cfg!(unix) {
+        "Writing GNOME proxy settings requires the gio feature"
+    } else {
+        "Writing proxy settings is not supported on this platform"
+    };
+
+    pub fn set(_settings: &Settings, _service: Option<&str>) -> Result<(), Box<dyn Error>> {
+        Err(UNSUPPORTED.into())
+    }
+
+    pub fn unset(_service: Option<&str>) -> Result<(), Box<dyn Error>> {
+        Err(UNSUPPORTED.into())
+    }
+}
+
+/// Write `settings` to the platform settings store, and enable the proxy.
+///
+/// On macOS write the settings of the given network `service`, which is required on macOS and
+/// ignored on other platforms.
+pub fn set(settings: &Settings, service: Option<&str>) -> Result<(), Box<dyn Error>> {
+    platform::set(settings, service)
+}
+
+/// Disable the proxy in the platform settings store.
+///
+/// On macOS disable the proxy of the given network `service`, which is required on macOS and
+/// ignored on other platforms.
+pub fn unset(service: Option<&str>) -> Result<(), Box<dyn Error>> {
+    platform::unset(service)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[cfg(any(unix, windows))]
+    #[test]
+    fn host_patterns_of_rules() {
+        let NoProxyRules::Rules(mut rules) =
+            NoProxyRules::parse_curl_env("example.com,.example.org,192.168.0.0/16")
+        else {
+            panic!("Expected rules");
+        };
+        rules.push(NoProxyRule::MatchSimpleHostnames);
+        let expected = if cfg!(windows) {
+            vec!["example.com", "example.org", "*.example.org", "<local>"]
+        } else {
+            vec![
+                "example.com",
+                "example.org",
+                "*.example.org",
+                "192.168.0.0/16",
+            ]
+        };
+        assert_eq!(host_patterns(&rules), expected);
+    }
+
+    #[cfg(any(unix, windows))]
+    #[test]
+    fn host_and_port_of_proxy() {
+        let proxy = Url::parse("http://proxy.example.com").unwrap();
+        assert_eq!(host_and_port(&proxy).unwrap(), ("proxy.example.com", 80));
+        let proxy = Url::parse("http://[2001:db8::1]:3128").unwrap();
+        assert_eq!(host_and_port(&proxy).unwrap(), ("[2001:db8::1]", 3128));
+        assert!(host_and_port(&Url::parse("unix:/run/proxy.sock").unwrap()).is_err());
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn gnome_proxy_by_scheme() {
+        let proxy = |url: &str| Url::parse(url).unwrap();
+        let http = proxy("http://proxy.example.com:3128");
+        assert_eq!(gnome_proxy("http", &http).unwrap(), GnomeProxy::Http);
+        assert_eq!(gnome_proxy("https", &http).unwrap(), GnomeProxy::Https);
+        for socks in [
+            "socks://proxy:1080",
+            "socks4://proxy:1080",
+            "socks5h://proxy:1080",
+        ] {
+            assert_eq!(
+                gnome_proxy("https", &proxy(socks)).unwrap(),
+                GnomeProxy::Socks
+            );
+        }
+        let error = gnome_proxy("https", &proxy("https://proxy.example.com:3128")).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "GNOME does not support https proxies like https://proxy.example.com:3128/"
+        );
+    }
+}
@@ -0,0 +1,164 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Receive diagnostics of this crate.
+//!
+//! This crate emits warnings, e.g. about invalid proxy settings, and debug messages.  By default
+//! it emits them as `log` records, or as `tracing` events with the `tracing` feature.
+//! Applications which use neither can install a [sink](set_sink) to receive diagnostics, and
+//! disable the default `log` feature.
+//!
+//! Warnings which repeat on every lookup, e.g. about invalid proxy environment variables, are
+//! throttled: this crate emits each distinct warning at most once per [`WARNING_INTERVAL`].
+
+use std::fmt::Arguments;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// The level of a diagnostic message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    /// A warning, e.g. about invalid proxy settings.
+    Warn,
+    /// An informational message.
+    Info,
+    /// A debug message.
+    Debug,
+}
+
+/// A function which receives diagnostics.
+pub type Sink = fn(Level, &str);
+
+static SINK: RwLock<Option<Sink>> = RwLock::new(None);
+
+/// The interval in which this crate emits each distinct throttled warning at most once.
+pub const WARNING_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Recently emitted throttled warnings, and when this crate emitted them.
+static RECENT_WARNINGS: Mutex<Vec<(String, Instant)>> = Mutex::new(Vec::new());
+
+/// Send all diagnostics of this crate to `sink`, instead of `log` or `tracing`.
+///
+/// Pass `None` to remove the sink, and emit diagnostics with `log` or `tracing` again.
+pub fn set_sink(sink: Option<Sink>) {
+    *SINK.write().unwrap_or_else(|error| error.into_inner()) = sink;
+}
+
+/// Send a diagnostic `message` at `level` to the sink.
+///
+/// Return `false` if no sink was installed.
+#[doc(hidden)]
+pub fn emit_to_sink(level: Level, message: Arguments<'_>) -> bool {
+    let sink = *SINK.read().unwrap_or_else(|error| error.into_inner());
+    match sink {
+        Some(sink) => {
+            sink(level, &message.to_string());
+            true
+        }
+        None => false,
+    }
+}
+
+/// Whether to emit the throttled warning `message` now.
+///
+/// Return `false` if this crate emitted the same `message` within the last
+/// [`WARNING_INTERVAL`].
+#[doc(hidden)]
+pub fn throttle_warning(message: &str) -> bool {
+    let mut recent = RECENT_WARNINGS
+        .lock()
+        .unwrap_or_else(|error| error.into_inner());
+    recent.retain(|(_, emitted_at)| emitted_at.elapsed() < WARNING_INTERVAL);
+    if recent.iter().any(|(recent, _)| recent == message) {
+        false
+    } else {
+        recent.push((message.to_string(), Instant::now()));
+        true
+    }
+}
+
+/// Emit a warning.
+macro_rules! warn {
+    ($($arg:tt)+) => {{
+        if !$crate::diagnostics::emit_to_sink(
+            $crate::diagnostics::Level::Warn,
+            format_args!($($arg)+),
+        ) {
+            #[cfg(feature = "tracing")]
+            tracing::warn!($($arg)+);
+            #[cfg(all(feature = "log", not(feature = "tracing")))]
+            log::warn!($($arg)+);
+        }
+    }};
+}
+
+/// Emit a warning at most once per [`WARNING_INTERVAL`](crate::diagnostics::WARNING_INTERVAL).
+///
+/// Use for warnings which would otherwise repeat on every lookup.
+macro_rules! warn_throttled {
+    ($($arg:tt)+) => {{
+        let message = format!($($arg)+);
+        if $crate::diagnostics::throttle_warning(&message) {
+            warn!("{message}");
+        }
+    }};
+}
+
+/// Emit an informational message.
+macro_rules! info {
+    ($($arg:tt)+) => {{
+        if !$crate::diagnostics::emit_to_sink(
+            $crate::diagnostics::Level::Info,
+            format_args!($($arg)+),
+        ) {
+            #[cfg(feature = "tracing")]
+            tracing::info!($($arg)+);
+            #[cfg(all(feature = "log", not(feature = "tracing")))]
+            log::info!($($arg)+);
+        }
+    }};
+}
+
+/// Emit a debug message.
+macro_rules! debug {
+    ($($arg:tt)+) => {{
+        if !$crate::diagnostics::emit_to_sink(
+            $crate::diagnostics::Level::Debug,
+            format_args!($($arg)+),
+        ) {
+            #[cfg(feature = "tracing")]
+            tracing::debug!($($arg)+);
+            #[cfg(all(feature = "log", not(feature = "tracing")))]
+            log::debug!($($arg)+);
+        }
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    static MESSAGES: Mutex<Vec<(Level, String)>> = Mutex::new(Vec::new());
+
+    #[test]
+    fn sink_receives_diagnostics() {
+        set_sink(Some(|level, message| {
+            MESSAGES.lock().unwrap().push((level, message.to_string()))
+        }));
+        warn!("Something is {}", "wrong");
+        set_sink(None);
+        let messages = MESSAGES.lock().unwrap();
+        assert!(messages.contains(&(Level::Warn, "Something is wrong".to_string())));
+    }
+
+    #[test]
+    fn throttles_repeated_warnings() {
+        assert!(throttle_warning("Invalid $throttled_proxy"));
+        assert!(!throttle_warning("Invalid $throttled_proxy"));
+        assert!(throttle_warning("Invalid $other_throttled_proxy"));
+    }
+}
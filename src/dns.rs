@@ -0,0 +1,88 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Resolve host names for WPAD discovery and proxy auto-config scripts.
+//!
+//! By default use the resolver of the system, which blocks for as long as the system likes.  With
+//! the `hickory-dns` feature query the name servers of the system with hickory-dns instead, and
+//! give up after [`TIMEOUT`].
+
+use std::io::Result;
+use std::net::IpAddr;
+
+/// How long to wait for every DNS query with hickory-dns.
+#[cfg(feature = "hickory-dns")]
+pub(crate) const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Look up all addresses of `host` with hickory-dns.
+#[cfg(feature = "hickory-dns")]
+fn lookup(host: &str) -> Result<Vec<IpAddr>> {
+    use std::sync::{Arc, Mutex};
+
+    use hickory_resolver::config::LookupIpStrategy;
+    use hickory_resolver::system_conf::read_system_conf;
+    use hickory_resolver::Resolver;
+
+    static RESOLVER: Mutex<Option<Arc<Resolver>>> = Mutex::new(None);
+
+    let resolver = {
+        let mut resolver = RESOLVER.lock().unwrap_or_else(|error| error.into_inner());
+        match resolver.as_ref() {
+            Some(resolver) => resolver.clone(),
+            None => {
+                let (config, mut options) = read_system_conf()?;
+                options.timeout = TIMEOUT;
+                options.ip_strategy = LookupIpStrategy::Ipv4AndIpv6;
+                resolver
+                    .insert(Arc::new(Resolver::new(config, options)?))
+                    .clone()
+            }
+        }
+    };
+    Ok(resolver.lookup_ip(host)?.iter().collect())
+}
+
+/// Look up all addresses of `host` with the resolver of the system.
+#[cfg(not(feature = "hickory-dns"))]
+fn lookup(host: &str) -> Result<Vec<IpAddr>> {
+    use std::net::ToSocketAddrs;
+    Ok((host, 0)
+        .to_socket_addrs()?
+        .map(|address| address.ip())
+        .collect())
+}
+
+/// Resolve `host` to all its addresses.
+///
+/// Take IP addresses literally, and return no addresses if `host` does not resolve.
+pub(crate) fn resolve(host: &str) -> Vec<IpAddr> {
+    if let Ok(address) = host.parse::<IpAddr>() {
+        return vec![address];
+    }
+    match lookup(host) {
+        Ok(addresses) => addresses,
+        Err(error) => {
+            debug!("Failed to resolve {host}: {error}");
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn resolve_addresses() {
+        assert_eq!(resolve("192.0.2.1"), vec![IpAddr::from([192, 0, 2, 1])]);
+        assert_eq!(
+            resolve("2001:db8::1"),
+            vec!["2001:db8::1".parse::<IpAddr>().unwrap()]
+        );
+        assert_eq!(resolve("does-not-exist.invalid"), Vec::<IpAddr>::new());
+    }
+}
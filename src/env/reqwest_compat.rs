@@ -0,0 +1,347 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Proxies from the environment, with the semantics of reqwest.
+
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+use url::{Host, Url};
+
+use super::{lookup, NoProxy};
+use crate::proxy::{is_non_network_url, DirectReason, ProxyDecision, ProxyResolver};
+
+/// An IP rule of a reqwest no proxy list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IpRule {
+    Address(IpAddr),
+    Network(IpNet),
+}
+
+impl Display for IpRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IpRule::Address(address) => write!(f, "{address}"),
+            IpRule::Network(network) => write!(f, "{network}"),
+        }
+    }
+}
+
+/// No proxy rules with the semantics of reqwest.
+///
+/// reqwest parses `$NO_PROXY` differently from curl, and hence from [`super::NoProxyRules`]:
+///
+/// - Entries which are IP addresses or IP networks in CIDR notation, e.g. `192.168.0.0/16`,
+///   match IP addresses only, and never host names.
+/// - Other entries match the host name and all its subdomains, regardless of whether they start
+///   with a dot.
+/// - A `*` entry matches all host names, even if other entries follow, but no IP addresses.
+///
+/// Use this type to keep the exact proxy behaviour of an application which moves from the
+/// builtin environment support of reqwest 0.11 to this crate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReqwestNoProxy {
+    ips: Vec<IpRule>,
+    domains: Vec<String>,
+}
+
+impl ReqwestNoProxy {
+    /// Parse a comma-separated no proxy `list` like reqwest.
+    ///
+    /// Return `None` if `list` is empty.
+    pub fn parse(list: &str) -> Option<Self> {
+        if list.is_empty() {
+            return None;
+        }
+        let mut rules = Self {
+            ips: Vec::new(),
+            domains: Vec::new(),
+        };
+        for entry in list.split(',').map(str::trim) {
+            if let Ok(network) = entry.parse::<IpNet>() {
+                rules.ips.push(IpRule::Network(network));
+            } else if let Ok(address) = entry.parse::<IpAddr>() {
+                rules.ips.push(IpRule::Address(address));
+            } else {
+                rules.domains.push(entry.to_string());
+            }
+        }
+        Some(rules)
+    }
+
+    /// Get no proxy rules from `$NO_PROXY` or `$no_proxy`, like reqwest.
+    ///
+    /// Unlike curl prefer the uppercase variable.
+    pub fn from_env() -> Option<Self> {
+        lookup("NO_PROXY")
+            .or_else(|| lookup("no_proxy"))
+            .and_then(|list| Self::parse(&list))
+    }
+
+    /// Format these rules as reqwest no proxy list.
+    #[cfg(feature = "reqwest")]
+    pub(crate) fn to_list(&self) -> String {
+        let ips = self.ips.iter().map(ToString::to_string);
+        ips.chain(self.domains.iter().cloned())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Get the entry which matches `url`.
+    pub(crate) fn matching_rule(&self, url: &Url) -> Option<String> {
+        match url.host() {
+            Some(Host::Domain(domain)) => self.find_domain(domain).map(ToString::to_string),
+            Some(Host::Ipv4(address)) => self.find_ip(address.into()).map(ToString::to_string),
+            Some(Host::Ipv6(address)) => self.find_ip(address.into()).map(ToString::to_string),
+            None => None,
+        }
+    }
+
+    fn find_ip(&self, address: IpAddr) -> Option<&IpRule> {
+        self.ips.iter().find(|rule| match rule {
+            IpRule::Address(rule) => *rule == address,
+            IpRule::Network(network) => network.contains(&address),
+        })
+    }
+
+    fn find_domain(&self, domain: &str) -> Option<&str> {
+        let rule = self.domains.iter().find(|rule| {
+            if *rule == domain || rule.strip_prefix('.') == Some(domain) {
+                true
+            } else if let Some(rest) = domain.strip_suffix(rule.as_str()) {
+                rule.starts_with('.') || rest.ends_with('.')
+            } else {
+                *rule == "*"
+            }
+        });
+        rule.map(String::as_str)
+    }
+}
+
+impl NoProxy for ReqwestNoProxy {
+    fn no_proxy_for(&self, url: &Url) -> bool {
+        match url.host() {
+            Some(Host::Domain(domain)) => self.find_domain(domain).is_some(),
+            Some(Host::Ipv4(address)) => self.find_ip(address.into()).is_some(),
+            Some(Host::Ipv6(address)) => self.find_ip(address.into()).is_some(),
+            None => false,
+        }
+    }
+}
+
+/// Parse a proxy URL like reqwest.
+///
+/// Assume `http://` for values without a scheme or host, and only accept the proxy schemes of
+/// reqwest.
+fn parse_proxy(value: &str) -> Option<Url> {
+    if value.trim().is_empty() {
+        return None;
+    }
+    let url = match Url::parse(value) {
+        Ok(url) if url.has_host() => url,
+        Ok(_) | Err(url::ParseError::RelativeUrlWithoutBase) => {
+            Url::parse(&format!("http://{value}")).ok()?
+        }
+        Err(_) => return None,
+    };
+    match url.scheme() {
+        "http" | "https" | "socks5" | "socks5h" => Some(url),
+        _ => None,
+    }
+}
+
+/// Lookup a proxy URL in `var` like reqwest.
+fn lookup_proxy(var: &str) -> Option<Url> {
+    let value = lookup(var)?;
+    let url = parse_proxy(&value);
+    if url.is_none() {
+        warn_throttled!("Failed to parse value of ${var} as proxy URL, skipping");
+    }
+    url
+}
+
+/// Proxies from the environment, with the semantics of reqwest.
+///
+/// Like [`super::EnvProxies`], but take proxies and no proxy rules from the environment exactly
+/// like reqwest 0.11 does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReqwestEnvProxies {
+    /// The proxy to use for `http:` URLs.
+    pub http: Option<Url>,
+    /// The proxy to use for `https:` URLs.
+    pub https: Option<Url>,
+    /// When not to use a proxy.
+    pub no_proxy: Option<ReqwestNoProxy>,
+}
+
+impl ReqwestEnvProxies {
+    /// Get proxies from the environment like reqwest.
+    ///
+    /// Prefer `$HTTP_PROXY`, `$HTTPS_PROXY` and `$NO_PROXY` over their lowercase variants, accept
+    /// proxies without a scheme as HTTP proxies, and ignore `$HTTP_PROXY` in CGI scripts, i.e. if
+    /// `$REQUEST_METHOD` is set, because it may come from the `Proxy` header of the request.
+    ///
+    /// Like reqwest let `$ALL_PROXY` or `$all_proxy` override the HTTP and HTTPS proxies.
+    pub fn from_env() -> Self {
+        let http = if std::env::var_os("REQUEST_METHOD").is_some() {
+            if std::env::var_os("HTTP_PROXY").is_some() {
+                warn_throttled!("Ignoring $HTTP_PROXY in CGI script");
+            }
+            None
+        } else {
+            lookup_proxy("HTTP_PROXY").or_else(|| lookup_proxy("http_proxy"))
+        };
+        let https = lookup_proxy("HTTPS_PROXY").or_else(|| lookup_proxy("https_proxy"));
+        let all = lookup_proxy("ALL_PROXY").or_else(|| lookup_proxy("all_proxy"));
+        Self {
+            http: all.clone().or(http),
+            https: all.or(https),
+            no_proxy: ReqwestNoProxy::from_env(),
+        }
+    }
+
+    /// Decide how to connect to `url`.
+    ///
+    /// Return [`ProxyDecision::Unspecified`] if there's no proxy for the scheme of `url`, and
+    /// [`ProxyDecision::Direct`] if the no proxy rules match `url` or `url` is a
+    /// [non-network URL](is_non_network_url).
+    pub fn decide(&self, url: &Url) -> ProxyDecision {
+        if is_non_network_url(url) {
+            return ProxyDecision::Direct;
+        }
+        let proxy = match url.scheme() {
+            "http" => self.http.as_ref(),
+            "https" => self.https.as_ref(),
+            _ => None,
+        };
+        match proxy {
+            None => ProxyDecision::Unspecified,
+            Some(_)
+                if self
+                    .no_proxy
+                    .as_ref()
+                    .map_or(false, |n| n.no_proxy_for(url)) =>
+            {
+                ProxyDecision::Direct
+            }
+            Some(proxy) => ProxyDecision::Proxy(proxy.clone()),
+        }
+    }
+}
+
+impl ProxyResolver for ReqwestEnvProxies {
+    fn for_url(&self, url: &Url) -> Option<Url> {
+        self.decide(url).into_proxy()
+    }
+
+    fn decide(&self, url: &Url) -> ProxyDecision {
+        ReqwestEnvProxies::decide(self, url)
+    }
+
+    /// Report the no proxy entry which matches `url`.
+    fn direct_reason(&self, url: &Url) -> Option<DirectReason> {
+        match self.decide(url) {
+            ProxyDecision::Direct if is_non_network_url(url) => Some(DirectReason::NonNetworkUrl),
+            ProxyDecision::Direct => self
+                .no_proxy
+                .as_ref()
+                .and_then(|rules| rules.matching_rule(url))
+                .map(DirectReason::NoProxyRule),
+            decision => DirectReason::from_decision(&decision),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn no_proxy_for(list: &str, url: &str) -> bool {
+        ReqwestNoProxy::parse(list)
+            .unwrap()
+            .no_proxy_for(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn parse_empty() {
+        assert_eq!(ReqwestNoProxy::parse(""), None);
+    }
+
+    #[test]
+    fn domains_match_subdomains() {
+        let list = "example.com, .example.org";
+        assert!(no_proxy_for(list, "http://example.com"));
+        assert!(no_proxy_for(list, "http://www.example.com"));
+        assert!(!no_proxy_for(list, "http://notexample.com"));
+        assert!(no_proxy_for(list, "http://example.org"));
+        assert!(no_proxy_for(list, "http://www.example.org"));
+        assert!(!no_proxy_for(list, "http://example.net"));
+    }
+
+    #[test]
+    fn wildcard_matches_domains_only() {
+        let list = "example.com,*";
+        assert!(no_proxy_for(list, "http://example.net"));
+        assert!(!no_proxy_for(list, "http://192.168.1.1"));
+    }
+
+    #[test]
+    fn ips_and_networks() {
+        let list = "10.0.0.0/8,192.168.1.1,fd00::/8,::1";
+        assert!(no_proxy_for(list, "http://10.1.2.3"));
+        assert!(!no_proxy_for(list, "http://11.1.2.3"));
+        assert!(no_proxy_for(list, "http://192.168.1.1:8080"));
+        assert!(!no_proxy_for(list, "http://192.168.1.2"));
+        assert!(no_proxy_for(list, "http://[fd12::1]"));
+        assert!(no_proxy_for(list, "http://[::1]"));
+        assert!(!no_proxy_for(list, "http://[fe80::1]"));
+        assert!(no_proxy_for("0.0.0.0/0", "http://8.8.8.8"));
+    }
+
+    #[test]
+    #[cfg(feature = "reqwest")]
+    fn to_list() {
+        let rules = ReqwestNoProxy::parse("example.com, 10.0.0.0/8,::1").unwrap();
+        assert_eq!(rules.to_list(), "10.0.0.0/8,::1,example.com");
+    }
+
+    #[test]
+    fn parse_proxy_urls() {
+        let parse = |value: &str| parse_proxy(value).map(|url| url.to_string());
+        assert_eq!(
+            parse("proxy.example.com:3128"),
+            Some("http://proxy.example.com:3128/".to_string())
+        );
+        assert_eq!(
+            parse("socks5h://proxy:1080"),
+            Some("socks5h://proxy:1080".to_string())
+        );
+        assert_eq!(parse("ftp://proxy:21"), None);
+        assert_eq!(parse(" "), None);
+    }
+
+    #[test]
+    fn decide() {
+        let proxies = ReqwestEnvProxies {
+            http: Some(Url::parse("http://proxy:3128").unwrap()),
+            https: None,
+            no_proxy: ReqwestNoProxy::parse("example.com"),
+        };
+        let decide = |url: &str| proxies.decide(&Url::parse(url).unwrap());
+        assert_eq!(
+            decide("http://example.org"),
+            ProxyDecision::Proxy(Url::parse("http://proxy:3128").unwrap())
+        );
+        assert_eq!(decide("http://www.example.com"), ProxyDecision::Direct);
+        assert_eq!(decide("https://example.org"), ProxyDecision::Unspecified);
+        assert_eq!(
+            proxies.direct_reason(&Url::parse("http://www.example.com").unwrap()),
+            Some(DirectReason::NoProxyRule("example.com".to_string()))
+        );
+    }
+}
@@ -0,0 +1,383 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Resolve proxies from a configuration file, and reload it when it changes.
+//!
+//! [`FileResolver`] reads proxies from a JSON or TOML file with the keys `http`, `https` and
+//! `no_proxy`, i.e. the JSON document of `system-proxy export --format json`:
+//!
+//! ```toml
+//! http = "http://proxy.example.com:3128"
+//! https = "http://proxy.example.com:3128"
+//! no_proxy = ".example.com,localhost"
+//! ```
+//!
+//! [`FileResolver::watch`] watches the file and swaps the configuration whenever the file
+//! changes, so that operators can push proxy changes to long-running services.  With the
+//! `watch` feature [`FileResolver::changes`] yields a [`crate::watch::ProxyWatcher`] event for
+//! every change.
+//!
+//! This module requires the `file` feature.
+
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use notify::Watcher;
+use url::Url;
+
+use crate::env::{EnvProxies, NoProxyRules};
+use crate::proxy::{DirectReason, ProxyDecision, ProxyResolver};
+use crate::snapshot::Change;
+
+/// The format of a proxy configuration file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    /// A JSON document.
+    Json,
+    /// A TOML document.
+    Toml,
+}
+
+impl FileFormat {
+    /// Get the format of the file at `path`, by its extension.
+    ///
+    /// Take `.toml` files as TOML, and all other files as JSON.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("toml") => FileFormat::Toml,
+            _ => FileFormat::Json,
+        }
+    }
+}
+
+/// The contents of a proxy configuration file.
+#[derive(Debug, Clone, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub struct ProxyFile {
+    /// The proxy for `http` URLs.
+    pub http: Option<Url>,
+    /// The proxy for `https` URLs.
+    pub https: Option<Url>,
+    /// Hosts to connect to directly, in the format of curl's `$no_proxy`.
+    pub no_proxy: Option<String>,
+}
+
+impl ProxyFile {
+    /// Parse the `contents` of a proxy configuration file in the given `format`.
+    pub fn parse(contents: &str, format: FileFormat) -> Result<Self> {
+        let invalid = |error: String| Error::new(ErrorKind::InvalidData, error);
+        match format {
+            FileFormat::Json => {
+                serde_json::from_str(contents).map_err(|error| invalid(error.to_string()))
+            }
+            FileFormat::Toml => {
+                toml::from_str(contents).map_err(|error| invalid(error.to_string()))
+            }
+        }
+    }
+
+    /// Read the proxy configuration file at `path`.
+    ///
+    /// Take the format from the extension of `path`; see [`FileFormat::from_path`].
+    pub fn read(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        Self::parse(&contents, FileFormat::from_path(path))
+            .map_err(|error| Error::new(error.kind(), format!("{}: {error}", path.display())))
+    }
+
+    /// Convert into proxies.
+    pub fn into_proxies(self) -> EnvProxies {
+        EnvProxies {
+            http: self.http,
+            https: self.https,
+            no_proxy_rules: self.no_proxy.map(NoProxyRules::parse_curl_env),
+        }
+    }
+
+    /// Get the changed fields from `self` to a `new` configuration.
+    fn diff(&self, new: &ProxyFile) -> Vec<Change> {
+        let fields = |file: &ProxyFile| {
+            [
+                ("file.http", file.http.as_ref().map(Url::to_string)),
+                ("file.https", file.https.as_ref().map(Url::to_string)),
+                ("file.no_proxy", file.no_proxy.clone()),
+            ]
+        };
+        fields(self)
+            .into_iter()
+            .zip(fields(new))
+            .filter(|((_, old), (_, new))| old != new)
+            .map(|((field, old), (_, new))| Change {
+                field: field.to_string(),
+                old,
+                new,
+            })
+            .collect()
+    }
+}
+
+/// The state of a file resolver, shared with the file watcher.
+#[derive(Debug)]
+struct Shared {
+    path: PathBuf,
+    config: RwLock<Arc<(ProxyFile, EnvProxies)>>,
+    #[cfg(feature = "watch")]
+    subscribers: Mutex<Vec<futures_channel::mpsc::UnboundedSender<Vec<Change>>>>,
+}
+
+impl Shared {
+    fn current(&self) -> Arc<(ProxyFile, EnvProxies)> {
+        self.config
+            .read()
+            .unwrap_or_else(|error| error.into_inner())
+            .clone()
+    }
+
+    /// Read the file again, and swap the configuration if it changed.
+    fn reload(&self) -> Result<()> {
+        let file = ProxyFile::read(&self.path)?;
+        let changes = {
+            let mut config = self
+                .config
+                .write()
+                .unwrap_or_else(|error| error.into_inner());
+            let changes = config.0.diff(&file);
+            if !changes.is_empty() {
+                let proxies = file.clone().into_proxies();
+                *config = Arc::new((file, proxies));
+            }
+            changes
+        };
+        if !changes.is_empty() {
+            info!("Reloaded proxy configuration from {}", self.path.display());
+            #[cfg(feature = "watch")]
+            self.subscribers
+                .lock()
+                .unwrap_or_else(|error| error.into_inner())
+                .retain(|subscriber| subscriber.unbounded_send(changes.clone()).is_ok());
+        }
+        Ok(())
+    }
+}
+
+/// A resolver which reads proxies from a configuration file.
+///
+/// See the [module documentation](self) for the file format.  Lookups use the configuration
+/// from the last successful read; if reading the file fails later, e.g. because the file has a
+/// syntax error, the resolver warns and keeps the previous configuration.
+pub struct FileResolver {
+    shared: Arc<Shared>,
+    watcher: Option<Mutex<notify::RecommendedWatcher>>,
+}
+
+impl FileResolver {
+    /// Read proxies from the file at `path`, once.
+    ///
+    /// Fail if the file does not exist or is invalid.
+    pub fn load<P: Into<PathBuf>>(path: P) -> Result<Self> {
+        let path = path.into();
+        let file = ProxyFile::read(&path)?;
+        let proxies = file.clone().into_proxies();
+        Ok(Self {
+            shared: Arc::new(Shared {
+                path,
+                config: RwLock::new(Arc::new((file, proxies))),
+                #[cfg(feature = "watch")]
+                subscribers: Mutex::default(),
+            }),
+            watcher: None,
+        })
+    }
+
+    /// Read proxies from the file at `path`, and reload them whenever the file changes.
+    ///
+    /// Watch the directory of the file, to also see changes if other tools atomically replace
+    /// the file.  Fail if the file does not exist or is invalid, or if watching fails.
+    pub fn watch<P: Into<PathBuf>>(path: P) -> Result<Self> {
+        let mut resolver = Self::load(path)?;
+        let shared = Arc::downgrade(&resolver.shared);
+        let file_name = resolver.shared.path.file_name().map(ToOwned::to_owned);
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let Some(shared) = shared.upgrade() else {
+                    return;
+                };
+                match event {
+                    Ok(event)
+                        if !event.kind.is_access()
+                            && event
+                                .paths
+                                .iter()
+                                .any(|path| path.file_name() == file_name.as_deref()) =>
+                    {
+                        if let Err(error) = shared.reload() {
+                            warn!("Failed to reload proxy configuration: {error}");
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(error) => warn!("Failed to watch proxy configuration: {error}"),
+                }
+            })
+            .map_err(|error| Error::new(ErrorKind::Other, error))?;
+        let directory = match resolver.shared.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        watcher
+            .watch(directory, notify::RecursiveMode::NonRecursive)
+            .map_err(|error| Error::new(ErrorKind::Other, error))?;
+        resolver.watcher = Some(Mutex::new(watcher));
+        Ok(resolver)
+    }
+
+    /// The path of the configuration file.
+    pub fn path(&self) -> &Path {
+        &self.shared.path
+    }
+
+    /// Whether this resolver watches its file for changes.
+    pub fn is_watching(&self) -> bool {
+        self.watcher.is_some()
+    }
+
+    /// Get the current proxies.
+    pub fn proxies(&self) -> EnvProxies {
+        self.shared.current().1.clone()
+    }
+
+    /// Read the file again now.
+    ///
+    /// Keep the previous configuration and fail if reading the file fails.
+    pub fn reload(&self) -> Result<()> {
+        self.shared.reload()
+    }
+
+    /// Get a stream of changes of the configuration file.
+    ///
+    /// Yield an event with the changed `file.http`, `file.https` and `file.no_proxy` fields, and
+    /// a fresh [snapshot](crate::snapshot::SystemProxySnapshot), whenever this resolver swaps
+    /// its configuration, either after [`FileResolver::reload`] or because the watched file
+    /// changed.
+    ///
+    /// Requires the `watch` feature.
+    #[cfg(feature = "watch")]
+    pub fn changes(&self) -> crate::watch::ProxyWatcher {
+        let (sender, receiver) = futures_channel::mpsc::unbounded();
+        self.shared
+            .subscribers
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .push(sender);
+        crate::watch::ProxyWatcher::from_changes(receiver)
+    }
+}
+
+impl std::fmt::Debug for FileResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FileResolver")
+            .field("path", &self.shared.path)
+            .field("watching", &self.is_watching())
+            .finish()
+    }
+}
+
+impl ProxyResolver for FileResolver {
+    fn for_url(&self, url: &Url) -> Option<Url> {
+        self.decide(url).into_proxy()
+    }
+
+    fn decide(&self, url: &Url) -> ProxyDecision {
+        self.shared.current().1.decide(url)
+    }
+
+    fn direct_reason(&self, url: &Url) -> Option<DirectReason> {
+        self.shared.current().1.direct_reason(url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn parse_json_and_toml() {
+        let expected = ProxyFile {
+            http: Some(Url::parse("http://proxy:3128").unwrap()),
+            https: None,
+            no_proxy: Some(".example.com".to_string()),
+        };
+        let json = r#"{"http": "http://proxy:3128", "https": null, "no_proxy": ".example.com"}"#;
+        assert_eq!(ProxyFile::parse(json, FileFormat::Json).unwrap(), expected);
+        let toml = "http = \"http://proxy:3128\"\nno_proxy = \".example.com\"\n";
+        assert_eq!(ProxyFile::parse(toml, FileFormat::Toml).unwrap(), expected);
+        let error = ProxyFile::parse("http = 42", FileFormat::Toml).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn format_from_path() {
+        assert_eq!(
+            FileFormat::from_path(Path::new("/etc/proxy.TOML")),
+            FileFormat::Toml
+        );
+        assert_eq!(
+            FileFormat::from_path(Path::new("/etc/proxy.json")),
+            FileFormat::Json
+        );
+    }
+
+    #[test]
+    fn diff() {
+        let old = ProxyFile {
+            http: Some(Url::parse("http://proxy:3128").unwrap()),
+            ..ProxyFile::default()
+        };
+        let new = ProxyFile {
+            no_proxy: Some("localhost".to_string()),
+            ..old.clone()
+        };
+        assert_eq!(
+            old.diff(&new),
+            vec![Change {
+                field: "file.no_proxy".to_string(),
+                old: None,
+                new: Some("localhost".to_string()),
+            }]
+        );
+        assert!(new.diff(&new).is_empty());
+    }
+
+    #[test]
+    fn reload_keeps_previous_configuration_on_error() {
+        let directory =
+            std::env::temp_dir().join(format!("system-proxy-file-resolver-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("proxy.json");
+        std::fs::write(&path, r#"{"http": "http://proxy:3128"}"#).unwrap();
+        let resolver = FileResolver::load(&path).unwrap();
+        let url = Url::parse("http://example.com").unwrap();
+        assert_eq!(
+            resolver.for_url(&url),
+            Some(Url::parse("http://proxy:3128").unwrap())
+        );
+
+        std::fs::write(&path, r#"{"http": "http://other-proxy:3128"}"#).unwrap();
+        resolver.reload().unwrap();
+        assert_eq!(
+            resolver.for_url(&url),
+            Some(Url::parse("http://other-proxy:3128").unwrap())
+        );
+
+        std::fs::write(&path, "{").unwrap();
+        assert!(resolver.reload().is_err());
+        assert_eq!(
+            resolver.for_url(&url),
+            Some(Url::parse("http://other-proxy:3128").unwrap())
+        );
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,449 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A process-wide proxy resolver.
+
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use url::Url;
+
+use crate::env::EnvProxies;
+use crate::plugin::{PluginResolver, Plugins};
+use crate::proxy::{
+    is_non_network_url, CacheStats, CachingResolver, DirectReason, LatencyRecorder, LatencyStats,
+    ProxyDecision, ProxyResolver,
+};
+use crate::system::{Source, SystemProxyResolver};
+
+static GLOBAL: RwLock<Option<Arc<GlobalResolver>>> = RwLock::new(None);
+
+/// Which of the environment and the system resolver takes precedence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Precedence {
+    /// Consult the curl environment variables first, then the system resolver.
+    #[default]
+    EnvFirst,
+    /// Consult the system resolver first, then the curl environment variables.
+    ///
+    /// Use this if the system settings should override stale environment variables.
+    SystemFirst,
+    /// Only consult the curl environment variables.
+    EnvOnly,
+    /// Only consult the system resolver.
+    SystemOnly,
+}
+
+/// The configuration of the [global resolver](resolver).
+///
+/// By default the global resolver consults [registered plugins](crate::plugin), then the curl
+/// environment variables and then the system resolver, and caches decisions for
+/// [`CachingResolver::DEFAULT_TTL`](crate::proxy::CachingResolver).  The global resolver builds
+/// on [`SystemProxyResolver`]; unless configured [otherwise](GlobalConfig::with_resolver) the
+/// system resolver is the best available resolver of the current platform.  If there is none,
+/// e.g. on targets which have no system backend, the global resolver only consults plugins and
+/// the environment, and leaves all other decisions [unspecified](ProxyDecision::Unspecified).
+#[derive(Clone)]
+pub struct GlobalConfig {
+    resolver: Option<PluginResolver>,
+    plugins: bool,
+    precedence: Precedence,
+    cache_ttl: Option<Duration>,
+}
+
+impl Default for GlobalConfig {
+    fn default() -> Self {
+        Self {
+            resolver: None,
+            plugins: true,
+            precedence: Precedence::default(),
+            cache_ttl: Some(CachingResolver::<()>::DEFAULT_TTL),
+        }
+    }
+}
+
+impl Debug for GlobalConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GlobalConfig")
+            .field("resolver", &self.resolver.is_some())
+            .field("plugins", &self.plugins)
+            .field("precedence", &self.precedence)
+            .field("cache_ttl", &self.cache_ttl)
+            .finish()
+    }
+}
+
+impl GlobalConfig {
+    /// Use `resolver` as system resolver, e.g. [`crate::unix::GioProxyResolver`], instead of the
+    /// best available resolver of the current platform.
+    ///
+    /// See [`GlobalConfig::with_precedence`] for the order of the system resolver and the
+    /// environment.
+    pub fn with_resolver<R>(mut self, resolver: R) -> Self
+    where
+        R: ProxyResolver + Send + Sync + 'static,
+    {
+        self.resolver = Some(Arc::new(resolver));
+        self
+    }
+
+    /// Whether to consult [registered plugins](crate::plugin).
+    pub fn with_plugins(mut self, plugins: bool) -> Self {
+        self.plugins = plugins;
+        self
+    }
+
+    /// Set which of the curl environment variables and the system resolver takes precedence.
+    ///
+    /// The global resolver reads the environment once, when it's created.
+    pub fn with_precedence(mut self, precedence: Precedence) -> Self {
+        self.precedence = precedence;
+        self
+    }
+
+    /// Cache decisions for `ttl`, or do not cache at all if `None`.
+    pub fn with_cache_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+}
+
+/// Statistics about the global resolver; see [`GlobalResolver::stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GlobalStats {
+    /// The latency of lookups in [registered plugins](crate::plugin).
+    pub plugins: LatencyStats,
+    /// The latency of lookups in the curl environment variables.
+    pub env: LatencyStats,
+    /// The latency of lookups in the system resolver.
+    pub system: LatencyStats,
+    /// Hits and misses of the decision cache.
+    pub cache: CacheStats,
+}
+
+/// The latency of the sources of the global resolver.
+#[derive(Debug, Default)]
+struct SourceLatency {
+    plugins: LatencyRecorder,
+    env: LatencyRecorder,
+    system: LatencyRecorder,
+}
+
+/// The sources of the global resolver.
+///
+/// Consult registered plugins, and then the environment and the system resolver of a
+/// [`SystemProxyResolver`].
+struct Sources {
+    plugins: bool,
+    system: SystemProxyResolver,
+    latency: SourceLatency,
+}
+
+/// Decide about `url` with `resolver`, and record the latency in `latency`.
+fn timed_decide(
+    latency: &LatencyRecorder,
+    resolver: &dyn ProxyResolver,
+    url: &Url,
+) -> ProxyDecision {
+    let start = Instant::now();
+    let decision = resolver.decide(url);
+    latency.record(start.elapsed());
+    decision
+}
+
+impl Sources {
+    /// Get the first source which decides about `url`, and its decision.
+    fn source(&self, url: &Url) -> Option<(&dyn ProxyResolver, ProxyDecision)> {
+        if self.plugins {
+            let decision = timed_decide(&self.latency.plugins, &Plugins, url);
+            if decision.is_specified() {
+                return Some((&Plugins, decision));
+            }
+        }
+        self.system.source_with(|source, resolver| {
+            let latency = match source {
+                Source::Env => &self.latency.env,
+                Source::System => &self.latency.system,
+            };
+            timed_decide(latency, resolver, url)
+        })
+    }
+}
+
+impl ProxyResolver for Sources {
+    fn for_url(&self, url: &Url) -> Option<Url> {
+        self.decide(url).into_proxy()
+    }
+
+    /// Connect directly to [non-network URLs](is_non_network_url), without asking any source.
+    fn decide(&self, url: &Url) -> ProxyDecision {
+        if is_non_network_url(url) {
+            ProxyDecision::Direct
+        } else {
+            self.source(url)
+                .map_or(ProxyDecision::Unspecified, |(_, decision)| decision)
+        }
+    }
+
+    /// Ask the source which decided about `url` for the reason.
+    fn direct_reason(&self, url: &Url) -> Option<DirectReason> {
+        if is_non_network_url(url) {
+            return Some(DirectReason::NonNetworkUrl);
+        }
+        match self.source(url) {
+            None => Some(DirectReason::NoProxyConfigured),
+            Some((_, ProxyDecision::Proxy(_))) => None,
+            Some((source, _)) => source.direct_reason(url),
+        }
+    }
+}
+
+/// The process-wide proxy resolver.
+///
+/// See [`init`] and [`resolver`].
+pub struct GlobalResolver {
+    resolver: CachingResolver<Sources>,
+}
+
+impl GlobalResolver {
+    fn new(config: GlobalConfig) -> Self {
+        let system = match config.resolver {
+            Some(resolver) => SystemProxyResolver::new(resolver),
+            // Do not probe the platform if we never consult it
+            None if config.precedence == Precedence::EnvOnly => {
+                SystemProxyResolver::from_parts(EnvProxies::from_curl_env(), None)
+            }
+            None => SystemProxyResolver::default(),
+        };
+        let sources = Sources {
+            plugins: config.plugins,
+            // The global resolver caches all decisions itself
+            system: system
+                .with_precedence(config.precedence)
+                .with_cache_ttl(None),
+            latency: SourceLatency::default(),
+        };
+        let resolver = match config.cache_ttl {
+            Some(ttl) => CachingResolver::new(sources).with_ttl(ttl),
+            None => CachingResolver::new(sources).with_capacity(0),
+        };
+        Self { resolver }
+    }
+
+    /// Drop all cached decisions, e.g. after the proxy configuration changed.
+    pub fn clear_cache(&self) {
+        self.resolver.clear();
+    }
+
+    /// Get statistics about lookups so far.
+    ///
+    /// Report the latency of each source, and the hit rate of the cache, to tell whether e.g. a
+    /// slow proxy auto-config script in the system resolver slows down lookups.  Lookups answered
+    /// from the cache do not count towards the latency of sources.
+    pub fn stats(&self) -> GlobalStats {
+        let latency = &self.resolver.resolver().latency;
+        GlobalStats {
+            plugins: latency.plugins.stats(),
+            env: latency.env.stats(),
+            system: latency.system.stats(),
+            cache: self.resolver.stats(),
+        }
+    }
+}
+
+impl Debug for GlobalResolver {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let sources = self.resolver.resolver();
+        f.debug_struct("GlobalResolver")
+            .field("plugins", &sources.plugins)
+            .field("system", &sources.system)
+            .finish()
+    }
+}
+
+impl ProxyResolver for GlobalResolver {
+    fn for_url(&self, url: &Url) -> Option<Url> {
+        self.resolver.for_url(url)
+    }
+
+    fn decide(&self, url: &Url) -> ProxyDecision {
+        self.resolver.decide(url)
+    }
+
+    fn direct_reason(&self, url: &Url) -> Option<DirectReason> {
+        self.resolver.direct_reason(url)
+    }
+}
+
+/// The global resolver was already initialized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlreadyInitializedError;
+
+impl Display for AlreadyInitializedError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The global proxy resolver is already initialized")
+    }
+}
+
+impl std::error::Error for AlreadyInitializedError {}
+
+/// Initialize the [global resolver](resolver) with `config`.
+///
+/// Call this early in `main`, before any library uses the global resolver.  Fail if the global
+/// resolver already exists, either from an earlier call, or because [`resolver`] already created
+/// it with the default configuration.
+pub fn init(config: GlobalConfig) -> Result<(), AlreadyInitializedError> {
+    if GLOBAL
+        .read()
+        .unwrap_or_else(|error| error.into_inner())
+        .is_some()
+    {
+        return Err(AlreadyInitializedError);
+    }
+    // Build the resolver without holding the lock, because probing the system may take a while.
+    let resolver = Arc::new(GlobalResolver::new(config));
+    let mut global = GLOBAL.write().unwrap_or_else(|error| error.into_inner());
+    if global.is_some() {
+        Err(AlreadyInitializedError)
+    } else {
+        *global = Some(resolver);
+        Ok(())
+    }
+}
+
+/// Get the process-wide proxy resolver.
+///
+/// Lets libraries resolve proxies consistently with the application, without passing a
+/// resolver through their APIs.  If the application did not [`init`] the global resolver, create
+/// it with the default [`GlobalConfig`].
+///
+/// Creating the resolver probes the system, which may block for a while; other threads keep
+/// resolving with an existing global resolver meanwhile.  If multiple threads create the
+/// resolver at the same time, the first one wins.
+pub fn resolver() -> Arc<GlobalResolver> {
+    if let Some(global) = GLOBAL
+        .read()
+        .unwrap_or_else(|error| error.into_inner())
+        .as_ref()
+    {
+        return global.clone();
+    }
+    // Build the resolver without holding the lock, because probing the system may take a while.
+    let resolver = Arc::new(GlobalResolver::new(GlobalConfig::default()));
+    let mut global = GLOBAL.write().unwrap_or_else(|error| error.into_inner());
+    global.get_or_insert(resolver).clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::NoProxyResolver;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn init_once() {
+        let proxy = Url::parse("http://global-proxy:3128").unwrap();
+        let env = EnvProxies {
+            http: Some(proxy.clone()),
+            ..EnvProxies::unset()
+        };
+        let config = GlobalConfig::default()
+            .with_resolver(env)
+            .with_plugins(false)
+            .with_precedence(Precedence::SystemOnly);
+        init(config).unwrap();
+        assert_eq!(init(GlobalConfig::default()), Err(AlreadyInitializedError));
+
+        let decide = |url: &str| resolver().decide(&Url::parse(url).unwrap());
+        assert_eq!(decide("http://example.com"), ProxyDecision::Proxy(proxy));
+        assert_eq!(decide("https://example.com"), ProxyDecision::Unspecified);
+    }
+
+    #[test]
+    fn precedence() {
+        let proxies = |proxy: &str| EnvProxies {
+            http: Some(Url::parse(proxy).unwrap()),
+            ..EnvProxies::unset()
+        };
+        let sources = |precedence| Sources {
+            plugins: false,
+            system: SystemProxyResolver::from_parts(
+                proxies("http://env-proxy:3128"),
+                Some(("test", Box::new(proxies("http://system-proxy:3128")))),
+            )
+            .with_precedence(precedence),
+            latency: SourceLatency::default(),
+        };
+        let url = Url::parse("http://example.com").unwrap();
+        assert_eq!(
+            sources(Precedence::EnvFirst).for_url(&url),
+            Some(Url::parse("http://env-proxy:3128").unwrap())
+        );
+        assert_eq!(
+            sources(Precedence::SystemFirst).for_url(&url),
+            Some(Url::parse("http://system-proxy:3128").unwrap())
+        );
+        let https = Url::parse("https://example.com").unwrap();
+        assert_eq!(
+            sources(Precedence::EnvFirst).decide(&https),
+            ProxyDecision::Unspecified
+        );
+        assert_eq!(
+            sources(Precedence::EnvFirst).direct_reason(&https),
+            Some(DirectReason::NoProxyConfigured)
+        );
+    }
+
+    #[test]
+    fn non_network_urls() {
+        let sources = Sources {
+            plugins: true,
+            system: SystemProxyResolver::from_parts(
+                EnvProxies::unset(),
+                Some((
+                    "test",
+                    Box::new(EnvProxies {
+                        http: Some(Url::parse("http://system-proxy:3128").unwrap()),
+                        ..EnvProxies::unset()
+                    }),
+                )),
+            ),
+            latency: SourceLatency::default(),
+        };
+        for url in [
+            "mailto:john@example.com",
+            "data:text/plain,foo",
+            "about:blank",
+        ] {
+            let url = Url::parse(url).unwrap();
+            assert_eq!(sources.decide(&url), ProxyDecision::Direct, "{url}");
+            assert_eq!(
+                sources.direct_reason(&url),
+                Some(DirectReason::NonNetworkUrl),
+                "{url}"
+            );
+        }
+    }
+
+    #[test]
+    fn stats() {
+        let resolver = GlobalResolver::new(
+            GlobalConfig::default()
+                .with_resolver(NoProxyResolver)
+                .with_plugins(false)
+                .with_precedence(Precedence::SystemOnly),
+        );
+        for url in ["http://example.com/foo", "http://example.com/bar"] {
+            resolver.decide(&Url::parse(url).unwrap());
+        }
+        let stats = resolver.stats();
+        assert_eq!(stats.cache, CacheStats { hits: 1, misses: 1 });
+        assert_eq!(stats.system.count, 1);
+        assert_eq!(stats.env.count, 0);
+        assert_eq!(stats.plugins.count, 0);
+    }
+}
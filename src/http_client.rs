@@ -0,0 +1,166 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Use proxies with surf and other clients of the `http-client` ecosystem.
+//!
+//! [`ProxyHttpClient`] implements [`http_client::HttpClient`] on top of isahc, and sends every
+//! request through the proxy a [`ProxyResolver`] returns for the request URL.  Pass it to
+//! `surf::Client::with_http_client` to let async-std based applications use proxies:
+//!
+//! ```ignore
+//! let client = surf::Client::with_http_client(ProxyHttpClient::new(system_proxy::resolver()));
+//! ```
+//!
+//! Requires the `http-client` feature.
+
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+use http_client::http_types::StatusCode;
+use http_client::isahc::IsahcClient;
+use http_client::{Config, Error, HttpClient, Request, Response};
+use isahc::config::Configurable;
+use url::Url;
+
+use crate::proxy::ProxyResolver;
+use crate::redact::redact;
+
+/// Create an isahc client which connects through `proxy`, or directly if `None`.
+fn isahc_client(config: &Config, proxy: Option<&Url>) -> Result<IsahcClient, Error> {
+    let proxy = proxy
+        .map(|proxy| proxy.as_str().parse::<isahc::http::Uri>())
+        .transpose()
+        .map_err(|error| Error::new(StatusCode::InternalServerError, error))?;
+    // An explicit `None` disables the proxy environment variables of curl.
+    let mut builder = isahc::HttpClient::builder()
+        .proxy(proxy)
+        .max_connections_per_host(config.max_connections_per_host);
+    if !config.http_keep_alive {
+        builder = builder.connection_cache_size(0);
+    }
+    if config.tcp_no_delay {
+        builder = builder.tcp_nodelay();
+    }
+    if let Some(timeout) = config.timeout {
+        builder = builder.timeout(timeout);
+    }
+    let client = builder.build()?;
+    let mut client = IsahcClient::from_client(client);
+    // Remember the config; the client is already configured accordingly.
+    client.set_config(config.clone())?;
+    Ok(client)
+}
+
+/// An HTTP client which sends requests through the proxies of a resolver.
+///
+/// The client keeps a separate isahc client for every proxy, and for direct connections, so
+/// that connections to a proxy are reused across requests.
+pub struct ProxyHttpClient<R> {
+    resolver: R,
+    config: Config,
+    clients: Mutex<HashMap<Option<Url>, Arc<IsahcClient>>>,
+}
+
+impl<R> ProxyHttpClient<R> {
+    /// Send requests through the proxies of `resolver`.
+    pub fn new(resolver: R) -> Self {
+        Self {
+            resolver,
+            config: Config::default(),
+            clients: Mutex::default(),
+        }
+    }
+
+    /// Get the resolver.
+    pub fn resolver(&self) -> &R {
+        &self.resolver
+    }
+}
+
+impl<R: ProxyResolver> ProxyHttpClient<R> {
+    /// Get the client for `url`.
+    fn client(&self, url: &Url) -> Result<Arc<IsahcClient>, Error> {
+        let proxy = self.resolver.for_url(url);
+        let mut clients = self
+            .clients
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        if let Some(client) = clients.get(&proxy) {
+            return Ok(client.clone());
+        }
+        match &proxy {
+            Some(proxy) => debug!("Creating client for proxy {}", redact(proxy)),
+            None => debug!("Creating client for direct connections"),
+        }
+        let client = Arc::new(isahc_client(&self.config, proxy.as_ref())?);
+        clients.insert(proxy, client.clone());
+        Ok(client)
+    }
+}
+
+impl<R> Debug for ProxyHttpClient<R> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProxyHttpClient")
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+#[http_client::async_trait]
+impl<R> HttpClient for ProxyHttpClient<R>
+where
+    R: ProxyResolver + Send + Sync + Unpin + 'static,
+{
+    async fn send(&self, request: Request) -> Result<Response, Error> {
+        let client = self.client(request.url())?;
+        client.send(request).await
+    }
+
+    /// Set the `config` for all future requests.
+    ///
+    /// Drop all clients created so far, and with them their connections.
+    fn set_config(&mut self, config: Config) -> http_client::http_types::Result<()> {
+        self.config = config;
+        self.clients
+            .get_mut()
+            .unwrap_or_else(|error| error.into_inner())
+            .clear();
+        Ok(())
+    }
+
+    fn config(&self) -> &Config {
+        &self.config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::EnvProxies;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn client_per_proxy() {
+        let resolver = EnvProxies {
+            http: Some(Url::parse("http://proxy:3128").unwrap()),
+            ..EnvProxies::unset()
+        };
+        let client = ProxyHttpClient::new(resolver);
+        let first = client
+            .client(&Url::parse("http://example.com").unwrap())
+            .unwrap();
+        let second = client
+            .client(&Url::parse("http://example.org").unwrap())
+            .unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        let direct = client
+            .client(&Url::parse("https://example.com").unwrap())
+            .unwrap();
+        assert!(!Arc::ptr_eq(&first, &direct));
+        assert_eq!(client.clients.lock().unwrap().len(), 2);
+    }
+}
@@ -24,6 +24,32 @@
 //!   libraries, but in turn requires the [`zbus`] crate for DBus support, and a running portal
 //!   implementation at runtime.
 //!
+//! [`default`] returns a [`SystemProxyResolver`] which combines the environment with the best
+//! available system resolver, as a one-call entry point.
+//!
+//! [`proxy::ProxyResolver`] is a common interface for synchronous proxy lookups, and [`plugin`]
+//! registers additional resolvers at runtime.  [`resolver`] returns a process-wide resolver which
+//! applications configure with [`init`].  [`redact`] masks credentials in URLs in log
+//! output.  With the `reqwest` feature [`reqwest`](crate::reqwest) configures reqwest clients
+//! with plain proxies for static proxy configurations, and with the `http-client` feature
+//! [`http_client::ProxyHttpClient`] sends requests of surf and other `http-client` users through
+//! proxies.  With the `file` feature
+//! [`file::FileResolver`] reads proxies from a configuration file and reloads it on changes.
+//! With the `pac` feature [`pac::PacResolver`] evaluates proxy auto-config scripts, and
+//! [`wpad::WpadResolver`] discovers them with WPAD.
+//!
+//! [`snapshot::SystemProxySnapshot`] captures the proxy configuration from all of these, for bug
+//! reports, and with the `watch` feature [`watch::ProxyWatcher`] yields a stream of changes of
+//! the proxy configuration.  [`profile::ProxyProfiles`] selects between named proxy
+//! configurations depending on the current network.
+//!
+//! # Async runtimes
+//!
+//! The async resolvers do not depend on a specific async runtime.  The Gio resolver runs on the
+//! Glib main loop, and the portal resolver needs the `tokio` feature for tokio applications, or
+//! the `async-io` feature for applications on async-std, smol, or other runtimes built on
+//! `async-io`.
+//!
 //! # Operating system support
 //!
 //! ## Linux
@@ -31,13 +57,64 @@
 //! Use either [`unix::GioProxyResolver`] or [`unix::FreedesktopPortalProxyResolver`] to access
 //! system proxy settings.
 //!
+//! ## FreeBSD, NetBSD, OpenBSD
+//!
+//! Like on Linux, use [`unix::FreedesktopPortalProxyResolver`] where DBus and a portal
+//! implementation are available, or [`unix::GioProxyResolver`] if Glib is installed.  The
+//! `netlink` feature has no effect on these systems.
+//!
 //! ## Windows
 //!
-//! Windows support is planned, see <https://github.com/swsnr/system_proxy.rs/issues/5>.
+//! Use `windows::WinHttpProxyResolver` to resolve proxies like Windows applications do,
+//! including proxy auto-config, and `windows::InternetSettings` to read and write the proxy
+//! settings of the current user.
 //!
 //! ## macOS
 //!
-//! MacOS support may come at some point, see <https://github.com/swsnr/system_proxy.rs/issues/2>.
+//! Use `macos::NetworkService` to write the proxy settings of a network service, and
+//! `macos::ProxySettings` to read and resolve the current proxy settings without proxy
+//! auto-config.  Full proxy lookup may come at some point, see
+//! <https://github.com/swsnr/system_proxy.rs/issues/2>.
+//!
+//! ## Other systems
+//!
+//! On systems without any system backend, e.g. illumos, Haiku or Fuchsia, the crate still
+//! builds, and offers the environment resolvers.  Without a system resolver the
+//! [global resolver](resolver) only consults plugins and the environment, so dependent crates
+//! need no target-specific `cfg`.
+
+#[macro_use]
+pub mod diagnostics;
+#[cfg(feature = "pac")]
+mod dns;
 
 pub mod env;
+#[cfg(feature = "file")]
+pub mod file;
+mod global;
+#[cfg(feature = "http-client")]
+pub mod http_client;
+#[cfg(target_os = "macos")]
+pub mod macos;
+#[cfg(feature = "pac")]
+pub mod pac;
+pub mod plugin;
+pub mod profile;
+pub mod proxy;
+pub mod redact;
+#[cfg(feature = "reqwest")]
+pub mod reqwest;
+pub mod snapshot;
+mod system;
 pub mod unix;
+#[cfg(feature = "watch")]
+pub mod watch;
+#[cfg(windows)]
+pub mod windows;
+#[cfg(feature = "pac")]
+pub mod wpad;
+
+pub use self::global::{
+    init, resolver, AlreadyInitializedError, GlobalConfig, GlobalResolver, GlobalStats, Precedence,
+};
+pub use self::system::{default, SystemProxyResolver};
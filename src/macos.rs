@@ -0,0 +1,140 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Provide proxy settings for macOS.
+//!
+//! This module writes the proxy settings of network services through the `networksetup` tool.
+//! Changing these settings usually requires administrator privileges.
+//!
+//! [`ProxySettings`] reads the current proxy settings through the `scutil` tool.
+
+use std::ffi::OsStr;
+use std::io::{Error, ErrorKind, Result};
+use std::process::Command;
+
+mod scutil;
+
+pub use self::scutil::ProxySettings;
+
+/// Run `networksetup` with `args`, and return its standard output.
+fn networksetup<I, S>(args: I) -> Result<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let output = Command::new("networksetup").args(args).output()?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(Error::new(
+            ErrorKind::Other,
+            format!(
+                "networksetup failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ))
+    }
+}
+
+/// A kind of proxy of a network service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyKind {
+    /// The proxy for HTTP.
+    Web,
+    /// The proxy for HTTPS.
+    SecureWeb,
+    /// The SOCKS proxy.
+    Socks,
+}
+
+impl ProxyKind {
+    /// The `networksetup` flags to set the proxy, and to set its state.
+    fn flags(self) -> (&'static str, &'static str) {
+        match self {
+            ProxyKind::Web => ("-setwebproxy", "-setwebproxystate"),
+            ProxyKind::SecureWeb => ("-setsecurewebproxy", "-setsecurewebproxystate"),
+            ProxyKind::Socks => ("-setsocksfirewallproxy", "-setsocksfirewallproxystate"),
+        }
+    }
+}
+
+/// A proxy server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyServer {
+    /// The host name of the proxy server.
+    pub host: String,
+    /// The port of the proxy server.
+    pub port: u16,
+}
+
+/// A network service, e.g. `Wi-Fi`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkService {
+    name: String,
+}
+
+impl NetworkService {
+    /// The network service with the given `name`.
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        Self { name: name.into() }
+    }
+
+    /// List all network services of this system, including disabled services.
+    pub fn list() -> Result<Vec<Self>> {
+        let output = networksetup(["-listallnetworkservices"])?;
+        // The first line tells that an asterisk denotes a disabled service.
+        Ok(output
+            .lines()
+            .skip(1)
+            .map(|line| line.trim_start_matches('*').trim())
+            .filter(|name| !name.is_empty())
+            .map(Self::new)
+            .collect())
+    }
+
+    /// The name of this network service.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Set the proxy of the given `kind` to `server`, or disable it if `server` is `None`.
+    pub fn set_proxy(&self, kind: ProxyKind, server: Option<&ProxyServer>) -> Result<()> {
+        let (set_proxy, set_state) = kind.flags();
+        match server {
+            Some(server) => networksetup([
+                set_proxy,
+                &self.name,
+                &server.host,
+                &server.port.to_string(),
+            ])?,
+            None => networksetup([set_state, &self.name, "off"])?,
+        };
+        Ok(())
+    }
+
+    /// Set the URL of a proxy auto-config script, or disable proxy auto-config if `url` is `None`.
+    pub fn set_auto_proxy_url(&self, url: Option<&str>) -> Result<()> {
+        match url {
+            Some(url) => networksetup(["-setautoproxyurl", &self.name, url])?,
+            None => networksetup(["-setautoproxystate", &self.name, "off"])?,
+        };
+        Ok(())
+    }
+
+    /// Set the hosts and domains to connect to directly.
+    ///
+    /// `domains` are host names with `*` wildcards.  An empty list clears the bypass domains.
+    pub fn set_bypass_domains<S: AsRef<str>>(&self, domains: &[S]) -> Result<()> {
+        let mut args = vec!["-setproxybypassdomains", &self.name];
+        if domains.is_empty() {
+            args.push("Empty");
+        } else {
+            args.extend(domains.iter().map(AsRef::as_ref));
+        }
+        networksetup(args)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,301 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Read the system proxy settings from the output of `scutil --proxy`.
+
+use std::io::{Error, ErrorKind, Result};
+use std::process::Command;
+
+use url::Url;
+
+use super::ProxyServer;
+use crate::env::{HostInterner, NoProxy, NoProxyRule, NoProxyRules};
+use crate::proxy::{is_non_network_url, DirectReason, ProxyDecision, ProxyResolver};
+
+/// The proxy settings of macOS.
+///
+/// These are the proxy settings of the primary network service, as `scutil --proxy` prints them.
+/// Reading these settings through `scutil` needs no FFI, and in particular does not link against
+/// the SystemConfiguration framework.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ProxySettings {
+    /// The proxy for HTTP, or `None` if disabled.
+    pub http: Option<ProxyServer>,
+    /// The proxy for HTTPS, or `None` if disabled.
+    pub https: Option<ProxyServer>,
+    /// The SOCKS proxy, or `None` if disabled.
+    pub socks: Option<ProxyServer>,
+    /// Hosts and domains to connect to directly, with `*` wildcards.
+    pub exceptions: Vec<String>,
+    /// Whether to connect directly to simple host names, i.e. host names without a dot.
+    pub exclude_simple_hostnames: bool,
+    /// The URL of a proxy auto-config script, or `None` if disabled.
+    pub auto_config_url: Option<String>,
+    /// Whether to discover proxies automatically with WPAD.
+    pub auto_discovery: bool,
+}
+
+/// Get the proxy of the given `kind` from scutil `values`.
+///
+/// Return `None` if the proxy is disabled, or if its host or port is missing.
+fn proxy_server(values: &[(&str, &str)], kind: &str) -> Option<ProxyServer> {
+    let value = |key: String| {
+        values
+            .iter()
+            .find(|(candidate, _)| *candidate == key)
+            .map(|(_, value)| *value)
+    };
+    if value(format!("{kind}Enable")) != Some("1") {
+        return None;
+    }
+    let host = value(format!("{kind}Proxy")).filter(|host| !host.is_empty())?;
+    let port = value(format!("{kind}Port"))?.parse().ok()?;
+    Some(ProxyServer {
+        host: host.to_string(),
+        port,
+    })
+}
+
+impl ProxySettings {
+    /// Read the current proxy settings with `scutil --proxy`.
+    pub fn read() -> Result<Self> {
+        let output = Command::new("scutil").arg("--proxy").output()?;
+        if output.status.success() {
+            Ok(Self::parse(&String::from_utf8_lossy(&output.stdout)))
+        } else {
+            Err(Error::new(
+                ErrorKind::Other,
+                format!(
+                    "scutil failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            ))
+        }
+    }
+
+    /// Parse the `output` of `scutil --proxy`.
+    ///
+    /// Ignore keys this crate does not know, e.g. FTP or Gopher proxies.
+    pub fn parse(output: &str) -> Self {
+        let mut values = Vec::new();
+        let mut exceptions = Vec::new();
+        let mut in_exceptions = false;
+        for line in output.lines().map(str::trim) {
+            if in_exceptions {
+                match line.split_once(" : ") {
+                    Some((_, exception)) => exceptions.push(exception.trim().to_string()),
+                    None => in_exceptions = false,
+                }
+            } else if let Some((key, value)) = line.split_once(" : ") {
+                if key == "ExceptionsList" {
+                    in_exceptions = true;
+                } else {
+                    values.push((key, value.trim()));
+                }
+            }
+        }
+        let enabled = |key| values.contains(&(key, "1"));
+        Self {
+            http: proxy_server(&values, "HTTP"),
+            https: proxy_server(&values, "HTTPS"),
+            socks: proxy_server(&values, "SOCKS"),
+            exceptions,
+            exclude_simple_hostnames: enabled("ExcludeSimpleHostnames"),
+            auto_config_url: values
+                .iter()
+                .find(|(key, _)| *key == "ProxyAutoConfigURLString")
+                .map(|(_, url)| url.to_string())
+                .filter(|url| enabled("ProxyAutoConfigEnable") && !url.is_empty()),
+            auto_discovery: enabled("ProxyAutoDiscoveryEnable"),
+        }
+    }
+
+    /// Convert the [exceptions](ProxySettings::exceptions) into no proxy rules.
+    ///
+    /// An exception with a leading `*.` or `.` matches a domain and all its subdomains, and any
+    /// other exception must match the host exactly.  Skip exceptions which [`NoProxyRules`]
+    /// cannot represent, i.e. exceptions with other wildcards and IP networks like
+    /// `169.254/16`.  If [`ProxySettings::exclude_simple_hostnames`] is set, also match
+    /// [simple host names](NoProxyRule::MatchSimpleHostnames).
+    pub fn bypass_rules(&self) -> NoProxyRules {
+        let mut hosts = HostInterner::default();
+        let mut rules = self
+            .exceptions
+            .iter()
+            .filter_map(|exception| {
+                let host = exception.to_ascii_lowercase();
+                let rule = match host.strip_prefix('*') {
+                    Some(domain) if domain.starts_with('.') => {
+                        NoProxyRule::MatchSubdomain(hosts.intern(domain))
+                    }
+                    _ if host.starts_with('.') => NoProxyRule::MatchSubdomain(hosts.intern(&host)),
+                    _ if host.contains(['*', '/']) => {
+                        debug!("Skipping unsupported proxy exception {exception}");
+                        return None;
+                    }
+                    _ => NoProxyRule::MatchExact(hosts.intern(&host)),
+                };
+                Some(rule)
+            })
+            .collect::<Vec<_>>();
+        if self.exclude_simple_hostnames {
+            rules.push(NoProxyRule::MatchSimpleHostnames);
+        }
+        NoProxyRules::new(rules)
+    }
+
+    /// Get the proxy for `url`, by its scheme.
+    ///
+    /// Use the SOCKS proxy for schemes without a proxy of their own.
+    fn proxy_for(&self, url: &Url) -> Option<Url> {
+        let (scheme, server) = match url.scheme() {
+            "http" => self.http.as_ref().map(|server| ("http", server)),
+            "https" => self.https.as_ref().map(|server| ("http", server)),
+            _ => None,
+        }
+        .or_else(|| self.socks.as_ref().map(|server| ("socks5", server)))?;
+        Url::parse(&format!("{scheme}://{}:{}", server.host, server.port)).ok()
+    }
+
+    /// Decide how to connect to `url` with these settings.
+    ///
+    /// Connect directly to [non-network URLs](is_non_network_url).  Return
+    /// [`ProxyDecision::Unspecified`] if proxy auto-config or automatic discovery is enabled,
+    /// because this crate can't evaluate these.  Otherwise connect directly if the exceptions
+    /// match `url`, or there's no proxy for `url`.
+    pub fn decide(&self, url: &Url) -> ProxyDecision {
+        if is_non_network_url(url) {
+            ProxyDecision::Direct
+        } else if self.auto_config_url.is_some() || self.auto_discovery {
+            ProxyDecision::Unspecified
+        } else if self.bypass_rules().no_proxy_for(url) {
+            ProxyDecision::Direct
+        } else {
+            self.proxy_for(url)
+                .map_or(ProxyDecision::Direct, ProxyDecision::Proxy)
+        }
+    }
+}
+
+impl ProxyResolver for ProxySettings {
+    fn for_url(&self, url: &Url) -> Option<Url> {
+        self.decide(url).into_proxy()
+    }
+
+    fn decide(&self, url: &Url) -> ProxyDecision {
+        ProxySettings::decide(self, url)
+    }
+
+    /// Report the exception which matches `url`, and a missing proxy as no proxy.
+    fn direct_reason(&self, url: &Url) -> Option<DirectReason> {
+        match self.decide(url) {
+            ProxyDecision::Direct if is_non_network_url(url) => Some(DirectReason::NonNetworkUrl),
+            ProxyDecision::Direct => self
+                .bypass_rules()
+                .matching_rule(url.host().as_ref())
+                .map(DirectReason::NoProxyRule)
+                .or(Some(DirectReason::NoProxyConfigured)),
+            decision => DirectReason::from_decision(&decision),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const OUTPUT: &str = "<dictionary> {
+  ExceptionsList : <array> {
+    0 : *.local
+    1 : 169.254/16
+    2 : Intranet
+  }
+  ExcludeSimpleHostnames : 1
+  FTPPassive : 1
+  HTTPEnable : 1
+  HTTPPort : 3128
+  HTTPProxy : proxy.example.com
+  HTTPSEnable : 0
+  HTTPSPort : 3129
+  HTTPSProxy : proxy.example.com
+  ProxyAutoConfigEnable : 0
+  ProxyAutoConfigURLString : http://wpad/wpad.dat
+  SOCKSEnable : 1
+  SOCKSPort : 1080
+  SOCKSProxy : socks.example.com
+}
+";
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            ProxySettings::parse(OUTPUT),
+            ProxySettings {
+                http: Some(ProxyServer {
+                    host: "proxy.example.com".to_string(),
+                    port: 3128
+                }),
+                https: None,
+                socks: Some(ProxyServer {
+                    host: "socks.example.com".to_string(),
+                    port: 1080
+                }),
+                exceptions: vec![
+                    "*.local".to_string(),
+                    "169.254/16".to_string(),
+                    "Intranet".to_string()
+                ],
+                exclude_simple_hostnames: true,
+                auto_config_url: None,
+                auto_discovery: false,
+            }
+        );
+        assert_eq!(ProxySettings::parse(""), ProxySettings::default());
+    }
+
+    #[test]
+    fn bypass_rules() {
+        assert_eq!(
+            ProxySettings::parse(OUTPUT).bypass_rules(),
+            NoProxyRules::new(vec![
+                NoProxyRule::MatchSubdomain(".local".into()),
+                NoProxyRule::MatchExact("intranet".into()),
+                NoProxyRule::MatchSimpleHostnames,
+            ])
+        );
+    }
+
+    #[test]
+    fn decide() {
+        let settings = ProxySettings::parse(OUTPUT);
+        let decide = |url: &str| settings.decide(&Url::parse(url).unwrap());
+        assert_eq!(
+            decide("http://example.com"),
+            ProxyDecision::Proxy(Url::parse("http://proxy.example.com:3128").unwrap())
+        );
+        assert_eq!(
+            decide("https://example.com"),
+            ProxyDecision::Proxy(Url::parse("socks5://socks.example.com:1080").unwrap())
+        );
+        assert_eq!(decide("http://printer.local"), ProxyDecision::Direct);
+        assert_eq!(decide("http://nas"), ProxyDecision::Direct);
+        assert_eq!(decide("mailto:john@example.com"), ProxyDecision::Direct);
+
+        let settings = ProxySettings {
+            auto_config_url: Some("http://wpad/wpad.dat".to_string()),
+            ..settings
+        };
+        assert_eq!(
+            settings.decide(&Url::parse("http://example.com").unwrap()),
+            ProxyDecision::Unspecified
+        );
+        assert_eq!(
+            settings.decide(&Url::parse("data:text/plain,foo").unwrap()),
+            ProxyDecision::Direct
+        );
+    }
+}
@@ -0,0 +1,454 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Fetch proxy auto-config scripts.
+//!
+//! Scripts come from `http:` URLs, e.g. from WPAD, or from local files.  Fetch them with a
+//! minimal HTTP/1.0 client, which always connects directly: there's no proxy before we've
+//! evaluated the script.  With the `pac-https` feature the client also speaks TLS with rustls,
+//! if the application provides a TLS configuration.
+
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::TcpStream;
+#[cfg(feature = "pac-https")]
+use std::sync::Arc;
+use std::time::Duration;
+
+use sha2::{Digest, Sha256};
+use url::{Position, Url};
+
+use crate::redact::redact;
+
+/// How long to wait for the server, when connecting and for every read or write.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The maximum size of a script.
+const MAX_SIZE: u64 = 10 * 1024 * 1024;
+
+/// How many redirects to follow.
+const MAX_REDIRECTS: usize = 5;
+
+/// Options for fetching proxy auto-config scripts with [`super::PacResolver::from_url_with`].
+#[derive(Debug, Clone, Default)]
+pub struct FetchOptions {
+    sha256: Option<[u8; 32]>,
+    #[cfg(feature = "pac-https")]
+    tls: Option<Arc<rustls::ClientConfig>>,
+}
+
+impl FetchOptions {
+    /// Create default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pin the script to the given SHA-256 `digest`.
+    ///
+    /// Refuse to evaluate a script whose SHA-256 digest differs, and fail with
+    /// [`ErrorKind::InvalidData`] instead.  This protects against tampered scripts when the
+    /// script is known in advance, e.g. in managed networks.
+    pub fn with_sha256(mut self, digest: [u8; 32]) -> Self {
+        self.sha256 = Some(digest);
+        self
+    }
+
+    /// Download scripts from `https:` URLs with the given TLS `config`.
+    ///
+    /// Without a TLS configuration fail with [`ErrorKind::Unsupported`] for `https:` URLs.  This
+    /// function requires the `pac-https` feature.
+    #[cfg(feature = "pac-https")]
+    pub fn with_tls_config(mut self, config: Arc<rustls::ClientConfig>) -> Self {
+        self.tls = Some(config);
+        self
+    }
+
+    /// Download scripts from `https:` URLs, and verify servers against the given `roots`.
+    ///
+    /// Use the safe default protocol versions and cipher suites of rustls, and no client
+    /// authentication; use [`FetchOptions::with_tls_config`] for anything else.  This function
+    /// requires the `pac-https` feature.
+    #[cfg(feature = "pac-https")]
+    pub fn with_root_certificates(self, roots: rustls::RootCertStore) -> Self {
+        self.with_tls_config(Arc::new(
+            rustls::ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(roots)
+                .with_no_client_auth(),
+        ))
+    }
+
+    /// Whether these options support downloading scripts from URLs with `scheme`.
+    fn supports(&self, scheme: &str) -> bool {
+        match scheme {
+            "http" => true,
+            #[cfg(feature = "pac-https")]
+            "https" => self.tls.is_some(),
+            _ => false,
+        }
+    }
+
+    /// Check `script` against the pinned digest, if any.
+    fn verify(&self, url: &Url, script: &[u8]) -> Result<()> {
+        match self.sha256 {
+            Some(expected) if Sha256::digest(script).as_slice() != expected => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "SHA-256 digest of proxy auto-config script at {} does not match",
+                    redact(url)
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Connect to the host of `url`, trying all its addresses.
+fn connect(url: &Url) -> Result<TcpStream> {
+    let mut last_error = Error::new(ErrorKind::NotFound, "Host has no addresses");
+    for address in url.socket_addrs(|| None)? {
+        match TcpStream::connect_timeout(&address, TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(error) => last_error = error,
+        }
+    }
+    Err(last_error)
+}
+
+/// A connection to a HTTP server.
+trait Stream: Read + Write {}
+
+impl<T: Read + Write> Stream for T {}
+
+/// Establish a TLS connection to the host of `url` over `stream`.
+#[cfg(feature = "pac-https")]
+fn tls(url: &Url, stream: TcpStream, options: &FetchOptions) -> Result<Box<dyn Stream>> {
+    use rustls::{ClientConnection, ServerName, StreamOwned};
+    use url::Host;
+
+    let config = options
+        .tls
+        .clone()
+        .ok_or_else(|| Error::new(ErrorKind::Unsupported, "No TLS configuration"))?;
+    let name = match url.host() {
+        Some(Host::Domain(domain)) => ServerName::try_from(domain)
+            .map_err(|error| Error::new(ErrorKind::InvalidInput, error))?,
+        Some(Host::Ipv4(address)) => ServerName::IpAddress(address.into()),
+        Some(Host::Ipv6(address)) => ServerName::IpAddress(address.into()),
+        None => return Err(Error::new(ErrorKind::InvalidInput, "URL has no host")),
+    };
+    let connection =
+        ClientConnection::new(config, name).map_err(|error| Error::new(ErrorKind::Other, error))?;
+    Ok(Box::new(StreamOwned::new(connection, stream)))
+}
+
+/// Establish a TLS connection to the host of `url` over `stream`.
+#[cfg(not(feature = "pac-https"))]
+fn tls(_url: &Url, _stream: TcpStream, _options: &FetchOptions) -> Result<Box<dyn Stream>> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        "TLS requires the pac-https feature",
+    ))
+}
+
+/// A HTTP response.
+#[derive(Debug)]
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    /// Parse a complete HTTP/1.x response from `data`.
+    fn parse(data: &[u8]) -> Result<Self> {
+        let invalid = || Error::new(ErrorKind::InvalidData, "Invalid HTTP response");
+        let end = data
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .ok_or_else(invalid)?;
+        let head = std::str::from_utf8(&data[..end]).map_err(|_| invalid())?;
+        let mut lines = head.split("\r\n");
+        let status = lines
+            .next()
+            .filter(|line| line.starts_with("HTTP/1."))
+            .and_then(|line| line.split(' ').nth(1))
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(invalid)?;
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+            .collect();
+        Ok(Self {
+            status,
+            headers,
+            body: data[end + 4..].to_vec(),
+        })
+    }
+
+    /// Get the value of the header with the lowercase `name`.
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Send a GET request for `url`, and read the whole response.
+///
+/// Speak TLS for `https:` URLs.
+fn get(url: &Url, options: &FetchOptions) -> Result<Response> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "URL has no host"))?;
+    let host = match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    };
+    let stream = connect(url)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut stream: Box<dyn Stream> = if url.scheme() == "https" {
+        tls(url, stream, options)?
+    } else {
+        Box::new(stream)
+    };
+    write!(
+        stream,
+        "GET {} HTTP/1.0\r\nHost: {host}\r\nUser-Agent: {}/{}\r\nAccept: application/x-ns-proxy-autoconfig, */*\r\nConnection: close\r\n\r\n",
+        &url[Position::BeforePath..Position::AfterQuery],
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+    )?;
+    stream.flush()?;
+    let mut data = Vec::new();
+    match stream.take(MAX_SIZE).read_to_end(&mut data) {
+        Ok(_) => Response::parse(&data),
+        // Many servers close TLS connections without notifying the client; accept the response
+        // if it's complete nonetheless.
+        Err(error) if error.kind() == ErrorKind::UnexpectedEof => {
+            let response = Response::parse(&data)?;
+            let length = response
+                .header("content-length")
+                .and_then(|length| length.parse::<usize>().ok());
+            if length == Some(response.body.len()) {
+                Ok(response)
+            } else {
+                Err(error)
+            }
+        }
+        Err(error) => Err(error),
+    }
+}
+
+/// Fetch the script at the `http:` or `https:` `url`, following redirects.
+///
+/// Never follow redirects from `https:` to `http:`.
+fn fetch_http(url: &Url, options: &FetchOptions) -> Result<Vec<u8>> {
+    let mut url = url.clone();
+    for _ in 0..=MAX_REDIRECTS {
+        let response = get(&url, options)?;
+        match response.status {
+            200 => {
+                if response
+                    .header("transfer-encoding")
+                    .map_or(false, |encoding| !encoding.eq_ignore_ascii_case("identity"))
+                {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "Unsupported transfer encoding",
+                    ));
+                }
+                return Ok(response.body);
+            }
+            301 | 302 | 303 | 307 | 308 => {
+                let location = response.header("location").ok_or_else(|| {
+                    Error::new(ErrorKind::InvalidData, "Redirect without location")
+                })?;
+                let target = url
+                    .join(location)
+                    .map_err(|error| Error::new(ErrorKind::InvalidData, error))?;
+                let downgrade = url.scheme() == "https" && target.scheme() != "https";
+                if downgrade || !options.supports(target.scheme()) {
+                    return Err(Error::new(
+                        ErrorKind::Unsupported,
+                        format!("Cannot follow redirect to {}", redact(&target)),
+                    ));
+                }
+                debug!(
+                    "Following redirect from {} to {}",
+                    redact(&url),
+                    redact(&target)
+                );
+                url = target;
+            }
+            status => {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!("HTTP status {status} for {}", redact(&url)),
+                ))
+            }
+        }
+    }
+    Err(Error::new(ErrorKind::Other, "Too many redirects"))
+}
+
+/// Fetch the script at `url` with `options`.
+///
+/// Download `http:` URLs, and `https:` URLs if `options` has a TLS configuration, and read
+/// `file:` URLs from disk.  Fail with [`ErrorKind::Unsupported`] for all other URLs.
+pub(super) fn fetch(url: &Url, options: &FetchOptions) -> Result<String> {
+    let script = match url.scheme() {
+        "file" => {
+            let path = url.to_file_path().map_err(|_| {
+                Error::new(ErrorKind::InvalidInput, format!("{url} is no file path"))
+            })?;
+            std::fs::read(path)?
+        }
+        scheme if options.supports(scheme) => fetch_http(url, options)?,
+        scheme => {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("Cannot fetch proxy auto-config scripts from {scheme}: URLs"),
+            ))
+        }
+    };
+    options.verify(url, &script)?;
+    Ok(String::from_utf8_lossy(&script).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::net::TcpListener;
+
+    /// Serve the given `responses` on a local port, one per connection.
+    fn serve(responses: Vec<String>) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        std::thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buffer).unwrap();
+                    request.extend_from_slice(&buffer[..n]);
+                }
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn fetch_with_redirect() {
+        let url = serve(vec![
+            "HTTP/1.0 302 Found\r\nLocation: /proxy.pac\r\n\r\n".to_string(),
+            "HTTP/1.0 200 OK\r\nContent-Type: application/x-ns-proxy-autoconfig\r\n\r\nfunction FindProxyForURL(url, host) { return \"DIRECT\"; }".to_string(),
+        ]);
+        assert_eq!(
+            fetch(&url.join("/wpad.dat").unwrap(), &FetchOptions::new()).unwrap(),
+            "function FindProxyForURL(url, host) { return \"DIRECT\"; }"
+        );
+    }
+
+    #[test]
+    fn fetch_not_found() {
+        let url = serve(vec!["HTTP/1.1 404 Not Found\r\n\r\n".to_string()]);
+        let error = fetch(&url, &FetchOptions::new()).unwrap_err();
+        assert!(error.to_string().contains("HTTP status 404"), "{error}");
+    }
+
+    #[test]
+    fn fetch_pinned() {
+        let script = "function FindProxyForURL(url, host) { return \"DIRECT\"; }";
+        let digest = Sha256::digest(script.as_bytes()).into();
+        let url = serve(vec![
+            format!("HTTP/1.0 200 OK\r\n\r\n{script}"),
+            "HTTP/1.0 200 OK\r\n\r\nfunction FindProxyForURL(url, host) { return \"PROXY evil:8080\"; }".to_string(),
+        ]);
+        let options = FetchOptions::new().with_sha256(digest);
+        assert_eq!(fetch(&url, &options).unwrap(), script);
+        let error = fetch(&url, &options).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    /// Serve the given `responses` over TLS on a local port, one per connection.
+    ///
+    /// Return the URL, and root certificates which trust the server.
+    #[cfg(feature = "pac-https")]
+    fn serve_tls(responses: Vec<String>) -> (Url, rustls::RootCertStore) {
+        use rustls::{Certificate, PrivateKey, ServerConfig, ServerConnection, StreamOwned};
+
+        // A self-signed certificate for localhost, valid until 4096.
+        let der = Certificate(include_bytes!("testdata/localhost.crt.der").to_vec());
+        let key = PrivateKey(include_bytes!("testdata/localhost.key.der").to_vec());
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(&der).unwrap();
+        let config = Arc::new(
+            ServerConfig::builder()
+                .with_safe_defaults()
+                .with_no_client_auth()
+                .with_single_cert(vec![der], key)
+                .unwrap(),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!(
+            "https://localhost:{}/",
+            listener.local_addr().unwrap().port()
+        ))
+        .unwrap();
+        std::thread::spawn(move || {
+            for response in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let connection = ServerConnection::new(config.clone()).unwrap();
+                let mut stream = StreamOwned::new(connection, stream);
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buffer).unwrap();
+                    request.extend_from_slice(&buffer[..n]);
+                }
+                stream.write_all(response.as_bytes()).unwrap();
+                stream.conn.send_close_notify();
+                stream.flush().unwrap();
+            }
+        });
+        (url, roots)
+    }
+
+    #[cfg(feature = "pac-https")]
+    #[test]
+    fn fetch_https() {
+        let (url, roots) = serve_tls(vec![
+            "HTTP/1.0 302 Found\r\nLocation: /proxy.pac\r\n\r\n".to_string(),
+            "HTTP/1.0 200 OK\r\n\r\nfunction FindProxyForURL(url, host) { return \"DIRECT\"; }"
+                .to_string(),
+            "HTTP/1.0 302 Found\r\nLocation: http://localhost/proxy.pac\r\n\r\n".to_string(),
+        ]);
+        let options = FetchOptions::new().with_root_certificates(roots);
+        assert_eq!(
+            fetch(&url.join("/wpad.dat").unwrap(), &options).unwrap(),
+            "function FindProxyForURL(url, host) { return \"DIRECT\"; }"
+        );
+        let error = fetch(&url, &options).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+        let error = fetch(&url, &FetchOptions::new()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn fetch_unsupported() {
+        let error = fetch(
+            &Url::parse("https://wpad.example.com/wpad.dat").unwrap(),
+            &FetchOptions::new(),
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The predefined functions of proxy auto-config scripts.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+
+use boa_engine::{Context, JsResult, JsValue};
+use ipnet::IpNet;
+
+use crate::dns::resolve;
+
+/// The predefined functions which we implement in Javascript.
+const PAC_UTILS: &str = include_str!("pac_utils.js");
+
+/// Get the address of the interface which routes to `remote`, if any.
+///
+/// Connecting a UDP socket sends no packets; it only selects the local address.
+fn local_address(remote: SocketAddr) -> Option<IpAddr> {
+    let unspecified: IpAddr = match remote {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let socket = UdpSocket::bind((unspecified, 0)).ok()?;
+    socket.connect(remote).ok()?;
+    Some(socket.local_addr().ok()?.ip()).filter(|address| !address.is_unspecified())
+}
+
+/// Get the first string argument at `index` of `args`.
+fn string_arg(args: &[JsValue], index: usize, context: &mut Context) -> JsResult<String> {
+    args.get(index)
+        .cloned()
+        .unwrap_or_default()
+        .to_string(context)
+        .map(|s| s.to_string())
+}
+
+/// `dnsResolve(host)`: The first IPv4 address of `host`, or `null`.
+fn dns_resolve(_this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let host = string_arg(args, 0, context)?;
+    Ok(resolve(&host)
+        .into_iter()
+        .find(IpAddr::is_ipv4)
+        .map_or_else(JsValue::null, |address| address.to_string().into()))
+}
+
+/// `dnsResolveEx(host)`: All addresses of `host`, separated by `;`.
+fn dns_resolve_ex(_this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let host = string_arg(args, 0, context)?;
+    let addresses = resolve(&host)
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    Ok(addresses.join(";").into())
+}
+
+/// `myIpAddress()`: The IPv4 address of this host.
+///
+/// Use the address of the interface with the default route, or the loopback address.
+fn my_ip_address(_this: &JsValue, _args: &[JsValue], _context: &mut Context) -> JsResult<JsValue> {
+    let address = local_address(SocketAddr::from((Ipv4Addr::new(198, 51, 100, 1), 80)))
+        .unwrap_or_else(|| Ipv4Addr::LOCALHOST.into());
+    Ok(address.to_string().into())
+}
+
+/// `myIpAddressEx()`: The IPv4 and IPv6 addresses of this host, separated by `;`.
+fn my_ip_address_ex(
+    _this: &JsValue,
+    _args: &[JsValue],
+    _context: &mut Context,
+) -> JsResult<JsValue> {
+    let remotes = [
+        SocketAddr::from((Ipv4Addr::new(198, 51, 100, 1), 80)),
+        SocketAddr::from((Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1), 80)),
+    ];
+    let addresses = remotes
+        .into_iter()
+        .filter_map(local_address)
+        .map(|address| address.to_string())
+        .collect::<Vec<_>>();
+    Ok(addresses.join(";").into())
+}
+
+/// `isInNetEx(host, prefix)`: Whether any address of `host` is in the IPv4 or IPv6 `prefix`.
+fn is_in_net_ex(_this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let host = string_arg(args, 0, context)?;
+    let prefix = string_arg(args, 1, context)?;
+    let is_in_net = match prefix.parse::<IpNet>() {
+        Ok(network) => resolve(&host)
+            .iter()
+            .any(|address| network.contains(address)),
+        Err(_) => false,
+    };
+    Ok(is_in_net.into())
+}
+
+/// `alert(message)`: Log `message`.
+fn alert(_this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let message = string_arg(args, 0, context)?;
+    info!("Proxy auto-config script: {message}");
+    Ok(JsValue::undefined())
+}
+
+/// Define all predefined functions of proxy auto-config scripts in `context`.
+pub(super) fn register(context: &mut Context) -> JsResult<()> {
+    context.register_global_function("dnsResolve", 1, dns_resolve);
+    context.register_global_function("dnsResolveEx", 1, dns_resolve_ex);
+    context.register_global_function("myIpAddress", 0, my_ip_address);
+    context.register_global_function("myIpAddressEx", 0, my_ip_address_ex);
+    context.register_global_function("isInNetEx", 2, is_in_net_ex);
+    context.register_global_function("alert", 1, alert);
+    context.eval(PAC_UTILS)?;
+    Ok(())
+}
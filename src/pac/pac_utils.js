@@ -0,0 +1,152 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// The predefined functions of proxy auto-config scripts which need no access
+// to the system.  dnsResolve, myIpAddress, alert and the IPv6 extensions of
+// Microsoft are native functions, see functions.rs.
+
+function dnsDomainIs(host, domain) {
+    return host.length >= domain.length &&
+        host.substring(host.length - domain.length) == domain;
+}
+
+function dnsDomainLevels(host) {
+    return host.split('.').length - 1;
+}
+
+function isPlainHostName(host) {
+    return host.indexOf('.') < 0;
+}
+
+function localHostOrDomainIs(host, hostdom) {
+    return host == hostdom || hostdom.lastIndexOf(host + '.', 0) == 0;
+}
+
+function convert_addr(ipchars) {
+    var bytes = ipchars.split('.');
+    return ((bytes[0] & 0xff) << 24) | ((bytes[1] & 0xff) << 16) |
+        ((bytes[2] & 0xff) << 8) | (bytes[3] & 0xff);
+}
+
+function isResolvable(host) {
+    return dnsResolve(host) !== null;
+}
+
+function isResolvableEx(host) {
+    return dnsResolveEx(host) !== '';
+}
+
+function isInNet(ipaddr, pattern, maskstr) {
+    if (!/^\d+\.\d+\.\d+\.\d+$/.test(ipaddr)) {
+        ipaddr = dnsResolve(ipaddr);
+        if (ipaddr === null) {
+            return false;
+        }
+    }
+    var mask = convert_addr(maskstr);
+    return (convert_addr(ipaddr) & mask) == (convert_addr(pattern) & mask);
+}
+
+function shExpMatch(str, shexp) {
+    var pattern = shexp.replace(/[.+^${}()|[\]\\]/g, '\\$&')
+        .replace(/\*/g, '.*')
+        .replace(/\?/g, '.');
+    return new RegExp('^' + pattern + '$').test(str);
+}
+
+var __pacDays = ['SUN', 'MON', 'TUE', 'WED', 'THU', 'FRI', 'SAT'];
+var __pacMonths = ['JAN', 'FEB', 'MAR', 'APR', 'MAY', 'JUN',
+                   'JUL', 'AUG', 'SEP', 'OCT', 'NOV', 'DEC'];
+
+// Split a trailing "GMT" off the arguments of the time functions.
+function __pacTimeArgs(args) {
+    var values = Array.prototype.slice.call(args);
+    var gmt = values[values.length - 1] === 'GMT';
+    if (gmt) {
+        values.pop();
+    }
+    return { values: values, gmt: gmt, now: new Date() };
+}
+
+// Whether value is between start and end, wrapping around if end < start.
+function __pacInRange(value, start, end) {
+    return start <= end ? start <= value && value <= end : start <= value || value <= end;
+}
+
+function weekdayRange() {
+    var args = __pacTimeArgs(arguments);
+    var day = args.gmt ? args.now.getUTCDay() : args.now.getDay();
+    var start = __pacDays.indexOf(args.values[0]);
+    var end = args.values.length < 2 ? start : __pacDays.indexOf(args.values[1]);
+    return 0 <= start && 0 <= end && __pacInRange(day, start, end);
+}
+
+// Get a comparable key for a date given as day, month name and year, with
+// only the fields in values, for date and for now.
+function __pacDateKeys(values, args) {
+    var date = {};
+    for (var i = 0; i < values.length; i++) {
+        var value = values[i];
+        if (typeof value === 'string') {
+            date.month = __pacMonths.indexOf(value);
+        } else if (31 < value) {
+            date.year = value;
+        } else {
+            date.day = value;
+        }
+    }
+    var now = args.now;
+    var today = {
+        year: args.gmt ? now.getUTCFullYear() : now.getFullYear(),
+        month: args.gmt ? now.getUTCMonth() : now.getMonth(),
+        day: args.gmt ? now.getUTCDate() : now.getDate()
+    };
+    var key = 0, todayKey = 0;
+    var fields = [['year', 10000], ['month', 100], ['day', 100]];
+    for (var j = 0; j < fields.length; j++) {
+        var field = fields[j][0];
+        if (field in date) {
+            key = key * fields[j][1] + date[field];
+            todayKey = todayKey * fields[j][1] + today[field];
+        }
+    }
+    return { date: key, today: todayKey };
+}
+
+function dateRange() {
+    var args = __pacTimeArgs(arguments);
+    var half = Math.max(1, args.values.length / 2);
+    var start = __pacDateKeys(args.values.slice(0, half), args);
+    if (args.values.length < 2) {
+        return start.date == start.today;
+    }
+    var end = __pacDateKeys(args.values.slice(half), args);
+    return __pacInRange(start.today, start.date, end.date);
+}
+
+// Hours match up to the end of the last hour, and minutes up to the end of
+// the last minute.
+function timeRange() {
+    var args = __pacTimeArgs(arguments);
+    var now = args.now;
+    var hours = args.gmt ? now.getUTCHours() : now.getHours();
+    var minutes = args.gmt ? now.getUTCMinutes() : now.getMinutes();
+    var seconds = args.gmt ? now.getUTCSeconds() : now.getSeconds();
+    var v = args.values;
+    var time = hours * 3600 + minutes * 60 + seconds;
+    switch (v.length) {
+    case 1:
+        return hours == v[0];
+    case 2:
+        return __pacInRange(time, v[0] * 3600, v[1] * 3600 + 3599);
+    case 4:
+        return __pacInRange(time, v[0] * 3600 + v[1] * 60, v[2] * 3600 + v[3] * 60 + 59);
+    case 6:
+        return __pacInRange(time, v[0] * 3600 + v[1] * 60 + v[2], v[3] * 3600 + v[4] * 60 + v[5]);
+    default:
+        return false;
+    }
+}
@@ -0,0 +1,139 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Register additional proxy resolvers at runtime.
+//!
+//! Applications and libraries can [register](register) resolvers for proprietary sources of
+//! proxy configuration, e.g. an internal configuration service, under a name.  [`Plugins`] then
+//! consults all registered resolvers in the order of registration.
+
+use std::sync::{Arc, RwLock};
+
+use url::Url;
+
+use crate::proxy::{ProxyDecision, ProxyResolver};
+use crate::redact::redact;
+
+/// A resolver which can be registered as plugin.
+pub type PluginResolver = Arc<dyn ProxyResolver + Send + Sync>;
+
+static PLUGINS: RwLock<Vec<(String, PluginResolver)>> = RwLock::new(Vec::new());
+
+/// Register `resolver` as plugin under the given `name`.
+///
+/// If a plugin with `name` is already registered replace its resolver, but keep its position.
+pub fn register<S, R>(name: S, resolver: R)
+where
+    S: Into<String>,
+    R: ProxyResolver + Send + Sync + 'static,
+{
+    let name = name.into();
+    let resolver: PluginResolver = Arc::new(resolver);
+    let mut plugins = PLUGINS.write().unwrap_or_else(|error| error.into_inner());
+    match plugins.iter_mut().find(|(candidate, _)| *candidate == name) {
+        Some((_, existing)) => *existing = resolver,
+        None => plugins.push((name, resolver)),
+    }
+}
+
+/// Remove the plugin with the given `name`.
+///
+/// Return `false` if no plugin with `name` was registered.
+pub fn unregister(name: &str) -> bool {
+    let mut plugins = PLUGINS.write().unwrap_or_else(|error| error.into_inner());
+    let len = plugins.len();
+    plugins.retain(|(candidate, _)| candidate != name);
+    plugins.len() != len
+}
+
+/// Get the names of all registered plugins, in order of registration.
+pub fn names() -> Vec<String> {
+    let plugins = PLUGINS.read().unwrap_or_else(|error| error.into_inner());
+    plugins.iter().map(|(name, _)| name.clone()).collect()
+}
+
+/// Get the resolver of the plugin with the given `name`.
+pub fn get(name: &str) -> Option<PluginResolver> {
+    let plugins = PLUGINS.read().unwrap_or_else(|error| error.into_inner());
+    plugins
+        .iter()
+        .find(|(candidate, _)| candidate == name)
+        .map(|(_, resolver)| resolver.clone())
+}
+
+/// A resolver which consults all registered plugins.
+///
+/// Ask plugins in order of registration, and use the first decision which is not
+/// [`ProxyDecision::Unspecified`].  Plugins registered after creating this resolver take part in
+/// lookups as well.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Plugins;
+
+impl ProxyResolver for Plugins {
+    fn for_url(&self, url: &Url) -> Option<Url> {
+        self.decide(url).into_proxy()
+    }
+
+    fn decide(&self, url: &Url) -> ProxyDecision {
+        // Take a snapshot of the plugins, so that plugins can register other plugins during
+        // lookups without deadlocking.
+        let plugins = PLUGINS
+            .read()
+            .unwrap_or_else(|error| error.into_inner())
+            .clone();
+        for (name, resolver) in plugins {
+            let decision = resolver.decide(url);
+            if decision.is_specified() {
+                debug!("Plugin {name} decided for {}", redact(url));
+                return decision;
+            }
+        }
+        ProxyDecision::Unspecified
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::{EnvProxies, NoProxyRules};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn plugins_decide_in_order() {
+        let http = EnvProxies {
+            http: Some(Url::parse("http://plugin-proxy:3128").unwrap()),
+            https: None,
+            no_proxy_rules: Some(NoProxyRules::parse_curl_env(".plugin-direct.example.com")),
+        };
+        let https = EnvProxies {
+            https: Some(Url::parse("http://plugin-proxy:3129").unwrap()),
+            ..EnvProxies::unset()
+        };
+        register("test-http", http);
+        register("test-https", https);
+        assert!(names().contains(&"test-http".to_string()));
+
+        let decide = |url: &str| Plugins.decide(&Url::parse(url).unwrap());
+        assert_eq!(
+            decide("http://example.com"),
+            ProxyDecision::Proxy(Url::parse("http://plugin-proxy:3128").unwrap())
+        );
+        assert_eq!(
+            decide("http://plugin-direct.example.com"),
+            ProxyDecision::Direct
+        );
+        assert_eq!(
+            decide("https://example.com"),
+            ProxyDecision::Proxy(Url::parse("http://plugin-proxy:3129").unwrap())
+        );
+
+        assert!(unregister("test-https"));
+        assert!(!unregister("test-https"));
+        assert!(get("test-https").is_none());
+        assert_eq!(decide("https://example.com"), ProxyDecision::Unspecified);
+        assert!(unregister("test-http"));
+    }
+}
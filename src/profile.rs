@@ -0,0 +1,229 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Switch between named proxy configurations depending on the network.
+//!
+//! A [`ProxyProfile`] is a named proxy configuration, e.g. "office" or "home", together with
+//! [`ProfileCondition`]s which tell when the profile applies.  [`ProxyProfiles`] selects the
+//! first profile whose conditions match the [`NetworkIdentity`] of the current network, and
+//! [`NetworkIdentity::detect`] detects the identity of the current network.
+
+use std::net::IpAddr;
+
+use crate::env::EnvProxies;
+
+mod identity;
+
+#[cfg(all(unix, feature = "pac"))]
+pub(crate) use self::identity::detect_dns_suffix;
+pub use self::identity::NetworkIdentity;
+
+/// A condition for when a profile applies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProfileCondition {
+    /// The network has the given Wi-Fi SSID.
+    Ssid(String),
+    /// The network has the given default gateway.
+    Gateway(IpAddr),
+    /// The default gateway of the network has the given MAC address.
+    ///
+    /// MAC addresses are compared case-insensitively, and `-` and `:` separators are
+    /// equivalent.
+    GatewayMac(String),
+    /// The DNS search domain of the network is the given domain or a subdomain of it.
+    ///
+    /// Domains are compared case-insensitively.
+    DnsSuffix(String),
+}
+
+impl ProfileCondition {
+    /// Whether this condition matches the given network `identity`.
+    pub fn matches(&self, identity: &NetworkIdentity) -> bool {
+        match self {
+            ProfileCondition::Ssid(ssid) => identity.ssid.as_ref() == Some(ssid),
+            ProfileCondition::Gateway(gateway) => identity.gateway.as_ref() == Some(gateway),
+            ProfileCondition::GatewayMac(mac) => {
+                let normalize = |mac: &str| mac.replace('-', ":").to_lowercase();
+                identity.gateway_mac.as_ref().map_or(false, |gateway_mac| {
+                    normalize(gateway_mac) == normalize(mac)
+                })
+            }
+            ProfileCondition::DnsSuffix(suffix) => {
+                identity.dns_suffix.as_ref().map_or(false, |domain| {
+                    let domain = domain.trim_end_matches('.').to_lowercase();
+                    let suffix = suffix.trim_end_matches('.').to_lowercase();
+                    domain == suffix || domain.ends_with(&format!(".{suffix}"))
+                })
+            }
+        }
+    }
+}
+
+/// A named proxy configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyProfile {
+    /// The name of this profile, e.g. "office".
+    pub name: String,
+    /// When this profile applies.
+    ///
+    /// The profile applies if all conditions match; a profile without conditions always
+    /// applies.
+    pub conditions: Vec<ProfileCondition>,
+    /// The proxies to use when this profile applies.
+    pub proxies: EnvProxies,
+}
+
+impl ProxyProfile {
+    /// Create a profile with the given `name` and `proxies`, which always applies.
+    pub fn new<S: Into<String>>(name: S, proxies: EnvProxies) -> Self {
+        Self {
+            name: name.into(),
+            conditions: Vec::new(),
+            proxies,
+        }
+    }
+
+    /// Add a `condition` to this profile.
+    pub fn when(mut self, condition: ProfileCondition) -> Self {
+        self.conditions.push(condition);
+        self
+    }
+
+    /// Whether this profile applies to the network with the given `identity`.
+    pub fn applies_to(&self, identity: &NetworkIdentity) -> bool {
+        self.conditions
+            .iter()
+            .all(|condition| condition.matches(identity))
+    }
+}
+
+/// An ordered list of proxy profiles.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ProxyProfiles {
+    profiles: Vec<ProxyProfile>,
+}
+
+impl ProxyProfiles {
+    /// Create a list of `profiles`, in order of precedence.
+    pub fn new(profiles: Vec<ProxyProfile>) -> Self {
+        Self { profiles }
+    }
+
+    /// Add a `profile` with least precedence.
+    pub fn push(&mut self, profile: ProxyProfile) {
+        self.profiles.push(profile);
+    }
+
+    /// All profiles, in order of precedence.
+    pub fn profiles(&self) -> &[ProxyProfile] {
+        &self.profiles
+    }
+
+    /// Get the profile with the given `name`.
+    pub fn get(&self, name: &str) -> Option<&ProxyProfile> {
+        self.profiles.iter().find(|profile| profile.name == name)
+    }
+
+    /// Select the first profile which applies to the network with the given `identity`.
+    ///
+    /// Return `None` if no profile applies.
+    pub fn select(&self, identity: &NetworkIdentity) -> Option<&ProxyProfile> {
+        self.profiles
+            .iter()
+            .find(|profile| profile.applies_to(identity))
+    }
+
+    /// Select the first profile which applies to the current network.
+    ///
+    /// Detect the identity of the current network with [`NetworkIdentity::detect`], and
+    /// [select](ProxyProfiles::select) a profile for it.  This function blocks while detecting
+    /// the network identity.
+    pub fn select_current(&self) -> Option<&ProxyProfile> {
+        self.select(&NetworkIdentity::detect())
+    }
+}
+
+impl From<Vec<ProxyProfile>> for ProxyProfiles {
+    fn from(profiles: Vec<ProxyProfile>) -> Self {
+        Self::new(profiles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use url::Url;
+
+    fn profiles() -> ProxyProfiles {
+        let office = EnvProxies {
+            http: Some(Url::parse("http://proxy.corp.example.com:3128").unwrap()),
+            ..EnvProxies::unset()
+        };
+        vec![
+            ProxyProfile::new("office", office.clone())
+                .when(ProfileCondition::DnsSuffix("corp.example.com".to_string())),
+            ProxyProfile::new("office-wifi", office)
+                .when(ProfileCondition::Ssid("CorpWifi".to_string()))
+                .when(ProfileCondition::Gateway("10.0.0.1".parse().unwrap())),
+            ProxyProfile::new("direct", EnvProxies::unset()),
+        ]
+        .into()
+    }
+
+    fn select(identity: NetworkIdentity) -> Option<String> {
+        profiles()
+            .select(&identity)
+            .map(|profile| profile.name.clone())
+    }
+
+    #[test]
+    fn select_dns_suffix() {
+        let identity = NetworkIdentity {
+            dns_suffix: Some("Berlin.Corp.Example.com.".to_string()),
+            ..NetworkIdentity::default()
+        };
+        assert_eq!(select(identity), Some("office".to_string()));
+        let identity = NetworkIdentity {
+            dns_suffix: Some("notcorp.example.com".to_string()),
+            ..NetworkIdentity::default()
+        };
+        assert_eq!(select(identity), Some("direct".to_string()));
+    }
+
+    #[test]
+    fn select_all_conditions_must_match() {
+        let identity = NetworkIdentity {
+            ssid: Some("CorpWifi".to_string()),
+            gateway: Some("10.0.0.1".parse().unwrap()),
+            ..NetworkIdentity::default()
+        };
+        assert_eq!(select(identity), Some("office-wifi".to_string()));
+        let identity = NetworkIdentity {
+            ssid: Some("CorpWifi".to_string()),
+            gateway: Some("192.168.1.1".parse().unwrap()),
+            ..NetworkIdentity::default()
+        };
+        assert_eq!(select(identity), Some("direct".to_string()));
+    }
+
+    #[test]
+    fn gateway_mac_matches() {
+        let identity = NetworkIdentity {
+            gateway_mac: Some("AA-BB-CC-00-11-22".to_string()),
+            ..NetworkIdentity::default()
+        };
+        assert!(ProfileCondition::GatewayMac("aa:bb:cc:00:11:22".to_string()).matches(&identity));
+        assert!(!ProfileCondition::GatewayMac("aa:bb:cc:00:11:23".to_string()).matches(&identity));
+    }
+
+    #[test]
+    fn select_none() {
+        let profiles = ProxyProfiles::new(vec![ProxyProfile::new("home", EnvProxies::unset())
+            .when(ProfileCondition::Ssid("HomeWifi".to_string()))]);
+        assert_eq!(profiles.select(&NetworkIdentity::default()), None);
+    }
+}
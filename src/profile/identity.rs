@@ -0,0 +1,342 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Detect the identity of the current network.
+
+use std::net::IpAddr;
+
+/// The identity of a network.
+///
+/// All fields are optional, because not every platform or network provides every piece of
+/// information.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct NetworkIdentity {
+    /// The SSID of the current Wi-Fi network, if connected to Wi-Fi.
+    pub ssid: Option<String>,
+    /// The IP address of the default gateway.
+    pub gateway: Option<IpAddr>,
+    /// The MAC address of the default gateway, e.g. `aa:bb:cc:00:11:22`.
+    pub gateway_mac: Option<String>,
+    /// The DNS search domain of the network, e.g. `corp.example.com`.
+    pub dns_suffix: Option<String>,
+}
+
+impl NetworkIdentity {
+    /// Detect the identity of the current network.
+    ///
+    /// Detection is best effort, and leaves fields empty if the corresponding information is not
+    /// available.  On Linux read the routing and ARP tables from `/proc` and `/etc/resolv.conf`,
+    /// and get the SSID from `iwgetid` or `nmcli`.  On macOS use `route`, `arp` and
+    /// `networksetup`, and on Windows `route`, `arp` and `netsh`.
+    ///
+    /// This function blocks while it runs these commands.
+    pub fn detect() -> Self {
+        platform::detect()
+    }
+}
+
+/// Run `program` with `args`, and return its standard output if it succeeded.
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    match std::process::Command::new(program).args(args).output() {
+        Ok(output) if output.status.success() => {
+            Some(String::from_utf8_lossy(&output.stdout).into_owned())
+        }
+        Ok(output) => {
+            debug!("{program} failed with {}", output.status);
+            None
+        }
+        Err(error) => {
+            debug!("Failed to run {program}: {error}");
+            None
+        }
+    }
+}
+
+/// Get the DNS search domain from the contents of `/etc/resolv.conf`.
+///
+/// Like the resolver take the first domain of the last `search` or `domain` line.
+#[cfg(unix)]
+fn parse_resolv_conf(contents: &str) -> Option<String> {
+    contents
+        .lines()
+        .rev()
+        .find_map(|line| {
+            let mut parts = line.split_whitespace();
+            match parts.next() {
+                Some("search") | Some("domain") => parts.next(),
+                _ => None,
+            }
+        })
+        .map(ToString::to_string)
+}
+
+/// Get the DNS search domain from `/etc/resolv.conf`.
+#[cfg(unix)]
+pub(crate) fn detect_dns_suffix() -> Option<String> {
+    std::fs::read_to_string("/etc/resolv.conf")
+        .ok()
+        .and_then(|contents| parse_resolv_conf(&contents))
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::{command_output, detect_dns_suffix, NetworkIdentity};
+
+    /// Get the gateway of the default route with the lowest metric from `/proc/net/route`.
+    pub(super) fn parse_proc_net_route(contents: &str) -> Option<Ipv4Addr> {
+        contents
+            .lines()
+            .skip(1)
+            .filter_map(|line| {
+                let fields = line.split_whitespace().collect::<Vec<_>>();
+                let destination = fields.get(1)?;
+                let gateway = u32::from_str_radix(fields.get(2)?, 16).ok()?;
+                let metric = fields.get(6)?.parse::<u32>().ok()?;
+                // The kernel prints addresses in network byte order as host integers.
+                if *destination == "00000000" && gateway != 0 {
+                    Some((metric, Ipv4Addr::from(gateway.to_ne_bytes())))
+                } else {
+                    None
+                }
+            })
+            .min_by_key(|(metric, _)| *metric)
+            .map(|(_, gateway)| gateway)
+    }
+
+    /// Get the MAC address of `ip` from `/proc/net/arp`.
+    pub(super) fn parse_proc_net_arp(contents: &str, ip: IpAddr) -> Option<String> {
+        contents.lines().skip(1).find_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let address = fields.first()?.parse::<IpAddr>().ok()?;
+            let mac = fields.get(3)?;
+            if address == ip && *mac != "00:00:00:00:00:00" {
+                Some(mac.to_string())
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Get the SSID of the active Wi-Fi from the terse output of `nmcli -t -f active,ssid dev wifi`.
+    pub(super) fn parse_nmcli_ssid(output: &str) -> Option<String> {
+        output
+            .lines()
+            .find_map(|line| line.strip_prefix("yes:"))
+            .map(|ssid| ssid.replace("\\:", ":"))
+            .filter(|ssid| !ssid.is_empty())
+    }
+
+    fn detect_ssid() -> Option<String> {
+        command_output("iwgetid", &["-r"])
+            .map(|ssid| ssid.trim().to_string())
+            .filter(|ssid| !ssid.is_empty())
+            .or_else(|| {
+                command_output("nmcli", &["-t", "-f", "active,ssid", "dev", "wifi"])
+                    .and_then(|output| parse_nmcli_ssid(&output))
+            })
+    }
+
+    pub fn detect() -> NetworkIdentity {
+        let gateway = std::fs::read_to_string("/proc/net/route")
+            .ok()
+            .and_then(|contents| parse_proc_net_route(&contents))
+            .map(IpAddr::V4);
+        let gateway_mac = gateway.and_then(|gateway| {
+            std::fs::read_to_string("/proc/net/arp")
+                .ok()
+                .and_then(|contents| parse_proc_net_arp(&contents, gateway))
+        });
+        NetworkIdentity {
+            ssid: detect_ssid(),
+            gateway,
+            gateway_mac,
+            dns_suffix: detect_dns_suffix(),
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::net::IpAddr;
+
+    use super::{command_output, detect_dns_suffix, NetworkIdentity};
+
+    fn detect_gateway() -> Option<IpAddr> {
+        let output = command_output("route", &["-n", "get", "default"])?;
+        output
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("gateway:"))
+            .and_then(|gateway| gateway.trim().parse().ok())
+    }
+
+    fn detect_gateway_mac(gateway: IpAddr) -> Option<String> {
+        // Output looks like "? (192.168.1.1) at aa:bb:cc:0:11:22 on en0 ifscope [ethernet]"
+        let output = command_output("arp", &["-n", &gateway.to_string()])?;
+        let mut words = output.split_whitespace().skip_while(|word| *word != "at");
+        words
+            .nth(1)
+            .filter(|mac| mac.contains(':'))
+            .map(ToString::to_string)
+    }
+
+    fn detect_ssid() -> Option<String> {
+        // Find the device of the Wi-Fi hardware port.
+        let ports = command_output("networksetup", &["-listallhardwareports"])?;
+        let mut lines = ports.lines();
+        lines.find(|line| line.trim() == "Hardware Port: Wi-Fi")?;
+        let device = lines.next()?.trim().strip_prefix("Device:")?.trim();
+        let output = command_output("networksetup", &["-getairportnetwork", device])?;
+        output
+            .trim()
+            .strip_prefix("Current Wi-Fi Network:")
+            .map(|ssid| ssid.trim().to_string())
+    }
+
+    pub fn detect() -> NetworkIdentity {
+        let gateway = detect_gateway();
+        NetworkIdentity {
+            ssid: detect_ssid(),
+            gateway,
+            gateway_mac: gateway.and_then(detect_gateway_mac),
+            dns_suffix: detect_dns_suffix(),
+        }
+    }
+}
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "macos"))))]
+mod platform {
+    use super::{detect_dns_suffix, NetworkIdentity};
+
+    pub fn detect() -> NetworkIdentity {
+        NetworkIdentity {
+            dns_suffix: detect_dns_suffix(),
+            ..NetworkIdentity::default()
+        }
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::net::IpAddr;
+
+    use super::{command_output, NetworkIdentity};
+
+    fn detect_gateway() -> Option<IpAddr> {
+        // The active routes table has lines like
+        // "0.0.0.0          0.0.0.0      192.168.1.1    192.168.1.23     25"
+        let output = command_output("route", &["print", "-4", "0.0.0.0"])?;
+        output.lines().find_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            match fields.as_slice() {
+                ["0.0.0.0", "0.0.0.0", gateway, ..] => gateway.parse().ok(),
+                _ => None,
+            }
+        })
+    }
+
+    fn detect_gateway_mac(gateway: IpAddr) -> Option<String> {
+        let gateway = gateway.to_string();
+        let output = command_output("arp", &["-a", &gateway])?;
+        output.lines().find_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            match fields.as_slice() {
+                [address, mac, ..] if *address == gateway => Some(mac.to_string()),
+                _ => None,
+            }
+        })
+    }
+
+    fn detect_ssid() -> Option<String> {
+        let output = command_output("netsh", &["wlan", "show", "interfaces"])?;
+        output.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            if key.trim() == "SSID" {
+                Some(value.trim().to_string())
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn detect() -> NetworkIdentity {
+        let gateway = detect_gateway();
+        NetworkIdentity {
+            ssid: detect_ssid(),
+            gateway,
+            gateway_mac: gateway.and_then(detect_gateway_mac),
+            dns_suffix: None,
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod platform {
+    use super::NetworkIdentity;
+
+    pub fn detect() -> NetworkIdentity {
+        NetworkIdentity::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    #[test]
+    fn parse_resolv_conf() {
+        let contents = "# Generated\nnameserver 10.0.0.1\nsearch corp.example.com example.com\n";
+        assert_eq!(
+            super::parse_resolv_conf(contents),
+            Some("corp.example.com".to_string())
+        );
+        assert_eq!(super::parse_resolv_conf("nameserver 10.0.0.1\n"), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn parse_proc_net_route() {
+        let contents =
+            "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+wlan0\t00000000\t0101A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0
+eth0\t00000000\t0100000A\t0003\t0\t0\t100\t00000000\t0\t0\t0
+eth0\t0000000A\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0
+";
+        let gateway = super::platform::parse_proc_net_route(contents).unwrap();
+        if cfg!(target_endian = "little") {
+            assert_eq!(gateway, std::net::Ipv4Addr::new(10, 0, 0, 1));
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn parse_proc_net_arp() {
+        let contents =
+            "IP address       HW type     Flags       HW address            Mask     Device
+192.168.1.1      0x1         0x2         aa:bb:cc:00:11:22     *        wlan0
+192.168.1.5      0x1         0x0         00:00:00:00:00:00     *        wlan0
+";
+        assert_eq!(
+            super::platform::parse_proc_net_arp(contents, "192.168.1.1".parse().unwrap()),
+            Some("aa:bb:cc:00:11:22".to_string())
+        );
+        assert_eq!(
+            super::platform::parse_proc_net_arp(contents, "192.168.1.5".parse().unwrap()),
+            None
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn parse_nmcli_ssid() {
+        let output = "no:Neighbours\nyes:Corp\\:Wifi\n";
+        assert_eq!(
+            super::platform::parse_nmcli_ssid(output),
+            Some("Corp:Wifi".to_string())
+        );
+    }
+}
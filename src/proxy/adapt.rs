@@ -0,0 +1,256 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Adapters between synchronous and asynchronous resolvers.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::Thread;
+
+use async_trait::async_trait;
+use url::Url;
+
+use super::{AsyncProxyResolver, ProxyDecision, ProxyResolver};
+
+/// The state shared between a [`ThreadFuture`] and its thread.
+struct ThreadState<T> {
+    result: Option<std::thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+/// A future which completes when a function on a separate thread returns.
+struct ThreadFuture<T> {
+    state: Arc<Mutex<ThreadState<T>>>,
+}
+
+impl<T: Send + 'static> ThreadFuture<T> {
+    /// Run `f` on a new thread.
+    fn spawn<F: FnOnce() -> T + Send + 'static>(f: F) -> Self {
+        let state = Arc::new(Mutex::new(ThreadState {
+            result: None,
+            waker: None,
+        }));
+        let thread_state = state.clone();
+        std::thread::spawn(move || {
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+            let mut state = thread_state
+                .lock()
+                .unwrap_or_else(|error| error.into_inner());
+            state.result = Some(result);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        });
+        Self { state }
+    }
+}
+
+impl<T> Future for ThreadFuture<T> {
+    type Output = T;
+
+    /// Poll for the result of the thread, and resume unwinding if the thread panicked.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock().unwrap_or_else(|error| error.into_inner());
+        match state.result.take() {
+            Some(Ok(value)) => Poll::Ready(value),
+            Some(Err(panic)) => std::panic::resume_unwind(panic),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Wake a thread blocked in [`block_on`].
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Run `future` to completion on the current thread.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(value) => return value,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+/// Use a synchronous resolver as [`AsyncProxyResolver`].
+///
+/// Run every lookup on a separate thread, so that slow synchronous lookups never block the
+/// executor.  This works with any async runtime, but spawns a thread per lookup; wrap the
+/// resolver in a [`CachingResolver`](super::CachingResolver) for frequent lookups.
+///
+/// See [`ProxyResolver::into_async`].
+#[derive(Debug)]
+pub struct AsyncAdapter<R> {
+    resolver: Arc<R>,
+}
+
+impl<R> AsyncAdapter<R> {
+    /// Adapt `resolver`.
+    pub fn new(resolver: R) -> Self {
+        Self {
+            resolver: Arc::new(resolver),
+        }
+    }
+
+    /// Get the adapted resolver.
+    pub fn resolver(&self) -> &R {
+        &self.resolver
+    }
+}
+
+impl<R> Clone for AsyncAdapter<R> {
+    fn clone(&self) -> Self {
+        Self {
+            resolver: self.resolver.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl<R: ProxyResolver + Send + Sync + 'static> AsyncProxyResolver for AsyncAdapter<R> {
+    async fn for_url(&self, url: &Url) -> Option<Url> {
+        let (resolver, url) = (self.resolver.clone(), url.clone());
+        ThreadFuture::spawn(move || resolver.for_url(&url)).await
+    }
+
+    async fn decide(&self, url: &Url) -> ProxyDecision {
+        let (resolver, url) = (self.resolver.clone(), url.clone());
+        ThreadFuture::spawn(move || resolver.decide(&url)).await
+    }
+}
+
+/// Use an [`AsyncProxyResolver`] as synchronous [`ProxyResolver`].
+///
+/// Block the calling thread on every lookup, with a minimal executor which polls the lookup on
+/// the calling thread.  The resolver must thus not depend on a specific runtime, e.g. a portal
+/// resolver must use the `async-io` feature rather than the `tokio` feature.  Do not use this
+/// adapter from within async code, where it would block the executor, except inside
+/// `tokio::task::block_in_place` on a multi-threaded tokio runtime, which also drives resolvers
+/// built on tokio; see `examples/reqwest_async_portal.rs`.
+///
+/// See [`AsyncProxyResolver::into_blocking`].
+#[derive(Debug, Clone)]
+pub struct BlockingAdapter<R> {
+    resolver: R,
+}
+
+impl<R> BlockingAdapter<R> {
+    /// Adapt `resolver`.
+    pub fn new(resolver: R) -> Self {
+        Self { resolver }
+    }
+
+    /// Get the adapted resolver.
+    pub fn resolver(&self) -> &R {
+        &self.resolver
+    }
+}
+
+impl<R: AsyncProxyResolver> ProxyResolver for BlockingAdapter<R> {
+    fn for_url(&self, url: &Url) -> Option<Url> {
+        block_on(self.resolver.for_url(url))
+    }
+
+    fn decide(&self, url: &Url) -> ProxyDecision {
+        block_on(self.resolver.decide(url))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::EnvProxies;
+    use pretty_assertions::assert_eq;
+
+    fn proxies() -> EnvProxies {
+        EnvProxies {
+            http: Some(Url::parse("http://proxy:3128").unwrap()),
+            ..EnvProxies::unset()
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let resolver = proxies().into_async().into_blocking();
+        assert_eq!(
+            resolver.for_url(&Url::parse("http://example.com").unwrap()),
+            Some(Url::parse("http://proxy:3128").unwrap())
+        );
+        assert_eq!(
+            resolver.decide(&Url::parse("mailto:john@example.com").unwrap()),
+            ProxyDecision::Direct
+        );
+    }
+
+    #[test]
+    fn async_on_tokio() {
+        let runtime = tokio::runtime::Builder::new_multi_thread().build().unwrap();
+        let resolver = proxies().into_async();
+        let decision = runtime.block_on(async {
+            let url = Url::parse("http://example.com").unwrap();
+            tokio::spawn(async move { resolver.decide(&url).await })
+                .await
+                .unwrap()
+        });
+        assert_eq!(
+            decision,
+            ProxyDecision::Proxy(Url::parse("http://proxy:3128").unwrap())
+        );
+    }
+
+    #[test]
+    fn boxed_resolvers() {
+        use crate::proxy::{LocalAsyncProxyResolver, NoProxyResolver};
+
+        let resolvers: Vec<Box<dyn AsyncProxyResolver>> = vec![
+            Box::new(NoProxyResolver.into_async()),
+            Box::new(proxies().into_async()),
+        ];
+        let url = Url::parse("http://example.com").unwrap();
+        let decisions = resolvers
+            .iter()
+            .map(|resolver| block_on(LocalAsyncProxyResolver::decide(resolver, &url)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            decisions,
+            vec![
+                ProxyDecision::Unspecified,
+                ProxyDecision::Proxy(Url::parse("http://proxy:3128").unwrap())
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "lookup failed")]
+    fn propagates_panics() {
+        struct Panicking;
+
+        impl ProxyResolver for Panicking {
+            fn for_url(&self, _url: &Url) -> Option<Url> {
+                panic!("lookup failed")
+            }
+        }
+
+        Panicking
+            .into_async()
+            .into_blocking()
+            .for_url(&Url::parse("http://example.com").unwrap());
+    }
+}
@@ -0,0 +1,165 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Spread load across multiple static proxies.
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use url::Url;
+
+use super::{is_non_network_url, ProxyDecision, ProxyResolver};
+
+/// How a [`BalancingResolver`] selects among the proxies for a scheme.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Selection {
+    /// Rotate through the proxies in order, using each proxy as often as its weight in a row.
+    #[default]
+    RoundRobin,
+    /// Select a random proxy, with a probability proportional to its weight.
+    Random,
+}
+
+/// A static proxy with a weight.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct WeightedProxy {
+    proxy: Url,
+    weight: usize,
+}
+
+/// A resolver which spreads URLs across multiple static proxies per scheme.
+///
+/// Lets applications spread load across a farm of proxies, while still resolving proxies with
+/// [`ProxyResolver`].  Return [`ProxyDecision::Unspecified`] for URLs whose scheme has no
+/// proxies, and connect directly to [non-network URLs](super::is_non_network_url).
+#[derive(Debug, Default)]
+pub struct BalancingResolver {
+    selection: Selection,
+    proxies: HashMap<String, Vec<WeightedProxy>>,
+    next: AtomicUsize,
+}
+
+impl BalancingResolver {
+    /// Create a resolver without proxies, which selects proxies with `selection`.
+    pub fn new(selection: Selection) -> Self {
+        Self {
+            selection,
+            ..Self::default()
+        }
+    }
+
+    /// Add `proxy` for URLs with the given `scheme`, e.g. `https`.
+    pub fn with_proxy(self, scheme: &str, proxy: Url) -> Self {
+        self.with_weighted_proxy(scheme, proxy, 1)
+    }
+
+    /// Add `proxy` with `weight` for URLs with the given `scheme`.
+    ///
+    /// A proxy with twice the weight receives twice as many URLs.  Ignore proxies with a weight
+    /// of zero.
+    pub fn with_weighted_proxy(mut self, scheme: &str, proxy: Url, weight: usize) -> Self {
+        if 0 < weight {
+            self.proxies
+                .entry(scheme.to_ascii_lowercase())
+                .or_default()
+                .push(WeightedProxy { proxy, weight });
+        }
+        self
+    }
+
+    /// Get the selection strategy.
+    pub fn selection(&self) -> Selection {
+        self.selection
+    }
+
+    /// Select a proxy among `proxies`.
+    fn select<'a>(&self, proxies: &'a [WeightedProxy]) -> Option<&'a Url> {
+        let total = proxies.iter().map(|proxy| proxy.weight).sum::<usize>();
+        if total == 0 {
+            return None;
+        }
+        let mut position = match self.selection {
+            Selection::RoundRobin => self.next.fetch_add(1, Ordering::Relaxed) % total,
+            // A freshly seeded hasher is a good enough source of randomness to spread load.
+            Selection::Random => RandomState::new().build_hasher().finish() as usize % total,
+        };
+        proxies.iter().find_map(|proxy| {
+            if position < proxy.weight {
+                Some(&proxy.proxy)
+            } else {
+                position -= proxy.weight;
+                None
+            }
+        })
+    }
+}
+
+impl ProxyResolver for BalancingResolver {
+    fn for_url(&self, url: &Url) -> Option<Url> {
+        self.decide(url).into_proxy()
+    }
+
+    fn decide(&self, url: &Url) -> ProxyDecision {
+        if is_non_network_url(url) {
+            return ProxyDecision::Direct;
+        }
+        self.proxies
+            .get(url.scheme())
+            .and_then(|proxies| self.select(proxies))
+            .map_or(ProxyDecision::Unspecified, |proxy| {
+                ProxyDecision::Proxy(proxy.clone())
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn proxy(n: usize) -> Url {
+        Url::parse(&format!("http://proxy{n}:3128")).unwrap()
+    }
+
+    fn resolve(resolver: &BalancingResolver, count: usize) -> Vec<Url> {
+        let url = Url::parse("https://example.com").unwrap();
+        (0..count)
+            .map(|_| resolver.for_url(&url).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn round_robin() {
+        let resolver = BalancingResolver::new(Selection::RoundRobin)
+            .with_proxy("https", proxy(1))
+            .with_weighted_proxy("https", proxy(2), 2)
+            .with_weighted_proxy("https", proxy(3), 0);
+        assert_eq!(
+            resolve(&resolver, 6),
+            vec![proxy(1), proxy(2), proxy(2), proxy(1), proxy(2), proxy(2)]
+        );
+    }
+
+    #[test]
+    fn random() {
+        let resolver = BalancingResolver::new(Selection::Random)
+            .with_proxy("https", proxy(1))
+            .with_proxy("https", proxy(2));
+        let proxies = resolve(&resolver, 100);
+        assert!(proxies.iter().all(|p| *p == proxy(1) || *p == proxy(2)));
+    }
+
+    #[test]
+    fn schemes() {
+        let resolver = BalancingResolver::default().with_proxy("HTTP", proxy(1));
+        let decide = |url: &str| resolver.decide(&Url::parse(url).unwrap());
+        assert_eq!(decide("http://example.com"), ProxyDecision::Proxy(proxy(1)));
+        assert_eq!(decide("https://example.com"), ProxyDecision::Unspecified);
+        assert_eq!(decide("mailto:john@example.com"), ProxyDecision::Direct);
+    }
+}
@@ -0,0 +1,266 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Cache the decisions of a resolver.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use url::Url;
+
+use super::{CacheStats, DirectReason, ProxyDecision, ProxyResolver};
+
+/// A resolver which caches the decisions of another resolver per origin.
+///
+/// Cache decisions by scheme, host and port of URLs, because proxy configuration rarely
+/// depends on the path of a URL, and never cache URLs without a host.  All threads share the
+/// cache, so a single `Arc<CachingResolver<_>>` serves a multi-threaded application.
+///
+/// Decisions expire after the [`CachingResolver::DEFAULT_TTL`].  If the cache grows beyond its
+/// capacity drop expired decisions, and if that does not help, drop all decisions.
+#[derive(Debug)]
+pub struct CachingResolver<R> {
+    resolver: R,
+    ttl: Duration,
+    capacity: usize,
+    cache: RwLock<HashMap<String, (ProxyDecision, Instant)>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<R> CachingResolver<R> {
+    /// The default time to keep decisions.
+    pub const DEFAULT_TTL: Duration = Duration::from_secs(60);
+
+    /// The default maximum number of cached origins.
+    pub const DEFAULT_CAPACITY: usize = 1024;
+
+    /// Cache the decisions of `resolver`.
+    pub fn new(resolver: R) -> Self {
+        Self {
+            resolver,
+            ttl: Self::DEFAULT_TTL,
+            capacity: Self::DEFAULT_CAPACITY,
+            cache: RwLock::default(),
+            hits: AtomicU64::default(),
+            misses: AtomicU64::default(),
+        }
+    }
+
+    /// Keep decisions for `ttl`.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Cache decisions for at most `capacity` origins.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Get the wrapped resolver.
+    pub fn resolver(&self) -> &R {
+        &self.resolver
+    }
+
+    /// Drop all cached decisions, e.g. after the proxy configuration changed.
+    pub fn clear(&self) {
+        self.cache
+            .write()
+            .unwrap_or_else(|error| error.into_inner())
+            .clear();
+    }
+
+    /// Get the number of cache hits and misses so far.
+    ///
+    /// URLs without a host count as neither, because they always bypass the cache.
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Get the cached decision for `url`, unless expired, and count a hit.
+    ///
+    /// Never count a miss, because the caller asks the resolver through [`ProxyResolver::decide`]
+    /// which counts the miss.
+    pub(crate) fn cached_decision(&self, url: &Url) -> Option<ProxyDecision> {
+        let origin = url.origin();
+        if !origin.is_tuple() {
+            return None;
+        }
+        let decision = self.cached(&origin.ascii_serialization())?;
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(decision)
+    }
+
+    /// Get the cached decision for `origin`, unless expired.
+    fn cached(&self, origin: &str) -> Option<ProxyDecision> {
+        let cache = self.cache.read().unwrap_or_else(|error| error.into_inner());
+        cache
+            .get(origin)
+            .filter(|(_, cached_at)| cached_at.elapsed() < self.ttl)
+            .map(|(decision, _)| decision.clone())
+    }
+
+    /// Cache `decision` for `origin`.
+    fn insert(&self, origin: String, decision: ProxyDecision) {
+        let mut cache = self
+            .cache
+            .write()
+            .unwrap_or_else(|error| error.into_inner());
+        if self.capacity <= cache.len() {
+            cache.retain(|_, (_, cached_at)| cached_at.elapsed() < self.ttl);
+            if self.capacity <= cache.len() {
+                cache.clear();
+            }
+        }
+        if 0 < self.capacity {
+            cache.insert(origin, (decision, Instant::now()));
+        }
+    }
+}
+
+impl<R: ProxyResolver> ProxyResolver for CachingResolver<R> {
+    fn for_url(&self, url: &Url) -> Option<Url> {
+        self.decide(url).into_proxy()
+    }
+
+    fn decide(&self, url: &Url) -> ProxyDecision {
+        let origin = url.origin();
+        if !origin.is_tuple() {
+            return self.resolver.decide(url);
+        }
+        let origin = origin.ascii_serialization();
+        match self.cached(&origin) {
+            Some(decision) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                decision
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                let decision = self.resolver.decide(url);
+                self.insert(origin, decision.clone());
+                decision
+            }
+        }
+    }
+
+    /// Decide about all cached origins from the cache, and ask the wrapped resolver about all
+    /// other URLs at once.
+    fn decide_many(&self, urls: &[Url]) -> Vec<ProxyDecision> {
+        let mut decisions = urls
+            .iter()
+            .map(|url| self.cached_decision(url))
+            .collect::<Vec<_>>();
+        let missing = urls
+            .iter()
+            .zip(&decisions)
+            .filter(|(_, decision)| decision.is_none())
+            .map(|(url, _)| url.clone())
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            return decisions.into_iter().flatten().collect();
+        }
+        let mut fresh = self.resolver.decide_many(&missing).into_iter();
+        for (url, decision) in urls.iter().zip(&mut decisions) {
+            if decision.is_none() {
+                let fresh = fresh.next().unwrap_or(ProxyDecision::Unspecified);
+                let origin = url.origin();
+                if origin.is_tuple() {
+                    self.misses.fetch_add(1, Ordering::Relaxed);
+                    self.insert(origin.ascii_serialization(), fresh.clone());
+                }
+                *decision = Some(fresh);
+            }
+        }
+        decisions.into_iter().flatten().collect()
+    }
+
+    /// Ask the wrapped resolver for the reason, without caching.
+    fn direct_reason(&self, url: &Url) -> Option<DirectReason> {
+        self.resolver.direct_reason(url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct Counting(AtomicUsize);
+
+    impl ProxyResolver for Counting {
+        fn for_url(&self, _url: &Url) -> Option<Url> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Some(Url::parse("http://proxy:3128").unwrap())
+        }
+    }
+
+    fn lookups(resolver: &CachingResolver<Counting>, urls: &[&str]) -> usize {
+        for url in urls {
+            resolver.for_url(&Url::parse(url).unwrap());
+        }
+        resolver.resolver().0.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn caches_per_origin() {
+        let resolver = CachingResolver::new(Counting::default());
+        let urls = [
+            "http://example.com/foo",
+            "http://example.com/bar",
+            "https://example.com/foo",
+            "mailto:john@example.com",
+            "mailto:john@example.com",
+        ];
+        // Resolvers connect directly to non-network URLs without a lookup.
+        assert_eq!(lookups(&resolver, &urls), 2);
+        assert_eq!(resolver.stats(), CacheStats { hits: 1, misses: 2 });
+        resolver.clear();
+        assert_eq!(lookups(&resolver, &urls[..1]), 3);
+    }
+
+    #[test]
+    fn decides_many_from_cache() {
+        let resolver = CachingResolver::new(Counting::default());
+        assert_eq!(lookups(&resolver, &["http://example.com/"]), 1);
+        let urls = [
+            "http://example.com/foo",
+            "http://example.org/foo",
+            "http://example.org/bar",
+        ]
+        .map(|url| Url::parse(url).unwrap());
+        let proxy = ProxyDecision::Proxy(Url::parse("http://proxy:3128").unwrap());
+        assert_eq!(resolver.decide_many(&urls), vec![proxy; 3]);
+        assert_eq!(resolver.resolver().0.load(Ordering::SeqCst), 3);
+        assert_eq!(resolver.stats(), CacheStats { hits: 1, misses: 3 });
+    }
+
+    #[test]
+    fn expires_decisions() {
+        let resolver = CachingResolver::new(Counting::default()).with_ttl(Duration::ZERO);
+        let urls = ["http://example.com/foo", "http://example.com/bar"];
+        assert_eq!(lookups(&resolver, &urls), 2);
+    }
+
+    #[test]
+    fn respects_capacity() {
+        let resolver = CachingResolver::new(Counting::default()).with_capacity(1);
+        let urls = [
+            "http://example.com/",
+            "http://example.org/",
+            "http://example.com/",
+        ];
+        assert_eq!(lookups(&resolver, &urls), 3);
+    }
+}
@@ -0,0 +1,388 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Restrict which proxies resolvers may use.
+
+use std::fmt::{Display, Formatter};
+
+use url::Url;
+
+use super::{
+    is_non_network_url, DirectReason, ProxyDecision, ProxyResolver, ProxyScheme, ResolvedProxy,
+};
+use crate::env::{NoProxy, NoProxyRules};
+use crate::redact::redact;
+
+/// What to do instead of using a proxy which a [`ProxyAllowList`] does not allow.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum DisallowedProxyAction {
+    /// Connect directly.
+    #[default]
+    Direct,
+    /// Use the given proxy instead.
+    Replace(Url),
+}
+
+/// An allow-list of proxies which resolvers may use.
+///
+/// Guards against proxy auto-config scripts or WPAD results which redirect traffic to unknown
+/// proxies.  A proxy must match the allowed hosts, the allowed ports, and the allowed schemes;
+/// an empty list of hosts, ports or schemes allows any host, port or scheme respectively.  By
+/// default the allow-list thus allows every proxy.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ProxyAllowList {
+    hosts: Vec<String>,
+    ports: Vec<u16>,
+    schemes: Vec<ProxyScheme>,
+    disallowed: DisallowedProxyAction,
+}
+
+/// Strip brackets around IPv6 addresses from `host`, and make it lowercase.
+fn normalize_host(host: &str) -> String {
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_ascii_lowercase()
+}
+
+impl ProxyAllowList {
+    /// Allow proxies on `host`.
+    ///
+    /// If `host` starts with a `.` allow proxies on the domain and all its subdomains, e.g.
+    /// `.proxy.example.com` allows `proxy.example.com` and `eu.proxy.example.com`.  Otherwise
+    /// the host of the proxy must match `host` exactly.
+    pub fn with_host(mut self, host: &str) -> Self {
+        self.hosts.push(normalize_host(host));
+        self
+    }
+
+    /// Allow proxies on `port`.
+    ///
+    /// Proxies without explicit port use the [default port](ProxyScheme::default_port) of their
+    /// scheme.
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.ports.push(port);
+        self
+    }
+
+    /// Allow proxies of the given kind, e.g. only [`ProxyScheme::Https`] proxies.
+    pub fn with_scheme(mut self, scheme: ProxyScheme) -> Self {
+        self.schemes.push(scheme);
+        self
+    }
+
+    /// Set what to do instead of using a disallowed proxy.
+    ///
+    /// By default connect directly.
+    pub fn with_disallowed_action(mut self, action: DisallowedProxyAction) -> Self {
+        self.disallowed = action;
+        self
+    }
+
+    /// Whether this list allows `proxy`.
+    ///
+    /// Never allow proxies without a host.
+    pub fn allows(&self, proxy: &Url) -> bool {
+        let Some(proxy) = ResolvedProxy::new(proxy.clone()) else {
+            return false;
+        };
+        let host = normalize_host(proxy.host());
+        let host_allowed = self.hosts.is_empty()
+            || self
+                .hosts
+                .iter()
+                .any(|allowed| match allowed.strip_prefix('.') {
+                    Some(domain) => {
+                        host == domain
+                            || host
+                                .strip_suffix(allowed.as_str())
+                                .map_or(false, |subdomain| !subdomain.is_empty())
+                    }
+                    None => host == *allowed,
+                });
+        host_allowed
+            && (self.ports.is_empty() || self.ports.contains(&proxy.port()))
+            && (self.schemes.is_empty() || self.schemes.contains(&proxy.scheme()))
+    }
+
+    /// Decide how to connect to `url` with `resolver`, under this allow-list.
+    pub fn decide<R: ProxyResolver + ?Sized>(&self, resolver: &R, url: &Url) -> ProxyDecision {
+        match resolver.decide(url) {
+            ProxyDecision::Proxy(proxy) if !self.allows(&proxy) => {
+                warn!(
+                    "Refusing disallowed proxy {} for {}",
+                    redact(&proxy),
+                    url.origin().ascii_serialization()
+                );
+                match &self.disallowed {
+                    DisallowedProxyAction::Direct => ProxyDecision::Direct,
+                    DisallowedProxyAction::Replace(proxy) => ProxyDecision::Proxy(proxy.clone()),
+                }
+            }
+            decision => decision,
+        }
+    }
+}
+
+/// A resolver which only uses proxies from a [`ProxyAllowList`].
+#[derive(Debug, Clone)]
+pub struct PolicyResolver<R> {
+    allow_list: ProxyAllowList,
+    resolver: R,
+}
+
+impl<R> PolicyResolver<R> {
+    /// Only let `resolver` use proxies from `allow_list`.
+    pub fn new(allow_list: ProxyAllowList, resolver: R) -> Self {
+        Self {
+            allow_list,
+            resolver,
+        }
+    }
+
+    /// Get the allow-list.
+    pub fn allow_list(&self) -> &ProxyAllowList {
+        &self.allow_list
+    }
+
+    /// Get the wrapped resolver.
+    pub fn resolver(&self) -> &R {
+        &self.resolver
+    }
+}
+
+impl<R: ProxyResolver> ProxyResolver for PolicyResolver<R> {
+    fn for_url(&self, url: &Url) -> Option<Url> {
+        self.decide(url).into_proxy()
+    }
+
+    fn decide(&self, url: &Url) -> ProxyDecision {
+        self.allow_list.decide(&self.resolver, url)
+    }
+
+    fn direct_reason(&self, url: &Url) -> Option<DirectReason> {
+        match self.decide(url) {
+            ProxyDecision::Proxy(_) => None,
+            // The allow-list may refuse the proxy of the wrapped resolver
+            ProxyDecision::Direct => self
+                .resolver
+                .direct_reason(url)
+                .or(Some(DirectReason::DisallowedProxy)),
+            ProxyDecision::Unspecified => Some(DirectReason::NoProxyConfigured),
+        }
+    }
+}
+
+/// A resolver refused to connect directly to an external URL; see [`FailClosedResolver`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectConnectionError {
+    origin: String,
+}
+
+impl DirectConnectionError {
+    /// The origin of the refused URL, e.g. `https://example.com`.
+    pub fn origin(&self) -> &str {
+        &self.origin
+    }
+}
+
+impl Display for DirectConnectionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Refusing to connect directly to external URL {}",
+            self.origin
+        )
+    }
+}
+
+impl std::error::Error for DirectConnectionError {}
+
+/// A resolver which never connects directly to external hosts.
+///
+/// Locked-down networks often must never connect directly to the internet.  This resolver
+/// requires a proxy for every URL except internal hosts, which match a set of no proxy rules,
+/// and [non-network URLs](is_non_network_url), and fails if the wrapped resolver connects
+/// directly to any other URL.
+///
+/// This resolver deliberately does not implement [`ProxyResolver`], whose methods can't fail,
+/// and which clients take as direct connection if they return no proxy.  Use
+/// [`FailClosedResolver::try_decide`] or [`FailClosedResolver::try_for_url`] instead.
+#[derive(Debug, Clone)]
+pub struct FailClosedResolver<R> {
+    internal: NoProxyRules,
+    resolver: R,
+}
+
+impl<R> FailClosedResolver<R> {
+    /// Require a proxy from `resolver` for all hosts except those which match `internal`.
+    pub fn new(internal: NoProxyRules, resolver: R) -> Self {
+        Self { internal, resolver }
+    }
+
+    /// Get the rules for internal hosts.
+    pub fn internal(&self) -> &NoProxyRules {
+        &self.internal
+    }
+
+    /// Get the wrapped resolver.
+    pub fn resolver(&self) -> &R {
+        &self.resolver
+    }
+}
+
+impl<R: ProxyResolver> FailClosedResolver<R> {
+    /// Whether `url` may connect directly, i.e. is internal or not a network URL.
+    pub fn is_internal(&self, url: &Url) -> bool {
+        is_non_network_url(url) || self.internal.no_proxy_for(url)
+    }
+
+    /// Decide how to connect to `url`.
+    ///
+    /// Return the decision of the wrapped resolver for internal URLs, and the proxy of the
+    /// wrapped resolver for all other URLs.  Fail if the wrapped resolver has no proxy for an
+    /// external URL.
+    pub fn try_decide(&self, url: &Url) -> Result<ProxyDecision, DirectConnectionError> {
+        match self.resolver.decide(url) {
+            ProxyDecision::Proxy(proxy) => Ok(ProxyDecision::Proxy(proxy)),
+            decision if self.is_internal(url) => Ok(decision),
+            _ => {
+                warn!(
+                    "Refusing to connect directly to {}",
+                    url.origin().ascii_serialization()
+                );
+                Err(DirectConnectionError {
+                    origin: url.origin().ascii_serialization(),
+                })
+            }
+        }
+    }
+
+    /// Get the proxy for `url`, or `None` if `url` is internal and connects directly.
+    ///
+    /// Fail if the wrapped resolver has no proxy for an external URL.
+    pub fn try_for_url(&self, url: &Url) -> Result<Option<Url>, DirectConnectionError> {
+        self.try_decide(url).map(ProxyDecision::into_proxy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::EnvProxies;
+    use pretty_assertions::assert_eq;
+
+    fn allows(allow_list: &ProxyAllowList, proxy: &str) -> bool {
+        allow_list.allows(&Url::parse(proxy).unwrap())
+    }
+
+    #[test]
+    fn default_allows_everything() {
+        let allow_list = ProxyAllowList::default();
+        assert!(allows(&allow_list, "http://proxy.example.com:3128"));
+        assert!(allows(&allow_list, "socks5://[::1]:1080"));
+        assert!(!allows(&allow_list, "custom:proxy"));
+    }
+
+    #[test]
+    fn allowed_hosts() {
+        let allow_list = ProxyAllowList::default()
+            .with_host("Proxy.Example.com")
+            .with_host(".corp.example.com")
+            .with_host("[::1]");
+        assert!(allows(&allow_list, "http://proxy.example.com:3128"));
+        assert!(allows(&allow_list, "http://corp.example.com:3128"));
+        assert!(allows(&allow_list, "http://eu.corp.example.com:3128"));
+        assert!(allows(&allow_list, "http://[::1]:3128"));
+        assert!(!allows(&allow_list, "http://eu.proxy.example.com:3128"));
+        assert!(!allows(&allow_list, "http://evilcorp.example.com:3128"));
+        assert!(!allows(&allow_list, "http://attacker.example.org:3128"));
+    }
+
+    #[test]
+    fn allowed_ports_and_schemes() {
+        let allow_list = ProxyAllowList::default()
+            .with_port(3128)
+            .with_port(443)
+            .with_scheme(ProxyScheme::Https);
+        assert!(allows(&allow_list, "https://proxy:3128"));
+        assert!(allows(&allow_list, "https://proxy"));
+        assert!(!allows(&allow_list, "http://proxy:3128"));
+        assert!(!allows(&allow_list, "https://proxy:8080"));
+    }
+
+    #[test]
+    fn allowed_default_port() {
+        let allow_list = ProxyAllowList::default().with_port(80);
+        assert!(allows(&allow_list, "http://proxy:80"));
+        assert!(allows(&allow_list, "http://proxy"));
+        assert!(!allows(&allow_list, "socks5://proxy"));
+    }
+
+    #[test]
+    fn refuse_disallowed_proxy() {
+        let env = EnvProxies {
+            http: Some(Url::parse("http://attacker.example.org:3128").unwrap()),
+            https: Some(Url::parse("http://proxy.example.com:3128").unwrap()),
+            no_proxy_rules: None,
+        };
+        let allow_list = ProxyAllowList::default().with_host("proxy.example.com");
+        let resolver = PolicyResolver::new(allow_list.clone(), env.clone());
+        let http = Url::parse("http://example.com").unwrap();
+        let https = Url::parse("https://example.com").unwrap();
+        assert_eq!(resolver.decide(&http), ProxyDecision::Direct);
+        assert_eq!(
+            resolver.direct_reason(&http),
+            Some(DirectReason::DisallowedProxy)
+        );
+        assert_eq!(
+            resolver.decide(&https),
+            ProxyDecision::Proxy(Url::parse("http://proxy.example.com:3128").unwrap())
+        );
+
+        let replacement = Url::parse("http://proxy.example.com:8080").unwrap();
+        let resolver = PolicyResolver::new(
+            allow_list.with_disallowed_action(DisallowedProxyAction::Replace(replacement.clone())),
+            env,
+        );
+        assert_eq!(resolver.decide(&http), ProxyDecision::Proxy(replacement));
+    }
+
+    #[test]
+    fn fail_closed() {
+        let env = EnvProxies {
+            http: Some(Url::parse("http://proxy.example.com:3128").unwrap()),
+            https: None,
+            no_proxy_rules: Some(NoProxyRules::parse_curl_env(
+                "example.org,.corp.example.com",
+            )),
+        };
+        let resolver =
+            FailClosedResolver::new(NoProxyRules::parse_curl_env(".corp.example.com"), env);
+        let try_decide = |url: &str| resolver.try_decide(&Url::parse(url).unwrap());
+        assert_eq!(
+            try_decide("http://example.com"),
+            Ok(ProxyDecision::Proxy(
+                Url::parse("http://proxy.example.com:3128").unwrap()
+            ))
+        );
+        assert_eq!(
+            try_decide("http://wiki.corp.example.com"),
+            Ok(ProxyDecision::Direct)
+        );
+        assert_eq!(
+            try_decide("mailto:john@example.com"),
+            Ok(ProxyDecision::Direct)
+        );
+        let error = try_decide("http://example.org").unwrap_err();
+        assert_eq!(error.origin(), "http://example.org");
+        assert_eq!(
+            resolver.try_for_url(&Url::parse("https://example.com").unwrap()),
+            Err(DirectConnectionError {
+                origin: "https://example.com".to_string()
+            })
+        );
+    }
+}
@@ -0,0 +1,220 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Policies about which kind of proxy handles which traffic.
+
+use url::Url;
+
+use super::{DirectReason, ProxyDecision, ProxyResolver, ProxyScheme, ResolvedProxy};
+use crate::redact::redact;
+
+/// A policy about which kind of proxy handles which traffic.
+///
+/// By default the policy changes nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchemePolicy {
+    https_proxy_for_http: bool,
+    plaintext_proxy_for_https: bool,
+}
+
+impl Default for SchemePolicy {
+    fn default() -> Self {
+        Self {
+            https_proxy_for_http: false,
+            plaintext_proxy_for_https: true,
+        }
+    }
+}
+
+impl SchemePolicy {
+    /// Whether to use the proxy for `https` URLs for `http` URLs as well.
+    ///
+    /// If enabled, tunnel plain HTTP traffic through the proxy for HTTPS traffic, e.g. the
+    /// `$https_proxy`, instead of the proxy for HTTP traffic.
+    pub fn with_https_proxy_for_http(mut self, enabled: bool) -> Self {
+        self.https_proxy_for_http = enabled;
+        self
+    }
+
+    /// Whether to allow plaintext `http://` proxies for `https` URLs.
+    ///
+    /// Clients tunnel `https` traffic through `http://` proxies with `CONNECT`: TLS stays
+    /// end-to-end, but the `CONNECT` request exposes the target host and port in plaintext.
+    ///
+    /// If disallowed, and the resolver returns a `http://` proxy for a `https` URL, return
+    /// [`ProxyDecision::Unspecified`] instead, i.e. leave the decision to other resolvers or
+    /// the client.  This never silently turns a proxied connection into a direct one.
+    pub fn with_plaintext_proxy_for_https(mut self, allowed: bool) -> Self {
+        self.plaintext_proxy_for_https = allowed;
+        self
+    }
+
+    /// Decide how to connect to `url` with `resolver`, under this policy.
+    pub fn decide<R: ProxyResolver + ?Sized>(&self, resolver: &R, url: &Url) -> ProxyDecision {
+        let https_url = if self.https_proxy_for_http && url.scheme() == "http" {
+            https_url(url)
+        } else {
+            None
+        };
+        let decision = resolver.decide(https_url.as_ref().unwrap_or(url));
+        match decision {
+            ProxyDecision::Proxy(proxy)
+                if !self.plaintext_proxy_for_https
+                    && url.scheme() == "https"
+                    && ResolvedProxy::new(proxy.clone())
+                        .map_or(false, |proxy| proxy.scheme() == ProxyScheme::Http) =>
+            {
+                debug!(
+                    "Refusing plaintext proxy {} for {}",
+                    redact(&proxy),
+                    url.origin().ascii_serialization()
+                );
+                ProxyDecision::Unspecified
+            }
+            decision => decision,
+        }
+    }
+}
+
+/// Turn a `http` URL into a `https` URL to the same host and port.
+fn https_url(url: &Url) -> Option<Url> {
+    let port = url.port_or_known_default();
+    let mut https_url = url.clone();
+    https_url.set_scheme("https").ok()?;
+    https_url.set_port(port).ok()?;
+    Some(https_url)
+}
+
+/// A resolver which applies a [`SchemePolicy`] to another resolver.
+#[derive(Debug, Clone)]
+pub struct SchemePolicyResolver<R> {
+    policy: SchemePolicy,
+    resolver: R,
+}
+
+impl<R> SchemePolicyResolver<R> {
+    /// Apply `policy` to `resolver`.
+    pub fn new(policy: SchemePolicy, resolver: R) -> Self {
+        Self { policy, resolver }
+    }
+
+    /// Get the policy.
+    pub fn policy(&self) -> SchemePolicy {
+        self.policy
+    }
+
+    /// Get the wrapped resolver.
+    pub fn resolver(&self) -> &R {
+        &self.resolver
+    }
+}
+
+impl<R: ProxyResolver> ProxyResolver for SchemePolicyResolver<R> {
+    fn for_url(&self, url: &Url) -> Option<Url> {
+        self.decide(url).into_proxy()
+    }
+
+    fn decide(&self, url: &Url) -> ProxyDecision {
+        self.policy.decide(&self.resolver, url)
+    }
+
+    fn direct_reason(&self, url: &Url) -> Option<DirectReason> {
+        match self.decide(url) {
+            ProxyDecision::Proxy(_) => None,
+            ProxyDecision::Direct => self
+                .resolver
+                .direct_reason(url)
+                .or(Some(DirectReason::Resolver)),
+            ProxyDecision::Unspecified => match self.resolver.decide(url) {
+                // The policy refused the plaintext proxy of the wrapped resolver
+                ProxyDecision::Proxy(_) => Some(DirectReason::DisallowedProxy),
+                _ => Some(DirectReason::NoProxyConfigured),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::EnvProxies;
+    use pretty_assertions::assert_eq;
+
+    fn decide(policy: SchemePolicy, url: &str) -> ProxyDecision {
+        let env = EnvProxies {
+            http: Some(Url::parse("http://http-proxy:3128").unwrap()),
+            https: Some(Url::parse("http://https-proxy:3129").unwrap()),
+            no_proxy_rules: None,
+        };
+        SchemePolicyResolver::new(policy, env).decide(&Url::parse(url).unwrap())
+    }
+
+    fn proxy(url: &str) -> ProxyDecision {
+        ProxyDecision::Proxy(Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn default_changes_nothing() {
+        let policy = SchemePolicy::default();
+        assert_eq!(
+            decide(policy, "http://example.com"),
+            proxy("http://http-proxy:3128")
+        );
+        assert_eq!(
+            decide(policy, "https://example.com"),
+            proxy("http://https-proxy:3129")
+        );
+    }
+
+    #[test]
+    fn https_proxy_for_http() {
+        let policy = SchemePolicy::default().with_https_proxy_for_http(true);
+        assert_eq!(
+            decide(policy, "http://example.com"),
+            proxy("http://https-proxy:3129")
+        );
+        assert_eq!(
+            https_url(&Url::parse("http://example.com/foo").unwrap()).unwrap(),
+            Url::parse("https://example.com:80/foo").unwrap()
+        );
+    }
+
+    #[test]
+    fn refuse_plaintext_proxy_for_https() {
+        let policy = SchemePolicy::default()
+            .with_https_proxy_for_http(true)
+            .with_plaintext_proxy_for_https(false);
+        assert_eq!(
+            decide(policy, "https://example.com"),
+            ProxyDecision::Unspecified
+        );
+        assert_eq!(
+            decide(policy, "http://example.com"),
+            proxy("http://https-proxy:3129")
+        );
+    }
+
+    #[test]
+    fn refused_plaintext_proxy_reason() {
+        let env = EnvProxies {
+            http: None,
+            https: Some(Url::parse("http://https-proxy:3129").unwrap()),
+            no_proxy_rules: None,
+        };
+        let resolver = SchemePolicyResolver::new(
+            SchemePolicy::default().with_plaintext_proxy_for_https(false),
+            env,
+        );
+        assert_eq!(
+            resolver.direct_reason(&Url::parse("https://example.com").unwrap()),
+            Some(DirectReason::DisallowedProxy)
+        );
+        assert_eq!(
+            resolver.direct_reason(&Url::parse("http://example.com").unwrap()),
+            Some(DirectReason::NoProxyConfigured)
+        );
+    }
+}
@@ -0,0 +1,135 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Statistics about proxy resolution.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Statistics about the latency of lookups.
+///
+/// All durations are zero if there were no lookups yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LatencyStats {
+    /// The number of lookups.
+    pub count: u64,
+    /// The fastest lookup.
+    pub min: Duration,
+    /// The average duration of all lookups.
+    pub avg: Duration,
+    /// The 95th percentile of the most recent lookups.
+    pub p95: Duration,
+}
+
+/// Statistics about a [`CachingResolver`](super::CachingResolver).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    /// The number of lookups answered from the cache.
+    pub hits: u64,
+    /// The number of lookups passed to the wrapped resolver.
+    pub misses: u64,
+}
+
+impl CacheStats {
+    /// The share of lookups answered from the cache, between 0 and 1.
+    ///
+    /// Return 0 if there were no lookups yet.
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Samples {
+    count: u64,
+    min: Option<Duration>,
+    total: Duration,
+    recent: VecDeque<Duration>,
+}
+
+/// Record the latency of lookups.
+///
+/// Compute the percentile over the most recent lookups only, to bound memory.
+#[derive(Debug, Default)]
+pub(crate) struct LatencyRecorder {
+    samples: Mutex<Samples>,
+}
+
+impl LatencyRecorder {
+    /// The number of recent lookups to compute the percentile over.
+    const RECENT: usize = 1024;
+
+    /// Record a lookup which took `elapsed`.
+    pub(crate) fn record(&self, elapsed: Duration) {
+        let mut samples = self
+            .samples
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        samples.count += 1;
+        samples.min = Some(samples.min.map_or(elapsed, |min| min.min(elapsed)));
+        samples.total = samples.total.saturating_add(elapsed);
+        if Self::RECENT <= samples.recent.len() {
+            samples.recent.pop_front();
+        }
+        samples.recent.push_back(elapsed);
+    }
+
+    /// Get statistics about all recorded lookups.
+    pub(crate) fn stats(&self) -> LatencyStats {
+        let samples = self
+            .samples
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        if samples.count == 0 {
+            return LatencyStats::default();
+        }
+        let mut recent = samples.recent.iter().copied().collect::<Vec<_>>();
+        recent.sort_unstable();
+        let p95 = recent[(recent.len() * 95 + 99) / 100 - 1];
+        LatencyStats {
+            count: samples.count,
+            min: samples.min.unwrap_or_default(),
+            avg: Duration::from_nanos((samples.total.as_nanos() / samples.count as u128) as u64),
+            p95,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn latency_stats() {
+        let recorder = LatencyRecorder::default();
+        assert_eq!(recorder.stats(), LatencyStats::default());
+        for millis in (1..=100).rev() {
+            recorder.record(Duration::from_millis(millis));
+        }
+        assert_eq!(
+            recorder.stats(),
+            LatencyStats {
+                count: 100,
+                min: Duration::from_millis(1),
+                avg: Duration::from_micros(50500),
+                p95: Duration::from_millis(95),
+            }
+        );
+    }
+
+    #[test]
+    fn hit_rate() {
+        assert_eq!(CacheStats::default().hit_rate(), 0.0);
+        assert_eq!(CacheStats { hits: 3, misses: 1 }.hit_rate(), 0.75);
+    }
+}
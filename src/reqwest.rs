@@ -0,0 +1,221 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Configure reqwest clients with proxies.
+//!
+//! [`reqwest::Proxy::custom`] lets reqwest use any [`ProxyResolver`], but calls the resolver for
+//! every single request.  Static proxy configurations, i.e. configurations without proxy
+//! auto-config, translate to plain [`reqwest::Proxy::http`] and [`reqwest::Proxy::https`]
+//! proxies with [`reqwest::NoProxy`] rules instead, which reqwest evaluates itself.
+//!
+//! [`StaticProxies`] converts configurations to plain reqwest proxies, and [`proxies`] falls back
+//! to a custom proxy if the conversion fails.
+//!
+//! ```no_run
+//! let client = reqwest::blocking::Client::builder();
+//! let client = system_proxy::reqwest::proxies(system_proxy::env::from_curl_env())
+//!     .into_iter()
+//!     .fold(client, |client, proxy| client.proxy(proxy))
+//!     .build()
+//!     .unwrap();
+//! ```
+//!
+//! Requires the `reqwest` feature.
+
+use std::net::IpAddr;
+
+use ::reqwest::{NoProxy, Proxy};
+use url::Url;
+
+use crate::env::{EnvProxies, NoProxyRule, NoProxyRules, ReqwestEnvProxies};
+use crate::proxy::ProxyResolver;
+
+/// A proxy configuration which may translate to plain reqwest proxies.
+pub trait StaticProxies {
+    /// Convert this configuration to plain reqwest proxies.
+    ///
+    /// Return `None` if reqwest can't represent this configuration exactly, e.g. because it uses
+    /// proxy auto-config, or no proxy rules which [`reqwest::NoProxy`] lacks.  Return an empty
+    /// list if this configuration always connects directly.
+    fn to_reqwest_proxies(&self) -> Option<Vec<Proxy>>;
+}
+
+/// Get reqwest proxies for `config`.
+///
+/// Return the [plain reqwest proxies](StaticProxies::to_reqwest_proxies) of `config` if possible,
+/// or else a single [`reqwest::Proxy::custom`] proxy which resolves every request with `config`.
+pub fn proxies<R>(config: R) -> Vec<Proxy>
+where
+    R: StaticProxies + ProxyResolver + Send + Sync + 'static,
+{
+    config.to_reqwest_proxies().unwrap_or_else(|| {
+        debug!("Proxy configuration is not static, resolving proxies for every request");
+        vec![Proxy::custom(move |url| config.for_url(url))]
+    })
+}
+
+/// Translate curl no proxy `rules` to a reqwest no proxy list.
+///
+/// Return `None` if reqwest can't represent `rules` exactly.  reqwest always matches subdomains
+/// of host names, so only IP addresses, subnets, and subdomain rules translate.
+fn no_proxy_list(rules: &[NoProxyRule]) -> Option<String> {
+    let entries = rules
+        .iter()
+        .map(|rule| match rule {
+            NoProxyRule::MatchExact(host) => host
+                .parse::<IpAddr>()
+                .ok()
+                .filter(|address| address.to_string() == **host)
+                .map(|_| host.to_string()),
+            NoProxyRule::MatchSubdomain(subdomain) => Some(subdomain.to_string()),
+            NoProxyRule::MatchSimpleHostnames => None,
+            NoProxyRule::MatchCidr(network) => Some(network.to_string()),
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(entries.join(","))
+}
+
+/// Create plain reqwest proxies for `http` and `https` with `no_proxy` rules.
+///
+/// Return `None` if reqwest does not support either proxy.
+fn plain_proxies(
+    http: Option<&Url>,
+    https: Option<&Url>,
+    no_proxy: Option<NoProxy>,
+) -> Option<Vec<Proxy>> {
+    [
+        http.map(|url| Proxy::http(url.as_str())),
+        https.map(|url| Proxy::https(url.as_str())),
+    ]
+    .into_iter()
+    .flatten()
+    .map(|proxy| match proxy {
+        Ok(proxy) => Some(proxy.no_proxy(no_proxy.clone())),
+        Err(error) => {
+            debug!("reqwest does not support proxy: {error}");
+            None
+        }
+    })
+    .collect()
+}
+
+/// Create plain reqwest proxies for `http` and `https` with curl no proxy `rules`.
+fn curl_proxies(
+    http: Option<&Url>,
+    https: Option<&Url>,
+    rules: Option<&NoProxyRules>,
+) -> Option<Vec<Proxy>> {
+    let no_proxy = match rules {
+        None => None,
+        Some(NoProxyRules::All) => return Some(Vec::new()),
+        Some(NoProxyRules::Rules(rules)) => NoProxy::from_string(&no_proxy_list(rules)?),
+    };
+    plain_proxies(http, https, no_proxy)
+}
+
+impl StaticProxies for EnvProxies {
+    fn to_reqwest_proxies(&self) -> Option<Vec<Proxy>> {
+        curl_proxies(
+            self.http.as_ref(),
+            self.https.as_ref(),
+            self.no_proxy_rules.as_ref(),
+        )
+    }
+}
+
+impl StaticProxies for ReqwestEnvProxies {
+    fn to_reqwest_proxies(&self) -> Option<Vec<Proxy>> {
+        let no_proxy = self
+            .no_proxy
+            .as_ref()
+            .and_then(|rules| NoProxy::from_string(&rules.to_list()));
+        plain_proxies(self.http.as_ref(), self.https.as_ref(), no_proxy)
+    }
+}
+
+/// Requires Windows.
+#[cfg(windows)]
+impl StaticProxies for crate::windows::ConnectionSettings {
+    /// Return `None` if the connection uses a proxy auto-config script or automatic detection.
+    fn to_reqwest_proxies(&self) -> Option<Vec<Proxy>> {
+        if self.auto_config_url.is_some() || self.auto_detect {
+            None
+        } else if !self.proxy_enable {
+            Some(Vec::new())
+        } else {
+            let servers = self.proxy_servers();
+            let lookup = |scheme| {
+                servers
+                    .for_scheme(scheme)
+                    .or_else(|| servers.for_scheme("socks"))
+                    .or(servers.all())
+                    .map(|proxy| proxy.as_url())
+            };
+            curl_proxies(lookup("http"), lookup("https"), Some(&self.bypass_rules()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn env_proxies(no_proxy: &str) -> EnvProxies {
+        EnvProxies {
+            http: Some(Url::parse("http://proxy:3128").unwrap()),
+            https: Some(Url::parse("http://proxy:3128").unwrap()),
+            no_proxy_rules: Some(NoProxyRules::parse_curl_env(no_proxy)),
+        }
+    }
+
+    #[test]
+    fn no_proxy_list_of_subdomains_and_ips() {
+        let rules = NoProxyRules::parse_curl_env(".example.com,192.168.1.1,::1");
+        let NoProxyRules::Rules(rules) = rules else {
+            panic!("Expected rules");
+        };
+        assert_eq!(
+            no_proxy_list(&rules).as_deref(),
+            Some(".example.com,192.168.1.1,::1")
+        );
+    }
+
+    #[test]
+    fn no_proxy_list_of_exact_hosts() {
+        assert_eq!(
+            no_proxy_list(&[NoProxyRule::MatchExact("intranet".into())]),
+            None
+        );
+        assert_eq!(no_proxy_list(&[NoProxyRule::MatchSimpleHostnames]), None);
+    }
+
+    #[test]
+    fn no_proxy_list_of_networks() {
+        assert_eq!(
+            no_proxy_list(&[NoProxyRule::MatchCidr("10.0.0.0/8".parse().unwrap())]).as_deref(),
+            Some("10.0.0.0/8")
+        );
+    }
+
+    #[test]
+    fn env_proxies_to_reqwest_proxies() {
+        let proxies = env_proxies(".example.com").to_reqwest_proxies().unwrap();
+        assert_eq!(proxies.len(), 2);
+        assert!(env_proxies("intranet").to_reqwest_proxies().is_none());
+        assert!(env_proxies("*").to_reqwest_proxies().unwrap().is_empty());
+        assert!(EnvProxies::unset().to_reqwest_proxies().unwrap().is_empty());
+    }
+
+    #[test]
+    fn unsupported_proxy_scheme() {
+        let proxies = EnvProxies {
+            http: Some(Url::parse("socks4://proxy:1080").unwrap()),
+            ..EnvProxies::unset()
+        };
+        assert!(proxies.to_reqwest_proxies().is_none());
+    }
+}
@@ -0,0 +1,393 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Capture the proxy configuration of the system.
+//!
+//! [`SystemProxySnapshot::capture`] collects the raw settings every available backend sees into
+//! a single structure, for inclusion in bug reports.  [`SystemProxySnapshot::diff`] compares
+//! two snapshots, e.g. to tell what changed when connecting to a VPN.  With the `serde` feature snapshots
+//! implement `Serialize` and `Deserialize`.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use url::Url;
+
+/// Environment variables relevant for proxy configuration.
+const VARIABLES: [&str; 6] = [
+    "http_proxy",
+    "HTTP_PROXY",
+    "https_proxy",
+    "HTTPS_PROXY",
+    "no_proxy",
+    "NO_PROXY",
+];
+
+/// URLs to probe backends with.
+const PROBE_URLS: [&str; 2] = ["http://example.com/", "https://example.com/"];
+
+/// The answer of a backend for a probe URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Probe {
+    /// The name of the backend, e.g. `environment` or `portal`.
+    pub backend: String,
+    /// The URL the backend was asked for.
+    pub url: Url,
+    /// The proxy the backend returned, `None` for a direct connection, or the error message if
+    /// the lookup failed.
+    pub proxy: Result<Option<Url>, String>,
+}
+
+impl Probe {
+    fn new<E: std::fmt::Display>(backend: &str, url: Url, proxy: Result<Option<Url>, E>) -> Self {
+        Self {
+            backend: backend.to_string(),
+            url,
+            proxy: proxy.map_err(|error| error.to_string()),
+        }
+    }
+}
+
+/// A changed field between two snapshots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Change {
+    /// The path of the changed field, e.g. `environment.http_proxy` or
+    /// `probes.portal.http://example.com/`.
+    pub field: String,
+    /// The old value, or `None` if the field was absent.
+    pub old: Option<String>,
+    /// The new value, or `None` if the field is absent now.
+    pub new: Option<String>,
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let old = self.old.as_deref().unwrap_or("<unset>");
+        let new = self.new.as_deref().unwrap_or("<unset>");
+        write!(f, "{}: {old} -> {new}", self.field)
+    }
+}
+
+/// Insert the fields of `settings` under `prefix` into `fields`, or its error.
+#[cfg(any(feature = "gio", windows))]
+fn insert_settings<T, F>(
+    fields: &mut BTreeMap<String, String>,
+    prefix: &str,
+    settings: &Result<T, String>,
+    flatten: F,
+) where
+    F: Fn(&T) -> Vec<(&'static str, String)>,
+{
+    match settings {
+        Ok(settings) => {
+            for (name, value) in flatten(settings) {
+                fields.insert(format!("{prefix}.{name}"), value);
+            }
+        }
+        Err(error) => {
+            fields.insert(format!("{prefix}.error"), error.clone());
+        }
+    }
+}
+
+/// Flatten GNOME proxy `settings` into named fields.
+#[cfg(feature = "gio")]
+fn gnome_fields(settings: &crate::unix::GnomeProxySettings) -> Vec<(&'static str, String)> {
+    let server = |server: &Option<crate::unix::GnomeProxyServer>| {
+        server
+            .as_ref()
+            .map(|server| format!("{}:{}", server.host, server.port))
+            .unwrap_or_default()
+    };
+    vec![
+        ("mode", settings.mode.as_str().to_string()),
+        ("autoconfig_url", settings.autoconfig_url.clone()),
+        ("http", server(&settings.http)),
+        ("https", server(&settings.https)),
+        ("ftp", server(&settings.ftp)),
+        ("socks", server(&settings.socks)),
+        ("ignore_hosts", settings.ignore_hosts.join(", ")),
+    ]
+}
+
+/// Flatten Windows Internet `settings` into named fields.
+#[cfg(windows)]
+fn internet_settings_fields(
+    settings: &crate::windows::InternetSettings,
+) -> Vec<(&'static str, String)> {
+    vec![
+        ("proxy_enable", settings.proxy_enable.to_string()),
+        ("proxy_server", settings.proxy_server.clone()),
+        ("proxy_override", settings.proxy_override.clone()),
+        (
+            "auto_config_url",
+            settings.auto_config_url.clone().unwrap_or_default(),
+        ),
+    ]
+}
+
+/// A snapshot of the proxy configuration of the system.
+///
+/// Besides the proxy environment variables a snapshot contains the settings of all platform
+/// settings stores supported by this crate and enabled at build time, and the answers of all
+/// enabled backends for some probe URLs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SystemProxySnapshot {
+    /// The proxy environment variables by name; `None` for unset variables.
+    pub environment: BTreeMap<String, Option<String>>,
+    /// The GNOME proxy settings, or the error message if reading the settings failed.
+    ///
+    /// Requires the `gio` feature.
+    #[cfg(feature = "gio")]
+    pub gnome: Result<crate::unix::GnomeProxySettings, String>,
+    /// The Internet Settings of the current user, or the error message if reading the settings
+    /// failed.
+    #[cfg(windows)]
+    pub internet_settings: Result<crate::windows::InternetSettings, String>,
+    /// The answers of all enabled backends for the probe URLs.
+    pub probes: Vec<Probe>,
+}
+
+impl SystemProxySnapshot {
+    /// Capture the current proxy configuration.
+    ///
+    /// This function never fails; instead the snapshot records errors of individual backends.
+    /// However it may block the current thread while reading settings or probing the Gio
+    /// backend.
+    pub async fn capture() -> Self {
+        let environment = VARIABLES
+            .iter()
+            .map(|name| {
+                let value = std::env::var_os(name).map(|v| v.to_string_lossy().into_owned());
+                (name.to_string(), value)
+            })
+            .collect();
+
+        let urls = PROBE_URLS
+            .iter()
+            .map(|url| Url::parse(url).unwrap())
+            .collect::<Vec<_>>();
+        let env_proxies = crate::env::from_curl_env();
+        #[allow(unused_mut)]
+        let mut probes = urls
+            .iter()
+            .map(|url| {
+                let proxy = env_proxies.lookup(url).cloned();
+                Probe::new("environment", url.clone(), Ok::<_, String>(proxy))
+            })
+            .collect::<Vec<_>>();
+
+        #[cfg(feature = "portal")]
+        match crate::unix::FreedesktopPortalProxyResolver::connect().await {
+            Ok(resolver) => {
+                for url in &urls {
+                    probes.push(Probe::new(
+                        "portal",
+                        url.clone(),
+                        resolver.lookup(url).await,
+                    ));
+                }
+            }
+            Err(error) => {
+                for url in &urls {
+                    probes.push(Probe::new("portal", url.clone(), Err(&error)));
+                }
+            }
+        }
+
+        #[cfg(feature = "gio")]
+        {
+            let resolver = crate::unix::GioProxyResolver::default();
+            let supported = resolver.is_supported();
+            for url in &urls {
+                let proxy = if supported {
+                    resolver
+                        .lookup_blocking(url)
+                        .map_err(|error| error.to_string())
+                } else {
+                    Err("Gio proxy resolver not supported".to_string())
+                };
+                probes.push(Probe::new("gio", url.clone(), proxy));
+            }
+        }
+
+        Self {
+            environment,
+            #[cfg(feature = "gio")]
+            gnome: crate::unix::GnomeProxySettings::read().map_err(|error| error.to_string()),
+            #[cfg(windows)]
+            internet_settings: crate::windows::InternetSettings::read()
+                .map_err(|error| error.to_string()),
+            probes,
+        }
+    }
+}
+
+impl SystemProxySnapshot {
+    /// Flatten this snapshot into a map of field paths to values.
+    fn fields(&self) -> BTreeMap<String, String> {
+        let mut fields = BTreeMap::new();
+        for (name, value) in &self.environment {
+            if let Some(value) = value {
+                fields.insert(format!("environment.{name}"), value.clone());
+            }
+        }
+        #[cfg(feature = "gio")]
+        insert_settings(&mut fields, "gnome", &self.gnome, gnome_fields);
+        #[cfg(windows)]
+        insert_settings(
+            &mut fields,
+            "internet_settings",
+            &self.internet_settings,
+            internet_settings_fields,
+        );
+        for probe in &self.probes {
+            let value = match &probe.proxy {
+                Ok(Some(proxy)) => proxy.to_string(),
+                Ok(None) => "direct".to_string(),
+                Err(error) => format!("error: {error}"),
+            };
+            fields.insert(format!("probes.{}.{}", probe.backend, probe.url), value);
+        }
+        fields
+    }
+
+    /// Compare this snapshot to an `other`, newer snapshot.
+    ///
+    /// Return all fields which differ between both snapshots, ordered by field path.  Return an
+    /// empty list if both snapshots are equal.
+    pub fn diff(&self, other: &SystemProxySnapshot) -> Vec<Change> {
+        let mut old_fields = self.fields();
+        let mut changes = Vec::new();
+        for (field, new) in other.fields() {
+            let old = old_fields.remove(&field);
+            if old.as_ref() != Some(&new) {
+                changes.push(Change {
+                    field,
+                    old,
+                    new: Some(new),
+                });
+            }
+        }
+        changes.extend(old_fields.into_iter().map(|(field, old)| Change {
+            field,
+            old: Some(old),
+            new: None,
+        }));
+        changes.sort_by(|a, b| a.field.cmp(&b.field));
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn snapshot(
+        http_proxy: Option<&str>,
+        probe: Result<Option<&str>, &str>,
+    ) -> SystemProxySnapshot {
+        SystemProxySnapshot {
+            environment: vec![
+                (
+                    "http_proxy".to_string(),
+                    http_proxy.map(ToString::to_string),
+                ),
+                ("no_proxy".to_string(), Some("example.com".to_string())),
+            ]
+            .into_iter()
+            .collect(),
+            #[cfg(feature = "gio")]
+            gnome: Ok(Default::default()),
+            #[cfg(windows)]
+            internet_settings: Ok(Default::default()),
+            probes: vec![Probe {
+                backend: "portal".to_string(),
+                url: Url::parse("http://example.com/").unwrap(),
+                proxy: probe
+                    .map(|proxy| proxy.map(|proxy| Url::parse(proxy).unwrap()))
+                    .map_err(ToString::to_string),
+            }],
+        }
+    }
+
+    #[test]
+    fn diff_equal() {
+        let snapshot = snapshot(None, Ok(None));
+        assert_eq!(snapshot.diff(&snapshot.clone()), vec![]);
+    }
+
+    #[test]
+    fn diff_changed() {
+        let old = snapshot(None, Ok(Some("http://thehttpproxy:1234")));
+        let new = snapshot(Some("http://localhost:3128"), Err("portal not running"));
+        assert_eq!(
+            old.diff(&new),
+            vec![
+                Change {
+                    field: "environment.http_proxy".to_string(),
+                    old: None,
+                    new: Some("http://localhost:3128".to_string())
+                },
+                Change {
+                    field: "probes.portal.http://example.com/".to_string(),
+                    old: Some("http://thehttpproxy:1234/".to_string()),
+                    new: Some("error: portal not running".to_string())
+                }
+            ]
+        );
+        assert_eq!(
+            new.diff(&old)[0].to_string(),
+            "environment.http_proxy: http://localhost:3128 -> <unset>"
+        );
+    }
+
+    #[test]
+    fn capture_environment() {
+        temp_env::with_vars(
+            vec![
+                ("http_proxy", Some("http://thehttpproxy:1234")),
+                ("https_proxy", None),
+                ("no_proxy", None),
+                ("HTTP_PROXY", None),
+                ("HTTPS_PROXY", None),
+                ("NO_PROXY", None),
+            ],
+            || {
+                let snapshot = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .unwrap()
+                    .block_on(SystemProxySnapshot::capture());
+                assert_eq!(
+                    snapshot.environment.get("http_proxy"),
+                    Some(&Some("http://thehttpproxy:1234".to_string()))
+                );
+                assert_eq!(snapshot.environment.get("https_proxy"), Some(&None));
+                let env_probes = snapshot
+                    .probes
+                    .iter()
+                    .filter(|probe| probe.backend == "environment")
+                    .map(|probe| (probe.url.as_str(), probe.proxy.clone()))
+                    .collect::<Vec<_>>();
+                assert_eq!(
+                    env_probes,
+                    vec![
+                        (
+                            "http://example.com/",
+                            Ok(Some(Url::parse("http://thehttpproxy:1234").unwrap()))
+                        ),
+                        ("https://example.com/", Ok(None)),
+                    ]
+                );
+            },
+        )
+    }
+}
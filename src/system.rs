@@ -0,0 +1,899 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The best available proxy resolver of the current system.
+
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use url::Url;
+
+use crate::env::EnvProxies;
+use crate::global::Precedence;
+use crate::proxy::{
+    is_non_network_url, CacheStats, CachingResolver, DirectReason, ProxyDecision, ProxyResolver,
+};
+
+/// A system resolver which can move across threads.
+pub(crate) type BoxedResolver = Box<dyn ProxyResolver + Send + Sync>;
+
+/// The cached system resolver of a [`SystemProxyResolver`], shared with lookup threads.
+type SharedResolver = Arc<CachingResolver<Arc<BoxedResolver>>>;
+
+/// Look up proxies with the default Gio resolver, blocking the current thread.
+///
+/// Gio resolvers can't move across threads, so get the default resolver for every lookup.
+#[cfg(all(unix, not(target_os = "macos"), feature = "gio"))]
+#[derive(Debug)]
+struct DefaultGioResolver;
+
+#[cfg(all(unix, not(target_os = "macos"), feature = "gio"))]
+impl ProxyResolver for DefaultGioResolver {
+    fn for_url(&self, url: &Url) -> Option<Url> {
+        self.decide(url).into_proxy()
+    }
+
+    fn decide(&self, url: &Url) -> ProxyDecision {
+        match crate::unix::GioProxyResolver::default().lookup_blocking(url) {
+            Ok(Some(proxy)) => ProxyDecision::Proxy(proxy),
+            Ok(None) => ProxyDecision::Direct,
+            Err(error) => {
+                warn!(
+                    "Failed to lookup proxy for {} with Gio: {error}",
+                    crate::redact::redact(url)
+                );
+                ProxyDecision::Unspecified
+            }
+        }
+    }
+}
+
+/// A job for the thread of a [`PortalThreadResolver`].
+#[cfg(all(unix, not(target_os = "macos"), feature = "portal"))]
+enum PortalJob {
+    /// Probe whether the portal is available.
+    Probe(std::sync::mpsc::SyncSender<zbus::Result<Option<u32>>>),
+    /// Decide how to connect to the URL.
+    Decide(Url, std::sync::mpsc::SyncSender<ProxyDecision>),
+    /// Decide how to connect to all URLs, concurrently.
+    DecideMany(Vec<Url>, std::sync::mpsc::SyncSender<Vec<ProxyDecision>>),
+}
+
+/// Look up proxies on the proxy resolver portal, on a dedicated thread.
+///
+/// Drive the async portal resolver, with its timeout, on a thread of its own, so that lookups
+/// work from any thread, including the threads of an async runtime, and never wait longer than
+/// [`PortalThreadResolver::DEADLINE`] for the portal.  The thread exits when the resolver is
+/// dropped.
+#[cfg(all(unix, not(target_os = "macos"), feature = "portal"))]
+struct PortalThreadResolver {
+    jobs: std::sync::Mutex<std::sync::mpsc::Sender<PortalJob>>,
+}
+
+#[cfg(all(unix, not(target_os = "macos"), feature = "portal"))]
+impl PortalThreadResolver {
+    /// The timeout for every call to the portal.
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// How long to wait for a lookup, including a retry after a transient error.
+    const DEADLINE: Duration = Duration::from_secs(12);
+
+    /// Start the thread.
+    fn spawn() -> std::io::Result<Self> {
+        let (jobs, receiver) = std::sync::mpsc::channel();
+        std::thread::Builder::new()
+            .name("system_proxy portal".into())
+            .spawn(move || Self::run(receiver))?;
+        Ok(Self {
+            jobs: std::sync::Mutex::new(jobs),
+        })
+    }
+
+    /// Run all `jobs` one after another, until the resolver is dropped.
+    fn run(jobs: std::sync::mpsc::Receiver<PortalJob>) {
+        use crate::proxy::AsyncProxyResolver;
+        use crate::unix::FreedesktopPortalProxyResolver;
+
+        let resolver = FreedesktopPortalProxyResolver::lazy()
+            .with_timeout(Self::TIMEOUT)
+            .with_retries(1, FreedesktopPortalProxyResolver::DEFAULT_BACKOFF);
+        // With the tokio feature zbus needs a tokio runtime to connect to the bus.
+        #[cfg(feature = "tokio")]
+        let runtime = match tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+        {
+            Ok(runtime) => runtime,
+            Err(error) => {
+                warn!("Failed to start runtime for proxy resolver portal: {error}");
+                return;
+            }
+        };
+        for job in jobs {
+            let run = async {
+                // The caller may have stopped waiting, so ignore failures to reply.
+                match job {
+                    PortalJob::Probe(reply) => {
+                        let _ = reply.send(resolver.probe().await);
+                    }
+                    PortalJob::Decide(url, reply) => {
+                        let _ = reply.send(AsyncProxyResolver::decide(&resolver, &url).await);
+                    }
+                    PortalJob::DecideMany(urls, reply) => {
+                        let decisions = urls
+                            .iter()
+                            .map(|url| AsyncProxyResolver::decide(&resolver, url))
+                            .collect();
+                        let _ = reply.send(crate::unix::join::join_all(decisions).await);
+                    }
+                }
+            };
+            #[cfg(feature = "tokio")]
+            runtime.block_on(run);
+            #[cfg(not(feature = "tokio"))]
+            async_io::block_on(run);
+        }
+    }
+
+    /// Send the `job` to the thread, and wait for its reply until `deadline`.
+    ///
+    /// Return `None` if the thread didn't reply in time.
+    fn request<T, F>(&self, job: F, deadline: Instant) -> Option<T>
+    where
+        F: FnOnce(std::sync::mpsc::SyncSender<T>) -> PortalJob,
+    {
+        let (reply, receiver) = std::sync::mpsc::sync_channel(1);
+        self.jobs
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .send(job(reply))
+            .ok()?;
+        receiver
+            .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            .ok()
+    }
+
+    /// Probe whether the portal is available.
+    ///
+    /// See [`crate::unix::FreedesktopPortalProxyResolver::probe`].
+    fn probe(&self) -> zbus::Result<Option<u32>> {
+        self.request(PortalJob::Probe, Instant::now() + Self::DEADLINE)
+            .unwrap_or_else(|| {
+                Err(zbus::Error::InputOutput(std::sync::Arc::new(
+                    std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "Proxy resolver portal did not reply in time",
+                    ),
+                )))
+            })
+    }
+
+    /// Decide how to connect to `url`, but give up at `deadline`.
+    fn decide_until(&self, url: &Url, deadline: Instant) -> ProxyDecision {
+        self.request(|reply| PortalJob::Decide(url.clone(), reply), deadline)
+            .unwrap_or_else(|| {
+                warn!(
+                    "Proxy resolver portal did not reply in time for {}",
+                    crate::redact::redact(url)
+                );
+                ProxyDecision::Unspecified
+            })
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos"), feature = "portal"))]
+impl ProxyResolver for PortalThreadResolver {
+    fn for_url(&self, url: &Url) -> Option<Url> {
+        self.decide(url).into_proxy()
+    }
+
+    fn decide(&self, url: &Url) -> ProxyDecision {
+        self.decide_until(url, Instant::now() + Self::DEADLINE)
+    }
+
+    /// Ask the portal about all `urls` concurrently.
+    fn decide_many(&self, urls: &[Url]) -> Vec<ProxyDecision> {
+        let deadline = Instant::now() + Self::DEADLINE;
+        self.request(
+            |reply| PortalJob::DecideMany(urls.to_vec(), reply),
+            deadline,
+        )
+        .unwrap_or_else(|| {
+            warn!(
+                "Proxy resolver portal did not reply in time for {} URLs",
+                urls.len()
+            );
+            vec![ProxyDecision::Unspecified; urls.len()]
+        })
+    }
+}
+
+/// Get the best available resolver of the current platform, and its name.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub(crate) fn platform_resolver() -> Option<(&'static str, BoxedResolver)> {
+    #[cfg(feature = "portal")]
+    match PortalThreadResolver::spawn()
+        .map_err(zbus::Error::from)
+        .and_then(|resolver| Ok(resolver.probe()?.map(|_| resolver)))
+    {
+        Ok(Some(resolver)) => return Some(("portal", Box::new(resolver))),
+        Ok(None) => debug!("Not using proxy resolver portal: Portal not available"),
+        Err(error) => debug!("Not using proxy resolver portal: {error}"),
+    }
+    #[cfg(feature = "gio")]
+    if crate::unix::GioProxyResolver::default().is_supported() {
+        return Some(("gio", Box::new(DefaultGioResolver)));
+    }
+    None
+}
+
+/// Get the best available resolver of the current platform, and its name.
+#[cfg(target_os = "macos")]
+pub(crate) fn platform_resolver() -> Option<(&'static str, BoxedResolver)> {
+    match crate::macos::ProxySettings::read() {
+        Ok(settings) => Some(("scutil", Box::new(settings))),
+        Err(error) => {
+            warn!("Failed to read proxy settings with scutil: {error}");
+            None
+        }
+    }
+}
+
+/// Get the best available resolver of the current platform, and its name.
+#[cfg(windows)]
+pub(crate) fn platform_resolver() -> Option<(&'static str, BoxedResolver)> {
+    match crate::windows::WinHttpProxyResolver::new() {
+        Ok(resolver) => return Some(("winhttp", Box::new(resolver))),
+        Err(error) => warn!("Failed to open WinHttp session: {error}"),
+    }
+    match crate::windows::ConnectionSettings::read("DefaultConnectionSettings") {
+        Ok(Some(settings)) => Some(("internet settings", Box::new(settings))),
+        Ok(None) => None,
+        Err(error) => {
+            warn!("Failed to read Internet Settings: {error}");
+            None
+        }
+    }
+}
+
+/// Get the best available resolver of the current platform, and its name.
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn platform_resolver() -> Option<(&'static str, BoxedResolver)> {
+    None
+}
+
+/// The proxy resolver of the current system.
+///
+/// Consult the curl environment variables first, like curl does, and then the best available
+/// system resolver, unless configured [otherwise](SystemProxyResolver::with_precedence):
+///
+/// - On Linux and the BSDs the Freedesktop proxy resolver portal with the `portal` feature, if
+///   the portal implements the proxy resolver, or else the default Gio resolver with the `gio`
+///   feature.  Query the portal on a dedicated thread, with a timeout, so that lookups work from
+///   async code as well, and fall through to the next source if the portal does not reply in
+///   time.
+/// - On macOS the settings of `scutil --proxy`, read once when created.
+/// - On Windows WinHttp, including proxy auto-config, or else the Internet Settings of the LAN
+///   connection, read once when created.
+///
+/// Without any system resolver only consult the environment.  Connect directly to
+/// [non-network URLs](is_non_network_url).
+///
+/// Cache the decisions of the system resolver per origin like [`CachingResolver`], for
+/// [`CachingResolver::DEFAULT_TTL`] unless configured [otherwise](Self::with_cache_ttl), so that
+/// a single `Arc<SystemProxyResolver>` serves a multi-threaded application without another cache.
+///
+/// See [`default`](crate::default) for a one-call entry point.
+pub struct SystemProxyResolver {
+    env: EnvProxies,
+    system: Option<(&'static str, SharedResolver)>,
+    precedence: Precedence,
+}
+
+/// A source of a [`SystemProxyResolver`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Source {
+    /// The curl environment variables.
+    Env,
+    /// The system resolver.
+    System,
+}
+
+impl SystemProxyResolver {
+    /// Combine the curl environment with the given `system` resolver.
+    pub fn new<R: ProxyResolver + Send + Sync + 'static>(system: R) -> Self {
+        Self::from_parts(
+            EnvProxies::from_curl_env(),
+            Some(("custom", Box::new(system))),
+        )
+    }
+
+    /// Combine `env` with the named `system` resolver, if any.
+    pub(crate) fn from_parts(
+        env: EnvProxies,
+        system: Option<(&'static str, BoxedResolver)>,
+    ) -> Self {
+        Self {
+            env,
+            system: system
+                .map(|(name, resolver)| (name, Arc::new(CachingResolver::new(Arc::new(resolver))))),
+            precedence: Precedence::default(),
+        }
+    }
+
+    /// Cache decisions of the system resolver for `ttl`, or do not cache at all if `None`.
+    pub fn with_cache_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.system = self.system.map(|(name, resolver)| {
+            let resolver = CachingResolver::new(resolver.resolver().clone());
+            let resolver = match ttl {
+                Some(ttl) => resolver
+                    .with_ttl(ttl)
+                    .with_capacity(CachingResolver::<()>::DEFAULT_CAPACITY),
+                None => resolver.with_capacity(0),
+            };
+            (name, Arc::new(resolver))
+        });
+        self
+    }
+
+    /// Drop all cached decisions, e.g. after the proxy configuration changed.
+    pub fn clear_cache(&self) {
+        if let Some((_, resolver)) = &self.system {
+            resolver.clear();
+        }
+    }
+
+    /// Get the number of cache hits and misses of the system resolver so far.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.system
+            .as_ref()
+            .map_or_else(CacheStats::default, |(_, resolver)| resolver.stats())
+    }
+
+    /// Set which of the curl environment variables and the system resolver takes precedence.
+    pub fn with_precedence(mut self, precedence: Precedence) -> Self {
+        self.precedence = precedence;
+        self
+    }
+
+    /// Get which of the curl environment variables and the system resolver takes precedence.
+    pub fn precedence(&self) -> Precedence {
+        self.precedence
+    }
+
+    /// Get the proxies from the curl environment.
+    pub fn env(&self) -> &EnvProxies {
+        &self.env
+    }
+
+    /// Get the name of the system resolver, e.g. `portal`, or `None` if there is none.
+    pub fn system_name(&self) -> Option<&'static str> {
+        self.system.as_ref().map(|(name, _)| *name)
+    }
+
+    /// Get all sources with their resolvers, in order of precedence.
+    fn sources(&self) -> impl Iterator<Item = (Source, &dyn ProxyResolver)> {
+        let env = Some((Source::Env, &self.env as &dyn ProxyResolver));
+        let system = self
+            .system
+            .as_ref()
+            .map(|(_, resolver)| (Source::System, &**resolver as &dyn ProxyResolver));
+        let (first, second) = match self.precedence {
+            Precedence::EnvFirst => (env, system),
+            Precedence::SystemFirst => (system, env),
+            Precedence::EnvOnly => (env, None),
+            Precedence::SystemOnly => (system, None),
+        };
+        [first, second].into_iter().flatten()
+    }
+
+    /// Get the first resolver which decides with `decide`, and its decision.
+    ///
+    /// Consult the sources in order of precedence, and let `decide` ask each source, e.g. to
+    /// record the latency of each source.
+    pub(crate) fn source_with<F>(
+        &self,
+        mut decide: F,
+    ) -> Option<(&dyn ProxyResolver, ProxyDecision)>
+    where
+        F: FnMut(Source, &dyn ProxyResolver) -> ProxyDecision,
+    {
+        self.sources()
+            .map(|(source, resolver)| (resolver, decide(source, resolver)))
+            .find(|(_, decision)| decision.is_specified())
+    }
+
+    /// Get the first resolver which decides about `url`, and its decision.
+    fn source(&self, url: &Url) -> Option<(&dyn ProxyResolver, ProxyDecision)> {
+        self.source_with(|_, resolver| resolver.decide(url))
+    }
+
+    /// Ask the system resolver about `url` on a separate thread, but give up at `deadline`.
+    ///
+    /// Return a cached decision right away.  If the system resolver does not decide before
+    /// `deadline` return [`ProxyDecision::Unspecified`], and let the thread cache the decision
+    /// once the system resolver returns.
+    fn system_decide_until(&self, url: &Url, deadline: Instant) -> ProxyDecision {
+        let Some((name, resolver)) = &self.system else {
+            return ProxyDecision::Unspecified;
+        };
+        if let Some(decision) = resolver.cached_decision(url) {
+            return decision;
+        }
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        if timeout.is_zero() {
+            debug!(
+                "Deadline passed before asking {name} for {}",
+                crate::redact::redact(url)
+            );
+            return ProxyDecision::Unspecified;
+        }
+        let (reply, decision) = std::sync::mpsc::sync_channel(1);
+        let lookup = {
+            let resolver = resolver.clone();
+            let url = url.clone();
+            // The caller may have stopped waiting, so ignore failures to reply.
+            move || drop(reply.send(resolver.decide(&url)))
+        };
+        if let Err(error) = std::thread::Builder::new()
+            .name("system_proxy lookup".to_string())
+            .spawn(lookup)
+        {
+            warn!("Failed to start thread to ask {name} for a proxy: {error}");
+            return ProxyDecision::Unspecified;
+        }
+        decision.recv_timeout(timeout).unwrap_or_else(|_| {
+            warn!(
+                "System resolver {name} did not decide in time for {}",
+                crate::redact::redact(url)
+            );
+            ProxyDecision::Unspecified
+        })
+    }
+
+    /// Get the proxy to use for `url`, but give up at `deadline`.
+    ///
+    /// Like [`ProxyResolver::for_url`], but bound the total time of the lookup: Consult the
+    /// sources in order of precedence, and give every source the time which remains until
+    /// `deadline`.  Ask the system resolver on a separate thread, whether it queries the portal,
+    /// Gio, or WinHttp, or evaluates a proxy auto-config script; if it does not decide in time
+    /// consult the next source, and connect directly if no source decided.  The system resolver
+    /// still completes the lookup in the background, until it gives up by itself, and caches its
+    /// decision for later lookups.
+    ///
+    /// Return `None` for a direct connection, or if no source decided before `deadline`.
+    pub fn lookup_with_deadline(&self, url: &Url, deadline: Instant) -> Option<Url> {
+        if is_non_network_url(url) {
+            return None;
+        }
+        self.source_with(|source, resolver| match source {
+            Source::Env => resolver.decide(url),
+            Source::System => self.system_decide_until(url, deadline),
+        })
+        .and_then(|(_, decision)| decision.into_proxy())
+    }
+
+    /// Get the proxies to use for all `urls`, in the order of `urls`.
+    ///
+    /// Like [`ProxyResolver::for_url`] for every URL, but ask every source about all URLs it
+    /// shall decide about at once: The system resolver answers cached origins from its cache,
+    /// and asks the portal about all other URLs concurrently, or evaluates a proxy auto-config
+    /// script only once for all URLs.
+    ///
+    /// Return `None` for every URL to connect to directly.
+    pub fn lookup_many(&self, urls: &[Url]) -> Vec<Option<Url>> {
+        self.decide_many(urls)
+            .into_iter()
+            .map(ProxyDecision::into_proxy)
+            .collect()
+    }
+
+    /// Get the proxies of this resolver as curl environment proxies.
+    ///
+    /// Lookup the proxies for `http://example.com/` and `https://example.com/`, and take the
+    /// no-proxy rules from the curl environment if it takes precedence.  System resolvers don't
+    /// expose their configuration, so the result has no rules for hosts which the system
+    /// resolver connects to directly, and no proxy auto-config logic.
+    pub fn to_env_proxies(&self) -> EnvProxies {
+        let urls = [
+            Url::parse("http://example.com/").unwrap(),
+            Url::parse("https://example.com/").unwrap(),
+        ];
+        let [http, https]: [Option<Url>; 2] = self.lookup_many(&urls).try_into().unwrap();
+        let no_proxy_rules = match self.precedence {
+            Precedence::EnvFirst | Precedence::EnvOnly => self.env.no_proxy_rules.clone(),
+            Precedence::SystemFirst | Precedence::SystemOnly => None,
+        };
+        EnvProxies {
+            http,
+            https,
+            no_proxy_rules,
+        }
+    }
+
+    /// Set the curl environment variables of the current process to the proxies of this resolver.
+    ///
+    /// See [`SystemProxyResolver::to_env_proxies`] and [`EnvProxies::apply_to_env`]; the latter
+    /// explains why to call this function early in `main`, before starting any threads.
+    pub fn export_env(&self) {
+        self.to_env_proxies().apply_to_env();
+    }
+}
+
+impl Default for SystemProxyResolver {
+    /// Combine the curl environment with the best available system resolver.
+    fn default() -> Self {
+        let system = platform_resolver();
+        match &system {
+            Some((name, _)) => debug!("Using system proxy resolver {name}"),
+            None => debug!("No system proxy resolver available"),
+        }
+        Self::from_parts(EnvProxies::from_curl_env(), system)
+    }
+}
+
+impl Debug for SystemProxyResolver {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SystemProxyResolver")
+            .field("env", &self.env)
+            .field("system", &self.system_name())
+            .field("precedence", &self.precedence)
+            .finish()
+    }
+}
+
+impl ProxyResolver for SystemProxyResolver {
+    fn for_url(&self, url: &Url) -> Option<Url> {
+        self.decide(url).into_proxy()
+    }
+
+    fn decide(&self, url: &Url) -> ProxyDecision {
+        if is_non_network_url(url) {
+            ProxyDecision::Direct
+        } else {
+            self.source(url)
+                .map_or(ProxyDecision::Unspecified, |(_, decision)| decision)
+        }
+    }
+
+    /// Ask every source about all URLs which the previous sources did not decide about.
+    fn decide_many(&self, urls: &[Url]) -> Vec<ProxyDecision> {
+        let mut decisions = urls
+            .iter()
+            .map(|url| {
+                if is_non_network_url(url) {
+                    ProxyDecision::Direct
+                } else {
+                    ProxyDecision::Unspecified
+                }
+            })
+            .collect::<Vec<_>>();
+        for (_, resolver) in self.sources() {
+            let (pending, pending_urls): (Vec<_>, Vec<_>) = urls
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| !decisions[*index].is_specified())
+                .map(|(index, url)| (index, url.clone()))
+                .unzip();
+            if pending.is_empty() {
+                break;
+            }
+            for (index, decision) in pending.into_iter().zip(resolver.decide_many(&pending_urls)) {
+                decisions[index] = decision;
+            }
+        }
+        decisions
+    }
+
+    /// Ask the resolver which decided about `url` for the reason.
+    fn direct_reason(&self, url: &Url) -> Option<DirectReason> {
+        if is_non_network_url(url) {
+            return Some(DirectReason::NonNetworkUrl);
+        }
+        match self.source(url) {
+            None => Some(DirectReason::NoProxyConfigured),
+            Some((_, ProxyDecision::Proxy(_))) => None,
+            Some((source, _)) => source.direct_reason(url),
+        }
+    }
+}
+
+/// Get the proxy resolver of the current system.
+///
+/// Combine the curl environment with the best available system resolver; see
+/// [`SystemProxyResolver`].  Unlike the [global resolver](crate::resolver) create a new resolver
+/// on every call.
+pub fn default() -> SystemProxyResolver {
+    SystemProxyResolver::default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::{NoProxyRule, NoProxyRules};
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn resolver(env: EnvProxies) -> SystemProxyResolver {
+        SystemProxyResolver::from_parts(
+            env,
+            Some((
+                "test",
+                Box::new(EnvProxies {
+                    http: Some(Url::parse("http://system-proxy:3128").unwrap()),
+                    https: Some(Url::parse("http://system-proxy:3128").unwrap()),
+                    no_proxy_rules: None,
+                }),
+            )),
+        )
+    }
+
+    #[test]
+    fn env_first() {
+        let resolver = resolver(EnvProxies {
+            http: Some(Url::parse("http://env-proxy:3128").unwrap()),
+            https: None,
+            no_proxy_rules: Some(NoProxyRules::Rules(vec![NoProxyRule::MatchExact(
+                "internal.example.com".into(),
+            )])),
+        });
+        let decide = |url: &str| resolver.decide(&Url::parse(url).unwrap());
+        assert_eq!(
+            decide("http://example.com"),
+            ProxyDecision::Proxy(Url::parse("http://env-proxy:3128").unwrap())
+        );
+        assert_eq!(
+            decide("https://example.com"),
+            ProxyDecision::Proxy(Url::parse("http://system-proxy:3128").unwrap())
+        );
+        assert_eq!(decide("http://internal.example.com"), ProxyDecision::Direct);
+        assert_eq!(decide("mailto:john@example.com"), ProxyDecision::Direct);
+        assert_eq!(
+            resolver.direct_reason(&Url::parse("http://internal.example.com").unwrap()),
+            Some(DirectReason::NoProxyRule("internal.example.com".into()))
+        );
+    }
+
+    #[test]
+    fn without_system() {
+        let resolver = SystemProxyResolver::from_parts(EnvProxies::unset(), None);
+        let url = Url::parse("http://example.com").unwrap();
+        assert_eq!(resolver.decide(&url), ProxyDecision::Unspecified);
+        assert_eq!(
+            resolver.direct_reason(&url),
+            Some(DirectReason::NoProxyConfigured)
+        );
+        assert_eq!(resolver.system_name(), None);
+    }
+
+    #[test]
+    fn precedence() {
+        let env = || EnvProxies {
+            http: Some(Url::parse("http://env-proxy:3128").unwrap()),
+            ..EnvProxies::unset()
+        };
+        let decide = |precedence, url: &str| {
+            resolver(env())
+                .with_precedence(precedence)
+                .decide(&Url::parse(url).unwrap())
+        };
+        let env_proxy = ProxyDecision::Proxy(Url::parse("http://env-proxy:3128").unwrap());
+        let system_proxy = ProxyDecision::Proxy(Url::parse("http://system-proxy:3128").unwrap());
+        assert_eq!(
+            decide(Precedence::EnvFirst, "http://example.com"),
+            env_proxy
+        );
+        assert_eq!(
+            decide(Precedence::SystemFirst, "http://example.com"),
+            system_proxy
+        );
+        assert_eq!(
+            decide(Precedence::EnvOnly, "https://example.com"),
+            ProxyDecision::Unspecified
+        );
+        assert_eq!(
+            decide(Precedence::SystemOnly, "http://example.com"),
+            system_proxy
+        );
+    }
+
+    #[cfg(all(unix, not(target_os = "macos"), feature = "portal"))]
+    #[test]
+    fn portal_thread_in_async_runtime() {
+        let resolver = PortalThreadResolver::spawn().unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let url = Url::parse("http://example.com").unwrap();
+        // There may be no portal at all; the lookup must neither panic nor miss its deadline.
+        let start = Instant::now();
+        runtime.block_on(async {
+            resolver.decide_until(&url, Instant::now() + Duration::from_millis(500))
+        });
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn caches_system_decisions() {
+        #[derive(Default)]
+        struct Counting(Arc<AtomicUsize>);
+
+        impl ProxyResolver for Counting {
+            fn for_url(&self, _url: &Url) -> Option<Url> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Some(Url::parse("http://system-proxy:3128").unwrap())
+            }
+        }
+
+        let lookups = |resolver: SystemProxyResolver, count: &AtomicUsize| {
+            for url in ["http://example.com/foo", "http://example.com/bar"] {
+                resolver.decide(&Url::parse(url).unwrap());
+            }
+            count.load(Ordering::SeqCst)
+        };
+        let count = Arc::new(AtomicUsize::default());
+        let resolver = || {
+            SystemProxyResolver::from_parts(
+                EnvProxies::unset(),
+                Some(("test", Box::new(Counting(count.clone())))),
+            )
+        };
+        assert_eq!(lookups(resolver(), &count), 1);
+        assert_eq!(lookups(resolver().with_cache_ttl(None), &count), 3);
+        let cached = resolver();
+        cached.decide(&Url::parse("http://example.com").unwrap());
+        cached.decide(&Url::parse("http://example.com").unwrap());
+        assert_eq!(cached.cache_stats(), CacheStats { hits: 1, misses: 1 });
+    }
+
+    #[test]
+    fn lookup_with_deadline() {
+        /// A system resolver which takes its time.
+        struct Slow;
+
+        impl ProxyResolver for Slow {
+            fn for_url(&self, _url: &Url) -> Option<Url> {
+                std::thread::sleep(Duration::from_millis(200));
+                Some(Url::parse("http://system-proxy:3128").unwrap())
+            }
+        }
+
+        let env_proxy = Url::parse("http://env-proxy:3128").unwrap();
+        let system_proxy = Url::parse("http://system-proxy:3128").unwrap();
+        let url = Url::parse("http://example.com").unwrap();
+        let resolver = |env| {
+            SystemProxyResolver::from_parts(env, Some(("slow", Box::new(Slow))))
+                .with_precedence(Precedence::SystemFirst)
+        };
+        let soon = || Instant::now() + Duration::from_millis(20);
+
+        // The system resolver misses the deadline, so fall back to the environment, or to a
+        // direct connection.
+        let start = Instant::now();
+        let env = EnvProxies {
+            http: Some(env_proxy.clone()),
+            ..EnvProxies::unset()
+        };
+        assert_eq!(
+            resolver(env).lookup_with_deadline(&url, soon()),
+            Some(env_proxy)
+        );
+        let slow = resolver(EnvProxies::unset());
+        assert_eq!(slow.lookup_with_deadline(&url, soon()), None);
+        assert!(start.elapsed() < Duration::from_millis(150));
+
+        // The lookup completes in the background, and later lookups use its decision.
+        let later = Instant::now() + Duration::from_secs(5);
+        assert_eq!(
+            slow.lookup_with_deadline(&url, later),
+            Some(system_proxy.clone())
+        );
+        std::thread::sleep(Duration::from_millis(250));
+        let start = Instant::now();
+        assert_eq!(slow.lookup_with_deadline(&url, soon()), Some(system_proxy));
+        assert!(start.elapsed() < Duration::from_millis(20));
+        assert_eq!(
+            slow.lookup_with_deadline(&Url::parse("file:///etc/hosts").unwrap(), soon()),
+            None
+        );
+    }
+
+    #[test]
+    fn lookup_many() {
+        /// A system resolver which records the URLs of every batch.
+        #[derive(Clone, Default)]
+        struct Batches(Arc<std::sync::Mutex<Vec<Vec<String>>>>);
+
+        impl ProxyResolver for Batches {
+            fn for_url(&self, _url: &Url) -> Option<Url> {
+                Some(Url::parse("http://system-proxy:3128").unwrap())
+            }
+
+            fn decide_many(&self, urls: &[Url]) -> Vec<ProxyDecision> {
+                let batch = urls.iter().map(|url| url.to_string()).collect();
+                self.0.lock().unwrap().push(batch);
+                urls.iter().map(|url| self.decide(url)).collect()
+            }
+        }
+
+        let batches = Batches::default();
+        let env = EnvProxies {
+            http: Some(Url::parse("http://env-proxy:3128").unwrap()),
+            ..EnvProxies::unset()
+        };
+        let resolver =
+            SystemProxyResolver::from_parts(env, Some(("batches", Box::new(batches.clone()))));
+        let urls = [
+            "http://example.com/",
+            "https://example.com/",
+            "mailto:john@example.com",
+            "https://example.org/",
+            "https://example.com/foo",
+        ]
+        .map(|url| Url::parse(url).unwrap());
+        let env_proxy = Url::parse("http://env-proxy:3128").unwrap();
+        let system_proxy = Url::parse("http://system-proxy:3128").unwrap();
+        let expected = vec![
+            Some(env_proxy),
+            Some(system_proxy.clone()),
+            None,
+            Some(system_proxy.clone()),
+            Some(system_proxy),
+        ];
+        assert_eq!(resolver.lookup_many(&urls), expected);
+        assert_eq!(
+            *batches.0.lock().unwrap(),
+            vec![vec![
+                "https://example.com/".to_string(),
+                "https://example.org/".to_string(),
+                "https://example.com/foo".to_string(),
+            ]]
+        );
+        // All origins are cached now.
+        assert_eq!(resolver.lookup_many(&urls), expected);
+        assert_eq!(batches.0.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn export_env() {
+        let env = EnvProxies {
+            http: Some(Url::parse("http://env-proxy:3128").unwrap()),
+            https: None,
+            no_proxy_rules: Some(NoProxyRules::parse_curl_env("internal.example.com")),
+        };
+        let expected = EnvProxies {
+            https: Some(Url::parse("http://system-proxy:3128").unwrap()),
+            ..env.clone()
+        };
+        assert_eq!(resolver(env.clone()).to_env_proxies(), expected);
+        assert_eq!(
+            resolver(env.clone())
+                .with_precedence(Precedence::SystemOnly)
+                .to_env_proxies(),
+            EnvProxies {
+                http: Some(Url::parse("http://system-proxy:3128").unwrap()),
+                https: Some(Url::parse("http://system-proxy:3128").unwrap()),
+                no_proxy_rules: None,
+            }
+        );
+        temp_env::with_vars(
+            vec![
+                ("http_proxy", None),
+                ("https_proxy", None),
+                ("no_proxy", Some("example.com")),
+                ("HTTP_PROXY", None),
+                ("HTTPS_PROXY", None),
+                ("NO_PROXY", Some("example.com")),
+            ],
+            || {
+                resolver(env).export_env();
+                assert_eq!(EnvProxies::from_curl_env(), expected);
+            },
+        )
+    }
+}
@@ -7,14 +7,46 @@
 //! Provide proxy resolvers for Unix systems.
 //!
 //! Depending on the enabled features this module provides a Gio based proxy resolver, and/or a
-//! resolver using the Freedesktop portal API.
+//! resolver using the Freedesktop portal API.  With the Gio feature it also provides access to
+//! the GNOME proxy settings.  On Linux the `netlink` feature adds a listener for network changes.
+//! The `portal-server` feature serves the proxy resolver portal from any resolver.
+//!
+//! On all Unix systems except macOS this module reads WPAD URLs from the leases of DHCP clients.
+//!
+//! All of these except the netlink listener also work on the BSDs.
+
+#[cfg(all(unix, not(target_os = "macos")))]
+pub mod dhcp;
 
 #[cfg(feature = "gio")]
 mod gio;
 #[cfg(feature = "gio")]
-pub use self::gio::GioProxyResolver;
+pub use self::gio::{GioOptions, GioProxyResolver};
+
+#[cfg(feature = "gio")]
+mod gnome;
+#[cfg(feature = "gio")]
+pub use self::gnome::{
+    GnomeProxyMode, GnomeProxyServer, GnomeProxySettings, GnomeProxySettingsChangedHandler,
+};
+
+#[cfg(any(feature = "gio", feature = "portal"))]
+pub(crate) mod join;
+
+#[cfg(all(target_os = "linux", feature = "netlink"))]
+mod netlink;
+#[cfg(all(target_os = "linux", feature = "netlink"))]
+pub use self::netlink::{NetlinkMonitor, NetworkChange};
 
 #[cfg(feature = "portal")]
 mod portal;
 #[cfg(feature = "portal")]
-pub use self::portal::FreedesktopPortalProxyResolver;
+pub use self::portal::{
+    BlockingFreedesktopPortalProxyResolver, FreedesktopPortalProxyResolver, PortalOptions,
+    ProxySettingChanged, ProxySettingsChangedStream,
+};
+
+#[cfg(feature = "portal-server")]
+mod portal_server;
+#[cfg(feature = "portal-server")]
+pub use self::portal_server::ProxyResolverPortal;
@@ -0,0 +1,250 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Read WPAD URLs from the lease files of DHCP clients.
+//!
+//! DHCP servers can announce the URL of a proxy auto-config script in option 252.  Asking the
+//! DHCP server directly requires privileges, but the DHCP client of the system already asked,
+//! and recorded the answer in its lease files.  This module reads the leases of dhclient,
+//! NetworkManager and systemd-networkd.
+
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use url::Url;
+
+/// Directories in which DHCP clients store their leases.
+///
+/// Respectively, dhclient on Debian and on Fedora, NetworkManager, and systemd-networkd.
+pub const LEASE_DIRECTORIES: &[&str] = &[
+    "/var/lib/dhcp",
+    "/var/lib/dhclient",
+    "/var/lib/NetworkManager",
+    "/run/systemd/netif/leases",
+];
+
+/// dhclient option names for option 252.
+///
+/// dhclient only knows the option by name if `dhclient.conf` declares it, so accept the common
+/// names, and the generic name dhclient uses for undeclared options.
+const DHCLIENT_WPAD_OPTIONS: &[&str] = &["wpad", "wpad-url", "proxy-auto-config", "unknown-252"];
+
+/// Convert the raw `bytes` of option 252 to a URL.
+///
+/// Many DHCP servers terminate the URL with a NUL byte, so strip trailing NULs.
+fn wpad_url_from_bytes(bytes: &[u8]) -> Option<Url> {
+    let text = std::str::from_utf8(bytes).ok()?;
+    Url::parse(text.trim_end_matches('\0').trim()).ok()
+}
+
+/// Decode a hex string, with or without `:` separators between bytes.
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    let digits = hex.replace(':', "");
+    if digits.len() % 2 != 0 {
+        return None;
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(digits.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Decode a quoted dhclient string, with `\"`, `\\` and octal `\NNN` escapes.
+fn decode_dhclient_string(quoted: &str) -> Option<Vec<u8>> {
+    let inner = quoted.strip_prefix('"')?.strip_suffix('"')?;
+    let mut bytes = Vec::with_capacity(inner.len());
+    let mut rest = inner.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte != b'\\' {
+            bytes.push(byte);
+            rest = tail;
+        } else if tail.len() >= 3 && tail[..3].iter().all(|b| (b'0'..=b'7').contains(b)) {
+            let octal = std::str::from_utf8(&tail[..3]).ok()?;
+            bytes.push(u8::from_str_radix(octal, 8).ok()?);
+            rest = &tail[3..];
+        } else {
+            let (&escaped, tail) = tail.split_first()?;
+            bytes.push(escaped);
+            rest = tail;
+        }
+    }
+    Some(bytes)
+}
+
+/// Get the WPAD URL of the most recent lease in dhclient `contents`.
+///
+/// dhclient appends new leases to its lease file, so only the last lease block is current.
+fn parse_dhclient_leases(contents: &str) -> Option<Url> {
+    let mut url = None;
+    for line in contents.lines().map(str::trim) {
+        if line.starts_with("lease ") || line == "lease{" {
+            url = None;
+        } else if let Some(option) = line.strip_prefix("option ") {
+            let option = option.trim_end_matches(';');
+            let (name, value) = option.split_once(' ').unwrap_or((option, ""));
+            if DHCLIENT_WPAD_OPTIONS.contains(&name) {
+                let value = value.trim();
+                let bytes = if value.starts_with('"') {
+                    decode_dhclient_string(value)
+                } else {
+                    decode_hex(value)
+                };
+                url = bytes.as_deref().and_then(wpad_url_from_bytes);
+            }
+        }
+    }
+    url
+}
+
+/// Get the WPAD URL from a systemd-networkd lease in `contents`.
+///
+/// NetworkManager uses the same format for leases of its internal DHCP client.  Both store
+/// private options such as option 252 as hex strings.
+fn parse_networkd_lease(contents: &str) -> Option<Url> {
+    contents
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("PRIVATE_252="))
+        .next_back()
+        .and_then(decode_hex)
+        .as_deref()
+        .and_then(wpad_url_from_bytes)
+}
+
+/// Get the WPAD URL from the `contents` of a lease file.
+///
+/// Detect whether `contents` is a dhclient lease file or a systemd-networkd lease, and return
+/// the WPAD URL of the current lease, if any.
+pub fn parse_lease(contents: &str) -> Option<Url> {
+    let is_dhclient = contents
+        .lines()
+        .any(|line| line.trim_start().starts_with("lease"));
+    if is_dhclient {
+        parse_dhclient_leases(contents)
+    } else {
+        parse_networkd_lease(contents)
+    }
+}
+
+/// Read the WPAD URLs from all lease files in `directory`.
+///
+/// Return the URLs of the most recently modified lease files first, without duplicates.  Ignore
+/// files which can't be read, and return no URLs if `directory` can't be read.
+pub fn read_wpad_urls_in<P: AsRef<Path>>(directory: P) -> Vec<Url> {
+    let entries = match std::fs::read_dir(directory.as_ref()) {
+        Ok(entries) => entries,
+        Err(error) => {
+            debug!(
+                "Skipping DHCP leases in {}: {error}",
+                directory.as_ref().display()
+            );
+            return Vec::new();
+        }
+    };
+    let mut leases = entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().map_or(false, |t| t.is_file()))
+        .filter_map(|entry| {
+            let path = entry.path();
+            let modified = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            let url = parse_lease(&std::fs::read_to_string(&path).ok()?)?;
+            debug!("Found WPAD URL {url} in DHCP lease {}", path.display());
+            Some((modified, url))
+        })
+        .collect::<Vec<_>>();
+    leases.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+    let mut urls: Vec<Url> = Vec::with_capacity(leases.len());
+    for (_, url) in leases {
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+    urls
+}
+
+/// Read the WPAD URLs from the lease files of all known DHCP clients.
+///
+/// Look into all [`LEASE_DIRECTORIES`], and return the URLs in the order of these directories.
+pub fn read_wpad_urls() -> Vec<Url> {
+    let mut urls: Vec<Url> = Vec::new();
+    for url in LEASE_DIRECTORIES
+        .iter()
+        .map(PathBuf::from)
+        .flat_map(read_wpad_urls_in)
+    {
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+    urls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn wpad() -> Option<Url> {
+        Some(Url::parse("http://wpad.example.com/wpad.dat").unwrap())
+    }
+
+    #[test]
+    fn dhclient_quoted() {
+        let leases = r#"lease {
+  interface "eth0";
+  fixed-address 192.168.1.10;
+  option subnet-mask 255.255.255.0;
+  option wpad "http://wpad.example.com/wpad.dat\000";
+  renew 4 2023/06/01 10:00:00;
+}
+"#;
+        assert_eq!(parse_lease(leases), wpad());
+    }
+
+    #[test]
+    fn dhclient_hex_unknown_option() {
+        let hex = "http://wpad.example.com/wpad.dat"
+            .bytes()
+            .map(|b| format!("{b:02x}"))
+            .collect::<Vec<_>>()
+            .join(":");
+        let leases = format!("lease {{\n  option unknown-252 {hex};\n}}\n");
+        assert_eq!(parse_lease(&leases), wpad());
+    }
+
+    #[test]
+    fn dhclient_uses_last_lease() {
+        let leases = r#"lease {
+  option wpad "http://wpad.example.com/wpad.dat";
+}
+lease {
+  option domain-name "example.org";
+}
+"#;
+        assert_eq!(parse_lease(leases), None);
+    }
+
+    #[test]
+    fn networkd() {
+        let lease = "# This is private data. Do not parse.\n\
+                     ADDRESS=192.168.1.10\n\
+                     PRIVATE_252=687474703a2f2f777061642e6578616d706c652e636f6d2f777061642e64617400\n";
+        assert_eq!(parse_lease(lease), wpad());
+        assert_eq!(parse_lease("ADDRESS=192.168.1.10\n"), None);
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(
+            decode_dhclient_string(r#""a\"b\\c\101""#),
+            Some(b"a\"b\\cA".to_vec())
+        );
+        assert_eq!(decode_hex("6:1:2"), None);
+        assert_eq!(decode_hex("0a:ff"), Some(vec![0x0a, 0xff]));
+    }
+}
@@ -9,12 +9,61 @@
 //! This module prpvides a thin wrapper around [`Gio.ProxyResolver`](https://docs.gtk.org/gio/iface.ProxyResolver.html)
 //! from Glib/Gio, and adds a more convenient [`Url`]-based API around the underlying API.
 //!
+//! [`GioProxyResolver::lookup`] looks up proxies asynchronously, and
+//! [`GioProxyResolver::lookup_blocking`] lets purely synchronous applications use Gio without an
+//! async runtime or Glib main loop.
+//!
 //! This module requires the `gio` feature.
 
+use std::future::Future;
+use std::task::Poll;
+
+use async_trait::async_trait;
 use gio::glib;
-use gio::traits::ProxyResolverExt;
+use gio::prelude::*;
 use url::Url;
 
+use crate::env::{EnvProxies, NoProxyRule, NoProxyRules};
+use crate::proxy::{
+    host_url, strip_url, HostlessPolicy, LocalAsyncProxyResolver, ProxyCandidate, ProxyDecision,
+};
+use crate::redact::redact;
+
+/// Options for a [`GioProxyResolver`].
+///
+/// See [`GioProxyResolver::with_options`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GioOptions {
+    /// Whether to strip URLs before looking them up; see
+    /// [`GioProxyResolver::with_url_stripping`].
+    pub strip_urls: bool,
+    /// How to handle URLs without a host; see [`GioProxyResolver::with_hostless_policy`].
+    pub hostless: HostlessPolicy,
+}
+
+impl Default for GioOptions {
+    fn default() -> Self {
+        Self {
+            strip_urls: true,
+            hostless: HostlessPolicy::default(),
+        }
+    }
+}
+
+impl GioOptions {
+    /// Whether to strip URLs before looking them up.
+    pub fn strip_urls(mut self, strip_urls: bool) -> Self {
+        self.strip_urls = strip_urls;
+        self
+    }
+
+    /// How to handle URLs without a host.
+    pub fn hostless(mut self, hostless: HostlessPolicy) -> Self {
+        self.hostless = hostless;
+        self
+    }
+}
+
 /// A convenience wrapper around [`gio::ProxyResolver`].
 ///
 /// See [`Gio.ProxyResolver`](https://docs.gtk.org/gio/iface.ProxyResolver.html) for the underlying
@@ -24,12 +73,155 @@ use url::Url;
 #[derive(Debug, Clone)]
 pub struct GioProxyResolver {
     resolver: gio::ProxyResolver,
+    strip_urls: bool,
+    hostless: HostlessPolicy,
 }
 
 impl GioProxyResolver {
     /// Wrap the given GIO proxy `resolver`.
     pub fn new(resolver: gio::ProxyResolver) -> Self {
-        Self { resolver }
+        Self {
+            resolver,
+            strip_urls: true,
+            hostless: HostlessPolicy::default(),
+        }
+    }
+
+    /// Whether to [strip](crate::proxy::strip_url) URLs before looking them up.
+    ///
+    /// By default pass only the scheme, host, and port of URLs to Gio, because Gio may pass URLs
+    /// on to proxy auto-config scripts.  Disable stripping to pass full URLs to Gio.
+    pub fn with_url_stripping(mut self, strip_urls: bool) -> Self {
+        self.strip_urls = strip_urls;
+        self
+    }
+
+    /// How to handle URLs without a host, e.g. `unix:/run/app.sock`.
+    ///
+    /// By default pass these URLs to Gio like any other URL.  Regardless of this policy connect
+    /// directly to [non-network URLs](crate::proxy::is_non_network_url).  With [`HostlessPolicy::Reject`]
+    /// fail lookups for these URLs with a [`gio::IOErrorEnum::InvalidArgument`] error.
+    pub fn with_hostless_policy(mut self, hostless: HostlessPolicy) -> Self {
+        self.hostless = hostless;
+        self
+    }
+
+    /// Apply all `options` to this resolver.
+    pub fn with_options(self, options: GioOptions) -> Self {
+        self.with_url_stripping(options.strip_urls)
+            .with_hostless_policy(options.hostless)
+    }
+
+    /// Get the URI to pass to Gio for `url`.
+    fn lookup_uri(&self, url: &Url) -> String {
+        if self.strip_urls {
+            strip_url(url).into()
+        } else {
+            url.to_string()
+        }
+    }
+
+    /// Whether to connect directly to `url` according to the hostless policy.
+    fn connects_directly(&self, url: &Url) -> Result<bool, glib::Error> {
+        self.hostless.connect_directly(url).map_err(|error| {
+            glib::Error::new(gio::IOErrorEnum::InvalidArgument, &error.to_string())
+        })
+    }
+
+    /// Lookup the proxies for `url` with Gio, unless the hostless policy decides `url`.
+    async fn lookup_proxies(&self, url: &Url) -> Result<Vec<glib::GString>, glib::Error> {
+        if self.connects_directly(url)? {
+            Ok(vec![DIRECT.into()])
+        } else {
+            self.resolver.lookup_future(&self.lookup_uri(url)).await
+        }
+    }
+
+    /// Lookup the proxies for `url` with Gio, blocking the current thread.
+    fn lookup_proxies_blocking(
+        &self,
+        url: &Url,
+        cancellable: Option<&gio::Cancellable>,
+    ) -> Result<Vec<glib::GString>, glib::Error> {
+        if self.connects_directly(url)? {
+            Ok(vec![DIRECT.into()])
+        } else {
+            self.resolver.lookup(&self.lookup_uri(url), cancellable)
+        }
+    }
+
+    /// Create a resolver with static proxies, using [`gio::SimpleProxyResolver`].
+    ///
+    /// The resolver returns the proxy in `uri_proxies` for the scheme of a URL if any, and
+    /// `default_proxy` otherwise, or a direct connection if `default_proxy` is `None`.  It
+    /// connects directly to all hosts which match `ignore_hosts`; see
+    /// [`GSimpleProxyResolver:ignore-hosts`](https://docs.gtk.org/gio/property.SimpleProxyResolver.ignore-hosts.html)
+    /// for the format.
+    ///
+    /// Use [`GioProxyResolver::resolver`] to pass the resolver to Glib-based libraries.
+    pub fn simple(
+        default_proxy: Option<&Url>,
+        ignore_hosts: &[&str],
+        uri_proxies: &[(&str, &Url)],
+    ) -> Self {
+        let resolver = gio::SimpleProxyResolver::new(default_proxy.map(Url::as_str), ignore_hosts);
+        let simple = resolver
+            .downcast_ref::<gio::SimpleProxyResolver>()
+            .expect("g_simple_proxy_resolver_new returned a different type");
+        for (scheme, proxy) in uri_proxies {
+            simple.set_uri_proxy(scheme, proxy.as_str());
+        }
+        Self::new(resolver)
+    }
+
+    /// Create a resolver with static proxies from the given `proxies`.
+    ///
+    /// Use the HTTP and HTTPS proxy of `proxies` for the corresponding schemes, and ignore all
+    /// hosts matched by the no-proxy rules of `proxies`.  Note that Gio also ignores all
+    /// subdomains of hosts which no-proxy rules match exactly, and has no rule for simple host
+    /// names, so this function skips [`NoProxyRule::MatchSimpleHostnames`].
+    ///
+    /// See [`GioProxyResolver::simple`].
+    pub fn from_env_proxies(proxies: &EnvProxies) -> Self {
+        let mut uri_proxies = Vec::new();
+        if !matches!(proxies.no_proxy_rules, Some(NoProxyRules::All)) {
+            for (scheme, proxy) in [("http", &proxies.http), ("https", &proxies.https)] {
+                if let Some(proxy) = proxy {
+                    uri_proxies.push((scheme, proxy));
+                }
+            }
+        }
+        let ignore_hosts: Vec<String> = match &proxies.no_proxy_rules {
+            Some(NoProxyRules::Rules(rules)) => rules
+                .iter()
+                .filter_map(|rule| match rule {
+                    NoProxyRule::MatchExact(host) => Some(host.to_string()),
+                    NoProxyRule::MatchSubdomain(domain) => Some(domain.to_string()),
+                    NoProxyRule::MatchSimpleHostnames => None,
+                    NoProxyRule::MatchCidr(network) => Some(network.to_string()),
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        let ignore_hosts = ignore_hosts.iter().map(String::as_str).collect::<Vec<_>>();
+        Self::simple(None, &ignore_hosts, &uri_proxies)
+    }
+
+    /// Whether the underlying Gio proxy resolver is supported on this system.
+    ///
+    /// The default resolver is unsupported if no proxy resolver implementation is installed,
+    /// e.g. if glib-networking is missing; in this case it resolves all URLs to a direct
+    /// connection.  Callers should skip unsupported resolvers, rather than silently connecting
+    /// directly.
+    ///
+    /// See [`g_proxy_resolver_is_supported`](https://docs.gtk.org/gio/method.ProxyResolver.is_supported.html).
+    pub fn is_supported(&self) -> bool {
+        self.resolver.is_supported()
+    }
+
+    /// Get the underlying Gio proxy resolver.
+    pub fn resolver(&self) -> &gio::ProxyResolver {
+        &self.resolver
     }
 
     /// Lookup the Gio proxy for the given `url`.
@@ -37,18 +229,216 @@ impl GioProxyResolver {
     /// Return the proxy to use, or `None` for a direct connection.  If accessing the proxy
     /// configuration fails or the proxy configuration returns an invalid URL return the
     /// corresponding error.
+    ///
+    /// Dropping the returned future cancels the lookup; use
+    /// [`GioProxyResolver::lookup_cancellable`] to cancel the lookup from elsewhere.
     pub async fn lookup(&self, url: &Url) -> Result<Option<Url>, glib::Error> {
-        let proxies = self.resolver.lookup_future(url.as_str()).await?;
-        match proxies.get(0) {
-            None => Ok(None),
-            Some(url) if url == "direct://" => Ok(None),
-            Some(url) => Url::parse(url).map(Some).map_err(|parse_error| {
-                glib::Error::new(
-                    glib::UriError::Failed,
-                    &format!("Failed to parse proxy URL {}: {}", url, parse_error),
-                )
-            }),
+        let proxies = self.lookup_proxies(url).await?;
+        first_proxy(&proxies)
+    }
+
+    /// Decide how to connect to `url`.
+    ///
+    /// Like [`GioProxyResolver::lookup`], but distinguish an explicit `direct://` answer from no
+    /// answer at all; see [`ProxyDecision`].
+    pub async fn decide(&self, url: &Url) -> Result<ProxyDecision, glib::Error> {
+        self.lookup_all(url)
+            .await
+            .map(ProxyDecision::from_candidates)
+    }
+
+    /// Lookup the Gio proxy for a connection to `host` and `port`.
+    ///
+    /// `scheme` hints at the kind of connection; see [`crate::proxy::host_url`].  Fail if `host`
+    /// or `scheme` are invalid.
+    pub async fn lookup_host(
+        &self,
+        host: &str,
+        port: u16,
+        scheme: &str,
+    ) -> Result<Option<Url>, glib::Error> {
+        let url = host_url(host, port, scheme).ok_or_else(|| {
+            glib::Error::new(
+                glib::UriError::Failed,
+                &format!("Invalid host {host} or scheme {scheme}"),
+            )
+        })?;
+        self.lookup(&url).await
+    }
+
+    /// Lookup the Gio proxies for all `urls`.
+    ///
+    /// Lookup proxies for all `urls` concurrently, and return the results in the order of
+    /// `urls`.  See [`GioProxyResolver::lookup`] for the result of each lookup.
+    pub async fn lookup_many(&self, urls: &[Url]) -> Vec<Result<Option<Url>, glib::Error>> {
+        super::join::join_all(urls.iter().map(|url| self.lookup(url)).collect()).await
+    }
+
+    /// Lookup all Gio proxy candidates for the given `url`.
+    ///
+    /// Unlike [`GioProxyResolver::lookup`] return all candidates in the order Gio returned them,
+    /// so that callers can fall back to later candidates if connecting through the first one
+    /// fails.  Gio returns at least one candidate, which is [`ProxyCandidate::Direct`] for a
+    /// direct connection.
+    ///
+    /// If accessing the proxy configuration fails or any candidate is an invalid URL return the
+    /// corresponding error.
+    pub async fn lookup_all(&self, url: &Url) -> Result<Vec<ProxyCandidate>, glib::Error> {
+        let proxies = self.lookup_proxies(url).await?;
+        proxies.iter().map(|proxy| parse_candidate(proxy)).collect()
+    }
+
+    /// Lookup the raw answer of Gio for the given `url`.
+    ///
+    /// Return the proxy URIs exactly as Gio returned them, without parsing them, e.g. to handle
+    /// nonstandard schemes.  See [`GioProxyResolver::lookup_all`] for parsed candidates.
+    pub async fn lookup_raw(&self, url: &Url) -> Result<Vec<String>, glib::Error> {
+        let proxies = self.lookup_proxies(url).await?;
+        Ok(proxies.iter().map(ToString::to_string).collect())
+    }
+
+    /// Lookup the Gio proxy for the given `url`, until `cancellable` is cancelled.
+    ///
+    /// Like [`GioProxyResolver::lookup`], but abort the lookup when `cancellable` is cancelled,
+    /// e.g. because the request which needed the proxy was cancelled.  In this case return a
+    /// [`gio::IOErrorEnum::Cancelled`] error.
+    pub async fn lookup_cancellable(
+        &self,
+        url: &Url,
+        cancellable: &gio::Cancellable,
+    ) -> Result<Option<Url>, glib::Error> {
+        if self.connects_directly(url)? {
+            return Ok(None);
         }
+        let (sender, receiver) = futures_channel::oneshot::channel();
+        self.resolver
+            .lookup_async(&self.lookup_uri(url), Some(cancellable), move |result| {
+                // The receiver may be gone if the caller dropped the future.
+                let _ = sender.send(result);
+            });
+        let proxies = receiver.await.map_err(|_| {
+            glib::Error::new(gio::IOErrorEnum::Cancelled, "Proxy lookup was dropped")
+        })??;
+        first_proxy(&proxies)
+    }
+
+    /// Lookup the Gio proxy for the given `url`, until `cancelled` completes.
+    ///
+    /// Like [`GioProxyResolver::lookup_cancellable`], but cancel the lookup as soon as the
+    /// `cancelled` future completes, for applications which signal cancellation with futures
+    /// rather than a [`gio::Cancellable`].
+    pub async fn lookup_until<C: Future>(
+        &self,
+        url: &Url,
+        cancelled: C,
+    ) -> Result<Option<Url>, glib::Error> {
+        let cancellable = gio::Cancellable::new();
+        let mut lookup = Box::pin(self.lookup_cancellable(url, &cancellable));
+        let mut cancelled = Some(Box::pin(cancelled));
+        std::future::poll_fn(|cx| {
+            if let Poll::Ready(result) = lookup.as_mut().poll(cx) {
+                return Poll::Ready(result);
+            }
+            if let Some(future) = cancelled.as_mut() {
+                if future.as_mut().poll(cx).is_ready() {
+                    // Gio then completes the lookup with a cancellation error.
+                    cancellable.cancel();
+                    cancelled = None;
+                }
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Lookup the Gio proxy for the given `url`, blocking the current thread.
+    ///
+    /// Like [`GioProxyResolver::lookup`], but does not need a running Glib main loop.  Note that
+    /// the lookup may block for a long time, e.g. if the proxy configuration requires fetching a
+    /// proxy auto-config script.
+    pub fn lookup_blocking(&self, url: &Url) -> Result<Option<Url>, glib::Error> {
+        let proxies = self.lookup_proxies_blocking(url, None)?;
+        first_proxy(&proxies)
+    }
+
+    /// Lookup the Gio proxy for the given `url`, blocking the current thread until the lookup
+    /// completes or `cancellable` is cancelled.
+    ///
+    /// Like [`GioProxyResolver::lookup_blocking`], but another thread can abort the lookup by
+    /// cancelling `cancellable`.  In this case return a [`gio::IOErrorEnum::Cancelled`] error.
+    pub fn lookup_blocking_cancellable(
+        &self,
+        url: &Url,
+        cancellable: &gio::Cancellable,
+    ) -> Result<Option<Url>, glib::Error> {
+        let proxies = self.lookup_proxies_blocking(url, Some(cancellable))?;
+        first_proxy(&proxies)
+    }
+
+    /// Call `callback` whenever the proxy configuration of GNOME changes.
+    ///
+    /// Gio proxy resolvers have no change notification of their own; however on GNOME the default
+    /// Gio proxy resolver uses the GNOME proxy settings.  This function watches these settings,
+    /// so that applications can lookup proxies again in `callback`.  `callback` receives the name
+    /// of the changed setting.
+    ///
+    /// See [`GnomeProxySettings::connect_changed`](crate::unix::GnomeProxySettings::connect_changed)
+    /// for details.
+    pub fn connect_changed<F: Fn(&str) + 'static>(
+        &self,
+        callback: F,
+    ) -> Result<crate::unix::GnomeProxySettingsChangedHandler, glib::BoolError> {
+        crate::unix::GnomeProxySettings::connect_changed(callback)
+    }
+}
+
+/// The candidate of Gio for a direct connection.
+const DIRECT: &str = "direct://";
+
+/// Parse a `candidate` returned by Gio.
+fn parse_candidate(candidate: &str) -> Result<ProxyCandidate, glib::Error> {
+    ProxyCandidate::parse(candidate).map_err(|parse_error| {
+        glib::Error::new(
+            glib::UriError::Failed,
+            &format!("Failed to parse proxy URL {}: {}", candidate, parse_error),
+        )
+    })
+}
+
+/// Get the first proxy of `proxies` returned by Gio.
+fn first_proxy(proxies: &[glib::GString]) -> Result<Option<Url>, glib::Error> {
+    match proxies.first() {
+        None => Ok(None),
+        Some(candidate) => parse_candidate(candidate).map(ProxyCandidate::into_proxy),
+    }
+}
+
+/// Look up proxies with Gio on the current thread.
+///
+/// Gio resolvers can't move across threads, so this resolver only implements the local variant
+/// of [`AsyncProxyResolver`](crate::proxy::AsyncProxyResolver).
+#[async_trait(?Send)]
+impl LocalAsyncProxyResolver for GioProxyResolver {
+    async fn for_url(&self, url: &Url) -> Option<Url> {
+        self.lookup(url).await.unwrap_or_else(|error| {
+            warn!(
+                "Failed to lookup proxy for {} with Gio: {error}",
+                redact(url)
+            );
+            None
+        })
+    }
+
+    async fn decide(&self, url: &Url) -> ProxyDecision {
+        GioProxyResolver::decide(self, url)
+            .await
+            .unwrap_or_else(|error| {
+                warn!(
+                    "Failed to lookup proxy for {} with Gio: {error}",
+                    redact(url)
+                );
+                ProxyDecision::Unspecified
+            })
     }
 }
 
@@ -58,8 +448,6 @@ impl Default for GioProxyResolver {
     /// See [`gio::ProxyResolver::default`], and [`g_proxy_resolver_get_default`](https://docs.gtk.org/gio/type_func.ProxyResolver.get_default.htmll)
     /// for the underlying Gio function.
     fn default() -> Self {
-        Self {
-            resolver: gio::ProxyResolver::default(),
-        }
+        Self::new(gio::ProxyResolver::default())
     }
 }
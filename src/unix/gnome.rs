@@ -0,0 +1,494 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Read and write GNOME proxy settings.
+//!
+//! This module provides access to the `org.gnome.system.proxy` settings schema of GNOME through
+//! [`gio::Settings`].  See [`GnomeProxySettings`], and [`GnomeProxySettings::connect_changed`]
+//! to get notified about changes.  [`GnomeProxySettings`] also resolves proxies from manual
+//! settings, like Glib applications do.
+//!
+//! This module requires the `gio` feature.
+
+use std::rc::Rc;
+
+use gio::glib;
+use gio::prelude::*;
+use ipnet::IpNet;
+use url::Url;
+
+use crate::env::{HostInterner, NoProxy, NoProxyRule, NoProxyRules};
+use crate::proxy::{is_non_network_url, DirectReason, ProxyDecision, ProxyResolver};
+
+/// The ID of the GNOME proxy settings schema.
+const SCHEMA_ID: &str = "org.gnome.system.proxy";
+
+/// The proxy mode of GNOME.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum GnomeProxyMode {
+    /// Do not use a proxy.
+    #[default]
+    None,
+    /// Use the proxy servers configured in the settings.
+    Manual,
+    /// Use the proxy auto-config script at the configured URL.
+    Auto,
+}
+
+impl GnomeProxyMode {
+    /// The value of this mode in the settings schema.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            GnomeProxyMode::None => "none",
+            GnomeProxyMode::Manual => "manual",
+            GnomeProxyMode::Auto => "auto",
+        }
+    }
+
+    fn from_settings(value: &str) -> Self {
+        match value {
+            "manual" => GnomeProxyMode::Manual,
+            "auto" => GnomeProxyMode::Auto,
+            _ => GnomeProxyMode::None,
+        }
+    }
+}
+
+/// A proxy server in GNOME settings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GnomeProxyServer {
+    /// The host name of the proxy server.
+    pub host: String,
+    /// The port of the proxy server.
+    pub port: u16,
+}
+
+impl GnomeProxyServer {
+    /// The URL of this proxy server with the given proxy `scheme`, e.g. `socks5`.
+    ///
+    /// Return `None` if the host is no valid host name.
+    pub fn to_url(&self, scheme: &str) -> Option<Url> {
+        let host = if self.host.contains(':') && !self.host.starts_with('[') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        Url::parse(&format!("{scheme}://{host}:{}", self.port)).ok()
+    }
+
+    fn read(settings: &gio::Settings) -> Option<Self> {
+        let host = settings.string("host");
+        let port = u16::try_from(settings.int("port")).unwrap_or_default();
+        if host.is_empty() {
+            None
+        } else {
+            Some(Self {
+                host: host.to_string(),
+                port,
+            })
+        }
+    }
+
+    fn write(server: Option<&Self>, settings: &gio::Settings) -> Result<(), glib::BoolError> {
+        match server {
+            Some(server) => {
+                settings.set_string("host", &server.host)?;
+                settings.set_int("port", i32::from(server.port))
+            }
+            None => {
+                settings.set_string("host", "")?;
+                settings.set_int("port", 0)
+            }
+        }
+    }
+}
+
+/// GNOME proxy settings.
+///
+/// See the `org.gnome.system.proxy` schema in [gsettings-desktop-schemas](https://gitlab.gnome.org/GNOME/gsettings-desktop-schemas/-/blob/master/schemas/org.gnome.system.proxy.gschema.xml.in)
+/// for the meaning of the individual settings.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GnomeProxySettings {
+    /// The proxy mode.
+    pub mode: GnomeProxyMode,
+    /// The URL of the proxy auto-config script for [`GnomeProxyMode::Auto`].
+    ///
+    /// If empty, GNOME uses WPAD to discover the URL.
+    pub autoconfig_url: String,
+    /// The proxy for http URLs.
+    pub http: Option<GnomeProxyServer>,
+    /// The proxy for https URLs.
+    pub https: Option<GnomeProxyServer>,
+    /// The proxy for ftp URLs.
+    pub ftp: Option<GnomeProxyServer>,
+    /// The SOCKS proxy.
+    pub socks: Option<GnomeProxyServer>,
+    /// Hosts to connect to directly.
+    ///
+    /// These can be host names, domains with an initial wildcard like `*.example.com`, IP
+    /// addresses, and networks with a netmask like `192.168.0.0/24`.
+    pub ignore_hosts: Vec<String>,
+}
+
+/// Open the GNOME proxy settings.
+///
+/// Fail if the settings schema is not installed; unlike [`gio::Settings::new`] this function
+/// does not abort the process in this case.
+fn open_settings() -> Result<gio::Settings, glib::BoolError> {
+    let schema = gio::SettingsSchemaSource::default()
+        .and_then(|source| source.lookup(SCHEMA_ID, true))
+        .ok_or_else(|| glib::bool_error!("Settings schema {} not installed", SCHEMA_ID))?;
+    Ok(gio::Settings::new_full(
+        &schema,
+        None::<&gio::SettingsBackend>,
+        None,
+    ))
+}
+
+/// The names of the child settings of the GNOME proxy settings.
+const CHILDREN: [&str; 4] = ["http", "https", "ftp", "socks"];
+
+/// Handlers connected to the `changed` signals of the GNOME proxy settings.
+///
+/// Dropping this value disconnects the handlers.
+#[derive(Debug)]
+pub struct GnomeProxySettingsChangedHandler {
+    connections: Vec<(gio::Settings, Option<glib::SignalHandlerId>)>,
+}
+
+impl Drop for GnomeProxySettingsChangedHandler {
+    fn drop(&mut self) {
+        for (settings, handler) in &mut self.connections {
+            if let Some(handler) = handler.take() {
+                settings.disconnect(handler);
+            }
+        }
+    }
+}
+
+impl GnomeProxySettings {
+    /// Read the current GNOME proxy settings.
+    ///
+    /// Fail if the GNOME proxy settings schema is not installed.
+    pub fn read() -> Result<Self, glib::BoolError> {
+        let settings = open_settings()?;
+        Ok(Self {
+            mode: GnomeProxyMode::from_settings(&settings.string("mode")),
+            autoconfig_url: settings.string("autoconfig-url").to_string(),
+            http: GnomeProxyServer::read(&settings.child("http")),
+            https: GnomeProxyServer::read(&settings.child("https")),
+            ftp: GnomeProxyServer::read(&settings.child("ftp")),
+            socks: GnomeProxyServer::read(&settings.child("socks")),
+            ignore_hosts: settings
+                .strv("ignore-hosts")
+                .iter()
+                .map(|host| host.to_string())
+                .collect(),
+        })
+    }
+
+    /// Call `callback` whenever any GNOME proxy setting changes.
+    ///
+    /// `callback` receives the name of the changed key, e.g. `mode` or `http/host` for keys of
+    /// the child settings.  Keep the returned handler alive as long as `callback` should be
+    /// called; dropping it disconnects `callback`.
+    ///
+    /// Gio emits change signals on the thread-default main context of the calling thread, so
+    /// `callback` is only called while a Glib main loop runs on this context.
+    ///
+    /// Fail if the GNOME proxy settings schema is not installed.
+    pub fn connect_changed<F: Fn(&str) + 'static>(
+        callback: F,
+    ) -> Result<GnomeProxySettingsChangedHandler, glib::BoolError> {
+        let settings = open_settings()?;
+        let callback = Rc::new(callback);
+        let mut all = vec![(None, settings.clone())];
+        all.extend(CHILDREN.map(|name| (Some(name), settings.child(name))));
+        let connections = all
+            .into_iter()
+            .map(|(prefix, settings)| {
+                let callback = callback.clone();
+                let handler = settings.connect_changed(None, move |_, key| match prefix {
+                    Some(prefix) => callback(&format!("{prefix}/{key}")),
+                    None => callback(key),
+                });
+                // Gio only emits change signals for keys which were read at least once.
+                if let Some(schema) = settings.settings_schema() {
+                    for key in schema.list_keys() {
+                        settings.value(&key);
+                    }
+                }
+                (settings, Some(handler))
+            })
+            .collect();
+        Ok(GnomeProxySettingsChangedHandler { connections })
+    }
+
+    /// Write these settings to the GNOME proxy settings.
+    ///
+    /// Apply all settings at once, and wait until they are written to the settings backend.
+    /// Fail if the GNOME proxy settings schema is not installed, or if a setting is not writable.
+    pub fn write(&self) -> Result<(), glib::BoolError> {
+        let settings = open_settings()?;
+        let children = [
+            ("http", self.http.as_ref()),
+            ("https", self.https.as_ref()),
+            ("ftp", self.ftp.as_ref()),
+            ("socks", self.socks.as_ref()),
+        ]
+        .map(|(name, server)| (settings.child(name), server));
+        settings.delay();
+        for (child, _) in &children {
+            child.delay();
+        }
+        let result = self.write_to(&settings, &children);
+        if result.is_ok() {
+            for (child, _) in &children {
+                child.apply();
+            }
+            settings.apply();
+            gio::Settings::sync();
+        } else {
+            for (child, _) in &children {
+                child.revert();
+            }
+            settings.revert();
+        }
+        result
+    }
+
+    fn write_to(
+        &self,
+        settings: &gio::Settings,
+        children: &[(gio::Settings, Option<&GnomeProxyServer>)],
+    ) -> Result<(), glib::BoolError> {
+        settings.set_string("mode", self.mode.as_str())?;
+        settings.set_string("autoconfig-url", &self.autoconfig_url)?;
+        settings.set_strv(
+            "ignore-hosts",
+            self.ignore_hosts
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>(),
+        )?;
+        for (child, server) in children {
+            GnomeProxyServer::write(*server, child)?;
+        }
+        Ok(())
+    }
+
+    /// Convert the [ignored hosts](GnomeProxySettings::ignore_hosts) into no proxy rules.
+    ///
+    /// A host with a leading `*.` or `.` matches a domain and all its subdomains, a network like
+    /// `192.168.0.0/24` matches all addresses in the network, and any other host must match
+    /// exactly.  Skip hosts which [`NoProxyRules`] cannot represent, i.e. hosts with other
+    /// wildcards.
+    pub fn ignore_rules(&self) -> NoProxyRules {
+        let mut hosts = HostInterner::default();
+        let rules = self
+            .ignore_hosts
+            .iter()
+            .filter_map(|ignore_host| {
+                let host = ignore_host.to_ascii_lowercase();
+                let rule = match host.strip_prefix('*') {
+                    Some(domain) if domain.starts_with('.') => {
+                        NoProxyRule::MatchSubdomain(hosts.intern(domain))
+                    }
+                    _ if host.starts_with('.') => NoProxyRule::MatchSubdomain(hosts.intern(&host)),
+                    _ => match host.parse::<IpNet>() {
+                        Ok(network) => NoProxyRule::MatchCidr(network),
+                        Err(_) if host.contains(['*', '/']) => {
+                            debug!("Skipping unsupported ignored host {ignore_host}");
+                            return None;
+                        }
+                        Err(_) => NoProxyRule::MatchExact(hosts.intern(&host)),
+                    },
+                };
+                Some(rule)
+            })
+            .collect();
+        NoProxyRules::new(rules)
+    }
+
+    /// Get the manual proxy for `url`, by its scheme.
+    ///
+    /// Use the `http`, `https` and `ftp` proxies for their schemes, as `http://` proxies.  Like
+    /// Glib use the SOCKS proxy as `socks5://` proxy for any URL without a proxy for its scheme.
+    /// Ignore the [mode](GnomeProxySettings::mode) and the
+    /// [ignored hosts](GnomeProxySettings::ignore_hosts).
+    pub fn manual_proxy(&self, url: &Url) -> Option<Url> {
+        let server = match url.scheme() {
+            "http" => self.http.as_ref(),
+            "https" => self.https.as_ref(),
+            "ftp" => self.ftp.as_ref(),
+            _ => None,
+        };
+        match server {
+            Some(server) => server.to_url("http"),
+            None => self
+                .socks
+                .as_ref()
+                .and_then(|server| server.to_url("socks5")),
+        }
+    }
+
+    /// Decide how to connect to `url` with these settings.
+    ///
+    /// Connect directly in [`GnomeProxyMode::None`].  Return [`ProxyDecision::Unspecified`] in
+    /// [`GnomeProxyMode::Auto`], because this crate can't evaluate proxy auto-config scripts.
+    /// In [`GnomeProxyMode::Manual`] connect directly if the ignored hosts match `url`, or else
+    /// use the [manual proxy](GnomeProxySettings::manual_proxy) if any.
+    pub fn decide(&self, url: &Url) -> ProxyDecision {
+        if is_non_network_url(url) {
+            return ProxyDecision::Direct;
+        }
+        match self.mode {
+            GnomeProxyMode::None => ProxyDecision::Direct,
+            GnomeProxyMode::Auto => ProxyDecision::Unspecified,
+            GnomeProxyMode::Manual if self.ignore_rules().no_proxy_for(url) => {
+                ProxyDecision::Direct
+            }
+            GnomeProxyMode::Manual => self
+                .manual_proxy(url)
+                .map_or(ProxyDecision::Direct, ProxyDecision::Proxy),
+        }
+    }
+
+    /// Set only the GNOME proxy mode to `mode`, and leave all other settings untouched.
+    ///
+    /// Fail if the GNOME proxy settings schema is not installed, or if the mode is not writable.
+    pub fn write_mode(mode: GnomeProxyMode) -> Result<(), glib::BoolError> {
+        let settings = open_settings()?;
+        settings.set_string("mode", mode.as_str())?;
+        gio::Settings::sync();
+        Ok(())
+    }
+}
+
+impl ProxyResolver for GnomeProxySettings {
+    fn for_url(&self, url: &Url) -> Option<Url> {
+        self.decide(url).into_proxy()
+    }
+
+    fn decide(&self, url: &Url) -> ProxyDecision {
+        GnomeProxySettings::decide(self, url)
+    }
+
+    /// Report the ignored host which matches `url`, and no mode or proxy as no proxy.
+    fn direct_reason(&self, url: &Url) -> Option<DirectReason> {
+        match self.decide(url) {
+            ProxyDecision::Direct if is_non_network_url(url) => Some(DirectReason::NonNetworkUrl),
+            ProxyDecision::Direct => self
+                .ignore_rules()
+                .matching_rule(url.host().as_ref())
+                .filter(|_| self.mode == GnomeProxyMode::Manual)
+                .map(DirectReason::NoProxyRule)
+                .or(Some(DirectReason::NoProxyConfigured)),
+            decision => DirectReason::from_decision(&decision),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn server(host: &str, port: u16) -> Option<GnomeProxyServer> {
+        Some(GnomeProxyServer {
+            host: host.to_string(),
+            port,
+        })
+    }
+
+    fn settings() -> GnomeProxySettings {
+        GnomeProxySettings {
+            mode: GnomeProxyMode::Manual,
+            http: server("proxy.example.com", 3128),
+            socks: server("socks.example.com", 1080),
+            ignore_hosts: vec![
+                "localhost".to_string(),
+                "*.corp.example.com".to_string(),
+                "192.168.0.0/24".to_string(),
+            ],
+            ..GnomeProxySettings::default()
+        }
+    }
+
+    #[test]
+    fn ignore_rules() {
+        assert_eq!(
+            settings().ignore_rules(),
+            NoProxyRules::new(vec![
+                NoProxyRule::MatchExact("localhost".into()),
+                NoProxyRule::MatchSubdomain(".corp.example.com".into()),
+                NoProxyRule::MatchCidr("192.168.0.0/24".parse().unwrap()),
+            ])
+        );
+    }
+
+    #[test]
+    fn socks_proxy_without_proxy_for_scheme() {
+        let settings = settings();
+        let decide = |url: &str| settings.decide(&Url::parse(url).unwrap());
+        assert_eq!(
+            decide("http://example.com"),
+            ProxyDecision::Proxy(Url::parse("http://proxy.example.com:3128").unwrap())
+        );
+        assert_eq!(
+            decide("https://example.com"),
+            ProxyDecision::Proxy(Url::parse("socks5://socks.example.com:1080").unwrap())
+        );
+        assert_eq!(
+            decide("ssh://example.com"),
+            ProxyDecision::Proxy(Url::parse("socks5://socks.example.com:1080").unwrap())
+        );
+        assert_eq!(
+            decide("https://wiki.corp.example.com"),
+            ProxyDecision::Direct
+        );
+        assert_eq!(
+            settings.direct_reason(&Url::parse("https://localhost").unwrap()),
+            Some(DirectReason::NoProxyRule("localhost".to_string()))
+        );
+    }
+
+    #[test]
+    fn ipv6_socks_proxy() {
+        let settings = GnomeProxySettings {
+            mode: GnomeProxyMode::Manual,
+            socks: server("::1", 1080),
+            ..GnomeProxySettings::default()
+        };
+        assert_eq!(
+            settings.manual_proxy(&Url::parse("https://example.com").unwrap()),
+            Some(Url::parse("socks5://[::1]:1080").unwrap())
+        );
+    }
+
+    #[test]
+    fn modes() {
+        let url = Url::parse("https://example.com").unwrap();
+        let settings = GnomeProxySettings {
+            mode: GnomeProxyMode::None,
+            ..settings()
+        };
+        assert_eq!(settings.decide(&url), ProxyDecision::Direct);
+        assert_eq!(
+            settings.direct_reason(&url),
+            Some(DirectReason::NoProxyConfigured)
+        );
+        let settings = GnomeProxySettings {
+            mode: GnomeProxyMode::Auto,
+            ..settings
+        };
+        assert_eq!(settings.decide(&url), ProxyDecision::Unspecified);
+    }
+}
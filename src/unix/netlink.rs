@@ -0,0 +1,194 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Detect network changes through rtnetlink on Linux.
+//!
+//! Whether a proxy applies usually changes exactly when the network changes, e.g. when
+//! connecting to a VPN or another Wi-Fi network.  [`NetlinkMonitor`] listens for changes of
+//! links, addresses, and routes in the kernel.
+//!
+//! This module requires the `netlink` feature.
+
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::time::Duration;
+
+/// A change of the network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NetworkChange {
+    /// A network interface was added or removed, or went up or down.
+    Link,
+    /// An address was added to or removed from a network interface.
+    Address,
+    /// A route was added or removed, e.g. a new default gateway.
+    Route,
+}
+
+/// The length of a netlink message header.
+const HEADER_LEN: usize = 16;
+
+/// Parse netlink messages in `buffer` into network changes.
+///
+/// Skip messages which do not denote changes, and stop at the first truncated message.
+fn parse_changes(buffer: &[u8]) -> Vec<NetworkChange> {
+    let mut changes = Vec::new();
+    let mut rest = buffer;
+    while rest.len() >= HEADER_LEN {
+        let len = u32::from_ne_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let kind = u16::from_ne_bytes([rest[4], rest[5]]);
+        if len < HEADER_LEN || rest.len() < len {
+            break;
+        }
+        let change = match kind {
+            libc::RTM_NEWLINK | libc::RTM_DELLINK => Some(NetworkChange::Link),
+            libc::RTM_NEWADDR | libc::RTM_DELADDR => Some(NetworkChange::Address),
+            libc::RTM_NEWROUTE | libc::RTM_DELROUTE => Some(NetworkChange::Route),
+            _ => None,
+        };
+        if let Some(change) = change {
+            if !changes.contains(&change) {
+                changes.push(change);
+            }
+        }
+        // Netlink aligns messages to four bytes
+        let aligned = (len + 3) & !3;
+        rest = &rest[aligned.min(rest.len())..];
+    }
+    changes
+}
+
+/// A listener for network changes on a rtnetlink socket.
+#[derive(Debug)]
+pub struct NetlinkMonitor {
+    socket: OwnedFd,
+}
+
+impl NetlinkMonitor {
+    /// Open a rtnetlink socket, and subscribe to changes of links, addresses, and routes.
+    pub fn new() -> Result<Self> {
+        // SAFETY: socket has no memory safety requirements.
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        // SAFETY: fd is a valid socket which we own exclusively.
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+        // SAFETY: sockaddr_nl is a plain C struct, for which all zeros is a valid value.
+        let mut address: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        address.nl_groups = (libc::RTMGRP_LINK
+            | libc::RTMGRP_IPV4_IFADDR
+            | libc::RTMGRP_IPV6_IFADDR
+            | libc::RTMGRP_IPV4_ROUTE
+            | libc::RTMGRP_IPV6_ROUTE) as u32;
+        // SAFETY: address is a valid sockaddr_nl with the given size.
+        let result = unsafe {
+            libc::bind(
+                socket.as_raw_fd(),
+                (&address as *const libc::sockaddr_nl).cast(),
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(Self { socket })
+    }
+
+    /// Wait for the next network changes.
+    ///
+    /// Block until the kernel reports changes, and return all distinct changes of the next
+    /// batch of messages.  The result may be empty if the kernel only reported irrelevant
+    /// messages.
+    pub fn recv(&self) -> Result<Vec<NetworkChange>> {
+        let mut buffer = vec![0u8; 16384];
+        // SAFETY: buffer is valid for writes of its length.
+        let len = unsafe {
+            libc::recv(
+                self.socket.as_raw_fd(),
+                buffer.as_mut_ptr().cast(),
+                buffer.len(),
+                0,
+            )
+        };
+        if len < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(parse_changes(&buffer[..len as usize]))
+    }
+
+    /// Wait at most `timeout` for the next network changes.
+    ///
+    /// Like [`Self::recv`], but return `None` if the kernel reported nothing within `timeout`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Option<Vec<NetworkChange>>> {
+        let mut socket = libc::pollfd {
+            fd: self.socket.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = libc::c_int::try_from(timeout.as_millis()).unwrap_or(libc::c_int::MAX);
+        // SAFETY: socket is a single valid pollfd.
+        let ready = unsafe { libc::poll(&mut socket, 1, timeout) };
+        match ready {
+            0 => Ok(None),
+            _ if ready < 0 => {
+                let error = Error::last_os_error();
+                if error.kind() == ErrorKind::Interrupted {
+                    Ok(None)
+                } else {
+                    Err(error)
+                }
+            }
+            _ => self.recv().map(Some),
+        }
+    }
+}
+
+impl AsRawFd for NetlinkMonitor {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn message(kind: u16, payload: usize) -> Vec<u8> {
+        let mut message = Vec::new();
+        message.extend_from_slice(&((HEADER_LEN + payload) as u32).to_ne_bytes());
+        message.extend_from_slice(&kind.to_ne_bytes());
+        message.extend_from_slice(&[0; 10]);
+        message.extend(std::iter::repeat(0).take((payload + 3) & !3));
+        message
+    }
+
+    #[test]
+    fn parse_changes_distinct() {
+        let mut buffer = message(libc::RTM_NEWROUTE, 5);
+        buffer.extend(message(libc::NLMSG_NOOP as u16, 0));
+        buffer.extend(message(libc::RTM_DELLINK, 8));
+        buffer.extend(message(libc::RTM_DELROUTE, 1));
+        assert_eq!(
+            parse_changes(&buffer),
+            vec![NetworkChange::Route, NetworkChange::Link]
+        );
+    }
+
+    #[test]
+    fn parse_changes_truncated() {
+        let mut buffer = message(libc::RTM_NEWADDR, 4);
+        buffer.extend(&message(libc::RTM_NEWLINK, 8)[..20]);
+        assert_eq!(parse_changes(&buffer), vec![NetworkChange::Address]);
+    }
+}
@@ -9,56 +9,1268 @@
 //! Similar to the GIO resolver, but does not require a Glib/GIO dependency.  Instead it uses zbus
 //! to talk to the DBus service directly.
 //!
+//! Additionally this module watches the proxy settings on the [Settings Portal](https://flatpak.github.io/xdg-desktop-portal/#gdbus-org.freedesktop.portal.Settings),
+//! see [`FreedesktopPortalProxyResolver::receive_proxy_settings_changed`].
+//!
 //! This module requires the `portal` feature.
 
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use async_lock::OnceCell;
+use async_trait::async_trait;
+use futures_core::Stream;
 use url::Url;
-use zbus::{Connection, Result};
+use zbus::{dbus_proxy, CacheProperties, Connection, DBusError, Result};
+
+use crate::proxy::{
+    host_url, strip_url, AsyncProxyResolver, HostlessPolicy, ProxyCandidate, ProxyDecision,
+    ProxyResolver,
+};
+use crate::redact::redact;
+
+#[dbus_proxy(
+    interface = "org.freedesktop.portal.ProxyResolver",
+    default_service = "org.freedesktop.portal.Desktop",
+    default_path = "/org/freedesktop/portal/desktop"
+)]
+trait PortalProxyResolver {
+    /// Lookup proxies for `uri`.
+    fn lookup(&self, uri: &str) -> Result<Vec<String>>;
+
+    /// The version of the proxy resolver portal.
+    #[dbus_proxy(property, name = "version")]
+    fn version(&self) -> Result<u32>;
+}
+
+#[dbus_proxy(
+    interface = "org.freedesktop.portal.Settings",
+    default_service = "org.freedesktop.portal.Desktop",
+    default_path = "/org/freedesktop/portal/desktop"
+)]
+trait Settings {
+    #[dbus_proxy(signal)]
+    fn setting_changed(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: zbus::zvariant::Value<'_>,
+    ) -> Result<()>;
+}
+
+/// Settings namespaces which contain proxy settings.
+///
+/// Currently only the GNOME proxy settings; the settings portal exposes these for GNOME and
+/// other desktops using GNOME settings.
+const PROXY_NAMESPACE: &str = "org.gnome.system.proxy";
+
+/// Whether `namespace` contains proxy settings.
+fn is_proxy_namespace(namespace: &str) -> bool {
+    namespace
+        .strip_prefix(PROXY_NAMESPACE)
+        .map_or(false, |rest| rest.is_empty() || rest.starts_with('.'))
+}
+
+/// Parse a proxy `candidate` returned by the portal.
+fn parse_candidate(candidate: &str) -> Result<ProxyCandidate> {
+    ProxyCandidate::parse(candidate).map_err(|parse_error| {
+        zbus::Error::Failure(format!(
+            "Failed to parse proxy URL {candidate}: {parse_error}"
+        ))
+    })
+}
+
+/// Run `future`, but fail with the error of `abort_error` as soon as `abort` completes.
+async fn abort_on<T, F, A, E>(future: F, abort: A, abort_error: E) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+    A: Future,
+    E: FnOnce() -> std::io::Error,
+{
+    let mut future = Box::pin(future);
+    let mut abort = Box::pin(abort);
+    let mut abort_error = Some(abort_error);
+    std::future::poll_fn(|cx| {
+        if let Poll::Ready(result) = future.as_mut().poll(cx) {
+            Poll::Ready(result)
+        } else if abort.as_mut().poll(cx).is_ready() {
+            // We never poll again after returning Ready, so the error is still there.
+            let error = abort_error.take().unwrap()();
+            Poll::Ready(Err(zbus::Error::InputOutput(Arc::new(error))))
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+/// Run `future`, but fail with a [timeout error](FreedesktopPortalProxyResolver::is_timeout) if
+/// it doesn't complete within `timeout`.
+async fn with_timeout<T, F>(timeout: Duration, future: F) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    abort_on(future, async_io::Timer::after(timeout), || {
+        std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            format!("Proxy resolver portal did not reply within {timeout:?}"),
+        )
+    })
+    .await
+}
+
+/// DBus errors which indicate a transient failure.
+///
+/// The portal process died before it replied, or our connection to the bus died.
+const TRANSIENT_ERRORS: [&str; 2] = [
+    "org.freedesktop.DBus.Error.NoReply",
+    "org.freedesktop.DBus.Error.Disconnected",
+];
+
+/// DBus errors which indicate a timeout.
+const TIMEOUT_ERRORS: [&str; 2] = [
+    "org.freedesktop.DBus.Error.Timeout",
+    "org.freedesktop.DBus.Error.TimedOut",
+];
+
+/// DBus errors which indicate that the portal or the proxy resolver interface doesn't exist.
+const MISSING_ERRORS: [&str; 6] = [
+    "org.freedesktop.DBus.Error.ServiceUnknown",
+    "org.freedesktop.DBus.Error.NameHasNoOwner",
+    "org.freedesktop.DBus.Error.UnknownObject",
+    "org.freedesktop.DBus.Error.UnknownInterface",
+    "org.freedesktop.DBus.Error.UnknownMethod",
+    "org.freedesktop.DBus.Error.UnknownProperty",
+];
+
+/// The interface of the proxy resolver portal.
+const PROXY_RESOLVER_INTERFACE: &str = "org.freedesktop.portal.ProxyResolver";
+
+/// Whether `error` is a DBus error with any of the given `names`.
+fn has_error_name(error: &zbus::Error, names: &[&str]) -> bool {
+    match error {
+        zbus::Error::MethodError(name, _, _) => names.contains(&name.as_str()),
+        zbus::Error::FDO(error) => names.contains(&error.name().as_str()),
+        _ => false,
+    }
+}
+
+/// Get the description of the DBus error `error`, if any.
+fn error_description(error: &zbus::Error) -> Option<&str> {
+    match error {
+        zbus::Error::MethodError(_, description, _) => description.as_deref(),
+        zbus::Error::FDO(error) => error.description(),
+        _ => None,
+    }
+}
+
+/// Whether `error` says that the portal or the proxy resolver interface doesn't exist.
+///
+/// GDBus based portals reply with `InvalidArgs` when getting a property of an unknown interface,
+/// so take `InvalidArgs` as missing interface only if its description names the interface of the
+/// proxy resolver; otherwise the portal rejected actual arguments.
+fn is_missing(error: &zbus::Error) -> bool {
+    has_error_name(error, &MISSING_ERRORS)
+        || (has_error_name(error, &["org.freedesktop.DBus.Error.InvalidArgs"])
+            && error_description(error).map_or(false, |description| {
+                description.contains(PROXY_RESOLVER_INTERFACE)
+            }))
+}
+
+/// Get the delay before retry number `attempt`, starting at 0.
+///
+/// Double `backoff` for every attempt, and add up to 50% random jitter, so that many clients
+/// which failed at the same time don't retry at the same time.
+fn retry_delay(backoff: Duration, attempt: u32) -> Duration {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    let delay = backoff.saturating_mul(2u32.saturating_pow(attempt));
+    // A freshly seeded hasher is a good enough source of randomness for jitter.
+    let random = RandomState::new().build_hasher().finish();
+    let jitter = delay.mul_f64((random % 1000) as f64 / 2000.0);
+    delay.saturating_add(jitter)
+}
+
+/// Get the URL to pass to the portal for `url`.
+fn lookup_url(url: &Url, strip_urls: bool) -> Url {
+    if strip_urls {
+        strip_url(url)
+    } else {
+        url.clone()
+    }
+}
+
+/// The candidate of the portal for a direct connection.
+const DIRECT: &str = "direct://";
+
+/// Whether to connect directly to `url` according to the `hostless` policy.
+fn hostless_connects_directly(hostless: HostlessPolicy, url: &Url) -> Result<bool> {
+    hostless
+        .connect_directly(url)
+        .map_err(|error| zbus::Error::Failure(error.to_string()))
+}
+
+/// A changed proxy setting on the settings portal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxySettingChanged {
+    /// The namespace of the changed setting, e.g. `org.gnome.system.proxy.http`.
+    pub namespace: String,
+    /// The key of the changed setting, e.g. `host`.
+    pub key: String,
+}
+
+/// A stream of changed proxy settings on the settings portal.
+///
+/// See [`FreedesktopPortalProxyResolver::receive_proxy_settings_changed`].
+#[derive(Debug)]
+pub struct ProxySettingsChangedStream {
+    signals: SettingChangedStream<'static>,
+}
+
+impl Stream for ProxySettingsChangedStream {
+    type Item = ProxySettingChanged;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match std::task::ready!(Pin::new(&mut self.signals).poll_next(cx)) {
+                None => return Poll::Ready(None),
+                Some(signal) => match signal.args() {
+                    Ok(args) if is_proxy_namespace(args.namespace()) => {
+                        return Poll::Ready(Some(ProxySettingChanged {
+                            namespace: args.namespace().to_string(),
+                            key: args.key().to_string(),
+                        }));
+                    }
+                    Ok(_) => {}
+                    Err(error) => {
+                        warn!("Ignoring invalid SettingChanged signal: {error}");
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// Options for a [`FreedesktopPortalProxyResolver`].
+///
+/// Collects all configuration of a portal resolver in one value, e.g. to load it from a
+/// configuration file or pass it through layers of an application; see
+/// [`FreedesktopPortalProxyResolver::with_options`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortalOptions {
+    /// The timeout for calls to the portal; see [`FreedesktopPortalProxyResolver::with_timeout`].
+    pub timeout: Duration,
+    /// How often to retry after transient errors; see
+    /// [`FreedesktopPortalProxyResolver::with_retries`].
+    pub retries: u32,
+    /// The delay before the first retry; see [`FreedesktopPortalProxyResolver::with_retries`].
+    pub backoff: Duration,
+    /// Whether to coalesce concurrent lookups; see
+    /// [`FreedesktopPortalProxyResolver::with_coalescing`].
+    pub coalescing: bool,
+    /// The minimum interval between calls to the portal, if any; see
+    /// [`FreedesktopPortalProxyResolver::with_rate_limit`].
+    pub rate_limit: Option<Duration>,
+    /// Whether to strip URLs before looking them up; see
+    /// [`FreedesktopPortalProxyResolver::with_url_stripping`].
+    pub strip_urls: bool,
+    /// How to handle URLs without a host; see
+    /// [`FreedesktopPortalProxyResolver::with_hostless_policy`].
+    pub hostless: HostlessPolicy,
+}
+
+impl Default for PortalOptions {
+    fn default() -> Self {
+        Self {
+            timeout: FreedesktopPortalProxyResolver::DEFAULT_TIMEOUT,
+            retries: FreedesktopPortalProxyResolver::DEFAULT_RETRIES,
+            backoff: FreedesktopPortalProxyResolver::DEFAULT_BACKOFF,
+            coalescing: false,
+            rate_limit: None,
+            strip_urls: true,
+            hostless: HostlessPolicy::default(),
+        }
+    }
+}
+
+impl PortalOptions {
+    /// Set the `timeout` for calls to the portal.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Retry up to `retries` times after transient errors, starting with a delay of `backoff`.
+    pub fn retries(mut self, retries: u32, backoff: Duration) -> Self {
+        self.retries = retries;
+        self.backoff = backoff;
+        self
+    }
+
+    /// Whether to coalesce concurrent lookups of the same URL.
+    pub fn coalescing(mut self, coalescing: bool) -> Self {
+        self.coalescing = coalescing;
+        self
+    }
+
+    /// Call the portal at most once every `min_interval`, or without limit for `None`.
+    pub fn rate_limit(mut self, min_interval: Option<Duration>) -> Self {
+        self.rate_limit = min_interval;
+        self
+    }
+
+    /// Whether to strip URLs before looking them up.
+    pub fn strip_urls(mut self, strip_urls: bool) -> Self {
+        self.strip_urls = strip_urls;
+        self
+    }
+
+    /// How to handle URLs without a host.
+    pub fn hostless(mut self, hostless: HostlessPolicy) -> Self {
+        self.hostless = hostless;
+        self
+    }
+}
 
 /// A proxy resolver which uses the Freedesktop proxy resolver portal.
 ///
-/// This struct only holds the underlying [`zbus::Connection`]; consequently it's cheap to clone
-/// this struct.
+/// This struct holds the underlying [`zbus::Connection`] and a typed proxy for the portal, which
+/// it builds only once and shares with all its clones; consequently it's cheap to clone this
+/// struct.  A [lazy](FreedesktopPortalProxyResolver::lazy) resolver connects to the bus on first
+/// use, and shares this connection with all its clones.
+///
+/// Calls to the portal time out after [`FreedesktopPortalProxyResolver::DEFAULT_TIMEOUT`], and
+/// get retried after transient errors; use [`FreedesktopPortalProxyResolver::with_timeout`] and
+/// [`FreedesktopPortalProxyResolver::with_retries`] to change the timeout and the retries.
 #[derive(Debug, Clone)]
 pub struct FreedesktopPortalProxyResolver {
-    connection: zbus::Connection,
+    connection: Arc<OnceCell<Connection>>,
+    resolver: Arc<OnceCell<PortalProxyResolverProxy<'static>>>,
+    timeout: Duration,
+    retries: u32,
+    backoff: Duration,
+    in_flight: Option<Arc<InFlightLookups>>,
+    rate_limit: Option<Arc<RateLimit>>,
+    strip_urls: bool,
+    hostless: HostlessPolicy,
 }
 
 static_assertions::assert_impl_all!(FreedesktopPortalProxyResolver: Send, Sync);
 
-impl<'a> FreedesktopPortalProxyResolver {
+impl FreedesktopPortalProxyResolver {
+    /// The default timeout for calls to the portal.
+    ///
+    /// This is the default timeout of libdbus for method calls.
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(25);
+
+    /// The default number of retries after transient errors.
+    pub const DEFAULT_RETRIES: u32 = 2;
+
+    /// The default delay before the first retry.
+    pub const DEFAULT_BACKOFF: Duration = Duration::from_millis(100);
+
     /// Use the proxy resolver portal on the given `connection`.
     pub fn new(connection: Connection) -> Self {
-        Self { connection }
+        Self::with_connection_cell(OnceCell::from(connection))
+    }
+
+    fn with_connection_cell(connection: OnceCell<Connection>) -> Self {
+        Self {
+            connection: Arc::new(connection),
+            resolver: Arc::new(OnceCell::new()),
+            timeout: Self::DEFAULT_TIMEOUT,
+            retries: Self::DEFAULT_RETRIES,
+            backoff: Self::DEFAULT_BACKOFF,
+            in_flight: None,
+            rate_limit: None,
+            strip_urls: true,
+            hostless: HostlessPolicy::default(),
+        }
+    }
+
+    /// Use the proxy resolver portal on the session bus, but connect to the bus only on first use.
+    ///
+    /// Unlike [`FreedesktopPortalProxyResolver::connect`] this function neither blocks nor fails,
+    /// so applications can create a resolver unconditionally, even in environments without a
+    /// session bus.  Instead the first lookup connects to the session bus, and fails if it
+    /// cannot connect; later lookups try to connect again.
+    pub fn lazy() -> Self {
+        Self::with_connection_cell(OnceCell::new())
+    }
+
+    /// Fail calls to the portal which take longer than `timeout`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The timeout for calls to the portal.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Retry calls to the portal up to `retries` times after transient errors.
+    ///
+    /// Wait `backoff` before the first retry, and double the delay for every further retry,
+    /// with some random jitter.  See [`FreedesktopPortalProxyResolver::is_transient`] for which
+    /// errors are transient.  With zero `retries` never retry.
+    pub fn with_retries(mut self, retries: u32, backoff: Duration) -> Self {
+        self.retries = retries;
+        self.backoff = backoff;
+        self
+    }
+
+    /// Coalesce concurrent lookups of the same URL into a single call to the portal.
+    ///
+    /// If a lookup for a URL is already in progress, wait for its result instead of calling the
+    /// portal again.  This resolver and all its clones share in-progress lookups.
+    pub fn with_coalescing(mut self) -> Self {
+        self.in_flight = Some(Arc::default());
+        self
+    }
+
+    /// Call the portal at most once every `min_interval`.
+    ///
+    /// Delay lookups which come in faster, so that high-throughput clients do not overload the
+    /// portal.  This resolver and all its clones share the rate limit.  Combine with
+    /// [`FreedesktopPortalProxyResolver::with_coalescing`] to avoid delaying concurrent lookups
+    /// of the same URL.
+    pub fn with_rate_limit(mut self, min_interval: Duration) -> Self {
+        self.rate_limit = Some(Arc::new(RateLimit::new(min_interval)));
+        self
+    }
+
+    /// Whether to [strip](crate::proxy::strip_url) URLs before looking them up.
+    ///
+    /// By default pass only the scheme, host, and port of URLs to the portal, because the portal
+    /// may pass URLs on to proxy auto-config scripts.  Disable stripping to pass full URLs.
+    pub fn with_url_stripping(mut self, strip_urls: bool) -> Self {
+        self.strip_urls = strip_urls;
+        self
+    }
+
+    /// How to handle URLs without a host, e.g. `unix:/run/app.sock`.
+    ///
+    /// By default pass these URLs to the portal like any other URL.  Regardless of this policy
+    /// connect directly to [non-network URLs](crate::proxy::is_non_network_url).  With
+    /// [`HostlessPolicy::Reject`] fail lookups for these URLs with [`zbus::Error::Failure`].
+    pub fn with_hostless_policy(mut self, hostless: HostlessPolicy) -> Self {
+        self.hostless = hostless;
+        self
+    }
+
+    /// Apply all `options` to this resolver.
+    ///
+    /// Replace any previous configuration of this resolver with `options`.
+    pub fn with_options(mut self, options: PortalOptions) -> Self {
+        self.timeout = options.timeout;
+        self.retries = options.retries;
+        self.backoff = options.backoff;
+        self.in_flight = if options.coalescing {
+            Some(Arc::default())
+        } else {
+            None
+        };
+        self.rate_limit = options
+            .rate_limit
+            .map(|min_interval| Arc::new(RateLimit::new(min_interval)));
+        self.strip_urls = options.strip_urls;
+        self.hostless = options.hostless;
+        self
+    }
+
+    /// Whether `error` denotes a transient failure, which may go away when retrying.
+    ///
+    /// I/O errors, e.g. if the connection to the bus died, and DBus errors which say that the
+    /// portal died before it replied are transient; all other errors are permanent, in
+    /// particular if the portal implementation doesn't implement the proxy resolver or denied
+    /// access.
+    ///
+    /// [Timeouts](FreedesktopPortalProxyResolver::is_timeout) and
+    /// [cancellations](FreedesktopPortalProxyResolver::is_cancelled) are not transient: A portal
+    /// which didn't reply within the timeout likely won't reply to a retry either, and retrying
+    /// would multiply the time until the lookup fails.
+    pub fn is_transient(error: &zbus::Error) -> bool {
+        match error {
+            zbus::Error::InputOutput(_) => !Self::is_timeout(error) && !Self::is_cancelled(error),
+            _ => has_error_name(error, &TRANSIENT_ERRORS),
+        }
+    }
+
+    /// Whether `error` denotes a [cancelled](FreedesktopPortalProxyResolver::lookup_until) lookup.
+    ///
+    /// Cancellations are [`zbus::Error::InputOutput`] errors of kind
+    /// [`std::io::ErrorKind::Interrupted`].
+    pub fn is_cancelled(error: &zbus::Error) -> bool {
+        match error {
+            zbus::Error::InputOutput(error) => error.kind() == std::io::ErrorKind::Interrupted,
+            _ => false,
+        }
+    }
+
+    /// Whether `error` denotes a call to the portal which timed out.
+    ///
+    /// Timeouts are [`zbus::Error::InputOutput`] errors of kind [`std::io::ErrorKind::TimedOut`],
+    /// or DBus timeout errors from the bus.
+    pub fn is_timeout(error: &zbus::Error) -> bool {
+        match error {
+            zbus::Error::InputOutput(error) => error.kind() == std::io::ErrorKind::TimedOut,
+            _ => has_error_name(error, &TIMEOUT_ERRORS),
+        }
     }
 
     /// Connect to session bus and use its proxy resolver portal.
     pub async fn connect() -> Result<Self> {
-        Ok(Self::new(zbus::Connection::session().await?))
+        Self::with_resolver(zbus::Connection::session().await?).await
+    }
+
+    /// Connect to the bus at the given DBus `address` and use its proxy resolver portal.
+    ///
+    /// Use this to connect to a bus other than the session bus, e.g. in tests or in nested
+    /// sessions.  `address` is a DBus address like `unix:path=/run/user/1000/bus`.
+    pub async fn connect_address(address: &str) -> Result<Self> {
+        Self::connect_with(zbus::ConnectionBuilder::address(address)?).await
+    }
+
+    /// Connect to the bus with the given connection `builder` and use its proxy resolver portal.
+    ///
+    /// Use this to customize the connection, e.g. to connect to a custom address with custom
+    /// authentication mechanisms.
+    pub async fn connect_with(builder: zbus::ConnectionBuilder<'_>) -> Result<Self> {
+        Self::with_resolver(builder.build().await?).await
+    }
+
+    /// Use the proxy resolver portal on `connection`, and build its typed proxy right away.
+    async fn with_resolver(connection: Connection) -> Result<Self> {
+        let resolver = Self::new(connection);
+        resolver.resolver().await?;
+        Ok(resolver)
+    }
+
+    /// Get the connection to the bus, and connect to the session bus if not yet connected.
+    async fn connection(&self) -> Result<&Connection> {
+        self.connection.get_or_try_init(Connection::session).await
+    }
+
+    /// Get the typed proxy for the proxy resolver portal, and build it on first use.
+    ///
+    /// The proxy doesn't cache properties, so building it needs no roundtrip to the bus.
+    async fn resolver(&self) -> Result<&PortalProxyResolverProxy<'static>> {
+        self.resolver
+            .get_or_try_init(|| async {
+                PortalProxyResolverProxy::builder(self.connection().await?)
+                    .cache_properties(CacheProperties::No)
+                    .build()
+                    .await
+            })
+            .await
+    }
+
+    /// Call the portal with `call`.
+    ///
+    /// Fail each attempt after the timeout, and retry transient errors.
+    async fn call<'a, T, F, Fut>(&'a self, call: F) -> Result<T>
+    where
+        F: Fn(&'a PortalProxyResolverProxy<'static>) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            let result =
+                with_timeout(self.timeout, async { call(self.resolver().await?).await }).await;
+            match result {
+                Err(error) if attempt < self.retries && Self::is_transient(&error) => {
+                    let delay = retry_delay(self.backoff, attempt);
+                    debug!("Retrying portal call after {delay:?}, due to {error}");
+                    async_io::Timer::after(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Get the version of the proxy resolver portal.
+    pub async fn version(&self) -> Result<u32> {
+        self.call(|resolver| async move { resolver.version().await })
+            .await
+    }
+
+    /// Probe whether the proxy resolver portal is available.
+    ///
+    /// Ping the portal object, and get the version of the proxy resolver portal.  Return the
+    /// version if the portal implements the proxy resolver, or `None` if there's no portal on
+    /// the bus, or the portal doesn't implement the proxy resolver, as is the case for many
+    /// minimal portal setups.  Return an error if accessing the bus failed.
+    pub async fn probe(&self) -> Result<Option<u32>> {
+        let probe = async {
+            let peer = zbus::fdo::PeerProxy::builder(self.connection().await?)
+                .destination("org.freedesktop.portal.Desktop")?
+                .path("/org/freedesktop/portal/desktop")?
+                .cache_properties(CacheProperties::No)
+                .build()
+                .await?;
+            peer.ping().await?;
+            self.resolver().await?.version().await
+        };
+        match with_timeout(self.timeout, probe).await {
+            Ok(version) => Ok(Some(version)),
+            Err(error) if is_missing(&error) => {
+                debug!("Proxy resolver portal not available: {error}");
+                Ok(None)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Lookup proxy candidates for `url` on the portal, subject to the rate limit.
+    async fn call_lookup(&self, url: &Url) -> Result<Vec<String>> {
+        if let Some(rate_limit) = &self.rate_limit {
+            rate_limit.wait().await;
+        }
+        self.call(|resolver| async move { resolver.lookup(url.as_str()).await })
+            .await
+    }
+
+    /// Lookup proxy candidates for `url` on the portal.
+    ///
+    /// If coalescing is enabled share the result with concurrent lookups for the same `url`.
+    async fn lookup_candidates(&self, url: &Url) -> Result<Vec<String>> {
+        if hostless_connects_directly(self.hostless, url)? {
+            return Ok(vec![DIRECT.to_string()]);
+        }
+        let url = &lookup_url(url, self.strip_urls);
+        match &self.in_flight {
+            None => self.call_lookup(url).await,
+            Some(in_flight) => {
+                let lookup = in_flight.get(url);
+                let result = lookup.get_or_init(|| self.call_lookup(url)).await.clone();
+                in_flight.finish(url, &lookup);
+                result
+            }
+        }
     }
 
     /// Lookup the proxy for the given `url`.
     ///
     /// Return the proxy to use, or `None` for a direct connection.  If accessing the proxy
     /// resolver portal failed or the connection to DBus died, return the corresponding error.
+    /// If the portal doesn't reply within the [timeout](FreedesktopPortalProxyResolver::timeout)
+    /// return a [timeout error](FreedesktopPortalProxyResolver::is_timeout).
+    ///
+    /// Return only the first candidate; see [`FreedesktopPortalProxyResolver::lookup_all`] to
+    /// get all candidates.
     pub async fn lookup(&self, url: &Url) -> Result<Option<Url>> {
-        let proxies: Vec<String> = self
-            .connection
-            .call_method(
-                Some("org.freedesktop.portal.Desktop"),
-                "/org/freedesktop/portal/desktop",
-                Some("org.freedesktop.portal.ProxyResolver"),
-                "Lookup",
-                &(url.as_str(),),
-            )
-            .await?
-            .body()?;
-
-        match proxies.get(0) {
+        let proxies = self.lookup_candidates(url).await?;
+        match proxies.first() {
             None => Ok(None),
-            Some(url) if url == "direct://" => Ok(None),
-            Some(url) => Url::parse(url).map(Some).map_err(|parse_error| {
-                zbus::Error::Failure(format!("Failed to parse proxy URL {url}: {parse_error}",))
-            }),
+            Some(candidate) => parse_candidate(candidate).map(ProxyCandidate::into_proxy),
         }
     }
+
+    /// Decide how to connect to `url`.
+    ///
+    /// Like [`FreedesktopPortalProxyResolver::lookup`], but distinguish an explicit `direct://`
+    /// answer from no answer at all; see [`ProxyDecision`].
+    pub async fn decide(&self, url: &Url) -> Result<ProxyDecision> {
+        self.lookup_all(url)
+            .await
+            .map(ProxyDecision::from_candidates)
+    }
+
+    /// Lookup the proxy for a connection to `host` and `port`.
+    ///
+    /// `scheme` hints at the kind of connection; see [`crate::proxy::host_url`].  Fail if `host`
+    /// or `scheme` are invalid.
+    pub async fn lookup_host(&self, host: &str, port: u16, scheme: &str) -> Result<Option<Url>> {
+        let url = host_url(host, port, scheme).ok_or_else(|| {
+            zbus::Error::Failure(format!("Invalid host {host} or scheme {scheme}"))
+        })?;
+        self.lookup(&url).await
+    }
+
+    /// Lookup the proxies for all `urls`.
+    ///
+    /// Lookup proxies for all `urls` concurrently, and return the results in the order of
+    /// `urls`.  This is much faster than looking up proxies one by one, if the application knows
+    /// its URLs up front.  See [`FreedesktopPortalProxyResolver::lookup`] for the result of each
+    /// lookup.
+    pub async fn lookup_many(&self, urls: &[Url]) -> Vec<Result<Option<Url>>> {
+        super::join::join_all(urls.iter().map(|url| self.lookup(url)).collect()).await
+    }
+
+    /// Lookup the proxy for the given `url`, until `cancelled` completes.
+    ///
+    /// Like [`FreedesktopPortalProxyResolver::lookup`], but abort the lookup as soon as
+    /// `cancelled` completes, e.g. because the request which needed the proxy was cancelled.  In
+    /// this case return a [cancellation error](FreedesktopPortalProxyResolver::is_cancelled).
+    ///
+    /// Note that dropping the future of any lookup also aborts the lookup.
+    pub async fn lookup_until<C: Future>(&self, url: &Url, cancelled: C) -> Result<Option<Url>> {
+        abort_on(self.lookup(url), cancelled, || {
+            std::io::Error::new(std::io::ErrorKind::Interrupted, "Proxy lookup cancelled")
+        })
+        .await
+    }
+
+    /// Lookup the proxy for the given `url`, but give up at `deadline`.
+    ///
+    /// Like [`FreedesktopPortalProxyResolver::lookup`], but if the lookup doesn't complete by
+    /// `deadline` fall back to a direct connection, i.e. return `Ok(None)`.  Unlike the
+    /// [timeout](FreedesktopPortalProxyResolver::with_timeout) the deadline bounds the total time
+    /// of the lookup, including all retries.
+    pub async fn lookup_with_deadline(&self, url: &Url, deadline: Instant) -> Result<Option<Url>> {
+        match self.lookup_until(url, async_io::Timer::at(deadline)).await {
+            Err(error) if Self::is_cancelled(&error) => {
+                debug!(
+                    "Proxy lookup for {} missed deadline, falling back to direct",
+                    redact(url)
+                );
+                Ok(None)
+            }
+            result => result,
+        }
+    }
+
+    /// Lookup all proxy candidates for the given `url`.
+    ///
+    /// Unlike [`FreedesktopPortalProxyResolver::lookup`] return all candidates in the order the
+    /// portal returned them, so that callers can fall back to later candidates if connecting
+    /// through the first one fails.
+    ///
+    /// If accessing the proxy resolver portal failed, the connection to DBus died, or any
+    /// candidate is an invalid URL return the corresponding error.  Like
+    /// [`FreedesktopPortalProxyResolver::lookup`] fail with a timeout error if the portal doesn't
+    /// reply in time.
+    pub async fn lookup_all(&self, url: &Url) -> Result<Vec<ProxyCandidate>> {
+        let proxies = self.lookup_candidates(url).await?;
+        proxies.iter().map(|proxy| parse_candidate(proxy)).collect()
+    }
+
+    /// Lookup the raw answer of the portal for the given `url`.
+    ///
+    /// Return the proxy URIs exactly as the portal returned them, without parsing them, e.g. to
+    /// handle nonstandard schemes.  See [`FreedesktopPortalProxyResolver::lookup_all`] for
+    /// parsed candidates.
+    pub async fn lookup_raw(&self, url: &Url) -> Result<Vec<String>> {
+        self.lookup_candidates(url).await
+    }
+
+    /// Receive changes of proxy settings on the settings portal.
+    ///
+    /// The proxy resolver portal has no change notification of its own; instead this function
+    /// watches the proxy settings which the settings portal exposes, so that applications can
+    /// lookup proxies again after a change.  Note that not all portal implementations expose
+    /// proxy settings; in this case the stream never yields any item.
+    pub async fn receive_proxy_settings_changed(&self) -> Result<ProxySettingsChangedStream> {
+        let settings = SettingsProxy::new(self.connection().await?).await?;
+        Ok(ProxySettingsChangedStream {
+            signals: settings.receive_setting_changed().await?,
+        })
+    }
+}
+
+#[async_trait]
+impl AsyncProxyResolver for FreedesktopPortalProxyResolver {
+    async fn for_url(&self, url: &Url) -> Option<Url> {
+        self.lookup(url).await.unwrap_or_else(|error| {
+            warn!(
+                "Failed to lookup proxy for {} on portal: {error}",
+                redact(url)
+            );
+            None
+        })
+    }
+
+    async fn decide(&self, url: &Url) -> ProxyDecision {
+        FreedesktopPortalProxyResolver::decide(self, url)
+            .await
+            .unwrap_or_else(|error| {
+                warn!(
+                    "Failed to lookup proxy for {} on portal: {error}",
+                    redact(url)
+                );
+                ProxyDecision::Unspecified
+            })
+    }
+}
+
+/// The result of a lookup which is shared by concurrent lookups.
+type SharedLookup = Arc<OnceCell<Result<Vec<String>>>>;
+
+/// Lookups which are currently in progress, by URL.
+#[derive(Debug, Default)]
+struct InFlightLookups {
+    lookups: Mutex<HashMap<String, SharedLookup>>,
+}
+
+impl InFlightLookups {
+    /// Get the lookup in progress for `url`, or start a new one.
+    fn get(&self, url: &Url) -> SharedLookup {
+        let mut lookups = self.lookups.lock().unwrap();
+        lookups
+            .entry(url.to_string())
+            .or_insert_with(|| Arc::new(OnceCell::new()))
+            .clone()
+    }
+
+    /// Forget the finished `lookup` for `url`, so that later lookups call the portal again.
+    fn finish(&self, url: &Url, lookup: &SharedLookup) {
+        let mut lookups = self.lookups.lock().unwrap();
+        if lookups
+            .get(url.as_str())
+            .map_or(false, |current| Arc::ptr_eq(current, lookup))
+        {
+            lookups.remove(url.as_str());
+        }
+    }
+}
+
+/// A limit for how often to call the portal.
+#[derive(Debug)]
+struct RateLimit {
+    min_interval: Duration,
+    next_call: async_lock::Mutex<Option<Instant>>,
+}
+
+impl RateLimit {
+    fn new(min_interval: Duration) -> Self {
+        Self {
+            min_interval,
+            next_call: async_lock::Mutex::new(None),
+        }
+    }
+
+    /// Wait until the next call is allowed.
+    async fn wait(&self) {
+        let mut next_call = self.next_call.lock().await;
+        if let Some(delay) = next_call.and_then(|next| next.checked_duration_since(Instant::now()))
+        {
+            async_io::Timer::after(delay).await;
+        }
+        *next_call = Some(Instant::now() + self.min_interval);
+    }
+}
+
+/// A blocking proxy resolver which uses the Freedesktop proxy resolver portal.
+///
+/// Like [`FreedesktopPortalProxyResolver`], but built on a [`zbus::blocking::Connection`], so
+/// that synchronous applications can use the portal without running an async executor.  Calls
+/// block the current thread until the portal replies, but fail with a
+/// [timeout error](FreedesktopPortalProxyResolver::is_timeout) after
+/// [`FreedesktopPortalProxyResolver::DEFAULT_TIMEOUT`]; use
+/// [`BlockingFreedesktopPortalProxyResolver::with_timeout`] to change the timeout.  Calls never
+/// retry after transient errors.
+///
+/// With the `tokio` feature zbus runs blocking calls on an internal tokio runtime, so do not
+/// use this resolver from within a tokio runtime, where zbus panics.  Use
+/// [`FreedesktopPortalProxyResolver`] in async code instead.
+///
+/// This struct holds the underlying [`zbus::blocking::Connection`] and a typed proxy for the
+/// portal, which it builds only once and shares with all its clones; consequently it's cheap to
+/// clone this struct.
+#[derive(Debug, Clone)]
+pub struct BlockingFreedesktopPortalProxyResolver {
+    connection: zbus::blocking::Connection,
+    resolver: Arc<OnceCell<PortalProxyResolverProxy<'static>>>,
+    timeout: Duration,
+    strip_urls: bool,
+    hostless: HostlessPolicy,
+}
+
+static_assertions::assert_impl_all!(BlockingFreedesktopPortalProxyResolver: Send, Sync);
+
+impl BlockingFreedesktopPortalProxyResolver {
+    /// Use the proxy resolver portal on the given `connection`.
+    pub fn new(connection: zbus::blocking::Connection) -> Self {
+        Self {
+            connection,
+            resolver: Arc::new(OnceCell::new()),
+            timeout: FreedesktopPortalProxyResolver::DEFAULT_TIMEOUT,
+            strip_urls: true,
+            hostless: HostlessPolicy::default(),
+        }
+    }
+
+    /// Fail calls to the portal which take longer than `timeout`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The timeout for calls to the portal.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Whether to [strip](crate::proxy::strip_url) URLs before looking them up.
+    ///
+    /// See [`FreedesktopPortalProxyResolver::with_url_stripping`].
+    pub fn with_url_stripping(mut self, strip_urls: bool) -> Self {
+        self.strip_urls = strip_urls;
+        self
+    }
+
+    /// How to handle URLs without a host.
+    ///
+    /// See [`FreedesktopPortalProxyResolver::with_hostless_policy`].
+    pub fn with_hostless_policy(mut self, hostless: HostlessPolicy) -> Self {
+        self.hostless = hostless;
+        self
+    }
+
+    /// Connect to session bus and use its proxy resolver portal.
+    pub fn connect() -> Result<Self> {
+        Ok(Self::new(zbus::blocking::Connection::session()?))
+    }
+
+    /// Connect to the bus at the given DBus `address` and use its proxy resolver portal.
+    ///
+    /// See [`FreedesktopPortalProxyResolver::connect_address`].
+    pub fn connect_address(address: &str) -> Result<Self> {
+        Self::connect_with(zbus::blocking::ConnectionBuilder::address(address)?)
+    }
+
+    /// Connect to the bus with the given connection `builder` and use its proxy resolver portal.
+    pub fn connect_with(builder: zbus::blocking::ConnectionBuilder<'_>) -> Result<Self> {
+        Ok(Self::new(builder.build()?))
+    }
+
+    /// Get the typed proxy for the proxy resolver portal, and build it on first use.
+    ///
+    /// Use an async proxy on the underlying connection, to run calls with a timeout.
+    async fn resolver(&self) -> Result<&PortalProxyResolverProxy<'static>> {
+        self.resolver
+            .get_or_try_init(|| {
+                PortalProxyResolverProxy::builder(self.connection.inner())
+                    .cache_properties(CacheProperties::No)
+                    .build()
+            })
+            .await
+    }
+
+    /// Block on the `call` to the portal, but fail after the timeout.
+    ///
+    /// Run `call` like zbus runs blocking calls.
+    fn call<T, F: Future<Output = Result<T>>>(&self, call: F) -> Result<T> {
+        zbus::block_on(with_timeout(self.timeout, call))
+    }
+
+    /// Get the version of the proxy resolver portal.
+    pub fn version(&self) -> Result<u32> {
+        self.call(async { self.resolver().await?.version().await })
+    }
+
+    /// Probe whether the proxy resolver portal is available.
+    ///
+    /// See [`FreedesktopPortalProxyResolver::probe`].
+    pub fn probe(&self) -> Result<Option<u32>> {
+        let probe = || {
+            self.call(async {
+                zbus::fdo::PeerProxy::builder(self.connection.inner())
+                    .destination("org.freedesktop.portal.Desktop")?
+                    .path("/org/freedesktop/portal/desktop")?
+                    .cache_properties(CacheProperties::No)
+                    .build()
+                    .await?
+                    .ping()
+                    .await?;
+                Ok(())
+            })?;
+            self.version()
+        };
+        match probe() {
+            Ok(version) => Ok(Some(version)),
+            Err(error) if is_missing(&error) => {
+                debug!("Proxy resolver portal not available: {error}");
+                Ok(None)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Lookup proxy candidates for `url` on the portal.
+    fn lookup_candidates(&self, url: &Url) -> Result<Vec<String>> {
+        if hostless_connects_directly(self.hostless, url)? {
+            return Ok(vec![DIRECT.to_string()]);
+        }
+        let url = lookup_url(url, self.strip_urls);
+        self.call(async { self.resolver().await?.lookup(url.as_str()).await })
+    }
+
+    /// Lookup the proxy for the given `url`.
+    ///
+    /// Return the proxy to use, or `None` for a direct connection.  If accessing the proxy
+    /// resolver portal failed or the connection to DBus died, return the corresponding error.
+    /// If the portal doesn't reply within the timeout return a
+    /// [timeout error](FreedesktopPortalProxyResolver::is_timeout).
+    pub fn lookup(&self, url: &Url) -> Result<Option<Url>> {
+        let proxies = self.lookup_candidates(url)?;
+        match proxies.first() {
+            None => Ok(None),
+            Some(candidate) => parse_candidate(candidate).map(ProxyCandidate::into_proxy),
+        }
+    }
+
+    /// Lookup the raw answer of the portal for the given `url`.
+    ///
+    /// See [`FreedesktopPortalProxyResolver::lookup_raw`].
+    pub fn lookup_raw(&self, url: &Url) -> Result<Vec<String>> {
+        self.lookup_candidates(url)
+    }
+
+    /// Lookup all proxy candidates for the given `url`.
+    ///
+    /// See [`FreedesktopPortalProxyResolver::lookup_all`].
+    pub fn lookup_all(&self, url: &Url) -> Result<Vec<ProxyCandidate>> {
+        let proxies = self.lookup_candidates(url)?;
+        proxies.iter().map(|proxy| parse_candidate(proxy)).collect()
+    }
+}
+
+impl ProxyResolver for BlockingFreedesktopPortalProxyResolver {
+    fn decide(&self, url: &Url) -> ProxyDecision {
+        match self.lookup_all(url) {
+            Ok(candidates) => ProxyDecision::from_candidates(candidates),
+            Err(error) => {
+                warn!(
+                    "Failed to lookup proxy for {} on portal: {error}",
+                    redact(url)
+                );
+                ProxyDecision::Unspecified
+            }
+        }
+    }
+
+    fn for_url(&self, url: &Url) -> Option<Url> {
+        self.lookup(url).unwrap_or_else(|error| {
+            warn!(
+                "Failed to lookup proxy for {} on portal: {error}",
+                redact(url)
+            );
+            None
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::ErrorKind;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    type Portal = FreedesktopPortalProxyResolver;
+
+    fn io_error(kind: ErrorKind) -> zbus::Error {
+        zbus::Error::InputOutput(Arc::new(std::io::Error::new(kind, "test")))
+    }
+
+    fn dbus_error(error: zbus::fdo::Error) -> zbus::Error {
+        zbus::Error::FDO(Box::new(error))
+    }
+
+    /// Serve a portal backed by `resolver` on a peer-to-peer connection, and connect to it.
+    ///
+    /// Return the server connection, which needs to stay alive, and a resolver for the portal.
+    #[cfg(feature = "portal-server")]
+    async fn serve<R: ProxyResolver + Send + Sync + 'static>(
+        resolver: R,
+    ) -> (Connection, FreedesktopPortalProxyResolver) {
+        #[cfg(feature = "tokio")]
+        let (server, client) = tokio::net::UnixStream::pair().unwrap();
+        #[cfg(not(feature = "tokio"))]
+        let (server, client) = std::os::unix::net::UnixStream::pair().unwrap();
+        let guid = zbus::Guid::generate();
+        // Serve the portal while building the connection; with serve_at the server may miss calls
+        // which arrive before its object server listens.
+        let server = crate::unix::ProxyResolverPortal::new(resolver)
+            .serve_with(zbus::ConnectionBuilder::unix_stream(server))
+            .unwrap();
+        let connections = crate::unix::join::join_all(vec![
+            server.server(&guid).p2p().build(),
+            zbus::ConnectionBuilder::unix_stream(client).p2p().build(),
+        ])
+        .await;
+        let [server, client]: [Connection; 2] = connections
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .unwrap()
+            .try_into()
+            .unwrap();
+        (server, FreedesktopPortalProxyResolver::new(client))
+    }
+
+    #[cfg(feature = "portal-server")]
+    #[test]
+    fn lookup_on_portal() {
+        let run = async {
+            let proxy = Url::parse("http://proxy.example.com:3128").unwrap();
+            let (_server, portal) = serve(crate::env::EnvProxies {
+                http: Some(proxy.clone()),
+                ..crate::env::EnvProxies::unset()
+            })
+            .await;
+            assert_eq!(portal.version().await.unwrap(), 1);
+            let url = Url::parse("http://example.com/foo").unwrap();
+            assert_eq!(portal.lookup(&url).await.unwrap(), Some(proxy.clone()));
+            let url = Url::parse("https://example.com/foo").unwrap();
+            assert_eq!(portal.lookup(&url).await.unwrap(), None);
+            // All clones share the typed proxy for the portal.
+            let clone = portal.clone().with_timeout(Duration::from_secs(1));
+            assert!(std::ptr::eq(
+                portal.resolver().await.unwrap(),
+                clone.resolver().await.unwrap()
+            ));
+        };
+        // The server and the client share the runtime, which then needs more than one thread.
+        #[cfg(feature = "tokio")]
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(run);
+        #[cfg(not(feature = "tokio"))]
+        async_io::block_on(run);
+    }
+
+    #[test]
+    fn transient_errors() {
+        for error in [
+            io_error(ErrorKind::BrokenPipe),
+            dbus_error(zbus::fdo::Error::NoReply("died".into())),
+            dbus_error(zbus::fdo::Error::Disconnected("died".into())),
+        ] {
+            assert!(Portal::is_transient(&error), "{error}");
+            assert!(!Portal::is_timeout(&error), "{error}");
+        }
+        for error in [
+            dbus_error(zbus::fdo::Error::AccessDenied("denied".into())),
+            dbus_error(zbus::fdo::Error::ServiceUnknown("unknown".into())),
+            zbus::Error::Failure("failed".into()),
+        ] {
+            assert!(!Portal::is_transient(&error), "{error}");
+        }
+    }
+
+    #[test]
+    fn timeouts_and_cancellations_are_not_transient() {
+        for error in [
+            io_error(ErrorKind::TimedOut),
+            dbus_error(zbus::fdo::Error::Timeout("slow".into())),
+            dbus_error(zbus::fdo::Error::TimedOut("slow".into())),
+        ] {
+            assert!(Portal::is_timeout(&error), "{error}");
+            assert!(!Portal::is_transient(&error), "{error}");
+        }
+        let cancelled = io_error(ErrorKind::Interrupted);
+        assert!(Portal::is_cancelled(&cancelled));
+        assert!(!Portal::is_transient(&cancelled));
+        assert!(!Portal::is_timeout(&cancelled));
+    }
+
+    #[test]
+    fn missing_errors() {
+        for error in [
+            zbus::fdo::Error::ServiceUnknown("unknown".into()),
+            zbus::fdo::Error::UnknownInterface("unknown".into()),
+            zbus::fdo::Error::UnknownProperty("unknown".into()),
+            zbus::fdo::Error::InvalidArgs(
+                "No such interface “org.freedesktop.portal.ProxyResolver”".into(),
+            ),
+        ] {
+            assert!(is_missing(&dbus_error(error.clone())), "{error}");
+        }
+        for error in [
+            zbus::fdo::Error::InvalidArgs("Invalid URI".into()),
+            zbus::fdo::Error::InvalidArgs("No such interface “org.example.Other”".into()),
+            zbus::fdo::Error::AccessDenied("denied".into()),
+        ] {
+            assert!(!is_missing(&dbus_error(error.clone())), "{error}");
+        }
+        assert!(!is_missing(&io_error(ErrorKind::NotFound)));
+    }
+
+    #[test]
+    fn retry_delay_doubles_with_jitter() {
+        let backoff = Duration::from_millis(100);
+        for attempt in 0..4 {
+            let delay = retry_delay(backoff, attempt);
+            let base = backoff * 2u32.pow(attempt);
+            assert!(base <= delay && delay <= base.mul_f64(1.5), "{delay:?}");
+        }
+        assert_eq!(retry_delay(Duration::ZERO, 3), Duration::ZERO);
+    }
+
+    #[test]
+    fn proxy_namespaces() {
+        assert!(is_proxy_namespace("org.gnome.system.proxy"));
+        assert!(is_proxy_namespace("org.gnome.system.proxy.http"));
+        assert!(!is_proxy_namespace("org.gnome.system.proxyfoo"));
+        assert!(!is_proxy_namespace("org.gnome.desktop.interface"));
+    }
+
+    #[test]
+    fn rate_limit_delays_calls() {
+        let rate_limit = RateLimit::new(Duration::from_millis(50));
+        let start = Instant::now();
+        async_io::block_on(rate_limit.wait());
+        assert!(start.elapsed() < Duration::from_millis(50));
+        async_io::block_on(async {
+            rate_limit.wait().await;
+            rate_limit.wait().await;
+        });
+        assert!(Duration::from_millis(100) <= start.elapsed());
+    }
+
+    #[test]
+    fn in_flight_lookups_coalesce() {
+        let in_flight = &InFlightLookups::default();
+        let calls = &AtomicUsize::new(0);
+        let url = &Url::parse("http://example.com").unwrap();
+        let lookup = || async move {
+            let lookup = in_flight.get(url);
+            let result = lookup
+                .get_or_init(|| async {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    async_io::Timer::after(Duration::from_millis(20)).await;
+                    Ok(vec![DIRECT.to_string()])
+                })
+                .await
+                .clone();
+            in_flight.finish(url, &lookup);
+            result.unwrap()
+        };
+
+        let results = async_io::block_on(crate::unix::join::join_all(vec![
+            lookup(),
+            lookup(),
+            lookup(),
+        ]));
+        assert_eq!(results, vec![vec![DIRECT.to_string()]; 3]);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(in_flight.lookups.lock().unwrap().is_empty());
+
+        // Finished lookups don't coalesce with later lookups.
+        async_io::block_on(lookup());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn in_flight_lookups_keep_newer_lookups() {
+        let in_flight = InFlightLookups::default();
+        let url = Url::parse("http://example.com").unwrap();
+        let first = in_flight.get(&url);
+        assert!(Arc::ptr_eq(&first, &in_flight.get(&url)));
+        in_flight.finish(&url, &first);
+        let second = in_flight.get(&url);
+        assert!(!Arc::ptr_eq(&first, &second));
+        in_flight.finish(&url, &first);
+        assert!(Arc::ptr_eq(&second, &in_flight.get(&url)));
+    }
 }
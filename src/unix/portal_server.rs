@@ -0,0 +1,114 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Serve the [Freedesktop Proxy Portal](https://flatpak.github.io/xdg-desktop-portal/#gdbus-org.freedesktop.portal.ProxyResolver)
+//! from any [`ProxyResolver`].
+//!
+//! Lets headless systems or custom desktops without a portal implementation provide proxy
+//! resolution to sandboxed applications.
+//!
+//! This module requires the `portal-server` feature.
+
+use url::Url;
+use zbus::{dbus_interface, fdo, Connection, ConnectionBuilder, Result};
+
+use crate::proxy::{ProxyDecision, ProxyResolver};
+use crate::redact::redact;
+
+/// The proxy resolver portal, backed by a [`ProxyResolver`].
+///
+/// Use [`ProxyResolverPortal::serve_session`] to provide the portal on the session bus, or
+/// [`ProxyResolverPortal::serve_at`] to serve it on an existing connection.
+///
+/// The portal calls the resolver directly on the executor of the connection, so the resolver
+/// should be fast and must not block on the bus itself; in particular do not back the portal
+/// with a [`crate::unix::BlockingFreedesktopPortalProxyResolver`] on the same bus.
+#[derive(Debug, Clone)]
+pub struct ProxyResolverPortal<R> {
+    resolver: R,
+}
+
+impl<R> ProxyResolverPortal<R> {
+    /// The well-known bus name of the portal.
+    pub const NAME: &'static str = "org.freedesktop.portal.Desktop";
+
+    /// The object path of the portal.
+    pub const PATH: &'static str = "/org/freedesktop/portal/desktop";
+
+    /// Create a portal interface backed by `resolver`.
+    pub fn new(resolver: R) -> Self {
+        Self { resolver }
+    }
+
+    /// Get the resolver backing this portal.
+    pub fn resolver(&self) -> &R {
+        &self.resolver
+    }
+}
+
+impl<R: ProxyResolver + Send + Sync + 'static> ProxyResolverPortal<R> {
+    /// Serve this portal on the session bus.
+    ///
+    /// Connect to the session bus, serve the portal, and request the portal name.  Return the
+    /// connection; the portal remains available as long as the connection is alive.  Fail if the
+    /// name is already taken, e.g. because a portal implementation is running.
+    pub async fn serve_session(self) -> Result<Connection> {
+        self.serve_with(ConnectionBuilder::session()?)?
+            .name(Self::NAME)?
+            .build()
+            .await
+    }
+
+    /// Serve this portal at [`ProxyResolverPortal::PATH`] on the connection built by `builder`.
+    ///
+    /// Unlike [`ProxyResolverPortal::serve_at`] the portal is ready as soon as the connection is.
+    pub(crate) fn serve_with(
+        self,
+        builder: ConnectionBuilder<'_>,
+    ) -> Result<ConnectionBuilder<'_>> {
+        builder.serve_at(Self::PATH, Interface(self))
+    }
+
+    /// Serve this portal at [`ProxyResolverPortal::PATH`] on `connection`.
+    ///
+    /// The caller needs to request [`ProxyResolverPortal::NAME`] on `connection` to make the
+    /// portal available under its well-known name.  Return `false` if `connection` already
+    /// serves the portal.
+    pub async fn serve_at(self, connection: &Connection) -> Result<bool> {
+        connection
+            .object_server()
+            .at(Self::PATH, Interface(self))
+            .await
+    }
+}
+
+/// The DBus interface of the portal.
+///
+/// Wraps the portal, because `dbus_interface` generates undocumented public methods.
+struct Interface<R>(ProxyResolverPortal<R>);
+
+#[dbus_interface(name = "org.freedesktop.portal.ProxyResolver")]
+impl<R: ProxyResolver + Send + Sync + 'static> Interface<R> {
+    /// Lookup the proxies for `uri`.
+    ///
+    /// Return the proxy for `uri`, or `direct://` for a direct connection.
+    fn lookup(&self, uri: &str) -> fdo::Result<Vec<String>> {
+        let url = Url::parse(uri)
+            .map_err(|error| fdo::Error::InvalidArgs(format!("Invalid URI {uri}: {error}")))?;
+        let proxy = match self.0.resolver.decide(&url) {
+            ProxyDecision::Proxy(proxy) => proxy.to_string(),
+            ProxyDecision::Direct | ProxyDecision::Unspecified => "direct://".to_string(),
+        };
+        debug!("Portal lookup for {} returned {proxy}", redact(&url));
+        Ok(vec![proxy])
+    }
+
+    /// The version of the portal interface.
+    #[dbus_interface(property, name = "version")]
+    fn version(&self) -> u32 {
+        1
+    }
+}
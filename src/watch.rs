@@ -0,0 +1,711 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Watch for changes of the proxy configuration.
+//!
+//! [`ProxyWatcher`] is a [`Stream`] of [`ProxyChange`] events, which applications can use to
+//! invalidate cached proxies and reconfigure HTTP clients.
+//!
+//! This module requires the `watch` feature.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_channel::mpsc;
+use futures_core::Stream;
+use futures_util::StreamExt;
+
+use crate::snapshot::{Change, SystemProxySnapshot};
+
+/// A change of the proxy configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyChange {
+    /// The fields which changed.
+    pub changes: Vec<Change>,
+    /// The proxy configuration after the change.
+    pub snapshot: SystemProxySnapshot,
+}
+
+/// How often threads which block on a source of changes check whether the watcher was dropped.
+#[cfg(any(all(target_os = "linux", feature = "netlink"), target_os = "macos"))]
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Stop a source of ticks when dropped.
+struct StopOnDrop(Option<Box<dyn FnOnce() + Send>>);
+
+impl StopOnDrop {
+    fn new<F: FnOnce() + Send + 'static>(stop: F) -> Self {
+        Self(Some(Box::new(stop)))
+    }
+}
+
+impl Drop for StopOnDrop {
+    fn drop(&mut self) {
+        if let Some(stop) = self.0.take() {
+            stop();
+        }
+    }
+}
+
+/// Create a flag for a thread to check whether it should stop, and a guard which sets the flag.
+#[cfg(any(all(target_os = "linux", feature = "netlink"), target_os = "macos"))]
+fn stop_flag() -> (StopOnDrop, Arc<std::sync::atomic::AtomicBool>) {
+    let stopped = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let flag = stopped.clone();
+    let stop = StopOnDrop::new(move || flag.store(true, Ordering::SeqCst));
+    (stop, stopped)
+}
+
+/// Count the sources which currently report changes of the proxy settings.
+///
+/// The fallback timer does not tick while any source reports changes.
+#[derive(Debug, Clone, Default)]
+struct Subscriptions(Arc<AtomicUsize>);
+
+impl Subscriptions {
+    /// Whether any source currently reports changes.
+    fn active(&self) -> bool {
+        0 < self.0.load(Ordering::SeqCst)
+    }
+
+    /// Record an active source until the returned value is dropped.
+    #[cfg_attr(
+        not(any(feature = "gio", feature = "portal", windows, target_os = "macos")),
+        allow(dead_code)
+    )]
+    fn subscribe(&self) -> Subscription {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        Subscription(self.0.clone())
+    }
+}
+
+/// An active source of changes; see [`Subscriptions::subscribe`].
+#[cfg_attr(
+    not(any(feature = "gio", feature = "portal", windows, target_os = "macos")),
+    allow(dead_code)
+)]
+struct Subscription(Arc<AtomicUsize>);
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Send a tick to `ticks`, and return `false` if the receiver was dropped.
+///
+/// Skip the tick if the receiver did not yet consume the previous tick.
+fn tick(ticks: &mut mpsc::Sender<()>) -> bool {
+    !matches!(ticks.try_send(()), Err(error) if error.is_disconnected())
+}
+
+/// Spawn a thread which sends a tick to `ticks` every `interval`.
+///
+/// If `fallback_for` is given, skip ticks while any of these subscriptions is active.  The
+/// thread exits when the returned guard or the receiver of `ticks` is dropped.
+fn spawn_ticker(
+    interval: Duration,
+    mut ticks: mpsc::Sender<()>,
+    fallback_for: Option<Subscriptions>,
+) -> StopOnDrop {
+    use std::sync::mpsc::RecvTimeoutError;
+
+    let (stop, stopped) = std::sync::mpsc::channel::<()>();
+    std::thread::spawn(move || {
+        while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+            let skip = fallback_for.as_ref().map_or(false, Subscriptions::active);
+            if !skip && !tick(&mut ticks) {
+                break;
+            }
+        }
+    });
+    StopOnDrop::new(move || drop(stop))
+}
+
+/// Spawn a thread which sends a tick to `ticks` whenever the network changes.
+///
+/// The thread exits shortly after the returned guard or the receiver of `ticks` is dropped, or if
+/// listening for network changes failed.
+#[cfg(all(target_os = "linux", feature = "netlink"))]
+fn spawn_network_ticker(mut ticks: mpsc::Sender<()>) -> Option<StopOnDrop> {
+    let monitor = match crate::unix::NetlinkMonitor::new() {
+        Ok(monitor) => monitor,
+        Err(error) => {
+            warn!("Failed to listen for network changes: {error}");
+            return None;
+        }
+    };
+    let (stop, stopped) = stop_flag();
+    std::thread::spawn(move || {
+        while !stopped.load(Ordering::SeqCst) {
+            match monitor.recv_timeout(STOP_CHECK_INTERVAL) {
+                Ok(None) => {}
+                Ok(Some(changes)) if changes.is_empty() => {}
+                Ok(Some(changes)) => {
+                    debug!("Network changed: {changes:?}");
+                    if !tick(&mut ticks) {
+                        break;
+                    }
+                }
+                Err(error) => {
+                    warn!("Failed to receive network changes: {error}");
+                    break;
+                }
+            }
+        }
+    });
+    Some(stop)
+}
+
+/// Spawn a thread which sends a tick to `ticks` whenever Gio's network monitor reports a change,
+/// or, if `settings` is given, whenever a GNOME proxy setting changes.
+///
+/// The thread runs a Glib main loop on a new main context, and exits when the returned guard is
+/// dropped.  Gio emits network changes on the main context which first created the default
+/// network monitor, so this only works if the application did not create the default monitor on
+/// another thread before.  The thread records an active subscription in `settings` while it
+/// receives changes of the GNOME proxy settings.
+#[cfg(feature = "gio")]
+fn spawn_gio_ticker(ticks: mpsc::Sender<()>, settings: Option<Subscriptions>) -> StopOnDrop {
+    use gio::prelude::*;
+
+    let context = gio::glib::MainContext::new();
+    let main_loop = gio::glib::MainLoop::new(Some(&context), false);
+    let quit = main_loop.clone();
+    std::thread::spawn(move || {
+        let result = context.with_thread_default(|| {
+            let monitor = gio::NetworkMonitor::default();
+            let network_ticks = ticks.clone();
+            let handler = monitor.connect_network_changed(move |_, available| {
+                debug!("Gio network changed, network available: {available}");
+                tick(&mut network_ticks.clone());
+            });
+            let _subscription = settings.and_then(|subscriptions| {
+                let changed = crate::unix::GnomeProxySettings::connect_changed(move |key| {
+                    debug!("GNOME proxy setting {key} changed");
+                    tick(&mut ticks.clone());
+                });
+                match changed {
+                    Ok(handler) => Some((handler, subscriptions.subscribe())),
+                    Err(error) => {
+                        warn!("Failed to watch GNOME proxy settings: {error}");
+                        None
+                    }
+                }
+            });
+            main_loop.run();
+            monitor.disconnect(handler);
+        });
+        if let Err(error) = result {
+            warn!("Failed to watch Gio network monitor: {error}");
+        }
+    });
+    // Quit the loop from within the loop, because quitting before the loop runs has no effect.
+    StopOnDrop::new(move || quit.context().invoke(move || quit.quit()))
+}
+
+/// The dconf directory of the GNOME proxy settings.
+#[cfg(all(unix, not(target_os = "macos"), feature = "portal"))]
+const DCONF_PROXY_DIR: &str = "/system/proxy/";
+
+/// Whether a dconf change of `keys` relative to `prefix` affects the GNOME proxy settings.
+///
+/// An empty key denotes `prefix` itself, and paths of directories end with a slash.
+#[cfg(all(unix, not(target_os = "macos"), feature = "portal"))]
+fn is_dconf_proxy_change(prefix: &str, keys: &[String]) -> bool {
+    keys.iter().any(|key| {
+        let path = format!("{prefix}{key}");
+        path.starts_with(DCONF_PROXY_DIR)
+            || (path.ends_with('/') && DCONF_PROXY_DIR.starts_with(&path))
+    })
+}
+
+/// Receive a tick for every change of the GNOME proxy settings which dconf reports on `connection`.
+#[cfg(all(unix, not(target_os = "macos"), feature = "portal"))]
+async fn receive_dconf_proxy_changes(
+    connection: &zbus::Connection,
+) -> zbus::Result<impl Stream<Item = ()>> {
+    let rule = zbus::MatchRule::builder()
+        .msg_type(zbus::MessageType::Signal)
+        .interface("ca.desrt.dconf.Writer")?
+        .member("Notify")?
+        .build();
+    let messages = zbus::MessageStream::for_match_rule(rule, connection, None).await?;
+    Ok(messages.filter_map(|message| {
+        let changed = message
+            .ok()
+            .and_then(|message| message.body::<(String, Vec<String>, String)>().ok())
+            .map_or(false, |(prefix, keys, _)| {
+                is_dconf_proxy_change(&prefix, &keys)
+            });
+        futures_util::future::ready(changed.then_some(()))
+    }))
+}
+
+/// Send a tick to `ticks` whenever the portal or dconf report changed proxy settings, until
+/// `stopped` completes.
+///
+/// Record an active subscription in `subscriptions` while receiving changes from either source.
+#[cfg(all(unix, not(target_os = "macos"), feature = "portal"))]
+async fn watch_dbus_settings(
+    mut ticks: mpsc::Sender<()>,
+    subscriptions: Subscriptions,
+    stopped: futures_channel::oneshot::Receiver<()>,
+) {
+    type Changes = Pin<Box<dyn Stream<Item = ()>>>;
+
+    let connection = match zbus::Connection::session().await {
+        Ok(connection) => connection,
+        Err(error) => {
+            warn!("Failed to connect to DBus to watch proxy settings: {error}");
+            return;
+        }
+    };
+    let portal = crate::unix::FreedesktopPortalProxyResolver::new(connection.clone());
+    let portal: Option<Changes> = match portal.receive_proxy_settings_changed().await {
+        Ok(changes) => Some(Box::pin(changes.map(|change| {
+            debug!(
+                "Portal proxy setting {}.{} changed",
+                change.namespace, change.key
+            );
+        }))),
+        Err(error) => {
+            debug!("Failed to watch proxy settings of the portal: {error}");
+            None
+        }
+    };
+    let dconf: Option<Changes> = match receive_dconf_proxy_changes(&connection).await {
+        Ok(changes) => Some(Box::pin(
+            changes.map(|()| debug!("dconf proxy settings changed")),
+        )),
+        Err(error) => {
+            debug!("Failed to watch dconf: {error}");
+            None
+        }
+    };
+    if portal.is_none() && dconf.is_none() {
+        warn!("Failed to watch proxy settings on DBus");
+        return;
+    }
+    let empty = || -> Changes { Box::pin(futures_util::stream::empty()) };
+    let mut changes =
+        futures_util::stream::select(portal.unwrap_or_else(empty), dconf.unwrap_or_else(empty))
+            .take_until(stopped);
+    let _subscription = subscriptions.subscribe();
+    while changes.next().await.is_some() {
+        if !tick(&mut ticks) {
+            break;
+        }
+    }
+}
+
+/// Spawn a thread which sends a tick to `ticks` whenever the proxy resolver portal or dconf
+/// report changed proxy settings on DBus.
+///
+/// The thread exits when the returned guard is dropped.
+#[cfg(all(unix, not(target_os = "macos"), feature = "portal"))]
+fn spawn_dbus_ticker(ticks: mpsc::Sender<()>, subscriptions: Subscriptions) -> StopOnDrop {
+    let (stop, stopped) = futures_channel::oneshot::channel::<()>();
+    std::thread::spawn(move || {
+        let watch = watch_dbus_settings(ticks, subscriptions, stopped);
+        // With the tokio feature zbus needs a tokio runtime to connect to the bus.
+        #[cfg(feature = "tokio")]
+        match tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+        {
+            Ok(runtime) => runtime.block_on(watch),
+            Err(error) => warn!("Failed to start runtime to watch proxy settings: {error}"),
+        }
+        #[cfg(not(feature = "tokio"))]
+        async_io::block_on(watch);
+    });
+    StopOnDrop::new(move || drop(stop))
+}
+
+/// Spawn a thread which sends a tick to `ticks` whenever the Internet Settings in the registry
+/// change.
+///
+/// The thread exits when the returned guard is dropped, and records an active subscription in
+/// `subscriptions` while it waits for changes.
+#[cfg(windows)]
+fn spawn_registry_ticker(
+    mut ticks: mpsc::Sender<()>,
+    subscriptions: Subscriptions,
+) -> Option<StopOnDrop> {
+    use crate::windows::{Event, InternetSettingsWatch};
+
+    let stop = match Event::new() {
+        Ok(stop) => Arc::new(stop),
+        Err(error) => {
+            warn!("Failed to create event to watch Internet Settings: {error}");
+            return None;
+        }
+    };
+    let stopped = stop.clone();
+    std::thread::spawn(move || {
+        let watch = match InternetSettingsWatch::new() {
+            Ok(watch) => watch,
+            Err(error) => {
+                warn!("Failed to watch Internet Settings: {error}");
+                return;
+            }
+        };
+        let _subscription = subscriptions.subscribe();
+        loop {
+            match watch.wait(&stopped) {
+                Ok(true) => {
+                    debug!("Internet Settings changed");
+                    if !tick(&mut ticks) {
+                        break;
+                    }
+                }
+                Ok(false) => break,
+                Err(error) => {
+                    warn!("Failed to wait for changes of Internet Settings: {error}");
+                    break;
+                }
+            }
+        }
+    });
+    Some(StopOnDrop::new(move || {
+        if let Err(error) = stop.set() {
+            warn!("Failed to stop watching Internet Settings: {error}");
+        }
+    }))
+}
+
+/// Spawn a thread which sends a tick to `ticks` whenever the proxy settings in the dynamic store
+/// of the system configuration change.
+///
+/// The thread runs a run loop with a dynamic store source, and exits shortly after the returned
+/// guard is dropped.  It records an active subscription in `subscriptions` while it waits for
+/// changes.
+#[cfg(target_os = "macos")]
+fn spawn_dynamic_store_ticker(ticks: mpsc::Sender<()>, subscriptions: Subscriptions) -> StopOnDrop {
+    use system_configuration::core_foundation::array::CFArray;
+    use system_configuration::core_foundation::runloop::{kCFRunLoopDefaultMode, CFRunLoop};
+    use system_configuration::core_foundation::string::CFString;
+    use system_configuration::dynamic_store::{
+        SCDynamicStore, SCDynamicStoreBuilder, SCDynamicStoreCallBackContext,
+    };
+
+    fn changed(_: SCDynamicStore, _: CFArray<CFString>, ticks: &mut mpsc::Sender<()>) {
+        debug!("Proxy settings changed in dynamic store");
+        tick(ticks);
+    }
+
+    let (stop, stopped) = stop_flag();
+    std::thread::spawn(move || {
+        let store = SCDynamicStoreBuilder::new("system_proxy")
+            .callback_context(SCDynamicStoreCallBackContext {
+                callout: changed,
+                info: ticks,
+            })
+            .build();
+        let keys = CFArray::from_CFTypes(&[CFString::new("State:/Network/Global/Proxies")]);
+        let patterns = CFArray::<CFString>::from_CFTypes(&[]);
+        if !store.set_notification_keys(&keys, &patterns) {
+            warn!("Failed to watch proxy settings in dynamic store");
+            return;
+        }
+        let source = store.create_run_loop_source();
+        // SAFETY: kCFRunLoopDefaultMode is an immutable constant.
+        let mode = unsafe { kCFRunLoopDefaultMode };
+        CFRunLoop::get_current().add_source(&source, mode);
+        let _subscription = subscriptions.subscribe();
+        while !stopped.load(Ordering::SeqCst) {
+            CFRunLoop::run_in_mode(mode, STOP_CHECK_INTERVAL, false);
+        }
+    });
+    stop
+}
+
+/// Wait for ticks on `ticks` and compare snapshots from `capture`, until a snapshot differs from
+/// `last`.
+async fn next_change<C, F>(
+    capture: C,
+    last: Option<SystemProxySnapshot>,
+    mut ticks: mpsc::Receiver<()>,
+) -> Option<(
+    ProxyChange,
+    (C, Option<SystemProxySnapshot>, mpsc::Receiver<()>),
+)>
+where
+    C: Fn() -> F,
+    F: Future<Output = SystemProxySnapshot>,
+{
+    let mut last = match last {
+        Some(last) => last,
+        None => capture().await,
+    };
+    while ticks.next().await.is_some() {
+        let current = capture().await;
+        let changes = last.diff(&current);
+        if !changes.is_empty() {
+            let change = ProxyChange {
+                changes,
+                snapshot: current.clone(),
+            };
+            return Some((change, (capture, Some(current), ticks)));
+        }
+        last = current;
+    }
+    None
+}
+
+/// A stream of changes of the proxy configuration across all backends.
+///
+/// The watcher captures a [`SystemProxySnapshot`] when first polled, and then captures a new
+/// snapshot and yields a [`ProxyChange`] whenever a source of changes reports that the
+/// configuration may have changed and the new snapshot differs from the previous one.  It does
+/// not depend on a specific async runtime; snapshots are captured on the task which polls the
+/// watcher, and separate threads listen for changes.  These threads exit when the watcher is
+/// dropped.
+///
+/// [`ProxyWatcher::new`] subscribes to change notifications of the system settings, and polls
+/// only while no such subscription is active; [`ProxyWatcher::polling`] always polls.
+///
+/// On Linux with the `netlink` feature, and with the `gio` feature through Gio's network
+/// monitor, the watcher additionally captures a new snapshot whenever the network changes, e.g.
+/// when a VPN connects or a captive portal lets the system through.
+///
+/// Note that the watcher may block the polling task while capturing a snapshot; see
+/// [`SystemProxySnapshot::capture`].
+pub struct ProxyWatcher {
+    changes: Pin<Box<dyn Stream<Item = ProxyChange> + Send>>,
+    _sources: Vec<StopOnDrop>,
+}
+
+impl ProxyWatcher {
+    /// Watch the proxy configuration through change notifications of the system settings.
+    ///
+    /// Subscribe to
+    ///
+    /// - changes of the GNOME proxy settings with the `gio` feature,
+    /// - changes of the proxy settings on the settings portal and in dconf with the `portal`
+    ///   feature,
+    /// - changes of the Internet Settings in the registry on Windows, and
+    /// - changes of the proxy settings in the dynamic store on macOS.
+    ///
+    /// Capture a snapshot every `fallback_interval` while none of these subscriptions is active,
+    /// e.g. if no DBus session bus runs.  No source reports changes of the proxy environment
+    /// variables of the process, so the watcher only notices these together with other changes,
+    /// or while it falls back to polling.
+    pub fn new(fallback_interval: Duration) -> Self {
+        Self::with_sources(fallback_interval, true)
+    }
+
+    /// Watch the proxy configuration by capturing a snapshot every `interval`.
+    pub fn polling(interval: Duration) -> Self {
+        Self::with_sources(interval, false)
+    }
+
+    /// Spawn all sources of changes, and a timer with `interval`.
+    ///
+    /// With `settings` subscribe to changes of the system settings, and only tick the timer while
+    /// no subscription is active.
+    fn with_sources(interval: Duration, settings: bool) -> Self {
+        let (sender, ticks) = mpsc::channel(0);
+        let subscriptions = Subscriptions::default();
+        let mut sources = Vec::new();
+        #[cfg(all(target_os = "linux", feature = "netlink"))]
+        sources.extend(spawn_network_ticker(sender.clone()));
+        #[cfg(feature = "gio")]
+        sources.push(spawn_gio_ticker(
+            sender.clone(),
+            settings.then(|| subscriptions.clone()),
+        ));
+        if settings {
+            #[cfg(all(unix, not(target_os = "macos"), feature = "portal"))]
+            sources.push(spawn_dbus_ticker(sender.clone(), subscriptions.clone()));
+            #[cfg(windows)]
+            sources.extend(spawn_registry_ticker(sender.clone(), subscriptions.clone()));
+            #[cfg(target_os = "macos")]
+            sources.push(spawn_dynamic_store_ticker(
+                sender.clone(),
+                subscriptions.clone(),
+            ));
+        }
+        sources.push(spawn_ticker(
+            interval,
+            sender,
+            settings.then_some(subscriptions),
+        ));
+        let changes = futures_util::stream::unfold(
+            (SystemProxySnapshot::capture, None, ticks),
+            |(capture, last, ticks)| next_change(capture, last, ticks),
+        );
+        Self {
+            changes: Box::pin(changes),
+            _sources: sources,
+        }
+    }
+}
+
+impl ProxyWatcher {
+    /// Yield an event for every list of `changes`, with a fresh snapshot.
+    #[cfg(feature = "file")]
+    pub(crate) fn from_changes(changes: mpsc::UnboundedReceiver<Vec<Change>>) -> Self {
+        let changes = changes.then(|changes| async move {
+            ProxyChange {
+                changes,
+                snapshot: SystemProxySnapshot::capture().await,
+            }
+        });
+        Self {
+            changes: Box::pin(changes),
+            _sources: Vec::new(),
+        }
+    }
+}
+
+impl std::fmt::Debug for ProxyWatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProxyWatcher").finish_non_exhaustive()
+    }
+}
+
+impl Stream for ProxyWatcher {
+    type Item = ProxyChange;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.changes.as_mut().poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    fn snapshot(http_proxy: Option<&str>) -> SystemProxySnapshot {
+        SystemProxySnapshot {
+            environment: std::iter::once((
+                "http_proxy".to_string(),
+                http_proxy.map(ToString::to_string),
+            ))
+            .collect(),
+            #[cfg(feature = "gio")]
+            gnome: Ok(Default::default()),
+            #[cfg(windows)]
+            internet_settings: Ok(Default::default()),
+            probes: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn next_change_skips_unchanged_snapshots() {
+        let snapshots = Mutex::new(VecDeque::from(vec![
+            snapshot(None),
+            snapshot(None),
+            snapshot(Some("http://proxy.example.com")),
+            snapshot(Some("http://proxy.example.com")),
+        ]));
+        let capture = || {
+            let next = snapshots.lock().unwrap().pop_front().unwrap();
+            futures_util::future::ready(next)
+        };
+        let (mut sender, ticks) = mpsc::channel(3);
+        for _ in 0..3 {
+            sender.try_send(()).unwrap();
+        }
+        drop(sender);
+
+        let (change, (capture, last, ticks)) = block_on(next_change(capture, None, ticks)).unwrap();
+        assert_eq!(
+            change,
+            ProxyChange {
+                changes: vec![Change {
+                    field: "environment.http_proxy".to_string(),
+                    old: None,
+                    new: Some("http://proxy.example.com".to_string()),
+                }],
+                snapshot: snapshot(Some("http://proxy.example.com")),
+            }
+        );
+        assert_eq!(last, Some(change.snapshot));
+        assert!(block_on(next_change(capture, last, ticks)).is_none());
+        assert!(snapshots.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn ticker_exits_on_drop() {
+        let (sender, mut ticks) = mpsc::channel(0);
+        let stop = spawn_ticker(Duration::from_millis(1), sender, None);
+        assert_eq!(block_on(ticks.next()), Some(()));
+        drop(stop);
+        // The stream only ends once the thread dropped its sender.
+        block_on(ticks.for_each(|_| async {}));
+    }
+
+    #[test]
+    fn ticker_skips_ticks_while_subscribed() {
+        let subscriptions = Subscriptions::default();
+        let subscription = subscriptions.subscribe();
+        let (sender, mut ticks) = mpsc::channel(0);
+        let stop = spawn_ticker(Duration::from_millis(1), sender, Some(subscriptions));
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(futures_util::FutureExt::now_or_never(ticks.next()), None);
+        drop(subscription);
+        assert_eq!(block_on(ticks.next()), Some(()));
+        drop(stop);
+        block_on(ticks.for_each(|_| async {}));
+    }
+
+    #[cfg(all(target_os = "linux", feature = "netlink"))]
+    #[test]
+    fn network_ticker_exits_on_drop() {
+        let (sender, ticks) = mpsc::channel(0);
+        if let Some(stop) = spawn_network_ticker(sender) {
+            drop(stop);
+            block_on(ticks.for_each(|_| async {}));
+        }
+    }
+
+    #[cfg(all(unix, not(target_os = "macos"), feature = "portal"))]
+    #[test]
+    fn dconf_proxy_change() {
+        let keys = |keys: &[&str]| keys.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert!(is_dconf_proxy_change("/system/proxy/mode", &keys(&[""])));
+        assert!(is_dconf_proxy_change(
+            "/system/proxy/",
+            &keys(&["http/host", "http/port"])
+        ));
+        assert!(is_dconf_proxy_change("/system/", &keys(&["proxy/"])));
+        assert!(is_dconf_proxy_change("/", &keys(&[""])));
+        assert!(!is_dconf_proxy_change(
+            "/org/gnome/",
+            &keys(&["desktop/interface/clock-format"])
+        ));
+        assert!(!is_dconf_proxy_change(
+            "/system/locale/",
+            &keys(&["region"])
+        ));
+        assert!(!is_dconf_proxy_change("/system/proxy", &keys(&["-other"])));
+    }
+
+    #[cfg(all(unix, not(target_os = "macos"), feature = "portal"))]
+    #[test]
+    fn dbus_ticker_exits_on_drop() {
+        let (sender, ticks) = mpsc::channel(0);
+        drop(spawn_dbus_ticker(sender, Subscriptions::default()));
+        block_on(ticks.for_each(|_| async {}));
+    }
+}
@@ -0,0 +1,27 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Provide proxy settings for Windows.
+//!
+//! This module provides access to the per-user Internet Settings in the registry, which hold the
+//! proxy configuration of the system, and to the proxy settings of individual dial-up and VPN
+//! connections.  [`WinHttpSettings`] reads the machine-wide WinHttp proxy settings through the
+//! `netsh` tool, and [`WinHttpProxyResolver`] resolves proxies with WinHttp, including proxy
+//! auto-config.
+
+mod connections;
+mod netsh;
+mod proxy_list;
+mod settings;
+mod winhttp;
+
+pub use self::connections::ConnectionSettings;
+pub use self::netsh::WinHttpSettings;
+pub use self::proxy_list::{parse_bypass_list, ProxyServerList};
+pub use self::settings::{notify_settings_changed, InternetSettings};
+#[cfg(feature = "watch")]
+pub(crate) use self::settings::{Event, InternetSettingsWatch};
+pub use self::winhttp::{WinHttpOptions, WinHttpProxyResolver};
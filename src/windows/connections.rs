@@ -0,0 +1,261 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Read the proxy settings of individual connections, e.g. VPN or dial-up connections.
+
+use std::io::Result;
+
+use url::Url;
+use windows_sys::Win32::System::Registry::KEY_QUERY_VALUE;
+
+use super::settings::{Key, INTERNET_SETTINGS_KEY};
+use super::{parse_bypass_list, ProxyServerList};
+use crate::env::{NoProxy, NoProxyRules};
+use crate::proxy::{is_non_network_url, DirectReason, ProxyDecision, ProxyResolver};
+
+/// The registry key of the per-connection settings, relative to `HKEY_CURRENT_USER`.
+fn connections_key() -> String {
+    format!(r"{INTERNET_SETTINGS_KEY}\Connections")
+}
+
+/// The registry value which holds a backup of the legacy settings, which is no connection.
+const SAVED_LEGACY_SETTINGS: &str = "SavedLegacySettings";
+
+/// Use a proxy server.
+const PROXY_TYPE_PROXY: u32 = 0x2;
+/// Use a proxy auto-config script.
+const PROXY_TYPE_AUTO_PROXY_URL: u32 = 0x4;
+/// Automatically detect settings.
+const PROXY_TYPE_AUTO_DETECT: u32 = 0x8;
+
+/// A reader for the binary format of connection settings.
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn bytes(&mut self, len: usize) -> Option<&[u8]> {
+        if self.0.len() < len {
+            None
+        } else {
+            let (value, rest) = self.0.split_at(len);
+            self.0 = rest;
+            Some(value)
+        }
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        Some(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+    }
+}
+
+/// The proxy settings of a single connection.
+///
+/// Windows keeps separate proxy settings for every dial-up and VPN connection in the
+/// `Connections` subkey of the Internet Settings, and uses the settings of the connection which
+/// is currently up.  The `DefaultConnectionSettings` connection holds the settings for the LAN.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionSettings {
+    /// The name of the connection.
+    pub name: String,
+    /// Whether to use the proxy server.
+    pub proxy_enable: bool,
+    /// Whether to automatically detect settings.
+    pub auto_detect: bool,
+    /// The proxy server, in the format of [`ProxyServerList`].
+    pub proxy_server: String,
+    /// Hosts to connect to directly, in the format of [`parse_bypass_list`].
+    pub proxy_override: String,
+    /// The URL of a proxy auto-config script, or `None` if the connection uses no script.
+    pub auto_config_url: Option<String>,
+}
+
+impl ConnectionSettings {
+    /// Parse the binary connection settings `data` of the connection `name`.
+    ///
+    /// Return `None` if `data` is truncated.
+    pub fn parse<S: Into<String>>(name: S, data: &[u8]) -> Option<Self> {
+        let mut reader = Reader(data);
+        let _version = reader.u32()?;
+        let _counter = reader.u32()?;
+        let flags = reader.u32()?;
+        let proxy_server = reader.string()?;
+        let proxy_override = reader.string()?;
+        let auto_config_url = reader.string()?;
+        Some(Self {
+            name: name.into(),
+            proxy_enable: flags & PROXY_TYPE_PROXY != 0,
+            auto_detect: flags & PROXY_TYPE_AUTO_DETECT != 0,
+            proxy_server,
+            proxy_override,
+            auto_config_url: Some(auto_config_url)
+                .filter(|url| flags & PROXY_TYPE_AUTO_PROXY_URL != 0 && !url.is_empty()),
+        })
+    }
+
+    /// Read the settings of all connections of this user.
+    ///
+    /// Skip connections whose settings are invalid.
+    pub fn read_all() -> Result<Vec<Self>> {
+        let key = Key::open_current_user(&connections_key(), KEY_QUERY_VALUE)?;
+        let mut connections = Vec::new();
+        for name in key.value_names()? {
+            if name == SAVED_LEGACY_SETTINGS {
+                continue;
+            }
+            if let Some(connection) = Self::read_from(&key, name)? {
+                connections.push(connection);
+            }
+        }
+        Ok(connections)
+    }
+
+    /// Read the settings of the connection with the given `name`.
+    ///
+    /// Return `None` if there's no such connection, or if its settings are invalid.
+    pub fn read(name: &str) -> Result<Option<Self>> {
+        let key = Key::open_current_user(&connections_key(), KEY_QUERY_VALUE)?;
+        Self::read_from(&key, name.to_string())
+    }
+
+    fn read_from(key: &Key, name: String) -> Result<Option<Self>> {
+        Ok(key.get_binary(&name)?.and_then(|data| {
+            let connection = Self::parse(name.as_str(), &data);
+            if connection.is_none() {
+                debug!("Skipping invalid settings of connection {name}");
+            }
+            connection
+        }))
+    }
+
+    /// Parse the [proxy server](ConnectionSettings::proxy_server) list.
+    pub fn proxy_servers(&self) -> ProxyServerList {
+        ProxyServerList::parse(&self.proxy_server)
+    }
+
+    /// Parse the [proxy override](ConnectionSettings::proxy_override) list into no proxy rules.
+    pub fn bypass_rules(&self) -> NoProxyRules {
+        parse_bypass_list(&self.proxy_override)
+    }
+
+    /// Decide how to connect to `url` with the settings of this connection.
+    ///
+    /// Connect directly to [non-network URLs](is_non_network_url).  Return
+    /// [`ProxyDecision::Unspecified`] if the connection uses a proxy auto-config script or
+    /// automatic detection, because this crate can't evaluate these.  Otherwise connect
+    /// directly if the proxy is disabled or the bypass list matches `url`, or use the proxy
+    /// server list.
+    pub fn decide(&self, url: &Url) -> ProxyDecision {
+        if is_non_network_url(url) {
+            ProxyDecision::Direct
+        } else if self.auto_config_url.is_some() || self.auto_detect {
+            ProxyDecision::Unspecified
+        } else if !self.proxy_enable || self.bypass_rules().no_proxy_for(url) {
+            ProxyDecision::Direct
+        } else {
+            self.proxy_servers()
+                .for_url(url)
+                .map_or(ProxyDecision::Direct, ProxyDecision::Proxy)
+        }
+    }
+}
+
+impl ProxyResolver for ConnectionSettings {
+    fn for_url(&self, url: &Url) -> Option<Url> {
+        self.decide(url).into_proxy()
+    }
+
+    fn decide(&self, url: &Url) -> ProxyDecision {
+        ConnectionSettings::decide(self, url)
+    }
+
+    /// Report the bypass rule which matches `url`, and a disabled proxy as no proxy.
+    fn direct_reason(&self, url: &Url) -> Option<DirectReason> {
+        match self.decide(url) {
+            ProxyDecision::Direct if is_non_network_url(url) => Some(DirectReason::NonNetworkUrl),
+            ProxyDecision::Direct if !self.proxy_enable => Some(DirectReason::NoProxyConfigured),
+            ProxyDecision::Direct => self
+                .bypass_rules()
+                .matching_rule(url.host().as_ref())
+                .map(DirectReason::NoProxyRule)
+                .or(Some(DirectReason::NoProxyConfigured)),
+            decision => DirectReason::from_decision(&decision),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn settings(flags: u32, proxy_server: &str, proxy_override: &str, url: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&0x46u32.to_le_bytes());
+        data.extend_from_slice(&1u32.to_le_bytes());
+        data.extend_from_slice(&flags.to_le_bytes());
+        for s in [proxy_server, proxy_override, url] {
+            data.extend_from_slice(&(s.len() as u32).to_le_bytes());
+            data.extend_from_slice(s.as_bytes());
+        }
+        // Trailing fields we don't read
+        data.extend_from_slice(&[0; 32]);
+        data
+    }
+
+    #[test]
+    fn parse_and_decide() {
+        let data = settings(0x3, "http=proxy:3128", "*.corp.example.com", "");
+        let connection = ConnectionSettings::parse("Corp VPN", &data).unwrap();
+        assert_eq!(
+            connection,
+            ConnectionSettings {
+                name: "Corp VPN".to_string(),
+                proxy_enable: true,
+                auto_detect: false,
+                proxy_server: "http=proxy:3128".to_string(),
+                proxy_override: "*.corp.example.com".to_string(),
+                auto_config_url: None,
+            }
+        );
+        assert_eq!(
+            connection.decide(&Url::parse("http://example.com").unwrap()),
+            ProxyDecision::Proxy(Url::parse("http://proxy:3128").unwrap())
+        );
+        assert_eq!(
+            connection.decide(&Url::parse("http://wiki.corp.example.com").unwrap()),
+            ProxyDecision::Direct
+        );
+        assert_eq!(
+            connection.decide(&Url::parse("https://example.com").unwrap()),
+            ProxyDecision::Direct
+        );
+    }
+
+    #[test]
+    fn parse_auto_config() {
+        let data = settings(0x5, "", "", "http://wpad/wpad.dat");
+        let connection = ConnectionSettings::parse("Dial-up", &data).unwrap();
+        assert!(!connection.proxy_enable);
+        assert_eq!(
+            connection.auto_config_url.as_deref(),
+            Some("http://wpad/wpad.dat")
+        );
+        assert_eq!(
+            connection.decide(&Url::parse("http://example.com").unwrap()),
+            ProxyDecision::Unspecified
+        );
+    }
+
+    #[test]
+    fn parse_truncated() {
+        let data = settings(0x3, "proxy:3128", "", "");
+        assert_eq!(ConnectionSettings::parse("LAN", &data[..18]), None);
+    }
+}
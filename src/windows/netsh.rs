@@ -0,0 +1,225 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Read the WinHttp proxy settings from the output of `netsh winhttp show proxy`.
+
+use std::io::{Error, ErrorKind, Result};
+use std::process::Command;
+
+use url::Url;
+
+use super::{parse_bypass_list, ProxyServerList};
+use crate::env::{NoProxy, NoProxyRules};
+use crate::proxy::{is_non_network_url, DirectReason, ProxyDecision, ProxyResolver};
+
+/// The machine-wide WinHttp proxy settings.
+///
+/// Services and other programs which use WinHttp without proxy auto-config use these settings,
+/// which `netsh winhttp set proxy` configures.  Reading these settings through `netsh` needs
+/// neither WinHttp nor registry access, e.g. in service sandboxes.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct WinHttpSettings {
+    /// The proxy server, in the format of [`ProxyServerList`].
+    ///
+    /// `None` for direct access.
+    pub proxy_server: Option<String>,
+    /// Hosts to connect to directly, in the format of [`parse_bypass_list`].
+    pub bypass_list: String,
+}
+
+impl WinHttpSettings {
+    /// Read the current WinHttp settings with `netsh winhttp show proxy`.
+    pub fn read() -> Result<Self> {
+        let output = Command::new("netsh")
+            .args(["winhttp", "show", "proxy"])
+            .output()?;
+        if output.status.success() {
+            Ok(Self::parse(&String::from_utf8_lossy(&output.stdout)))
+        } else {
+            Err(Error::new(
+                ErrorKind::Other,
+                format!(
+                    "netsh failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            ))
+        }
+    }
+
+    /// Parse the `output` of `netsh winhttp show proxy`.
+    ///
+    /// Take the proxy server and the bypass list from the `Proxy Server(s)` and `Bypass List`
+    /// lines.  `netsh` translates these labels on non-English systems; as the labels come in
+    /// fixed order, fall back to the first and second label otherwise.  Without any labels,
+    /// e.g. for `Direct access (no proxy server).`, return direct access.
+    pub fn parse(output: &str) -> Self {
+        let values = output
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .map(|(label, value)| (label.trim(), value.trim()))
+            .filter(|(label, value)| !label.is_empty() && !value.is_empty())
+            .collect::<Vec<_>>();
+        let value = |label: &str, index: usize| {
+            values
+                .iter()
+                .find(|(candidate, _)| candidate.eq_ignore_ascii_case(label))
+                .or_else(|| values.get(index))
+                .map(|(_, value)| value.to_string())
+        };
+        Self {
+            proxy_server: value("Proxy Server(s)", 0),
+            bypass_list: value("Bypass List", 1)
+                .filter(|list| !list.starts_with('('))
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Parse the [proxy server](WinHttpSettings::proxy_server) list.
+    pub fn proxy_servers(&self) -> ProxyServerList {
+        self.proxy_server
+            .as_deref()
+            .map(ProxyServerList::parse)
+            .unwrap_or_default()
+    }
+
+    /// Parse the [bypass list](WinHttpSettings::bypass_list) into no proxy rules.
+    pub fn bypass_rules(&self) -> NoProxyRules {
+        parse_bypass_list(&self.bypass_list)
+    }
+
+    /// Decide how to connect to `url` with these settings.
+    ///
+    /// Connect directly for direct access, for [non-network URLs](is_non_network_url), or if the
+    /// bypass list matches `url`, or else use the proxy server list.
+    pub fn decide(&self, url: &Url) -> ProxyDecision {
+        if self.proxy_server.is_none()
+            || is_non_network_url(url)
+            || self.bypass_rules().no_proxy_for(url)
+        {
+            ProxyDecision::Direct
+        } else {
+            self.proxy_servers()
+                .for_url(url)
+                .map_or(ProxyDecision::Direct, ProxyDecision::Proxy)
+        }
+    }
+}
+
+impl ProxyResolver for WinHttpSettings {
+    fn for_url(&self, url: &Url) -> Option<Url> {
+        self.decide(url).into_proxy()
+    }
+
+    fn decide(&self, url: &Url) -> ProxyDecision {
+        WinHttpSettings::decide(self, url)
+    }
+
+    /// Report the bypass rule which matches `url`, and direct access as no proxy.
+    fn direct_reason(&self, url: &Url) -> Option<DirectReason> {
+        match self.decide(url) {
+            ProxyDecision::Direct if is_non_network_url(url) => Some(DirectReason::NonNetworkUrl),
+            ProxyDecision::Direct if self.proxy_server.is_none() => {
+                Some(DirectReason::NoProxyConfigured)
+            }
+            ProxyDecision::Direct => self
+                .bypass_rules()
+                .matching_rule(url.host().as_ref())
+                .map(DirectReason::NoProxyRule)
+                .or(Some(DirectReason::NoProxyConfigured)),
+            decision => DirectReason::from_decision(&decision),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn parse_proxy() {
+        let output = "
+Current WinHTTP proxy settings:
+
+    Proxy Server(s) :  http=proxy:3128;https=proxy:3129
+    Bypass List     :  <local>;*.corp.example.com
+";
+        let settings = WinHttpSettings::parse(output);
+        assert_eq!(
+            settings,
+            WinHttpSettings {
+                proxy_server: Some("http=proxy:3128;https=proxy:3129".to_string()),
+                bypass_list: "<local>;*.corp.example.com".to_string(),
+            }
+        );
+        let decide = |url: &str| settings.decide(&Url::parse(url).unwrap());
+        assert_eq!(
+            decide("https://example.com"),
+            ProxyDecision::Proxy(Url::parse("http://proxy:3129").unwrap())
+        );
+        assert_eq!(
+            decide("http://wiki.corp.example.com"),
+            ProxyDecision::Direct
+        );
+        assert_eq!(
+            settings.direct_reason(&Url::parse("http://intranet").unwrap()),
+            Some(DirectReason::NoProxyRule("<local>".to_string()))
+        );
+        assert_eq!(
+            settings.direct_reason(&Url::parse("mailto:john@example.com").unwrap()),
+            Some(DirectReason::NonNetworkUrl)
+        );
+    }
+
+    #[test]
+    fn parse_without_bypass_list() {
+        let output = "
+Current WinHTTP proxy settings:
+
+    Proxy Server(s) :  proxy:3128
+    Bypass List     :  (none)
+";
+        assert_eq!(
+            WinHttpSettings::parse(output),
+            WinHttpSettings {
+                proxy_server: Some("proxy:3128".to_string()),
+                bypass_list: String::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_localized() {
+        let output = "
+Aktuelle WinHTTP-Proxyeinstellungen:
+
+    Proxyserver :  proxy:3128
+    Umgehungsliste     :  intranet
+";
+        assert_eq!(
+            WinHttpSettings::parse(output),
+            WinHttpSettings {
+                proxy_server: Some("proxy:3128".to_string()),
+                bypass_list: "intranet".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_direct_access() {
+        let output = "
+Current WinHTTP proxy settings:
+
+    Direct access (no proxy server).
+";
+        let settings = WinHttpSettings::parse(output);
+        assert_eq!(settings, WinHttpSettings::default());
+        assert_eq!(
+            settings.direct_reason(&Url::parse("http://example.com").unwrap()),
+            Some(DirectReason::NoProxyConfigured)
+        );
+    }
+}
@@ -0,0 +1,234 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Parse proxy server and bypass lists of WinHttp and the Internet Settings.
+
+use url::Url;
+
+use crate::env::{HostInterner, NoProxyRule, NoProxyRules};
+use crate::proxy::{ProxyResolver, ResolvedProxy};
+
+/// A list of proxy servers as WinHttp and the Internet Settings store them.
+///
+/// The list is either a single `host:port` for all schemes, or a list of `scheme=host:port`
+/// entries separated by semicolons or whitespace, e.g.
+/// `http=proxy1:80;https=proxy2:443;socks=proxy3:1080`.  Entries may also be full proxy URLs.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ProxyServerList {
+    /// The proxy for all schemes without a scheme-specific proxy.
+    all: Option<ResolvedProxy>,
+    /// Proxies by scheme, in order of the list.
+    schemes: Vec<(String, ResolvedProxy)>,
+}
+
+/// Parse a single proxy server `entry` of a list.
+///
+/// Entries without a scheme are HTTP proxies, except for SOCKS entries which are SOCKS 4 proxies
+/// like in Windows.
+fn parse_entry(scheme: Option<&str>, entry: &str) -> Option<ResolvedProxy> {
+    let url = if entry.contains("://") {
+        entry.to_string()
+    } else if scheme == Some("socks") {
+        format!("socks4://{entry}")
+    } else {
+        format!("http://{entry}")
+    };
+    match Url::parse(&url) {
+        Ok(url) => ResolvedProxy::new(url),
+        Err(error) => {
+            debug!("Skipping invalid proxy server {entry}: {error}");
+            None
+        }
+    }
+}
+
+impl ProxyServerList {
+    /// Parse a proxy server `list`.
+    ///
+    /// Skip invalid entries.  If the list has multiple entries for the same scheme use the first.
+    pub fn parse(list: &str) -> Self {
+        let mut proxies = Self::default();
+        for entry in list
+            .split(|c: char| c == ';' || c.is_whitespace())
+            .filter(|entry| !entry.is_empty())
+        {
+            match entry.split_once('=') {
+                Some((scheme, server)) => {
+                    let scheme = scheme.to_ascii_lowercase();
+                    if proxies.for_scheme(&scheme).is_none() {
+                        if let Some(proxy) = parse_entry(Some(&scheme), server) {
+                            proxies.schemes.push((scheme, proxy));
+                        }
+                    }
+                }
+                None => {
+                    if proxies.all.is_none() {
+                        proxies.all = parse_entry(None, entry);
+                    }
+                }
+            }
+        }
+        proxies
+    }
+
+    /// Whether this list has no proxies at all.
+    pub fn is_empty(&self) -> bool {
+        self.all.is_none() && self.schemes.is_empty()
+    }
+
+    /// Get the proxy for all schemes, if any.
+    pub fn all(&self) -> Option<&ResolvedProxy> {
+        self.all.as_ref()
+    }
+
+    /// Get the proxy for the given `scheme`, e.g. `https` or `socks`.
+    ///
+    /// Only return a scheme-specific proxy; see [`ProxyServerList::lookup`] to fall back to
+    /// other proxies.
+    pub fn for_scheme(&self, scheme: &str) -> Option<&ResolvedProxy> {
+        self.schemes
+            .iter()
+            .find(|(candidate, _)| candidate.eq_ignore_ascii_case(scheme))
+            .map(|(_, proxy)| proxy)
+    }
+
+    /// Lookup the proxy for a connection to `url`.
+    ///
+    /// Use the proxy for the scheme of `url` if any, or else the SOCKS proxy, or else the proxy
+    /// for all schemes.  Return `None` if there's no proxy.
+    pub fn lookup(&self, url: &Url) -> Option<&ResolvedProxy> {
+        self.for_scheme(url.scheme())
+            .or_else(|| self.for_scheme("socks"))
+            .or(self.all.as_ref())
+    }
+}
+
+impl ProxyResolver for ProxyServerList {
+    fn for_url(&self, url: &Url) -> Option<Url> {
+        self.lookup(url).map(|proxy| proxy.as_url().clone())
+    }
+}
+
+/// Parse a proxy bypass `list` of WinHttp and the Internet Settings into no proxy rules.
+///
+/// The list has entries separated by semicolons or whitespace, e.g.
+/// `*.example.com;intranet;<local>`.  A single `*` bypasses the proxy for all hosts, an entry
+/// with a leading `*.` or `.` matches a domain and all its subdomains, `<local>` matches all
+/// [simple host names](NoProxyRule::MatchSimpleHostnames), and any other entry must match the
+/// host exactly.  Skip entries which [`NoProxyRules`] cannot represent, i.e. entries with other
+/// wildcards, and strip any scheme and port from entries.
+pub fn parse_bypass_list(list: &str) -> NoProxyRules {
+    let entries = list
+        .split(|c: char| c == ';' || c.is_whitespace())
+        .filter(|entry| !entry.is_empty())
+        .collect::<Vec<_>>();
+    if entries.contains(&"*") {
+        return NoProxyRules::all();
+    }
+    let mut hosts = HostInterner::default();
+    let rules = entries
+        .into_iter()
+        .filter_map(|entry| {
+            let host = entry.split_once("://").map_or(entry, |(_, host)| host);
+            // Strip the port, but leave IPv6 addresses alone
+            let host = match host.rsplit_once(':') {
+                Some((host, port)) if !host.contains(':') && port.parse::<u16>().is_ok() => host,
+                _ => host,
+            };
+            let host = host.to_ascii_lowercase();
+            let rule = match host.strip_prefix('*') {
+                Some(domain) if domain.starts_with('.') => {
+                    NoProxyRule::MatchSubdomain(hosts.intern(domain))
+                }
+                _ if host.starts_with('.') => NoProxyRule::MatchSubdomain(hosts.intern(&host)),
+                _ if host == "<local>" => NoProxyRule::MatchSimpleHostnames,
+                _ if host.contains(['*', '<']) => {
+                    debug!("Skipping unsupported proxy bypass entry {entry}");
+                    return None;
+                }
+                _ => NoProxyRule::MatchExact(hosts.intern(&host)),
+            };
+            Some(rule)
+        })
+        .collect();
+    NoProxyRules::new(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn lookup(list: &ProxyServerList, url: &str) -> Option<String> {
+        list.for_url(&Url::parse(url).unwrap())
+            .map(|proxy| proxy.to_string())
+    }
+
+    #[test]
+    fn parse_single_proxy() {
+        let list = ProxyServerList::parse("proxy.example.com:8080");
+        assert_eq!(
+            lookup(&list, "https://example.com"),
+            Some("http://proxy.example.com:8080/".to_string())
+        );
+        assert_eq!(
+            lookup(&list, "ftp://example.com"),
+            Some("http://proxy.example.com:8080/".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_per_scheme_proxies() {
+        let list = ProxyServerList::parse("http=proxy1:80;https=proxy2:443 socks=proxy3:1080");
+        assert_eq!(
+            lookup(&list, "http://example.com"),
+            Some("http://proxy1/".to_string())
+        );
+        assert_eq!(
+            lookup(&list, "https://example.com"),
+            Some("http://proxy2:443/".to_string())
+        );
+        assert_eq!(
+            lookup(&list, "ftp://example.com"),
+            Some("socks4://proxy3:1080".to_string())
+        );
+        assert!(list.all().is_none());
+    }
+
+    #[test]
+    fn parse_skips_invalid_and_duplicate_entries() {
+        let list = ProxyServerList::parse("http=[invalid;http=proxy1:80;;http=proxy2:80");
+        assert_eq!(
+            lookup(&list, "http://example.com"),
+            Some("http://proxy1/".to_string())
+        );
+        assert_eq!(lookup(&list, "https://example.com"), None);
+    }
+
+    #[test]
+    fn parse_bypass_list_rules() {
+        assert_eq!(
+            parse_bypass_list(
+                "*.Example.com; intranet;<local> 10.*;.corp.example.com;http://host:80"
+            ),
+            NoProxyRules::new(vec![
+                NoProxyRule::MatchSubdomain(".example.com".into()),
+                NoProxyRule::MatchExact("intranet".into()),
+                NoProxyRule::MatchSimpleHostnames,
+                NoProxyRule::MatchSubdomain(".corp.example.com".into()),
+                NoProxyRule::MatchExact("host".into()),
+            ])
+        );
+        assert_eq!(parse_bypass_list("intranet;*"), NoProxyRules::all());
+        assert_eq!(parse_bypass_list(""), NoProxyRules::none());
+    }
+
+    #[test]
+    fn parse_empty() {
+        assert!(ProxyServerList::parse("").is_empty());
+        assert!(ProxyServerList::parse(" ; ").is_empty());
+    }
+}
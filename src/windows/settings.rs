@@ -0,0 +1,438 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Read and write the per-user Internet Settings in the registry.
+
+use std::ffi::OsString;
+use std::io::{Error, Result};
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::ptr::{null, null_mut};
+
+#[cfg(feature = "watch")]
+use windows_sys::Win32::Foundation::{CloseHandle, HANDLE, WAIT_OBJECT_0};
+use windows_sys::Win32::Foundation::{
+    ERROR_FILE_NOT_FOUND, ERROR_NO_MORE_ITEMS, ERROR_SUCCESS, WIN32_ERROR,
+};
+use windows_sys::Win32::Networking::WinInet::{
+    InternetSetOptionW, INTERNET_OPTION_REFRESH, INTERNET_OPTION_SETTINGS_CHANGED,
+};
+use windows_sys::Win32::System::Registry::{
+    RegCloseKey, RegDeleteValueW, RegEnumValueW, RegGetValueW, RegOpenKeyExW, RegSetValueExW, HKEY,
+    HKEY_CURRENT_USER, KEY_QUERY_VALUE, KEY_SET_VALUE, REG_DWORD, REG_SAM_FLAGS, REG_SZ,
+    RRF_RT_REG_BINARY, RRF_RT_REG_DWORD, RRF_RT_REG_SZ,
+};
+#[cfg(feature = "watch")]
+use windows_sys::Win32::System::Registry::{
+    RegNotifyChangeKeyValue, KEY_NOTIFY, REG_NOTIFY_CHANGE_LAST_SET, REG_NOTIFY_CHANGE_NAME,
+};
+#[cfg(feature = "watch")]
+use windows_sys::Win32::System::Threading::{
+    CreateEventW, SetEvent, WaitForMultipleObjects, INFINITE,
+};
+use windows_sys::Win32::UI::WindowsAndMessaging::{
+    SendMessageTimeoutW, HWND_BROADCAST, SMTO_ABORTIFHUNG, WM_SETTINGCHANGE,
+};
+
+use super::{parse_bypass_list, ProxyServerList};
+use crate::env::NoProxyRules;
+
+/// The registry key of the Internet Settings, relative to `HKEY_CURRENT_USER`.
+pub(super) const INTERNET_SETTINGS_KEY: &str =
+    r"Software\Microsoft\Windows\CurrentVersion\Internet Settings";
+
+/// Encode `s` as nul-terminated wide string.
+pub(super) fn wide(s: &str) -> Vec<u16> {
+    std::ffi::OsStr::new(s)
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect()
+}
+
+fn check(code: WIN32_ERROR) -> Result<()> {
+    if code == ERROR_SUCCESS {
+        Ok(())
+    } else {
+        Err(Error::from_raw_os_error(code as i32))
+    }
+}
+
+/// An open registry key.
+pub(super) struct Key(HKEY);
+
+impl Key {
+    pub(super) fn open_current_user(subkey: &str, access: REG_SAM_FLAGS) -> Result<Self> {
+        let subkey = wide(subkey);
+        let mut key = null_mut();
+        // SAFETY: subkey is a valid nul-terminated wide string, and key a valid pointer.
+        check(unsafe { RegOpenKeyExW(HKEY_CURRENT_USER, subkey.as_ptr(), 0, access, &mut key) })?;
+        Ok(Self(key))
+    }
+
+    fn get_dword(&self, name: &str) -> Result<Option<u32>> {
+        let name = wide(name);
+        let mut value = 0u32;
+        let mut size = std::mem::size_of::<u32>() as u32;
+        // SAFETY: name is a valid nul-terminated wide string, and value has the given size.
+        let code = unsafe {
+            RegGetValueW(
+                self.0,
+                null(),
+                name.as_ptr(),
+                RRF_RT_REG_DWORD,
+                null_mut(),
+                (&mut value as *mut u32).cast(),
+                &mut size,
+            )
+        };
+        match code {
+            ERROR_FILE_NOT_FOUND => Ok(None),
+            code => check(code).map(|_| Some(value)),
+        }
+    }
+
+    fn get_string(&self, name: &str) -> Result<Option<String>> {
+        let name = wide(name);
+        let mut size = 0u32;
+        // SAFETY: name is a valid nul-terminated wide string; with a null buffer the call only
+        // returns the required size.
+        let code = unsafe {
+            RegGetValueW(
+                self.0,
+                null(),
+                name.as_ptr(),
+                RRF_RT_REG_SZ,
+                null_mut(),
+                null_mut(),
+                &mut size,
+            )
+        };
+        match code {
+            ERROR_FILE_NOT_FOUND => return Ok(None),
+            code => check(code)?,
+        }
+        let mut buffer = vec![0u16; (size as usize + 1) / 2];
+        // SAFETY: buffer has at least the size returned by the previous call.
+        check(unsafe {
+            RegGetValueW(
+                self.0,
+                null(),
+                name.as_ptr(),
+                RRF_RT_REG_SZ,
+                null_mut(),
+                buffer.as_mut_ptr().cast(),
+                &mut size,
+            )
+        })?;
+        let len = buffer.iter().position(|c| *c == 0).unwrap_or(buffer.len());
+        Ok(Some(
+            OsString::from_wide(&buffer[..len])
+                .to_string_lossy()
+                .into_owned(),
+        ))
+    }
+
+    pub(super) fn get_binary(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let name = wide(name);
+        let mut size = 0u32;
+        // SAFETY: name is a valid nul-terminated wide string; with a null buffer the call only
+        // returns the required size.
+        let code = unsafe {
+            RegGetValueW(
+                self.0,
+                null(),
+                name.as_ptr(),
+                RRF_RT_REG_BINARY,
+                null_mut(),
+                null_mut(),
+                &mut size,
+            )
+        };
+        match code {
+            ERROR_FILE_NOT_FOUND => return Ok(None),
+            code => check(code)?,
+        }
+        let mut buffer = vec![0u8; size as usize];
+        // SAFETY: buffer has the size returned by the previous call.
+        check(unsafe {
+            RegGetValueW(
+                self.0,
+                null(),
+                name.as_ptr(),
+                RRF_RT_REG_BINARY,
+                null_mut(),
+                buffer.as_mut_ptr().cast(),
+                &mut size,
+            )
+        })?;
+        buffer.truncate(size as usize);
+        Ok(Some(buffer))
+    }
+
+    /// Get the names of all values of this key.
+    pub(super) fn value_names(&self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        // The maximum length of a value name, plus the terminating nul
+        let mut buffer = vec![0u16; 16384];
+        for index in 0.. {
+            let mut len = buffer.len() as u32;
+            // SAFETY: buffer has the given length in characters, and we don't query type or data.
+            let code = unsafe {
+                RegEnumValueW(
+                    self.0,
+                    index,
+                    buffer.as_mut_ptr(),
+                    &mut len,
+                    null(),
+                    null_mut(),
+                    null_mut(),
+                    null_mut(),
+                )
+            };
+            match code {
+                ERROR_NO_MORE_ITEMS => break,
+                code => check(code)?,
+            }
+            names.push(
+                OsString::from_wide(&buffer[..len as usize])
+                    .to_string_lossy()
+                    .into_owned(),
+            );
+        }
+        Ok(names)
+    }
+
+    fn set_dword(&self, name: &str, value: u32) -> Result<()> {
+        let name = wide(name);
+        let data = value.to_ne_bytes();
+        // SAFETY: name is a valid nul-terminated wide string, and data has the given size.
+        check(unsafe {
+            RegSetValueExW(
+                self.0,
+                name.as_ptr(),
+                0,
+                REG_DWORD,
+                data.as_ptr(),
+                data.len() as u32,
+            )
+        })
+    }
+
+    fn set_string(&self, name: &str, value: &str) -> Result<()> {
+        let name = wide(name);
+        let value = wide(value);
+        // SAFETY: name and value are valid nul-terminated wide string, and the size includes the
+        // terminating nul as required by RegSetValueExW.
+        check(unsafe {
+            RegSetValueExW(
+                self.0,
+                name.as_ptr(),
+                0,
+                REG_SZ,
+                value.as_ptr().cast(),
+                (value.len() * 2) as u32,
+            )
+        })
+    }
+
+    fn delete_value(&self, name: &str) -> Result<()> {
+        let name = wide(name);
+        // SAFETY: name is a valid nul-terminated wide string.
+        match unsafe { RegDeleteValueW(self.0, name.as_ptr()) } {
+            ERROR_FILE_NOT_FOUND => Ok(()),
+            code => check(code),
+        }
+    }
+}
+
+impl Drop for Key {
+    fn drop(&mut self) {
+        // SAFETY: We own the key, and it's not used after this point.
+        unsafe { RegCloseKey(self.0) };
+    }
+}
+
+/// The per-user proxy settings of Windows.
+///
+/// These settings live in the `HKEY_CURRENT_USER\Software\Microsoft\Windows\CurrentVersion\Internet Settings`
+/// registry key.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InternetSettings {
+    /// Whether to use the proxy server, from the `ProxyEnable` value.
+    pub proxy_enable: bool,
+    /// The proxy server, from the `ProxyServer` value.
+    ///
+    /// Either a single `host:port` for all schemes, or a semicolon separated list of
+    /// `scheme=host:port` entries.
+    pub proxy_server: String,
+    /// Hosts to connect to directly, from the `ProxyOverride` value.
+    ///
+    /// A semicolon separated list of host names with `*` wildcards; the special entry `<local>`
+    /// bypasses the proxy for all host names without a dot.
+    pub proxy_override: String,
+    /// The URL of a proxy auto-config script, from the `AutoConfigURL` value.
+    ///
+    /// `None` if no proxy auto-config script is set.
+    pub auto_config_url: Option<String>,
+}
+
+impl InternetSettings {
+    /// Read the current Internet Settings of this user.
+    pub fn read() -> Result<Self> {
+        let key = Key::open_current_user(INTERNET_SETTINGS_KEY, KEY_QUERY_VALUE)?;
+        Ok(Self {
+            proxy_enable: key.get_dword("ProxyEnable")?.unwrap_or_default() != 0,
+            proxy_server: key.get_string("ProxyServer")?.unwrap_or_default(),
+            proxy_override: key.get_string("ProxyOverride")?.unwrap_or_default(),
+            auto_config_url: key.get_string("AutoConfigURL")?,
+        })
+    }
+
+    /// Parse the [proxy server](InternetSettings::proxy_server) list.
+    pub fn proxy_servers(&self) -> ProxyServerList {
+        ProxyServerList::parse(&self.proxy_server)
+    }
+
+    /// Parse the [proxy override](InternetSettings::proxy_override) list into no proxy rules.
+    ///
+    /// See [`parse_bypass_list`] for details and limitations.
+    pub fn bypass_rules(&self) -> NoProxyRules {
+        parse_bypass_list(&self.proxy_override)
+    }
+
+    /// Write these settings to the Internet Settings of this user.
+    ///
+    /// After writing the settings notify running applications about the change with
+    /// [`notify_settings_changed`].
+    pub fn write(&self) -> Result<()> {
+        let key = Key::open_current_user(INTERNET_SETTINGS_KEY, KEY_SET_VALUE)?;
+        key.set_dword("ProxyEnable", u32::from(self.proxy_enable))?;
+        key.set_string("ProxyServer", &self.proxy_server)?;
+        key.set_string("ProxyOverride", &self.proxy_override)?;
+        match &self.auto_config_url {
+            Some(url) => key.set_string("AutoConfigURL", url)?,
+            None => key.delete_value("AutoConfigURL")?,
+        }
+        notify_settings_changed()
+    }
+}
+
+/// Notify running applications that the Internet Settings changed.
+///
+/// Tell WinINet to reload its settings, and broadcast `WM_SETTINGCHANGE` for `Internet Settings`
+/// to all top-level windows.  Without this notification many applications only pick up new
+/// settings after a restart.
+pub fn notify_settings_changed() -> Result<()> {
+    for option in [INTERNET_OPTION_SETTINGS_CHANGED, INTERNET_OPTION_REFRESH] {
+        // SAFETY: Both options take no buffer.
+        if unsafe { InternetSetOptionW(null(), option, null(), 0) } == 0 {
+            return Err(Error::last_os_error());
+        }
+    }
+    let area = wide("Internet Settings");
+    // SAFETY: area is a valid nul-terminated wide string which outlives the call.  We don't care
+    // whether all windows processed the message, so we ignore the result.
+    unsafe {
+        SendMessageTimeoutW(
+            HWND_BROADCAST,
+            WM_SETTINGCHANGE,
+            0,
+            area.as_ptr() as isize,
+            SMTO_ABORTIFHUNG,
+            1000,
+            null_mut(),
+        )
+    };
+    Ok(())
+}
+
+/// An unnamed auto-reset event object.
+#[cfg(feature = "watch")]
+#[derive(Debug)]
+pub(crate) struct Event(HANDLE);
+
+// SAFETY: Event handles can be signaled, waited for, and closed from any thread.
+#[cfg(feature = "watch")]
+unsafe impl Send for Event {}
+#[cfg(feature = "watch")]
+unsafe impl Sync for Event {}
+
+#[cfg(feature = "watch")]
+impl Event {
+    /// Create a new event which is not signaled.
+    pub(crate) fn new() -> Result<Self> {
+        // SAFETY: All pointer arguments may be null.
+        let handle = unsafe { CreateEventW(null(), 0, 0, null()) };
+        if handle.is_null() {
+            Err(Error::last_os_error())
+        } else {
+            Ok(Self(handle))
+        }
+    }
+
+    /// Signal this event.
+    pub(crate) fn set(&self) -> Result<()> {
+        // SAFETY: self.0 is a valid event handle.
+        if unsafe { SetEvent(self.0) } == 0 {
+            Err(Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "watch")]
+impl Drop for Event {
+    fn drop(&mut self) {
+        // SAFETY: We own the handle, and nobody uses it after drop.
+        unsafe { CloseHandle(self.0) };
+    }
+}
+
+/// A watch for changes of the Internet Settings in the registry.
+#[cfg(feature = "watch")]
+pub(crate) struct InternetSettingsWatch {
+    key: Key,
+    changed: Event,
+}
+
+#[cfg(feature = "watch")]
+impl InternetSettingsWatch {
+    /// Open the Internet Settings key for notifications.
+    pub(crate) fn new() -> Result<Self> {
+        Ok(Self {
+            key: Key::open_current_user(INTERNET_SETTINGS_KEY, KEY_NOTIFY)?,
+            changed: Event::new()?,
+        })
+    }
+
+    /// Wait until the Internet Settings change, or until `stop` is signaled.
+    ///
+    /// Return `true` if any value below the Internet Settings key changed, and `false` if `stop`
+    /// was signaled.
+    pub(crate) fn wait(&self, stop: &Event) -> Result<bool> {
+        // SAFETY: The key and the event are valid handles.
+        check(unsafe {
+            RegNotifyChangeKeyValue(
+                self.key.0,
+                1,
+                REG_NOTIFY_CHANGE_NAME | REG_NOTIFY_CHANGE_LAST_SET,
+                self.changed.0,
+                1,
+            )
+        })?;
+        let handles = [self.changed.0, stop.0];
+        // SAFETY: handles holds two valid event handles.
+        let result = unsafe { WaitForMultipleObjects(2, handles.as_ptr(), 0, INFINITE) };
+        if result == WAIT_OBJECT_0 {
+            Ok(true)
+        } else if result == WAIT_OBJECT_0 + 1 {
+            Ok(false)
+        } else {
+            Err(Error::last_os_error())
+        }
+    }
+}
@@ -0,0 +1,413 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Resolve proxies with WinHttp, including proxy auto-config.
+
+use std::ffi::c_void;
+use std::io::{Error, Result};
+use std::ptr::{null, null_mut};
+
+use url::Url;
+use windows_sys::core::PWSTR;
+use windows_sys::Win32::Foundation::GlobalFree;
+use windows_sys::Win32::Networking::WinHttp::{
+    WinHttpCloseHandle, WinHttpGetIEProxyConfigForCurrentUser, WinHttpGetProxyForUrl, WinHttpOpen,
+    WinHttpResetAutoProxy, ERROR_WINHTTP_AUTODETECTION_FAILED, ERROR_WINHTTP_LOGIN_FAILURE,
+    ERROR_WINHTTP_UNABLE_TO_DOWNLOAD_SCRIPT, WINHTTP_ACCESS_TYPE_NAMED_PROXY,
+    WINHTTP_ACCESS_TYPE_NO_PROXY, WINHTTP_AUTOPROXY_AUTO_DETECT, WINHTTP_AUTOPROXY_CONFIG_URL,
+    WINHTTP_AUTOPROXY_NO_CACHE_CLIENT, WINHTTP_AUTOPROXY_NO_CACHE_SVC, WINHTTP_AUTOPROXY_OPTIONS,
+    WINHTTP_AUTO_DETECT_TYPE_DHCP, WINHTTP_AUTO_DETECT_TYPE_DNS_A,
+    WINHTTP_CURRENT_USER_IE_PROXY_CONFIG, WINHTTP_PROXY_INFO, WINHTTP_RESET_ALL,
+    WINHTTP_RESET_OUT_OF_PROC,
+};
+
+use super::settings::wide;
+use super::{parse_bypass_list, ProxyServerList};
+use crate::env::NoProxy;
+use crate::proxy::{is_non_network_url, ProxyDecision, ProxyResolver};
+use crate::redact::redact;
+
+/// A wide string which WinHttp allocated, and which we must free with `GlobalFree`.
+struct GlobalString(PWSTR);
+
+impl GlobalString {
+    /// Convert this string to a Rust string, or `None` if WinHttp returned no string.
+    fn to_string_lossy(&self) -> Option<String> {
+        if self.0.is_null() {
+            return None;
+        }
+        // SAFETY: WinHttp returns nul-terminated wide strings, which live until we free them.
+        let string = unsafe {
+            let len = (0..).take_while(|&i| *self.0.add(i) != 0).count();
+            std::slice::from_raw_parts(self.0, len)
+        };
+        Some(String::from_utf16_lossy(string))
+    }
+}
+
+impl Drop for GlobalString {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            // SAFETY: WinHttp allocated the string with GlobalAlloc, and we own it.
+            unsafe { GlobalFree(self.0 as *mut c_void) };
+        }
+    }
+}
+
+/// The proxy configuration of Internet Explorer for the current user.
+struct IeProxyConfig {
+    auto_detect: bool,
+    auto_config_url: GlobalString,
+    proxy: GlobalString,
+    proxy_bypass: GlobalString,
+}
+
+impl IeProxyConfig {
+    /// Get the configuration of the current user.
+    fn current_user() -> Result<Self> {
+        let mut config = WINHTTP_CURRENT_USER_IE_PROXY_CONFIG {
+            fAutoDetect: 0,
+            lpszAutoConfigUrl: null_mut(),
+            lpszProxy: null_mut(),
+            lpszProxyBypass: null_mut(),
+        };
+        // SAFETY: config is a valid pointer; we take ownership of the strings below.
+        if unsafe { WinHttpGetIEProxyConfigForCurrentUser(&mut config) } == 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(Self {
+            auto_detect: config.fAutoDetect != 0,
+            auto_config_url: GlobalString(config.lpszAutoConfigUrl),
+            proxy: GlobalString(config.lpszProxy),
+            proxy_bypass: GlobalString(config.lpszProxyBypass),
+        })
+    }
+}
+
+/// Decide how to connect to `url` with a proxy server `list` and a `bypass` list.
+fn decide_with_lists(url: &Url, list: Option<String>, bypass: Option<String>) -> ProxyDecision {
+    let bypass = parse_bypass_list(bypass.as_deref().unwrap_or_default());
+    match list {
+        Some(list) if !bypass.no_proxy_for(url) => ProxyServerList::parse(&list)
+            .for_url(url)
+            .map_or(ProxyDecision::Direct, ProxyDecision::Proxy),
+        _ => ProxyDecision::Direct,
+    }
+}
+
+/// A WinHttp session handle.
+struct Session(*mut c_void);
+
+// SAFETY: WinHttp session handles may be used from any thread.
+unsafe impl Send for Session {}
+// SAFETY: WinHttp session handles may be used from any thread concurrently.
+unsafe impl Sync for Session {}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        // SAFETY: We own the handle, and it's not used after this point.
+        unsafe { WinHttpCloseHandle(self.0) };
+    }
+}
+
+/// Options for a [`WinHttpProxyResolver`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WinHttpOptions {
+    user_agent: String,
+    auto_logon: bool,
+    cache: bool,
+}
+
+impl Default for WinHttpOptions {
+    fn default() -> Self {
+        Self {
+            user_agent: concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")).to_string(),
+            auto_logon: true,
+            cache: true,
+        }
+    }
+}
+
+impl WinHttpOptions {
+    /// Set the user agent of the WinHttp session, e.g. the name and version of the application.
+    ///
+    /// WinHttp sends the user agent when it downloads proxy auto-config scripts; some servers
+    /// only serve scripts to known user agents.  Defaults to `system_proxy/<version>`.
+    pub fn with_user_agent<S: Into<String>>(mut self, user_agent: S) -> Self {
+        self.user_agent = user_agent.into();
+        self
+    }
+
+    /// Whether to send the credentials of the current user if the server of a proxy auto-config
+    /// script asks for authentication.
+    ///
+    /// If enabled, first try to download the script without credentials, and only if the server
+    /// asks for authentication try again with the credentials of the current user, as Microsoft
+    /// recommends.  Sending credentials exposes them to the server, which may be anyone in the
+    /// network for auto-discovered scripts.  If disabled, fall back to the manual proxy settings
+    /// if the server requires authentication.  Enabled by default.
+    pub fn with_auto_logon(mut self, auto_logon: bool) -> Self {
+        self.auto_logon = auto_logon;
+        self
+    }
+
+    /// Whether WinHttp may cache discovered and downloaded proxy auto-config scripts.
+    ///
+    /// If disabled, discover and download scripts for every lookup, both in this process and
+    /// in the WinHttp auto-proxy service, which makes lookups slower, but never returns stale
+    /// results after the network changed.  Enabled by default; see also
+    /// [`WinHttpProxyResolver::reset_auto_proxy`].
+    pub fn with_cache(mut self, cache: bool) -> Self {
+        self.cache = cache;
+        self
+    }
+}
+
+/// A proxy resolver which uses WinHttp.
+///
+/// Resolve proxies like Windows applications do: Use the proxy settings of the current user,
+/// i.e. the [Internet Settings](super::InternetSettings), and evaluate proxy auto-config scripts
+/// from the configured URL or from WPAD auto-discovery with `WinHttpGetProxyForUrl`.  If proxy
+/// auto-config fails, because auto-discovery found no script or the script can't be
+/// downloaded, fall back to the manual proxy settings.  See [`WinHttpOptions`] for options.
+///
+/// Lookups block the current thread, possibly for a long time if WinHttp needs to download a
+/// proxy auto-config script.
+pub struct WinHttpProxyResolver {
+    session: Session,
+    options: WinHttpOptions,
+}
+
+impl std::fmt::Debug for WinHttpProxyResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WinHttpProxyResolver")
+            .field("options", &self.options)
+            .finish_non_exhaustive()
+    }
+}
+
+impl WinHttpProxyResolver {
+    /// Open a WinHttp session for proxy lookups, with default options.
+    pub fn new() -> Result<Self> {
+        Self::with_options(WinHttpOptions::default())
+    }
+
+    /// Open a WinHttp session for proxy lookups, with the given `options`.
+    pub fn with_options(options: WinHttpOptions) -> Result<Self> {
+        let agent = wide(&options.user_agent);
+        // SAFETY: agent is a valid nul-terminated wide string which outlives the call.
+        let handle = unsafe {
+            WinHttpOpen(
+                agent.as_ptr(),
+                WINHTTP_ACCESS_TYPE_NO_PROXY,
+                null(),
+                null(),
+                0,
+            )
+        };
+        if handle.is_null() {
+            Err(Error::last_os_error())
+        } else {
+            Ok(Self {
+                session: Session(handle),
+                options,
+            })
+        }
+    }
+
+    /// Evaluate proxy auto-config for `url`.
+    ///
+    /// Use auto-discovery if `auto_detect` and the script at `auto_config_url` if any.  Return
+    /// `None` if auto-discovery found no script, or if the script could not be downloaded.
+    ///
+    /// Download the script without credentials first, and retry with the credentials of the
+    /// current user if the server asks for authentication and the options allow auto-logon.
+    fn auto_config(
+        &self,
+        url: &Url,
+        auto_detect: bool,
+        auto_config_url: Option<&str>,
+    ) -> Result<Option<ProxyDecision>> {
+        match self.auto_config_with_logon(url, auto_detect, auto_config_url, false) {
+            Err(error)
+                if self.options.auto_logon
+                    && error.raw_os_error().map(|code| code as u32)
+                        == Some(ERROR_WINHTTP_LOGIN_FAILURE) =>
+            {
+                debug!(
+                    "Proxy auto-config for {} requires authentication, retrying with auto-logon",
+                    redact(url)
+                );
+                self.auto_config_with_logon(url, auto_detect, auto_config_url, true)
+            }
+            result => result,
+        }
+    }
+
+    /// Evaluate proxy auto-config for `url`, with or without `auto_logon`.
+    ///
+    /// See [`WinHttpProxyResolver::auto_config`].
+    fn auto_config_with_logon(
+        &self,
+        url: &Url,
+        auto_detect: bool,
+        auto_config_url: Option<&str>,
+        auto_logon: bool,
+    ) -> Result<Option<ProxyDecision>> {
+        let auto_config_url = auto_config_url.map(wide);
+        let mut options = WINHTTP_AUTOPROXY_OPTIONS {
+            dwFlags: 0,
+            dwAutoDetectFlags: 0,
+            lpszAutoConfigUrl: null(),
+            lpvReserved: null_mut(),
+            dwReserved: 0,
+            fAutoLogonIfChallenged: auto_logon.into(),
+        };
+        if auto_detect {
+            options.dwFlags |= WINHTTP_AUTOPROXY_AUTO_DETECT;
+            options.dwAutoDetectFlags =
+                WINHTTP_AUTO_DETECT_TYPE_DHCP | WINHTTP_AUTO_DETECT_TYPE_DNS_A;
+        }
+        if let Some(auto_config_url) = &auto_config_url {
+            options.dwFlags |= WINHTTP_AUTOPROXY_CONFIG_URL;
+            options.lpszAutoConfigUrl = auto_config_url.as_ptr();
+        }
+        if !self.options.cache {
+            options.dwFlags |= WINHTTP_AUTOPROXY_NO_CACHE_CLIENT | WINHTTP_AUTOPROXY_NO_CACHE_SVC;
+        }
+        let target = wide(url.as_str());
+        let mut info = WINHTTP_PROXY_INFO {
+            dwAccessType: WINHTTP_ACCESS_TYPE_NO_PROXY,
+            lpszProxy: null_mut(),
+            lpszProxyBypass: null_mut(),
+        };
+        // SAFETY: All strings are valid nul-terminated wide strings which outlive the call, and
+        // options and info are valid pointers; we take ownership of the strings in info below.
+        let succeeded = unsafe {
+            WinHttpGetProxyForUrl(self.session.0, target.as_ptr(), &mut options, &mut info)
+        } != 0;
+        if !succeeded {
+            let error = Error::last_os_error();
+            return match error.raw_os_error().map(|code| code as u32) {
+                Some(ERROR_WINHTTP_AUTODETECTION_FAILED)
+                | Some(ERROR_WINHTTP_UNABLE_TO_DOWNLOAD_SCRIPT) => {
+                    debug!("Proxy auto-config unavailable for {}: {error}", redact(url));
+                    Ok(None)
+                }
+                Some(ERROR_WINHTTP_LOGIN_FAILURE) if auto_logon || !self.options.auto_logon => {
+                    debug!(
+                        "Proxy auto-config for {} requires authentication: {error}",
+                        redact(url)
+                    );
+                    Ok(None)
+                }
+                _ => Err(error),
+            };
+        }
+        let proxy = GlobalString(info.lpszProxy);
+        let bypass = GlobalString(info.lpszProxyBypass);
+        Ok(Some(
+            if info.dwAccessType == WINHTTP_ACCESS_TYPE_NAMED_PROXY {
+                decide_with_lists(url, proxy.to_string_lossy(), bypass.to_string_lossy())
+            } else {
+                ProxyDecision::Direct
+            },
+        ))
+    }
+
+    /// Flush the cached proxy auto-config state of WinHttp.
+    ///
+    /// Forget discovered and downloaded proxy auto-config scripts, in this process and in the
+    /// WinHttp auto-proxy service, so that the next lookup discovers and downloads the script
+    /// again.  Call this after the network changed, e.g. when a VPN connected.
+    pub fn reset_auto_proxy(&self) -> Result<()> {
+        // SAFETY: The session handle is valid while we own it.
+        let error = unsafe {
+            WinHttpResetAutoProxy(
+                self.session.0,
+                WINHTTP_RESET_ALL | WINHTTP_RESET_OUT_OF_PROC,
+            )
+        };
+        if error == 0 {
+            Ok(())
+        } else {
+            Err(Error::from_raw_os_error(error as i32))
+        }
+    }
+
+    /// Lookup how to connect to `url`.
+    ///
+    /// Connect directly to [non-network URLs](is_non_network_url).  Fail if WinHttp can't read
+    /// the proxy settings, or if proxy auto-config fails for other reasons than a missing
+    /// script.
+    pub fn lookup(&self, url: &Url) -> Result<ProxyDecision> {
+        if is_non_network_url(url) {
+            return Ok(ProxyDecision::Direct);
+        }
+        let config = IeProxyConfig::current_user()?;
+        let auto_config_url = config.auto_config_url.to_string_lossy();
+        if config.auto_detect || auto_config_url.is_some() {
+            if let Some(decision) =
+                self.auto_config(url, config.auto_detect, auto_config_url.as_deref())?
+            {
+                return Ok(decision);
+            }
+        }
+        Ok(decide_with_lists(
+            url,
+            config.proxy.to_string_lossy(),
+            config.proxy_bypass.to_string_lossy(),
+        ))
+    }
+}
+
+impl ProxyResolver for WinHttpProxyResolver {
+    fn for_url(&self, url: &Url) -> Option<Url> {
+        self.decide(url).into_proxy()
+    }
+
+    fn decide(&self, url: &Url) -> ProxyDecision {
+        self.lookup(url).unwrap_or_else(|error| {
+            warn!(
+                "Failed to lookup proxy for {} with WinHttp: {error}",
+                redact(url)
+            );
+            ProxyDecision::Unspecified
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn lists() {
+        let url = Url::parse("https://example.com").unwrap();
+        let list = || Some("http=proxy1:80;https=proxy2:443".to_string());
+        assert_eq!(
+            decide_with_lists(&url, list(), None),
+            ProxyDecision::Proxy(Url::parse("http://proxy2:443").unwrap())
+        );
+        assert_eq!(
+            decide_with_lists(&url, list(), Some("*.example.org;example.com".into())),
+            ProxyDecision::Direct
+        );
+        assert_eq!(decide_with_lists(&url, None, None), ProxyDecision::Direct);
+    }
+
+    #[test]
+    fn non_network_urls() {
+        let resolver = WinHttpProxyResolver::new().unwrap();
+        assert_eq!(
+            resolver
+                .lookup(&Url::parse("mailto:john@example.com").unwrap())
+                .unwrap(),
+            ProxyDecision::Direct
+        );
+    }
+}
@@ -0,0 +1,236 @@
+// Copyright (c) Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Discover proxy auto-config scripts with WPAD.
+//!
+//! Web Proxy Auto-Discovery finds the URL of a proxy auto-config script in the network:
+//!
+//! 1. DHCP servers announce the URL in option 252; on Linux and the BSDs [`discover_urls`] reads
+//!    it from the lease files of the DHCP client, see [`crate::unix::dhcp`].
+//! 2. Otherwise clients probe `http://wpad.<domain>/wpad.dat` for the DNS search domain and its
+//!    parent domains, see [`dns_candidates`].
+//!
+//! [`WpadResolver`] discovers the script once, and evaluates it with [`PacResolver`].  On Windows
+//! prefer `windows::WinHttpProxyResolver`, which implements WPAD natively, including DHCP.
+//!
+//! WPAD trusts the network: Anyone who controls DHCP or DNS in the network, or who registers
+//! `wpad` in a parent domain, controls the proxy.  Only use WPAD in trusted networks.
+//!
+//! This module requires the `pac` feature.
+
+use std::sync::Mutex;
+
+use url::Url;
+
+use crate::pac::PacResolver;
+use crate::proxy::{is_non_network_url, ProxyDecision, ProxyResolver};
+use crate::redact::redact;
+
+/// Get the candidate URLs of WPAD scripts for the DNS `domain`.
+///
+/// Return `http://wpad.<domain>/wpad.dat` for `domain` and all its parent domains with at least
+/// two labels, most specific first.  Never probe `wpad` in a top-level domain; however, this
+/// function does not know about public suffixes like `co.uk`.
+pub fn dns_candidates(domain: &str) -> Vec<Url> {
+    let labels = domain
+        .trim_end_matches('.')
+        .split('.')
+        .filter(|label| !label.is_empty())
+        .collect::<Vec<_>>();
+    (0..labels.len().saturating_sub(1))
+        .filter_map(|start| {
+            Url::parse(&format!(
+                "http://wpad.{}/wpad.dat",
+                labels[start..].join(".")
+            ))
+            .ok()
+        })
+        .collect()
+}
+
+/// Get WPAD URLs from DHCP.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn dhcp_urls() -> Vec<Url> {
+    crate::unix::dhcp::read_wpad_urls()
+}
+
+/// Get WPAD URLs from DHCP.
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+fn dhcp_urls() -> Vec<Url> {
+    Vec::new()
+}
+
+/// Get the DNS search domain of the current network.
+#[cfg(unix)]
+fn search_domain() -> Option<String> {
+    crate::profile::detect_dns_suffix()
+}
+
+/// Get the DNS search domain of the current network.
+#[cfg(not(unix))]
+fn search_domain() -> Option<String> {
+    None
+}
+
+/// Whether the host of `url` resolves.
+///
+/// See [`crate::dns`] for how this function resolves hosts.
+fn resolves(url: &Url) -> bool {
+    url.host_str()
+        .map_or(false, |host| !crate::dns::resolve(host).is_empty())
+}
+
+/// Discover the URLs of WPAD scripts in the current network.
+///
+/// Return URLs from DHCP leases first, and then the [DNS candidates](dns_candidates) for the
+/// DNS search domain whose hosts resolve.  This function blocks while it resolves host names;
+/// with the `hickory-dns` feature it gives up on every name after a timeout.
+pub fn discover_urls() -> Vec<Url> {
+    let mut urls = dhcp_urls();
+    match search_domain() {
+        Some(domain) => {
+            for url in dns_candidates(&domain) {
+                if !resolves(&url) {
+                    debug!("Skipping WPAD candidate {url}, host does not resolve");
+                } else if !urls.contains(&url) {
+                    urls.push(url);
+                }
+            }
+        }
+        None => debug!("No DNS search domain, skipping WPAD DNS discovery"),
+    }
+    urls
+}
+
+/// Discover and load the WPAD script of the current network.
+///
+/// Load the first script from [`discover_urls`] which loads successfully, or return `None` if
+/// there's no script.  This function blocks while it discovers and downloads scripts.
+pub fn discover() -> Option<PacResolver> {
+    discover_urls()
+        .into_iter()
+        .find_map(|url| match PacResolver::from_url(&url) {
+            Ok(resolver) => {
+                info!("Using WPAD script from {}", redact(&url));
+                Some(resolver)
+            }
+            Err(error) => {
+                debug!("Failed to load WPAD script from {}: {error}", redact(&url));
+                None
+            }
+        })
+}
+
+/// A proxy resolver which discovers and evaluates the WPAD script of the network.
+///
+/// Discover the script with [`discover`] on the first lookup, and reuse it for all further
+/// lookups; call [`WpadResolver::refresh`] when the network changes.  If the network has no
+/// script leave the decision to other resolvers, i.e. return [`ProxyDecision::Unspecified`].
+/// Connect directly to [non-network URLs](is_non_network_url).
+///
+/// Lookups block while discovering the script, and while evaluating it; see [`PacResolver`].
+#[derive(Debug, Default)]
+pub struct WpadResolver {
+    script: Mutex<Option<Option<PacResolver>>>,
+}
+
+impl WpadResolver {
+    /// Create a new resolver, which discovers the script on the first lookup.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the discovered script, discovering it if necessary.
+    ///
+    /// Return `None` if the network has no WPAD script.
+    pub fn script(&self) -> Option<PacResolver> {
+        let mut script = self
+            .script
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        script.get_or_insert_with(discover).clone()
+    }
+
+    /// Forget the discovered script, and discover it again on the next lookup.
+    pub fn refresh(&self) {
+        *self
+            .script
+            .lock()
+            .unwrap_or_else(|error| error.into_inner()) = None;
+    }
+}
+
+impl ProxyResolver for WpadResolver {
+    fn for_url(&self, url: &Url) -> Option<Url> {
+        self.decide(url).into_proxy()
+    }
+
+    fn decide(&self, url: &Url) -> ProxyDecision {
+        if is_non_network_url(url) {
+            ProxyDecision::Direct
+        } else {
+            self.script()
+                .map_or(ProxyDecision::Unspecified, |script| script.decide(url))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn candidates() {
+        let urls = |domain: &str| {
+            dns_candidates(domain)
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            urls("eng.corp.example.com."),
+            vec![
+                "http://wpad.eng.corp.example.com/wpad.dat",
+                "http://wpad.corp.example.com/wpad.dat",
+                "http://wpad.example.com/wpad.dat",
+            ]
+        );
+        assert_eq!(
+            urls("example.com"),
+            vec!["http://wpad.example.com/wpad.dat"]
+        );
+        assert_eq!(urls("localdomain"), Vec::<String>::new());
+        assert_eq!(urls(""), Vec::<String>::new());
+    }
+
+    #[test]
+    fn cached_script() {
+        let resolver = WpadResolver {
+            script: Mutex::new(Some(Some(
+                PacResolver::new(
+                    "function FindProxyForURL(url, host) { return 'PROXY wpad-proxy:3128'; }",
+                )
+                .unwrap(),
+            ))),
+        };
+        assert_eq!(
+            resolver.decide(&Url::parse("http://example.com").unwrap()),
+            ProxyDecision::Proxy(Url::parse("http://wpad-proxy:3128").unwrap())
+        );
+        assert_eq!(
+            resolver.decide(&Url::parse("mailto:john@example.com").unwrap()),
+            ProxyDecision::Direct
+        );
+        let resolver = WpadResolver {
+            script: Mutex::new(Some(None)),
+        };
+        assert_eq!(
+            resolver.decide(&Url::parse("http://example.com").unwrap()),
+            ProxyDecision::Unspecified
+        );
+    }
+}